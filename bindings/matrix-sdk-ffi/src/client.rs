@@ -5,22 +5,19 @@ use matrix_sdk::{
     config::SyncSettings,
     media::{MediaFormat, MediaRequest, MediaThumbnailSize},
     ruma::{
-        api::{
-            client::{
-                account::whoami,
-                error::ErrorKind,
-                filter::{FilterDefinition, LazyLoadOptions, RoomEventFilter, RoomFilter},
-                media::get_content_thumbnail::v3::Method,
-                session::get_login_types,
-                sync::sync_events::v3::Filter,
-            },
-            error::{FromHttpResponseError, ServerError},
+        api::client::{
+            account::whoami,
+            error::ErrorKind,
+            filter::{FilterDefinition, LazyLoadOptions, RoomEventFilter, RoomFilter},
+            media::get_content_thumbnail::v3::Method,
+            session::get_login_types,
+            sync::sync_events::v3::Filter,
         },
         events::room::MediaSource,
         serde::Raw,
         TransactionId, UInt,
     },
-    Client as MatrixClient, Error, HttpError, LoopCtrl, RumaApiError, Session,
+    Client as MatrixClient, Error, LoopCtrl, Session, SessionState,
 };
 
 use super::{
@@ -77,6 +74,7 @@ impl Client {
                 builder = builder.device_id(device_id);
             }
             builder.send().await?;
+            self.state.write().unwrap().is_soft_logout = false;
             Ok(())
         })
     }
@@ -198,7 +196,7 @@ impl Client {
                 session,
                 homeurl,
                 is_guest: self.state.read().unwrap().is_guest,
-                is_soft_logout: self.state.read().unwrap().is_soft_logout,
+                is_soft_logout: self.is_soft_logout(),
             })?)
         })
     }
@@ -327,21 +325,20 @@ impl Client {
 
     /// Process a sync error and return loop control accordingly
     fn process_sync_error(&self, sync_error: Error) -> LoopCtrl {
-        let mut control = LoopCtrl::Continue;
-        if let Error::Http(HttpError::Api(FromHttpResponseError::Server(ServerError::Known(
-            RumaApiError::ClientApi(error),
-        )))) = sync_error
-        {
-            if let ErrorKind::UnknownToken { soft_logout } = error.kind {
-                self.state.write().unwrap().is_soft_logout = soft_logout;
-                if let Some(delegate) = &*self.delegate.read().unwrap() {
-                    delegate.did_update_restore_token();
-                    delegate.did_receive_auth_error(soft_logout);
-                }
-                control = LoopCtrl::Break
-            }
+        let soft_logout = match &sync_error {
+            Error::Http(error) => match error.client_api_error_kind() {
+                Some(ErrorKind::UnknownToken { soft_logout }) => *soft_logout,
+                _ => return LoopCtrl::Continue,
+            },
+            _ => return LoopCtrl::Continue,
+        };
+
+        if let Some(delegate) = &*self.delegate.read().unwrap() {
+            delegate.did_update_restore_token();
+            delegate.did_receive_auth_error(soft_logout);
         }
-        control
+
+        LoopCtrl::Break
     }
 }
 
@@ -370,7 +367,13 @@ impl Client {
 
     /// Flag indicating whether the session is in soft logout mode
     pub fn is_soft_logout(&self) -> bool {
-        self.state.read().unwrap().is_soft_logout
+        match self.client.session_state() {
+            SessionState::SoftLoggedOut => true,
+            SessionState::LoggedOut => false,
+            // A session restored in soft logout mode is only detected again
+            // at the next request.
+            SessionState::LoggedIn => self.state.read().unwrap().is_soft_logout,
+        }
     }
 
     pub fn rooms(&self) -> Vec<Arc<Room>> {
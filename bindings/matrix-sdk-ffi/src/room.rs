@@ -7,10 +7,7 @@ use anyhow::{bail, Context, Result};
 use futures_util::{pin_mut, StreamExt};
 use matrix_sdk::{
     room::Room as MatrixRoom,
-    ruma::{
-        events::room::message::{RoomMessageEvent, RoomMessageEventContent},
-        EventId, UserId,
-    },
+    ruma::{events::room::message::RoomMessageEventContent, EventId, UserId},
 };
 
 use super::{
@@ -183,20 +180,8 @@ impl Room {
             in_reply_to_event_id.as_str().try_into().context("Failed to create EventId.")?;
 
         RUNTIME.block_on(async move {
-            let timeline_event = room.event(event_id).await.context("Couldn't find event.")?;
-
-            let event_content = timeline_event
-                .event
-                .deserialize_as::<RoomMessageEvent>()
-                .context("Couldn't deserialise event")?;
-
-            let original_message =
-                event_content.as_original().context("Couldn't retrieve original message.")?;
-
-            let reply_content =
-                RoomMessageEventContent::text_markdown(msg).make_reply_to(original_message);
-
-            room.send(reply_content, txn_id.as_deref().map(Into::into)).await?;
+            let content = RoomMessageEventContent::text_markdown(msg);
+            room.reply_to(content, event_id, txn_id.as_deref().map(Into::into)).await?;
 
             Ok(())
         })
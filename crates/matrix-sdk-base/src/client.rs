@@ -15,7 +15,7 @@
 
 use std::{
     borrow::Borrow,
-    collections::{BTreeMap, BTreeSet},
+    collections::{btree_map::Entry, BTreeMap, BTreeSet},
     fmt,
 };
 #[cfg(feature = "e2e-encryption")]
@@ -27,7 +27,7 @@ use matrix_sdk_common::deserialized_responses::TimelineSlice;
 use matrix_sdk_common::{
     deserialized_responses::{
         AmbiguityChanges, JoinedRoom, LeftRoom, MembersResponse, Rooms, SyncResponse,
-        SyncTimelineEvent, Timeline,
+        SyncTimelineEvent, ThreadSummary, Timeline,
     },
     instant::Instant,
 };
@@ -46,7 +46,9 @@ use ruma::events::{
 use ruma::{
     api::client::{self as api, push::get_notifications::v3::Notification},
     events::{
+        ignored_user_list::{IgnoredUserListEvent, IgnoredUserListEventContent},
         push_rules::{PushRulesEvent, PushRulesEventContent},
+        receipt::ReceiptType,
         room::{
             member::{MembershipState, SyncRoomMemberEvent},
             power_levels::{RoomPowerLevelsEvent, RoomPowerLevelsEventContent},
@@ -57,8 +59,9 @@ use ruma::{
     },
     push::{Action, PushConditionRoomCtx, Ruleset},
     serde::Raw,
-    MilliSecondsSinceUnixEpoch, OwnedUserId, RoomId, UInt, UserId,
+    uint, MilliSecondsSinceUnixEpoch, OwnedEventId, OwnedUserId, RoomId, UInt, UserId,
 };
+use serde_json::Value as JsonValue;
 use tracing::{debug, info, trace, warn};
 
 #[cfg(feature = "e2e-encryption")]
@@ -264,6 +267,8 @@ impl BaseClient {
         room_info: &mut RoomInfo,
         changes: &mut StateChanges,
         ambiguity_cache: &mut AmbiguityCache,
+        read_receipts: &[OwnedEventId],
+        ignored_users: &BTreeSet<OwnedUserId>,
     ) -> Result<Timeline> {
         let room_id = room.room_id();
         let user_id = room.own_user_id();
@@ -276,6 +281,13 @@ impl BaseClient {
 
             match event.event.deserialize() {
                 Ok(e) => {
+                    // State events are kept, as they are part of the room state.
+                    if !matches!(e, AnySyncTimelineEvent::State(_))
+                        && ignored_users.contains(e.sender())
+                    {
+                        continue;
+                    }
+
                     #[allow(clippy::single_match)]
                     match &e {
                         AnySyncTimelineEvent::State(s) => match s {
@@ -370,8 +382,10 @@ impl BaseClient {
                         push_context = self.get_push_room_context(room, room_info, changes).await?;
                     }
 
+                    let mut actions: &[Action] = &[];
+
                     if let Some(context) = &push_context {
-                        let actions = push_rules.get_actions(&event.event, context);
+                        actions = push_rules.get_actions(&event.event, context);
 
                         if actions.iter().any(|a| matches!(a, Action::Notify)) {
                             changes.add_notification(
@@ -394,6 +408,20 @@ impl BaseClient {
                         // with events and to
                         // store them.
                     }
+
+                    // The user read this event and the previous ones if they
+                    // sent it or if it has their read receipt.
+                    if e.sender() == user_id
+                        || read_receipts.iter().any(|event_id| event_id == e.event_id())
+                    {
+                        room_info.local_unread_counts.reset(e.event_id());
+                    } else {
+                        room_info.local_unread_counts.handle_event(
+                            e.event_id(),
+                            &event.event,
+                            actions,
+                        );
+                    }
                 }
                 Err(e) => {
                     warn!("Error deserializing event {:?}", e);
@@ -586,6 +614,34 @@ impl BaseClient {
 
         let now = Instant::now();
 
+        let mut changes = StateChanges::new(next_batch.clone());
+        let mut ambiguity_cache = AmbiguityCache::new(self.store.inner.clone());
+
+        self.handle_account_data(&account_data.events, &mut changes).await;
+
+        let push_rules = self.get_push_rules(&changes).await?;
+
+        let ignored_users = self.get_ignored_users(&changes).await?;
+        let changed_ignored_users: BTreeSet<_> =
+            if changes.account_data.contains_key(&GlobalAccountDataEventType::IgnoredUserList) {
+                let previous_ignored_users = self.stored_ignored_users().await?;
+                previous_ignored_users.symmetric_difference(&ignored_users).cloned().collect()
+            } else {
+                BTreeSet::new()
+            };
+
+        // The to-device events of ignored users are dropped before the crypto
+        // machine sees them, so their key and verification requests are
+        // ignored too.
+        let mut to_device = to_device;
+        to_device.events.retain(|event| {
+            event
+                .get_field::<OwnedUserId>("sender")
+                .ok()
+                .flatten()
+                .map_or(true, |sender| !ignored_users.contains(&sender))
+        });
+
         #[cfg(feature = "e2e-encryption")]
         let to_device = {
             if let Some(o) = self.olm_machine() {
@@ -605,13 +661,6 @@ impl BaseClient {
             }
         };
 
-        let mut changes = StateChanges::new(next_batch.clone());
-        let mut ambiguity_cache = AmbiguityCache::new(self.store.inner.clone());
-
-        self.handle_account_data(&account_data.events, &mut changes).await;
-
-        let push_rules = self.get_push_rules(&changes).await?;
-
         let mut new_rooms = Rooms::default();
 
         for (room_id, new_info) in rooms.join {
@@ -631,20 +680,43 @@ impl BaseClient {
                 )
                 .await?;
 
+            let mut read_receipts = Vec::new();
+
             if let Some(event) =
                 new_info.ephemeral.events.iter().find_map(|e| match e.deserialize() {
                     Ok(AnySyncEphemeralRoomEvent::Receipt(event)) => Some(event.content),
                     _ => None,
                 })
             {
+                read_receipts = event
+                    .iter()
+                    .filter(|(_, receipts)| {
+                        receipts
+                            .get(&ReceiptType::Read)
+                            .map_or(false, |users| users.contains_key(room.own_user_id()))
+                    })
+                    .map(|(event_id, _)| event_id.clone())
+                    .collect();
+
                 changes.add_receipts(&room_id, event);
             }
 
+            // Receipts for events in this timeline are handled with the
+            // timeline. Otherwise, we only know that the receipt is more recent
+            // than the counted events if it is for the latest one. A receipt
+            // for any other event is older, so the counts are left unchanged.
+            if let Some(event_id) = read_receipts
+                .iter()
+                .find(|event_id| room_info.local_unread_counts.is_latest_event(event_id))
+            {
+                room_info.local_unread_counts.reset(event_id);
+            }
+
             if new_info.timeline.limited {
                 room_info.mark_members_missing();
             }
 
-            let timeline = self
+            let mut timeline = self
                 .handle_timeline(
                     &room,
                     new_info.timeline,
@@ -653,9 +725,13 @@ impl BaseClient {
                     &mut room_info,
                     &mut changes,
                     &mut ambiguity_cache,
+                    &read_receipts,
+                    &ignored_users,
                 )
                 .await?;
 
+            handle_thread_replies(&room_id, room.own_user_id(), &mut timeline, &mut changes);
+
             self.handle_room_account_data(&room_id, &new_info.account_data.events, &mut changes)
                 .await;
 
@@ -730,6 +806,8 @@ impl BaseClient {
                     &mut room_info,
                     &mut changes,
                     &mut ambiguity_cache,
+                    &[],
+                    &ignored_users,
                 )
                 .await?;
 
@@ -743,6 +821,13 @@ impl BaseClient {
         }
 
         for (room_id, new_info) in rooms.invite {
+            if let Some(session_meta) = self.store.session_meta() {
+                let sender = invite_sender(&new_info.invite_state.events, &session_meta.user_id);
+                if sender.map_or(false, |sender| ignored_users.contains(&sender)) {
+                    continue;
+                }
+            }
+
             let room = self.store.get_or_create_stripped_room(&room_id).await;
             let mut room_info = room.clone_info();
 
@@ -765,6 +850,26 @@ impl BaseClient {
         // process the `m.direct` account data event.
         self.handle_account_data(&account_data.events, &mut changes).await;
 
+        // The events of users whose ignored state changed were filtered
+        // differently until now, so the timelines of the rooms they are in
+        // need to be reset.
+        if !changed_ignored_users.is_empty() {
+            for room in self.store.get_rooms() {
+                let user_ids = self.store.get_joined_user_ids(room.room_id()).await?;
+                if !user_ids.iter().any(|user_id| changed_ignored_users.contains(user_id)) {
+                    continue;
+                }
+
+                let mut room_info = changes
+                    .room_infos
+                    .get(room.room_id())
+                    .cloned()
+                    .unwrap_or_else(|| room.clone_info());
+                room_info.timeline_reset_needed = true;
+                changes.add_room(room_info);
+            }
+        }
+
         changes.presence = presence
             .events
             .iter()
@@ -1034,6 +1139,54 @@ impl BaseClient {
         }
     }
 
+    /// Get the users ignored by the own user.
+    ///
+    /// Gets the list from `changes` if it has been updated, otherwise get it
+    /// from the store.
+    pub async fn get_ignored_users(&self, changes: &StateChanges) -> Result<BTreeSet<OwnedUserId>> {
+        if let Some(event) = changes
+            .account_data
+            .get(&GlobalAccountDataEventType::IgnoredUserList)
+            .and_then(|ev| ev.deserialize_as::<IgnoredUserListEvent>().ok())
+        {
+            Ok(event.content.ignored_users.into_iter().collect())
+        } else {
+            self.stored_ignored_users().await
+        }
+    }
+
+    async fn stored_ignored_users(&self) -> Result<BTreeSet<OwnedUserId>> {
+        Ok(self
+            .store
+            .get_account_data_event_static::<IgnoredUserListEventContent>()
+            .await?
+            .and_then(|ev| ev.deserialize().ok())
+            .map(|ev| ev.content.ignored_users.into_iter().collect())
+            .unwrap_or_default())
+    }
+
+    /// Mark the timeline of the given room as reset, after it was flagged by
+    /// [`Room::is_timeline_reset_needed()`].
+    pub async fn mark_timeline_reset(&self, room_id: &RoomId) -> Result<()> {
+        let room = match self.store.get_room(room_id) {
+            Some(room) => room,
+            None => return Ok(()),
+        };
+
+        let mut room_info = room.clone_info();
+        if !room_info.timeline_reset_needed {
+            return Ok(());
+        }
+        room_info.timeline_reset_needed = false;
+
+        let mut changes = StateChanges::default();
+        changes.add_room(room_info);
+        self.store.save_changes(&changes).await?;
+        self.apply_changes(&changes).await;
+
+        Ok(())
+    }
+
     /// Get the push context for the given room.
     ///
     /// Tries to get the data from `changes` or the up to date `room_info`.
@@ -1138,15 +1291,137 @@ impl Default for BaseClient {
     }
 }
 
+/// Get the sender of the invite of the given user from the stripped state of
+/// an invited room.
+fn invite_sender(events: &[Raw<AnyStrippedStateEvent>], user_id: &UserId) -> Option<OwnedUserId> {
+    events.iter().find_map(|raw_event| match raw_event.deserialize() {
+        Ok(AnyStrippedStateEvent::RoomMember(member))
+            if *member.state_key == *user_id
+                && member.content.membership == MembershipState::Invite =>
+        {
+            Some(member.sender)
+        }
+        _ => None,
+    })
+}
+
+/// Update the summaries of the threads that received replies in the given
+/// timeline of a sync response.
+///
+/// The thread roots that are part of the timeline are updated in place, the
+/// other ones are updated in the stored timeline.
+#[cfg_attr(not(feature = "experimental-timeline"), allow(unused_variables))]
+fn handle_thread_replies(
+    room_id: &RoomId,
+    own_user_id: &UserId,
+    timeline: &mut Timeline,
+    changes: &mut StateChanges,
+) {
+    let is_own_event = |event: &SyncTimelineEvent| {
+        event.event.get_field::<OwnedUserId>("sender").ok().flatten().as_deref()
+            == Some(own_user_id)
+    };
+
+    // The summary bundled with a thread root in the timeline already counts
+    // the replies up to its latest event, which can be part of the timeline
+    // too, so only the replies after it must be added.
+    let mut bundled_latest_events: BTreeMap<OwnedEventId, OwnedEventId> = timeline
+        .events
+        .iter()
+        .filter_map(|event| {
+            let latest_event = event.thread_summary()?.latest_event;
+            let latest_event_id = latest_event.get_field::<OwnedEventId>("event_id").ok()??;
+            Some((event.event_id()?, latest_event_id))
+        })
+        .collect();
+
+    let mut threads: BTreeMap<OwnedEventId, ThreadSummary> = BTreeMap::new();
+
+    for event in &timeline.events {
+        let thread_root = match event.thread_root() {
+            Some(thread_root) => thread_root,
+            None => continue,
+        };
+
+        if let Some(latest_event_id) = bundled_latest_events.get(&thread_root) {
+            if event.event_id().as_ref() == Some(latest_event_id) {
+                bundled_latest_events.remove(&thread_root);
+            }
+
+            continue;
+        }
+
+        // The latest event of a thread summary is a full event, with a room ID.
+        let latest_event = match event
+            .event
+            .deserialize_as::<BTreeMap<String, JsonValue>>()
+            .and_then(|mut json| {
+                json.insert("room_id".to_owned(), room_id.as_str().into());
+                Raw::new(&json)
+            }) {
+            Ok(latest_event) => latest_event.cast(),
+            Err(error) => {
+                warn!(%room_id, %thread_root, "Failed to add a thread reply to its summary: {error}");
+                continue;
+            }
+        };
+        let current_user_participated = is_own_event(event);
+
+        match threads.entry(thread_root) {
+            Entry::Vacant(entry) => {
+                entry.insert(ThreadSummary {
+                    latest_event,
+                    count: uint!(1),
+                    current_user_participated,
+                });
+            }
+            Entry::Occupied(mut entry) => {
+                let summary = entry.get_mut();
+                summary.latest_event = latest_event;
+                summary.count += uint!(1);
+                summary.current_user_participated |= current_user_participated;
+            }
+        }
+    }
+
+    for (thread_root, mut replies) in threads {
+        let root = timeline
+            .events
+            .iter_mut()
+            .find(|event| event.event_id().as_ref() == Some(&thread_root));
+
+        #[allow(clippy::single_match)]
+        match root {
+            Some(root) => {
+                // The sender of the thread root participates in the thread.
+                replies.current_user_participated |= is_own_event(root);
+
+                if let Err(error) = root.add_thread_replies(&replies) {
+                    warn!(%room_id, %thread_root, "Failed to update a thread summary: {error}");
+                }
+            }
+            None => {
+                #[cfg(feature = "experimental-timeline")]
+                changes
+                    .thread_replies
+                    .entry(room_id.to_owned())
+                    .or_default()
+                    .insert(thread_root, replies);
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use matrix_sdk_test::{
-        async_test, response_from_file, EventBuilder, InvitedRoomBuilder, LeftRoomBuilder,
-        StrippedStateTestEvent, TimelineTestEvent,
+        async_test, response_from_file, EphemeralTestEvent, EventBuilder,
+        GlobalAccountDataTestEvent, InvitedRoomBuilder, JoinedRoomBuilder, LeftRoomBuilder,
+        StateTestEvent, StrippedStateTestEvent, TimelineTestEvent,
     };
     use ruma::{
         api::{client as api, IncomingResponse},
-        room_id, user_id,
+        room_id, uint, user_id, UserId,
     };
     use serde_json::json;
 
@@ -1208,6 +1483,141 @@ mod tests {
         assert_eq!(client.get_room(room_id).unwrap().room_type(), RoomType::Invited);
     }
 
+    #[async_test]
+    async fn ignored_users() {
+        let user_id = user_id!("@alice:example.org");
+        let spammer = user_id!("@spammer:example.org");
+        let room_id = room_id!("!test:example.org");
+        let invited_room_id = room_id!("!invite:example.org");
+
+        let client = BaseClient::new();
+        client
+            .restore_login(Session {
+                access_token: "token".to_owned(),
+                refresh_token: None,
+                user_id: user_id.to_owned(),
+                device_id: "FOOBAR".into(),
+            })
+            .await
+            .unwrap();
+
+        let ignored_user_list = |ignored_users: Vec<&UserId>| {
+            let ignored_users: serde_json::Map<_, _> =
+                ignored_users.into_iter().map(|user_id| (user_id.to_string(), json!({}))).collect();
+            GlobalAccountDataTestEvent::Custom(json!({
+                "content": { "ignored_users": ignored_users },
+                "type": "m.ignored_user_list",
+            }))
+        };
+        let message = |event_id: &str, sender: &UserId| {
+            TimelineTestEvent::Custom(json!({
+                "content": { "body": "hello", "msgtype": "m.text" },
+                "event_id": event_id,
+                "origin_server_ts": 1432135524678u64,
+                "sender": sender,
+                "type": "m.room.message",
+            }))
+        };
+
+        let mut ev_builder = EventBuilder::new();
+
+        let response = ev_builder
+            .add_global_account_data_event(ignored_user_list(vec![spammer]))
+            .add_joined_room(
+                JoinedRoomBuilder::new(room_id)
+                    .add_timeline_event(TimelineTestEvent::Custom(json!({
+                        "content": { "membership": "join" },
+                        "event_id": "$member:example.org",
+                        "origin_server_ts": 1432135524678u64,
+                        "sender": spammer,
+                        "state_key": spammer,
+                        "type": "m.room.member",
+                    })))
+                    .add_timeline_event(message("$spam:example.org", spammer))
+                    .add_timeline_event(message("$hello:example.org", user_id)),
+            )
+            .add_invited_room(InvitedRoomBuilder::new(invited_room_id).add_state_event(
+                StrippedStateTestEvent::Custom(json!({
+                    "content": { "membership": "invite" },
+                    "sender": spammer,
+                    "state_key": user_id,
+                    "type": "m.room.member",
+                })),
+            ))
+            .build_sync_response();
+        let response = client.receive_sync_response(response).await.unwrap();
+
+        // The membership of the ignored user is kept, but not their message.
+        let timeline = &response.rooms.join[room_id].timeline;
+        assert_eq!(timeline.events.len(), 2);
+        assert!(response.rooms.invite.is_empty());
+        assert!(client.get_room(invited_room_id).is_none());
+
+        let room = client.get_room(room_id).unwrap();
+        assert!(!room.is_timeline_reset_needed());
+
+        let response = ev_builder
+            .add_global_account_data_event(ignored_user_list(Vec::new()))
+            .build_sync_response();
+        client.receive_sync_response(response).await.unwrap();
+        assert!(room.is_timeline_reset_needed());
+
+        client.mark_timeline_reset(room_id).await.unwrap();
+        assert!(!room.is_timeline_reset_needed());
+    }
+
+    #[cfg(feature = "e2e-encryption")]
+    #[async_test]
+    async fn ignored_users_to_device_events() {
+        let user_id = user_id!("@alice:example.org");
+        let friend = user_id!("@bob:example.org");
+        let spammer = user_id!("@spammer:example.org");
+
+        let client = BaseClient::new();
+        client
+            .restore_login(Session {
+                access_token: "token".to_owned(),
+                refresh_token: None,
+                user_id: user_id.to_owned(),
+                device_id: "FOOBAR".into(),
+            })
+            .await
+            .unwrap();
+
+        let verification_request = |sender: &UserId, transaction_id: &str| {
+            ruma::serde::Raw::new(&json!({
+                "content": {
+                    "from_device": "DEVICE",
+                    "methods": ["m.sas.v1"],
+                    "timestamp": ruma::MilliSecondsSinceUnixEpoch::now(),
+                    "transaction_id": transaction_id,
+                },
+                "sender": sender,
+                "type": "m.key.verification.request",
+            }))
+            .unwrap()
+            .cast()
+        };
+
+        let mut response = EventBuilder::new()
+            .add_global_account_data_event(GlobalAccountDataTestEvent::Custom(json!({
+                "content": { "ignored_users": { spammer.as_str(): {} } },
+                "type": "m.ignored_user_list",
+            })))
+            .build_sync_response();
+        response.to_device.events = vec![
+            verification_request(friend, "friend_txn"),
+            verification_request(spammer, "spam_txn"),
+        ];
+        let response = client.receive_sync_response(response).await.unwrap();
+
+        // The crypto machine never saw the request of the ignored user.
+        let olm = client.olm_machine().unwrap();
+        assert!(olm.get_verification_request(friend, "friend_txn").is_some());
+        assert!(olm.get_verification_request(spammer, "spam_txn").is_none());
+        assert_eq!(response.to_device.events.len(), 1);
+    }
+
     #[async_test]
     async fn invite_displayname_integration_test() {
         let user_id = user_id!("@alice:example.org");
@@ -1304,4 +1714,269 @@ mod tests {
             DisplayName::Calculated("Kyra".to_owned())
         );
     }
+
+    #[async_test]
+    async fn local_unread_counts() {
+        let user_id = user_id!("@alice:example.org");
+        let room_id = room_id!("!test:example.org");
+
+        let client = BaseClient::new();
+        client
+            .restore_login(Session {
+                access_token: "token".to_owned(),
+                refresh_token: None,
+                user_id: user_id.to_owned(),
+                device_id: "FOOBAR".into(),
+            })
+            .await
+            .unwrap();
+
+        fn message(event_id: &str, content: serde_json::Value) -> TimelineTestEvent {
+            TimelineTestEvent::Custom(json!({
+                "content": content,
+                "event_id": event_id,
+                "origin_server_ts": 1432135524678u64,
+                "sender": "@bob:example.org",
+                "type": "m.room.message",
+            }))
+        }
+
+        let mut ev_builder = EventBuilder::new();
+        let response = ev_builder
+            .add_joined_room(
+                JoinedRoomBuilder::new(room_id)
+                    .add_state_event(StateTestEvent::Custom(json!({
+                        "content": { "membership": "join" },
+                        "event_id": "$member:example.org",
+                        "origin_server_ts": 1432135524678u64,
+                        "sender": user_id,
+                        "state_key": user_id,
+                        "type": "m.room.member",
+                    })))
+                    .add_state_event(StateTestEvent::PowerLevels),
+            )
+            .build_sync_response();
+        client.receive_sync_response(response).await.unwrap();
+
+        let response = ev_builder
+            .add_joined_room(
+                JoinedRoomBuilder::new(room_id)
+                    .add_timeline_event(message(
+                        "$hello:example.org",
+                        json!({ "body": "Hello", "msgtype": "m.text" }),
+                    ))
+                    .add_timeline_event(message(
+                        "$mention:example.org",
+                        json!({ "body": "Hello alice", "msgtype": "m.text" }),
+                    ))
+                    .add_timeline_event(message(
+                        "$edit:example.org",
+                        json!({
+                            "body": "* Hello alice!",
+                            "msgtype": "m.text",
+                            "m.new_content": { "body": "Hello alice!", "msgtype": "m.text" },
+                            "m.relates_to": {
+                                "rel_type": "m.replace",
+                                "event_id": "$mention:example.org",
+                            },
+                        }),
+                    )),
+            )
+            .build_sync_response();
+        client.receive_sync_response(response).await.unwrap();
+
+        let counts = client.get_room(room_id).unwrap().local_unread_counts();
+        assert_eq!(counts.unread_count, 2);
+        assert_eq!(counts.notification_count, 2);
+        assert_eq!(counts.highlight_count, 1);
+
+        // A read receipt for the latest event resets the counts.
+        let response = ev_builder
+            .add_joined_room(
+                JoinedRoomBuilder::new(room_id)
+                    .add_ephemeral_event(EphemeralTestEvent::Custom(json!({
+                        "content": {
+                            "$edit:example.org": {
+                                "m.read": {
+                                    "@alice:example.org": { "ts": 1432135524679u64 },
+                                },
+                            },
+                        },
+                        "type": "m.receipt",
+                    })))
+                    .add_timeline_event(message(
+                        "$new:example.org",
+                        json!({ "body": "Are you there?", "msgtype": "m.text" }),
+                    )),
+            )
+            .build_sync_response();
+        client.receive_sync_response(response).await.unwrap();
+
+        let counts = client.get_room(room_id).unwrap().local_unread_counts();
+        assert_eq!(counts.unread_count, 1);
+        assert_eq!(counts.notification_count, 1);
+        assert_eq!(counts.highlight_count, 0);
+
+        // A read receipt for an older event doesn't change the counts.
+        let response = ev_builder
+            .add_joined_room(JoinedRoomBuilder::new(room_id).add_ephemeral_event(
+                EphemeralTestEvent::Custom(json!({
+                    "content": {
+                        "$hello:example.org": {
+                            "m.read": {
+                                "@alice:example.org": { "ts": 1432135524680u64 },
+                            },
+                        },
+                    },
+                    "type": "m.receipt",
+                })),
+            ))
+            .build_sync_response();
+        client.receive_sync_response(response).await.unwrap();
+
+        let counts = client.get_room(room_id).unwrap().local_unread_counts();
+        assert_eq!(counts.unread_count, 1);
+        assert_eq!(counts.notification_count, 1);
+        assert_eq!(counts.highlight_count, 0);
+
+        // A read receipt for the latest event resets the counts.
+        let response = ev_builder
+            .add_joined_room(JoinedRoomBuilder::new(room_id).add_ephemeral_event(
+                EphemeralTestEvent::Custom(json!({
+                    "content": {
+                        "$new:example.org": {
+                            "m.read": {
+                                "@alice:example.org": { "ts": 1432135524681u64 },
+                            },
+                        },
+                    },
+                    "type": "m.receipt",
+                })),
+            ))
+            .build_sync_response();
+        client.receive_sync_response(response).await.unwrap();
+
+        let counts = client.get_room(room_id).unwrap().local_unread_counts();
+        assert_eq!(counts.unread_count, 0);
+        assert_eq!(counts.notification_count, 0);
+        assert_eq!(counts.highlight_count, 0);
+    }
+
+    #[async_test]
+    async fn thread_summaries() {
+        let user_id = user_id!("@alice:example.org");
+        let room_id = room_id!("!test:example.org");
+
+        let client = BaseClient::new();
+        client
+            .restore_login(Session {
+                access_token: "token".to_owned(),
+                refresh_token: None,
+                user_id: user_id.to_owned(),
+                device_id: "FOOBAR".into(),
+            })
+            .await
+            .unwrap();
+
+        fn message(event_id: &str, sender: &str, content: serde_json::Value) -> TimelineTestEvent {
+            TimelineTestEvent::Custom(json!({
+                "content": content,
+                "event_id": event_id,
+                "origin_server_ts": 1432135524678u64,
+                "sender": sender,
+                "type": "m.room.message",
+            }))
+        }
+
+        fn reply(event_id: &str, sender: &str) -> TimelineTestEvent {
+            message(
+                event_id,
+                sender,
+                json!({
+                    "body": "In the thread",
+                    "msgtype": "m.text",
+                    "m.relates_to": {
+                        "rel_type": "m.thread",
+                        "event_id": "$root:example.org",
+                    },
+                }),
+            )
+        }
+
+        let mut ev_builder = EventBuilder::new();
+        let response = ev_builder
+            .add_joined_room(
+                JoinedRoomBuilder::new(room_id)
+                    .add_timeline_event(message(
+                        "$root:example.org",
+                        "@bob:example.org",
+                        json!({ "body": "Root", "msgtype": "m.text" }),
+                    ))
+                    .add_timeline_event(reply("$first:example.org", "@alice:example.org"))
+                    .add_timeline_event(reply("$second:example.org", "@bob:example.org")),
+            )
+            .build_sync_response();
+        let response = client.receive_sync_response(response).await.unwrap();
+
+        // The summary of a thread root in the same sync response is updated.
+        let timeline = &response.rooms.join[room_id].timeline;
+        let summary = timeline.events[0].thread_summary().unwrap();
+        assert_eq!(summary.count, uint!(2));
+        assert!(summary.current_user_participated);
+        assert_eq!(
+            summary.latest_event.get_field::<String>("event_id").unwrap().as_deref(),
+            Some("$second:example.org")
+        );
+        assert_eq!(
+            summary.latest_event.get_field::<String>("room_id").unwrap().as_deref(),
+            Some(room_id.as_str())
+        );
+        assert!(timeline.events[1].thread_summary().is_none());
+
+        // The replies already counted in the summary bundled with the thread
+        // root are not counted twice.
+        let room_id = room_id!("!bundled:example.org");
+        let root = TimelineTestEvent::Custom(json!({
+            "content": { "body": "Root", "msgtype": "m.text" },
+            "event_id": "$root:example.org",
+            "origin_server_ts": 1432135524678u64,
+            "sender": "@bob:example.org",
+            "type": "m.room.message",
+            "unsigned": {
+                "m.relations": {
+                    "m.thread": {
+                        "latest_event": {
+                            "content": { "body": "In the thread", "msgtype": "m.text" },
+                            "event_id": "$first:example.org",
+                            "origin_server_ts": 1432135524678u64,
+                            "room_id": room_id,
+                            "sender": "@bob:example.org",
+                            "type": "m.room.message",
+                        },
+                        "count": 2,
+                        "current_user_participated": false,
+                    },
+                },
+            },
+        }));
+
+        let response = ev_builder
+            .add_joined_room(
+                JoinedRoomBuilder::new(room_id)
+                    .add_timeline_event(root)
+                    .add_timeline_event(reply("$first:example.org", "@bob:example.org"))
+                    .add_timeline_event(reply("$second:example.org", "@bob:example.org")),
+            )
+            .build_sync_response();
+        let response = client.receive_sync_response(response).await.unwrap();
+
+        let timeline = &response.rooms.join[room_id].timeline;
+        let summary = timeline.events[0].thread_summary().unwrap();
+        assert_eq!(summary.count, uint!(3));
+        assert!(!summary.current_user_participated);
+        assert_eq!(
+            summary.latest_event.get_field::<String>("event_id").unwrap().as_deref(),
+            Some("$second:example.org")
+        );
+    }
 }
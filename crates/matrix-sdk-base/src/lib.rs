@@ -44,7 +44,7 @@ pub use http;
 #[cfg(feature = "e2e-encryption")]
 pub use matrix_sdk_crypto as crypto;
 pub use once_cell;
-pub use rooms::{DisplayName, Room, RoomInfo, RoomMember, RoomType};
+pub use rooms::{DisplayName, LocalUnreadCounts, Room, RoomInfo, RoomMember, RoomType};
 pub use store::{StateChanges, StateStore, StoreError};
 pub use utils::{
     MinimalRoomMemberEvent, MinimalStateEvent, OriginalMinimalStateEvent, RedactedMinimalStateEvent,
@@ -1,5 +1,7 @@
 //! Common types for [media content](https://matrix.org/docs/spec/client_server/r0.6.1#id66).
 
+use std::{cmp::Reverse, time::Duration};
+
 use ruma::{
     api::client::media::get_content_thumbnail::v3::Method,
     events::{
@@ -12,11 +14,22 @@ use ruma::{
         },
         sticker::StickerEventContent,
     },
-    UInt,
+    MilliSecondsSinceUnixEpoch, UInt,
 };
+use serde::{Deserialize, Serialize};
 
 const UNIQUE_SEPARATOR: &str = "_";
 
+/// The default maximum size of the media cache, 400 MiB.
+const DEFAULT_MAX_CACHE_SIZE: u64 = 400 * 1024 * 1024;
+
+/// The default maximum size of a file in the media cache, 20 MiB.
+const DEFAULT_MAX_FILE_SIZE: u64 = 20 * 1024 * 1024;
+
+/// The default duration after which a file that wasn't accessed is removed
+/// from the media cache, 60 days.
+const DEFAULT_LAST_ACCESS_EXPIRY: Duration = Duration::from_secs(60 * 24 * 60 * 60);
+
 /// A trait to uniquely identify values of the same type.
 pub trait UniqueKey {
     /// A string that uniquely identifies `Self` compared to other values of
@@ -88,6 +101,156 @@ impl UniqueKey for MediaRequest {
         format!("{}{}{}", self.source.unique_key(), UNIQUE_SEPARATOR, self.format.unique_key())
     }
 }
+/// The policy deciding which media files are kept in the media cache of a
+/// store.
+///
+/// When the cache grows beyond its maximum size, the files that were accessed
+/// the least recently are removed first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct MediaRetentionPolicy {
+    /// The maximum total size of the files in the cache, in bytes.
+    ///
+    /// `None` means that the size of the cache is not limited.
+    pub max_cache_size: Option<u64>,
+
+    /// The maximum size of a single file, in bytes. Larger files are not
+    /// cached.
+    ///
+    /// `None` means that the size of a file is not limited.
+    pub max_file_size: Option<u64>,
+
+    /// The duration after which a file that wasn't accessed is removed from
+    /// the cache.
+    ///
+    /// `None` means that files never expire.
+    pub last_access_expiry: Option<Duration>,
+}
+
+impl MediaRetentionPolicy {
+    /// Create a policy that keeps every file forever.
+    pub fn unlimited() -> Self {
+        Self { max_cache_size: None, max_file_size: None, last_access_expiry: None }
+    }
+
+    /// Whether a file of the given size is too big to be cached.
+    pub fn exceeds_max_file_size(&self, size: u64) -> bool {
+        self.max_file_size.map_or(false, |max| size > max)
+    }
+
+    /// Whether a file that was last accessed at `last_access` has expired at
+    /// `now`.
+    pub fn has_expired(
+        &self,
+        now: MilliSecondsSinceUnixEpoch,
+        last_access: MilliSecondsSinceUnixEpoch,
+    ) -> bool {
+        self.last_access_expiry.map_or(false, |expiry| {
+            let elapsed = u64::from(now.0).saturating_sub(u64::from(last_access.0));
+            Duration::from_millis(elapsed) >= expiry
+        })
+    }
+
+    /// Get the keys of the entries that must be removed from the cache to
+    /// comply with this policy at `now`.
+    ///
+    /// Expired and oversized files are always removed, then the least
+    /// recently accessed files are removed until the cache fits in its
+    /// maximum size.
+    pub fn entries_to_evict<K>(
+        &self,
+        entries: impl IntoIterator<Item = (K, MediaCacheEntry)>,
+        now: MilliSecondsSinceUnixEpoch,
+    ) -> Vec<K> {
+        let mut evicted = Vec::new();
+        let mut kept = Vec::new();
+
+        for (key, entry) in entries {
+            if self.exceeds_max_file_size(entry.size) || self.has_expired(now, entry.last_access) {
+                evicted.push(key);
+            } else {
+                kept.push((key, entry));
+            }
+        }
+
+        if let Some(max_cache_size) = self.max_cache_size {
+            // Most recently accessed first.
+            kept.sort_by_key(|(_, entry)| Reverse(entry.last_access));
+
+            let mut cache_size = 0u64;
+            for (key, entry) in kept {
+                cache_size = cache_size.saturating_add(entry.size);
+
+                if cache_size > max_cache_size {
+                    evicted.push(key);
+                }
+            }
+        }
+
+        evicted
+    }
+}
+
+impl Default for MediaRetentionPolicy {
+    fn default() -> Self {
+        Self {
+            max_cache_size: Some(DEFAULT_MAX_CACHE_SIZE),
+            max_file_size: Some(DEFAULT_MAX_FILE_SIZE),
+            last_access_expiry: Some(DEFAULT_LAST_ACCESS_EXPIRY),
+        }
+    }
+}
+
+/// The metadata of a file in the media cache.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MediaCacheEntry {
+    /// The size of the file, in bytes.
+    pub size: u64,
+
+    /// When the file was last accessed.
+    pub last_access: MilliSecondsSinceUnixEpoch,
+}
+
+impl MediaCacheEntry {
+    /// Create the metadata of a file of the given size that is accessed now.
+    pub fn new(size: u64) -> Self {
+        Self { size, last_access: MilliSecondsSinceUnixEpoch::now() }
+    }
+
+    /// Mark the file as accessed now.
+    pub fn touch(&mut self) {
+        self.last_access = MilliSecondsSinceUnixEpoch::now();
+    }
+}
+
+/// The space used by the media cache of a store.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct MediaCacheUsage {
+    /// The total size of the files in the cache, in bytes.
+    pub size: u64,
+
+    /// The number of files in the cache.
+    pub count: u64,
+}
+
+impl MediaCacheUsage {
+    /// Create a new `MediaCacheUsage` with the given total size and number of
+    /// files.
+    pub fn new(size: u64, count: u64) -> Self {
+        Self { size, count }
+    }
+}
+
+impl FromIterator<MediaCacheEntry> for MediaCacheUsage {
+    fn from_iter<I: IntoIterator<Item = MediaCacheEntry>>(iter: I) -> Self {
+        iter.into_iter().fold(Self::default(), |usage, entry| Self {
+            size: usage.size + entry.size,
+            count: usage.count + 1,
+        })
+    }
+}
+
 /// Trait for media event content.
 pub trait MediaEventContent {
     /// Get the source of the file for `Self`.
@@ -166,3 +329,40 @@ impl MediaEventContent for LocationMessageEventContent {
         self.info.as_ref()?.thumbnail_source.clone()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use ruma::{uint, MilliSecondsSinceUnixEpoch};
+
+    use super::{MediaCacheEntry, MediaRetentionPolicy};
+
+    fn entry(size: u64, last_access: u32) -> MediaCacheEntry {
+        MediaCacheEntry { size, last_access: MilliSecondsSinceUnixEpoch(last_access.into()) }
+    }
+
+    #[test]
+    fn entries_to_evict() {
+        let now = MilliSecondsSinceUnixEpoch(uint!(10_000));
+        let entries = [
+            ("old", entry(10, 1_000)),
+            ("big", entry(100, 9_000)),
+            ("recent", entry(30, 9_500)),
+            ("older", entry(30, 8_000)),
+            ("oldest", entry(30, 7_000)),
+        ];
+
+        let mut policy = MediaRetentionPolicy::unlimited();
+        assert!(policy.entries_to_evict(entries, now).is_empty());
+
+        policy.max_file_size = Some(50);
+        assert_eq!(policy.entries_to_evict(entries, now), ["big"]);
+
+        policy.last_access_expiry = Some(Duration::from_secs(5));
+        assert_eq!(policy.entries_to_evict(entries, now), ["old", "big"]);
+
+        policy.max_cache_size = Some(60);
+        assert_eq!(policy.entries_to_evict(entries, now), ["old", "big", "oldest"]);
+    }
+}
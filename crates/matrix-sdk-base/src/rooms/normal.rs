@@ -31,17 +31,23 @@ use ruma::{
     events::{
         receipt::{Receipt, ReceiptType},
         room::{
-            create::RoomCreateEventContent, encryption::RoomEncryptionEventContent,
-            guest_access::GuestAccess, history_visibility::HistoryVisibility, join_rules::JoinRule,
-            redaction::OriginalSyncRoomRedactionEvent, tombstone::RoomTombstoneEventContent,
+            create::{PreviousRoom, RoomCreateEventContent},
+            encryption::RoomEncryptionEventContent,
+            guest_access::GuestAccess,
+            history_visibility::HistoryVisibility,
+            join_rules::JoinRule,
+            redaction::OriginalSyncRoomRedactionEvent,
+            tombstone::RoomTombstoneEventContent,
         },
         tag::Tags,
-        AnyRoomAccountDataEvent, AnyStrippedStateEvent, AnySyncStateEvent,
+        AnyRoomAccountDataEvent, AnyStrippedStateEvent, AnySyncStateEvent, AnySyncTimelineEvent,
         RoomAccountDataEventType,
     },
+    push::{Action, Tweak},
     room::RoomType as CreateRoomType,
-    EventId, OwnedEventId, OwnedMxcUri, OwnedRoomAliasId, OwnedUserId, RoomAliasId, RoomId,
-    RoomVersionId, UserId,
+    serde::Raw,
+    EventId, OwnedEventId, OwnedMxcUri, OwnedRoomAliasId, OwnedRoomId, OwnedUserId, RoomAliasId,
+    RoomId, RoomVersionId, UserId,
 };
 use serde::{Deserialize, Serialize};
 use tracing::debug;
@@ -150,6 +156,15 @@ impl Room {
         self.inner.read().unwrap().notification_counts
     }
 
+    /// Get the unread counts computed locally.
+    ///
+    /// Unlike [`unread_notification_counts()`](Self::unread_notification_counts),
+    /// these are correct for encrypted rooms as the push rules are evaluated
+    /// against the decrypted events.
+    pub fn local_unread_counts(&self) -> LocalUnreadCounts {
+        self.inner.read().unwrap().local_unread_counts.clone()
+    }
+
     /// Check if the room has it's members fully synced.
     ///
     /// Members might be missing if lazy member loading was enabled for the
@@ -160,6 +175,18 @@ impl Room {
         self.inner.read().unwrap().members_synced
     }
 
+    /// Whether the timeline of this room needs to be reset.
+    ///
+    /// This is the case when a user that is a member of this room was ignored
+    /// or unignored, because the events received until now were filtered
+    /// with the previous list of ignored users. The timeline should be loaded
+    /// again, and [`BaseClient::mark_timeline_reset()`] called once it's done.
+    ///
+    /// [`BaseClient::mark_timeline_reset()`]: crate::BaseClient::mark_timeline_reset
+    pub fn is_timeline_reset_needed(&self) -> bool {
+        self.inner.read().unwrap().timeline_reset_needed
+    }
+
     /// Get the `prev_batch` token that was received from the last sync. May be
     /// `None` if the last sync contained the full room history.
     pub fn last_prev_batch(&self) -> Option<String> {
@@ -274,6 +301,21 @@ impl Room {
         self.inner.read().unwrap().tombstone().cloned()
     }
 
+    /// Get the ID of the room that replaces this room, if it was upgraded.
+    ///
+    /// This is the `replacement_room` of the `m.room.tombstone` event.
+    pub fn successor(&self) -> Option<OwnedRoomId> {
+        self.inner.read().unwrap().tombstone().map(|t| t.replacement_room.clone())
+    }
+
+    /// Get the room that this room replaces, if it was created by upgrading
+    /// another room.
+    ///
+    /// This is the `predecessor` of the `m.room.create` event.
+    pub fn predecessor(&self) -> Option<PreviousRoom> {
+        self.create_content().and_then(|c| c.predecessor)
+    }
+
     /// Get the topic of the room.
     pub fn topic(&self) -> Option<String> {
         self.inner.read().unwrap().topic().map(ToOwned::to_owned)
@@ -623,6 +665,98 @@ impl Room {
     }
 }
 
+/// Unread counts of a room, computed locally from the events received in the
+/// sync timeline and the read receipts of the user.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct LocalUnreadCounts {
+    /// The number of unread messages.
+    pub unread_count: u64,
+    /// The number of unread messages that triggered a notification.
+    pub notification_count: u64,
+    /// The number of unread messages that triggered a highlight.
+    pub highlight_count: u64,
+    /// The ID of the latest event that was taken into account.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    latest_event_id: Option<OwnedEventId>,
+}
+
+impl LocalUnreadCounts {
+    /// Update the counts with a new event from another user and the push
+    /// actions it triggered.
+    pub(crate) fn handle_event(
+        &mut self,
+        event_id: &EventId,
+        event: &Raw<AnySyncTimelineEvent>,
+        actions: &[Action],
+    ) {
+        self.latest_event_id = Some(event_id.to_owned());
+
+        if !is_countable(event) {
+            return;
+        }
+
+        self.unread_count += 1;
+
+        if actions.iter().any(|a| matches!(a, Action::Notify)) {
+            self.notification_count += 1;
+        }
+        if actions.iter().any(|a| matches!(a, Action::SetTweak(Tweak::Highlight(true)))) {
+            self.highlight_count += 1;
+        }
+    }
+
+    /// Mark all the events up to and including the given one as read.
+    pub(crate) fn reset(&mut self, event_id: &EventId) {
+        *self = Self { latest_event_id: Some(event_id.to_owned()), ..Default::default() };
+    }
+
+    /// Whether the given event is the latest event that was taken into
+    /// account.
+    pub(crate) fn is_latest_event(&self, event_id: &EventId) -> bool {
+        self.latest_event_id.as_deref() == Some(event_id)
+    }
+}
+
+/// Whether the given event should be counted as an unread message.
+///
+/// Edits, reactions, redacted and state events are not counted.
+fn is_countable(event: &Raw<AnySyncTimelineEvent>) -> bool {
+    #[derive(Deserialize)]
+    struct RelatesTo {
+        rel_type: Option<String>,
+    }
+
+    #[derive(Deserialize)]
+    struct Content {
+        #[serde(rename = "m.relates_to")]
+        relates_to: Option<RelatesTo>,
+    }
+
+    #[derive(Deserialize)]
+    struct Unsigned {
+        redacted_because: Option<serde::de::IgnoredAny>,
+    }
+
+    let event_type = event.get_field::<String>("type").ok().flatten();
+    if !matches!(event_type.as_deref(), Some("m.room.message" | "m.room.encrypted" | "m.sticker")) {
+        return false;
+    }
+
+    let is_edit = event
+        .get_field::<Content>("content")
+        .ok()
+        .flatten()
+        .and_then(|c| c.relates_to?.rel_type)
+        .map_or(false, |rel_type| rel_type == "m.replace");
+    let is_redacted = event
+        .get_field::<Unsigned>("unsigned")
+        .ok()
+        .flatten()
+        .map_or(false, |u| u.redacted_because.is_some());
+
+    !is_edit && !is_redacted
+}
+
 /// The underlying pure data structure for joined and left rooms.
 ///
 /// Holds all the info needed to persist a room into the state store.
@@ -634,12 +768,19 @@ pub struct RoomInfo {
     pub(crate) room_type: RoomType,
     /// The unread notifications counts.
     pub(crate) notification_counts: UnreadNotificationsCount,
+    /// The unread counts computed locally.
+    #[serde(default)]
+    pub(crate) local_unread_counts: LocalUnreadCounts,
     /// The summary of this room.
     pub(crate) summary: RoomSummary,
     /// Flag remembering if the room members are synced.
     pub(crate) members_synced: bool,
     /// The prev batch of this room we received during the last sync.
     pub(crate) last_prev_batch: Option<String>,
+    /// Whether the timeline of this room needs to be reset, because the list
+    /// of ignored users changed.
+    #[serde(default)]
+    pub(crate) timeline_reset_needed: bool,
     /// Base room info which holds some basic event contents important for the
     /// room state.
     pub(crate) base_info: BaseRoomInfo,
@@ -652,9 +793,11 @@ impl RoomInfo {
             room_id: room_id.into(),
             room_type,
             notification_counts: Default::default(),
+            local_unread_counts: Default::default(),
             summary: Default::default(),
             members_synced: false,
             last_prev_batch: None,
+            timeline_reset_needed: false,
             base_info: BaseRoomInfo::new(),
         }
     }
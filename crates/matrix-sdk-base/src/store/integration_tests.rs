@@ -33,6 +33,7 @@ macro_rules! statestore_integration_tests {
             use std::{
                 collections::{BTreeMap, BTreeSet},
                 sync::Arc,
+                time::Duration,
             };
 
             #[cfg(feature = "experimental-timeline")]
@@ -74,12 +75,17 @@ macro_rules! statestore_integration_tests {
 
             #[cfg(feature = "experimental-timeline")]
             use $crate::{
-                deserialized_responses::{SyncTimelineEvent, TimelineEvent, TimelineSlice},
+                deserialized_responses::{
+                    SyncTimelineEvent, ThreadSummary, TimelineEvent, TimelineSlice,
+                },
                 http::Response,
             };
             use $crate::{
-                media::{MediaFormat, MediaRequest, MediaThumbnailSize},
-                store::{Result as StoreResult, StateChanges, StateStore, StateStoreExt},
+                media::{MediaFormat, MediaRequest, MediaRetentionPolicy, MediaThumbnailSize},
+                store::{
+                    migration::migrate_state_store, Result as StoreResult, StateChanges,
+                    StateStore, StateStoreExt,
+                },
                 RoomInfo, RoomType,
             };
 
@@ -671,6 +677,106 @@ macro_rules! statestore_integration_tests {
                 );
             }
 
+            #[async_test]
+            async fn test_media_cache_usage() {
+                let store = get_store().await.unwrap();
+
+                let request_a = MediaRequest {
+                    source: MediaSource::Plain(mxc_uri!("mxc://localhost/media-a").to_owned()),
+                    format: MediaFormat::File,
+                };
+                let request_b = MediaRequest {
+                    source: MediaSource::Plain(mxc_uri!("mxc://localhost/media-b").to_owned()),
+                    format: MediaFormat::File,
+                };
+
+                let usage = store.media_cache_usage().await.unwrap();
+                assert_eq!(usage.count, 0);
+                assert_eq!(usage.size, 0);
+
+                store.add_media_content(&request_a, vec![0; 10]).await.unwrap();
+                store.add_media_content(&request_b, vec![0; 20]).await.unwrap();
+
+                let usage = store.media_cache_usage().await.unwrap();
+                assert_eq!(usage.count, 2);
+                assert_eq!(usage.size, 30);
+
+                store.clear_media_cache().await.expect("clearing the media cache failed");
+                assert!(store.get_media_content(&request_a).await.unwrap().is_none());
+                assert_eq!(store.media_cache_usage().await.unwrap().count, 0);
+            }
+
+            #[async_test]
+            async fn test_media_retention_policy() {
+                let store = get_store().await.unwrap();
+
+                let request = |uri: &str| MediaRequest {
+                    source: MediaSource::Plain(uri.into()),
+                    format: MediaFormat::File,
+                };
+
+                let mut policy = MediaRetentionPolicy::unlimited();
+                policy.max_cache_size = Some(20);
+                policy.max_file_size = Some(10);
+                store.set_media_retention_policy(policy).await.unwrap();
+
+                // Files bigger than the maximum file size are not cached.
+                let big = request("mxc://localhost/big");
+                store.add_media_content(&big, vec![0; 16]).await.unwrap();
+                assert!(store.get_media_content(&big).await.unwrap().is_none());
+
+                // The least recently accessed files are removed when the cache is full.
+                for uri in ["mxc://localhost/a", "mxc://localhost/b", "mxc://localhost/c"] {
+                    store.add_media_content(&request(uri), vec![0; 8]).await.unwrap();
+                }
+                let usage = store.media_cache_usage().await.unwrap();
+                assert_eq!(usage.count, 2);
+                assert_eq!(usage.size, 16);
+
+                // Expired files are removed.
+                policy.last_access_expiry = Some(Duration::ZERO);
+                store.set_media_retention_policy(policy).await.unwrap();
+                assert_eq!(store.media_cache_usage().await.unwrap().count, 0);
+            }
+
+            #[async_test]
+            async fn test_migrate_to_other_store() {
+                let room_id = room_id();
+                let user_id = user_id();
+                let source = Arc::new(get_store().await.unwrap());
+                let target = get_store().await.unwrap();
+                populate_store(source.clone()).await.unwrap();
+
+                let request = MediaRequest {
+                    source: MediaSource::Plain(mxc_uri!("mxc://localhost/media").to_owned()),
+                    format: MediaFormat::File,
+                };
+                source.add_media_content(&request, "somebinarydata".into()).await.unwrap();
+
+                let report =
+                    migrate_state_store(&*source, &target, &[request.clone()], |_, _, _| {})
+                        .await
+                        .expect("Can't migrate the store");
+
+                assert_eq!(report.room_infos, 1);
+                assert_eq!(report.stripped_room_infos, 1);
+                assert_eq!(report.members, 3);
+                assert_eq!(report.media, 1);
+
+                assert!(target
+                    .get_state_event(room_id, StateEventType::RoomName, "")
+                    .await
+                    .unwrap()
+                    .is_some());
+                assert!(target.get_profile(room_id, user_id).await.unwrap().is_some());
+                assert_eq!(target.get_invited_user_ids(room_id).await.unwrap().len(), 1);
+                assert_eq!(
+                    target.get_users_with_display_name(room_id, "example").await.unwrap().len(),
+                    2
+                );
+                assert!(target.get_media_content(&request).await.unwrap().is_some());
+            }
+
             #[async_test]
             async fn test_custom_storage() -> StoreResult<()> {
                 let key = "my_key";
@@ -975,6 +1081,78 @@ macro_rules! statestore_integration_tests {
                 check_timeline_events(room_id, &store, &Vec::new(), end_token.as_deref()).await;
             }
 
+            #[async_test]
+            #[cfg(feature = "experimental-timeline")]
+            async fn test_thread_replies() {
+                let store = get_store().await.unwrap();
+                let room_id = room_id();
+                let thread_root = event_id!("$thread_root:localhost");
+
+                let root: Raw<ruma::events::AnySyncTimelineEvent> = Raw::new(&json!({
+                    "content": { "body": "Root", "msgtype": "m.text" },
+                    "event_id": thread_root,
+                    "origin_server_ts": 0,
+                    "sender": user_id(),
+                    "type": "m.room.message",
+                }))
+                .unwrap()
+                .cast();
+
+                let timeline_slice = TimelineSlice::new(
+                    vec![root.into()],
+                    "first".to_owned(),
+                    Some("start".to_owned()),
+                    false,
+                    true,
+                );
+                let mut changes = StateChanges::new("first".to_owned());
+                changes.add_timeline(room_id, timeline_slice);
+                store.save_changes(&changes).await.unwrap();
+
+                let replies = ThreadSummary {
+                    latest_event: Raw::new(&json!({
+                        "content": { "body": "Reply", "msgtype": "m.text" },
+                        "event_id": "$reply:localhost",
+                        "origin_server_ts": 1,
+                        "room_id": room_id,
+                        "sender": user_id(),
+                        "type": "m.room.message",
+                    }))
+                    .unwrap()
+                    .cast(),
+                    count: uint!(2),
+                    current_user_participated: true,
+                };
+
+                let timeline_slice = TimelineSlice::new(
+                    Vec::new(),
+                    "second".to_owned(),
+                    Some("first".to_owned()),
+                    false,
+                    true,
+                );
+                let mut changes = StateChanges::new("second".to_owned());
+                changes.add_timeline(room_id, timeline_slice);
+                changes
+                    .thread_replies
+                    .entry(room_id.to_owned())
+                    .or_default()
+                    .insert(thread_root.to_owned(), replies);
+                store.save_changes(&changes).await.unwrap();
+
+                let (timeline, _) = store.room_timeline(room_id).await.unwrap().unwrap();
+                let timeline = timeline.collect::<Vec<StoreResult<SyncTimelineEvent>>>().await;
+                assert_eq!(timeline.len(), 1);
+
+                let summary = timeline[0].as_ref().unwrap().thread_summary().unwrap();
+                assert_eq!(summary.count, uint!(2));
+                assert!(summary.current_user_participated);
+                assert_eq!(
+                    summary.latest_event.get_field::<String>("event_id").unwrap().as_deref(),
+                    Some("$reply:localhost")
+                );
+            }
+
             #[cfg(feature = "experimental-timeline")]
             async fn check_timeline_events(
                 room_id: &RoomId,
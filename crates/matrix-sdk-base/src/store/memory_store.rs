@@ -13,9 +13,9 @@
 // limitations under the License.
 
 #[cfg(feature = "experimental-timeline")]
-use std::collections::{BTreeMap, HashMap};
+use std::collections::BTreeMap;
 use std::{
-    collections::BTreeSet,
+    collections::{BTreeSet, HashMap},
     sync::{Arc, RwLock},
 };
 
@@ -23,7 +23,6 @@ use std::{
 use async_stream::stream;
 use async_trait::async_trait;
 use dashmap::{DashMap, DashSet};
-use lru::LruCache;
 #[allow(unused_imports)]
 use matrix_sdk_common::{instant::Instant, locks::Mutex};
 use ruma::{
@@ -36,8 +35,8 @@ use ruma::{
         AnySyncStateEvent, GlobalAccountDataEventType, RoomAccountDataEventType, StateEventType,
     },
     serde::Raw,
-    CanonicalJsonObject, EventId, MxcUri, OwnedEventId, OwnedRoomId, OwnedUserId, RoomId,
-    RoomVersionId, UserId,
+    CanonicalJsonObject, EventId, MilliSecondsSinceUnixEpoch, MxcUri, OwnedEventId, OwnedRoomId,
+    OwnedUserId, RoomId, RoomVersionId, UserId,
 };
 #[cfg(feature = "experimental-timeline")]
 use ruma::{
@@ -55,7 +54,7 @@ use super::{Result, RoomInfo, StateChanges, StateStore, StoreError};
 use crate::deserialized_responses::SyncTimelineEvent;
 use crate::{
     deserialized_responses::MemberEvent,
-    media::{MediaRequest, UniqueKey},
+    media::{MediaCacheEntry, MediaCacheUsage, MediaRequest, MediaRetentionPolicy, UniqueKey},
     MinimalRoomMemberEvent,
 };
 
@@ -91,7 +90,7 @@ pub struct MemoryStore {
     room_event_receipts: Arc<
         DashMap<OwnedRoomId, DashMap<String, DashMap<OwnedEventId, DashMap<OwnedUserId, Receipt>>>>,
     >,
-    media: Arc<Mutex<LruCache<String, Vec<u8>>>>,
+    media: Arc<Mutex<MediaCache>>,
     custom: Arc<DashMap<Vec<u8>, Vec<u8>>>,
     #[cfg(feature = "experimental-timeline")]
     room_timeline: Arc<DashMap<OwnedRoomId, TimelineData>>,
@@ -127,9 +126,7 @@ impl MemoryStore {
             presence: Default::default(),
             room_user_receipts: Default::default(),
             room_event_receipts: Default::default(),
-            media: Arc::new(Mutex::new(LruCache::new(
-                100.try_into().expect("100 is a non-zero usize"),
-            ))),
+            media: Default::default(),
             custom: DashMap::new().into(),
             #[cfg(feature = "experimental-timeline")]
             room_timeline: Default::default(),
@@ -417,6 +414,17 @@ impl MemoryStore {
                 });
 
             if timeline.sync {
+                // Update the summaries of the thread roots already in store
+                if let Some(thread_replies) = changes.thread_replies.get(room_id) {
+                    for (thread_root, replies) in thread_replies {
+                        let pos = data.event_id_to_position.get(thread_root).copied();
+
+                        if let Some(root) = pos.and_then(|pos| data.events.get_mut(&pos)) {
+                            root.add_thread_replies(replies)?;
+                        }
+                    }
+                }
+
                 let mut room_version = None;
                 for event in &timeline.events {
                     // Redact events already in store only on sync response
@@ -645,34 +653,78 @@ impl MemoryStore {
     }
 
     async fn add_media_content(&self, request: &MediaRequest, data: Vec<u8>) -> Result<()> {
-        self.media.lock().await.put(request.unique_key(), data);
+        let mut media = self.media.lock().await;
+
+        if media.policy.exceeds_max_file_size(data.len() as u64) {
+            return Ok(());
+        }
+
+        let entry = MediaCacheEntry::new(data.len() as u64);
+        media.entries.insert(request.unique_key(), (entry, data));
+        media.clean_up();
 
         Ok(())
     }
 
     async fn get_media_content(&self, request: &MediaRequest) -> Result<Option<Vec<u8>>> {
-        Ok(self.media.lock().await.get(&request.unique_key()).cloned())
+        let mut media = self.media.lock().await;
+        let key = request.unique_key();
+
+        let expired = match media.entries.get(&key) {
+            Some((entry, _)) => {
+                media.policy.has_expired(MilliSecondsSinceUnixEpoch::now(), entry.last_access)
+            }
+            None => return Ok(None),
+        };
+
+        if expired {
+            media.entries.remove(&key);
+            return Ok(None);
+        }
+
+        Ok(media.entries.get_mut(&key).map(|(entry, data)| {
+            entry.touch();
+            data.clone()
+        }))
+    }
+
+    async fn peek_media_content(&self, request: &MediaRequest) -> Result<Option<Vec<u8>>> {
+        Ok(self.media.lock().await.entries.get(&request.unique_key()).map(|(_, data)| data.clone()))
     }
 
     async fn remove_media_content(&self, request: &MediaRequest) -> Result<()> {
-        self.media.lock().await.pop(&request.unique_key());
+        self.media.lock().await.entries.remove(&request.unique_key());
 
         Ok(())
     }
 
     async fn remove_media_content_for_uri(&self, uri: &MxcUri) -> Result<()> {
-        let mut media_store = self.media.lock().await;
+        let uri = uri.to_string();
+        self.media.lock().await.entries.retain(|key, _| !key.starts_with(&uri));
+
+        Ok(())
+    }
 
-        let keys: Vec<String> = media_store
-            .iter()
-            .filter_map(
-                |(key, _)| if key.starts_with(&uri.to_string()) { Some(key.clone()) } else { None },
-            )
-            .collect();
+    async fn set_media_retention_policy(&self, policy: MediaRetentionPolicy) -> Result<()> {
+        let mut media = self.media.lock().await;
+        media.policy = policy;
+        media.clean_up();
 
-        for key in keys {
-            media_store.pop(&key);
-        }
+        Ok(())
+    }
+
+    async fn clean_up_media_cache(&self) -> Result<()> {
+        self.media.lock().await.clean_up();
+
+        Ok(())
+    }
+
+    async fn media_cache_usage(&self) -> Result<MediaCacheUsage> {
+        Ok(self.media.lock().await.entries.values().map(|(entry, _)| *entry).collect())
+    }
+
+    async fn clear_media_cache(&self) -> Result<()> {
+        self.media.lock().await.entries.clear();
 
         Ok(())
     }
@@ -867,6 +919,10 @@ impl StateStore for MemoryStore {
         self.get_media_content(request).await
     }
 
+    async fn peek_media_content(&self, request: &MediaRequest) -> Result<Option<Vec<u8>>> {
+        self.peek_media_content(request).await
+    }
+
     async fn remove_media_content(&self, request: &MediaRequest) -> Result<()> {
         self.remove_media_content(request).await
     }
@@ -875,6 +931,22 @@ impl StateStore for MemoryStore {
         self.remove_media_content_for_uri(uri).await
     }
 
+    async fn set_media_retention_policy(&self, policy: MediaRetentionPolicy) -> Result<()> {
+        self.set_media_retention_policy(policy).await
+    }
+
+    async fn clean_up_media_cache(&self) -> Result<()> {
+        self.clean_up_media_cache().await
+    }
+
+    async fn media_cache_usage(&self) -> Result<MediaCacheUsage> {
+        self.media_cache_usage().await
+    }
+
+    async fn clear_media_cache(&self) -> Result<()> {
+        self.clear_media_cache().await
+    }
+
     async fn remove_room(&self, room_id: &RoomId) -> Result<()> {
         self.remove_room(room_id).await
     }
@@ -888,6 +960,26 @@ impl StateStore for MemoryStore {
     }
 }
 
+#[derive(Debug, Default)]
+struct MediaCache {
+    policy: MediaRetentionPolicy,
+    entries: HashMap<String, (MediaCacheEntry, Vec<u8>)>,
+}
+
+impl MediaCache {
+    /// Remove the entries that don't comply with the retention policy.
+    fn clean_up(&mut self) {
+        let evicted = self.policy.entries_to_evict(
+            self.entries.iter().map(|(key, (entry, _))| (key.clone(), *entry)),
+            MilliSecondsSinceUnixEpoch::now(),
+        );
+
+        for key in evicted {
+            self.entries.remove(&key);
+        }
+    }
+}
+
 #[derive(Debug, Default)]
 #[cfg(feature = "experimental-timeline")]
 struct TimelineData {
@@ -0,0 +1,331 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Copy the contents of one [`StateStore`] into another one.
+//!
+//! The [`StateStore`] trait only allows state events to be fetched by type, so
+//! only the state events of the types defined in the Matrix specification are
+//! copied. Media can't be listed either, the [`MediaRequest`]s of the media
+//! that should be copied need to be passed in.
+//!
+//! The sync token isn't copied, the next sync will thus be an initial sync
+//! which fetches everything that wasn't copied, e.g. account data, receipts
+//! and the state of invited rooms.
+//!
+//! The crypto store can be migrated using the `store::migration` module of the
+//! `matrix-sdk-crypto` crate.
+
+use ruma::{events::StateEventType, serde::Raw, RoomId};
+use thiserror::Error;
+use tracing::info;
+
+use super::{StateChanges, StateStore, StoreError};
+use crate::{
+    deserialized_responses::MemberEvent,
+    media::{MediaRequest, MediaRetentionPolicy},
+    RoomInfo,
+};
+
+/// The state event types that are copied, i.e. the ones defined in the Matrix
+/// specification.
+const STATE_EVENT_TYPES: &[StateEventType] = &[
+    StateEventType::PolicyRuleRoom,
+    StateEventType::PolicyRuleServer,
+    StateEventType::PolicyRuleUser,
+    StateEventType::RoomAliases,
+    StateEventType::RoomAvatar,
+    StateEventType::RoomCanonicalAlias,
+    StateEventType::RoomCreate,
+    StateEventType::RoomEncryption,
+    StateEventType::RoomGuestAccess,
+    StateEventType::RoomHistoryVisibility,
+    StateEventType::RoomJoinRules,
+    StateEventType::RoomMember,
+    StateEventType::RoomName,
+    StateEventType::RoomPinnedEvents,
+    StateEventType::RoomPowerLevels,
+    StateEventType::RoomServerAcl,
+    StateEventType::RoomThirdPartyInvite,
+    StateEventType::RoomTombstone,
+    StateEventType::RoomTopic,
+    StateEventType::SpaceChild,
+    StateEventType::SpaceParent,
+];
+
+/// The individual steps of a state store migration.
+///
+/// These are passed to the progress listener of [`migrate_state_store`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MigrationStep {
+    /// The joined and left rooms, including their state events and members,
+    /// are being copied.
+    Rooms,
+    /// The invited rooms, including their members, are being copied.
+    StrippedRooms,
+    /// The media content is being copied.
+    Media,
+}
+
+/// Summary of the data that was copied by [`migrate_state_store`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MigrationReport {
+    /// The number of room infos that were copied.
+    pub room_infos: usize,
+    /// The number of stripped room infos that were copied.
+    pub stripped_room_infos: usize,
+    /// The number of state events that were copied.
+    pub state_events: usize,
+    /// The number of member events that were copied, for stripped and regular
+    /// rooms alike.
+    pub members: usize,
+    /// The number of media files that were copied.
+    pub media: usize,
+}
+
+/// Error type for [`migrate_state_store`].
+#[derive(Debug, Error)]
+pub enum MigrationError {
+    /// Reading from the source store or writing to the target store failed.
+    #[error(transparent)]
+    Store(#[from] StoreError),
+
+    /// The target store doesn't contain the data that was copied into it.
+    #[error("The target store contains {found} entries for {step:?}, expected {expected}")]
+    CountMismatch {
+        /// The step that copied the missing data.
+        step: MigrationStep,
+        /// The number of entries that were copied.
+        expected: usize,
+        /// The number of entries the target store contains.
+        found: usize,
+    },
+}
+
+/// Copy all the data of the `source` store into the `target` store.
+///
+/// Once everything has been copied, the target store is read back and the
+/// number of entries it contains is compared to the number of entries that
+/// were copied. The target store should thus be empty before the migration
+/// starts.
+///
+/// # Arguments
+///
+/// * `source` - The store that should be copied.
+///
+/// * `target` - The store that the data should be copied into.
+///
+/// * `media_requests` - The media that should be copied, requests for media
+/// that isn't in the source store are ignored. The media is read from the
+/// source store without updating its last access time, and an unlimited
+/// [`MediaRetentionPolicy`] is set on the target store so no copied media is
+/// evicted. The desired policy should be set on the target store again after
+/// the migration.
+///
+/// * `progress_listener` - Closure that is called with the current step, the
+/// number of entries copied so far and the total number of entries of that
+/// step.
+pub async fn migrate_state_store(
+    source: &dyn StateStore,
+    target: &dyn StateStore,
+    media_requests: &[MediaRequest],
+    progress_listener: impl Fn(MigrationStep, usize, usize),
+) -> Result<MigrationReport, MigrationError> {
+    let mut report = MigrationReport::default();
+
+    let room_infos = source.get_room_infos().await?;
+    let total = room_infos.len();
+
+    for (i, room_info) in room_infos.into_iter().enumerate() {
+        let room_id = room_info.room_id().to_owned();
+        let mut changes = StateChanges::default();
+
+        for event_type in STATE_EVENT_TYPES {
+            for event in source.get_state_events(&room_id, event_type.clone()).await? {
+                let state_key = state_key(&event)?;
+
+                changes
+                    .state
+                    .entry(room_id.clone())
+                    .or_default()
+                    .entry(event_type.clone())
+                    .or_default()
+                    .insert(state_key, event);
+
+                report.state_events += 1;
+            }
+        }
+
+        report.members += copy_members(source, &room_id, &mut changes).await?;
+        changes.add_room(room_info);
+
+        target.save_changes(&changes).await?;
+        report.room_infos += 1;
+
+        progress_listener(MigrationStep::Rooms, i + 1, total);
+    }
+
+    let stripped_room_infos = source.get_stripped_room_infos().await?;
+    let total = stripped_room_infos.len();
+
+    for (i, room_info) in stripped_room_infos.into_iter().enumerate() {
+        let room_id = room_info.room_id().to_owned();
+        let mut changes = StateChanges::default();
+
+        report.members += copy_members(source, &room_id, &mut changes).await?;
+        changes.add_stripped_room(room_info);
+
+        target.save_changes(&changes).await?;
+        report.stripped_room_infos += 1;
+
+        progress_listener(MigrationStep::StrippedRooms, i + 1, total);
+    }
+
+    let total = media_requests.len();
+    target.set_media_retention_policy(MediaRetentionPolicy::unlimited()).await?;
+
+    for (i, request) in media_requests.iter().enumerate() {
+        if let Some(content) = source.peek_media_content(request).await? {
+            target.add_media_content(request, content).await?;
+            report.media += 1;
+        }
+
+        progress_listener(MigrationStep::Media, i + 1, total);
+    }
+
+    verify(target, media_requests, &report).await?;
+
+    info!(?report, "Successfully migrated the state store");
+
+    Ok(report)
+}
+
+/// Add the member events, profiles and display names of the given room in the
+/// `source` store to `changes`.
+///
+/// Returns the number of member events that were added.
+async fn copy_members(
+    source: &dyn StateStore,
+    room_id: &RoomId,
+    changes: &mut StateChanges,
+) -> Result<usize, StoreError> {
+    let mut count = 0;
+
+    for user_id in source.get_user_ids(room_id).await? {
+        let event = match source.get_member_event(room_id, &user_id).await? {
+            Some(e) => e,
+            None => continue,
+        };
+
+        if let Some(display_name) = event.original_content().and_then(|c| c.displayname.clone()) {
+            let users = source.get_users_with_display_name(room_id, &display_name).await?;
+            changes
+                .ambiguity_maps
+                .entry(room_id.to_owned())
+                .or_default()
+                .insert(display_name, users);
+        }
+
+        if let Some(profile) = source.get_profile(room_id, &user_id).await? {
+            changes
+                .profiles
+                .entry(room_id.to_owned())
+                .or_default()
+                .insert(user_id.clone(), profile);
+        }
+
+        match event {
+            MemberEvent::Sync(e) => {
+                changes.members.entry(room_id.to_owned()).or_default().insert(user_id, e);
+            }
+            MemberEvent::Stripped(e) => {
+                changes.stripped_members.entry(room_id.to_owned()).or_default().insert(user_id, e);
+            }
+        }
+
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+/// Check that the `target` store contains as many entries as the `report`
+/// says were copied.
+async fn verify(
+    target: &dyn StateStore,
+    media_requests: &[MediaRequest],
+    report: &MigrationReport,
+) -> Result<(), MigrationError> {
+    fn check(step: MigrationStep, expected: usize, found: usize) -> Result<(), MigrationError> {
+        if expected == found {
+            Ok(())
+        } else {
+            Err(MigrationError::CountMismatch { step, expected, found })
+        }
+    }
+
+    async fn count_members(target: &dyn StateStore, room: &RoomInfo) -> Result<usize, StoreError> {
+        let mut count = 0;
+
+        for user_id in target.get_user_ids(room.room_id()).await? {
+            if target.get_member_event(room.room_id(), &user_id).await?.is_some() {
+                count += 1;
+            }
+        }
+
+        Ok(count)
+    }
+
+    let room_infos = target.get_room_infos().await?;
+    let stripped_room_infos = target.get_stripped_room_infos().await?;
+
+    check(MigrationStep::Rooms, report.room_infos, room_infos.len())?;
+    check(MigrationStep::StrippedRooms, report.stripped_room_infos, stripped_room_infos.len())?;
+
+    let mut state_events = 0;
+    let mut members = 0;
+
+    for room in &room_infos {
+        for event_type in STATE_EVENT_TYPES {
+            state_events +=
+                target.get_state_events(room.room_id(), event_type.clone()).await?.len();
+        }
+
+        members += count_members(target, room).await?;
+    }
+
+    for room in &stripped_room_infos {
+        members += count_members(target, room).await?;
+    }
+
+    check(MigrationStep::Rooms, report.state_events, state_events)?;
+    check(MigrationStep::Rooms, report.members, members)?;
+
+    let mut media = 0;
+
+    for request in media_requests {
+        if target.peek_media_content(request).await?.is_some() {
+            media += 1;
+        }
+    }
+
+    check(MigrationStep::Media, report.media, media)
+}
+
+/// Get the state key of the given raw state event without deserializing the
+/// whole event.
+fn state_key<T>(event: &Raw<T>) -> Result<String, StoreError> {
+    event
+        .get_field::<String>("state_key")?
+        .ok_or_else(|| StoreError::Codec("State event without a state key".to_owned()))
+}
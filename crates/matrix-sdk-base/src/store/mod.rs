@@ -65,16 +65,17 @@ use serde::de::DeserializeOwned;
 pub type BoxStream<T> = Pin<Box<dyn futures_util::Stream<Item = T> + Send>>;
 
 #[cfg(feature = "experimental-timeline")]
-use crate::deserialized_responses::{SyncTimelineEvent, TimelineSlice};
+use crate::deserialized_responses::{SyncTimelineEvent, ThreadSummary, TimelineSlice};
 use crate::{
     deserialized_responses::MemberEvent,
-    media::MediaRequest,
+    media::{MediaCacheUsage, MediaRequest, MediaRetentionPolicy},
     rooms::{RoomInfo, RoomType},
     MinimalRoomMemberEvent, Room, Session, SessionMeta, SessionTokens,
 };
 
 pub(crate) mod ambiguity_map;
 mod memory_store;
+pub mod migration;
 
 pub use self::memory_store::MemoryStore;
 
@@ -346,6 +347,16 @@ pub trait StateStore: AsyncTraitDeps {
     /// * `request` - The `MediaRequest` of the file.
     async fn get_media_content(&self, request: &MediaRequest) -> Result<Option<Vec<u8>>>;
 
+    /// Get a media file's content out of the media store, without marking it
+    /// as accessed or removing it if it expired.
+    ///
+    /// This is meant to copy the media store, e.g. during a migration.
+    ///
+    /// # Arguments
+    ///
+    /// * `request` - The `MediaRequest` of the file.
+    async fn peek_media_content(&self, request: &MediaRequest) -> Result<Option<Vec<u8>>>;
+
     /// Removes a media file's content from the media store.
     ///
     /// # Arguments
@@ -361,6 +372,28 @@ pub trait StateStore: AsyncTraitDeps {
     /// * `uri` - The `MxcUri` of the media files.
     async fn remove_media_content_for_uri(&self, uri: &MxcUri) -> Result<()>;
 
+    /// Set the policy deciding which media files are kept in the media store,
+    /// and remove the files that don't comply with it.
+    ///
+    /// The policy is not persisted, stores start with the default
+    /// [`MediaRetentionPolicy`].
+    ///
+    /// # Arguments
+    ///
+    /// * `policy` - The new `MediaRetentionPolicy`.
+    async fn set_media_retention_policy(&self, policy: MediaRetentionPolicy) -> Result<()>;
+
+    /// Remove the media files that don't comply with the current
+    /// `MediaRetentionPolicy`, e.g. because they expired, from the media
+    /// store.
+    async fn clean_up_media_cache(&self) -> Result<()>;
+
+    /// Get the space used by the media store.
+    async fn media_cache_usage(&self) -> Result<MediaCacheUsage>;
+
+    /// Remove all the media files from the media store.
+    async fn clear_media_cache(&self) -> Result<()>;
+
     /// Removes a room and all elements associated from the state store.
     ///
     /// # Arguments
@@ -714,6 +747,11 @@ pub struct StateChanges {
     /// A mapping of `RoomId` to a `TimelineSlice`
     #[cfg(feature = "experimental-timeline")]
     pub timeline: BTreeMap<OwnedRoomId, TimelineSlice>,
+    /// A mapping of `RoomId` to a map of thread root `EventId` to the summary
+    /// of the replies to the thread received in a sync response, to update the
+    /// thread roots in the stored timeline.
+    #[cfg(feature = "experimental-timeline")]
+    pub thread_replies: BTreeMap<OwnedRoomId, BTreeMap<OwnedEventId, ThreadSummary>>,
 }
 
 impl StateChanges {
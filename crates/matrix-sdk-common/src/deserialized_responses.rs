@@ -20,9 +20,10 @@ use ruma::{
     },
     serde::Raw,
     DeviceKeyAlgorithm, EventId, MilliSecondsSinceUnixEpoch, OwnedDeviceId, OwnedEventId,
-    OwnedRoomId, OwnedUserId, UserId,
+    OwnedRoomId, OwnedUserId, UInt, UserId,
 };
 use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
 
 /// A change in ambiguity of room members that an `m.room.member` event
 /// triggers.
@@ -105,6 +106,102 @@ impl SyncTimelineEvent {
     pub fn event_id(&self) -> Option<OwnedEventId> {
         self.event.get_field::<OwnedEventId>("event_id").ok().flatten()
     }
+
+    /// Get the ID of the thread root if this event is part of a thread, i.e.
+    /// if it has an `m.thread` relation.
+    ///
+    /// The thread root itself isn't part of the thread, this returns `None`
+    /// for it.
+    pub fn thread_root(&self) -> Option<OwnedEventId> {
+        #[derive(Deserialize)]
+        struct RelatesTo {
+            rel_type: String,
+            event_id: OwnedEventId,
+        }
+
+        #[derive(Deserialize)]
+        struct Content {
+            #[serde(rename = "m.relates_to")]
+            relates_to: Option<RelatesTo>,
+        }
+
+        let relates_to = self.event.get_field::<Content>("content").ok().flatten()?.relates_to?;
+        (relates_to.rel_type == "m.thread").then(|| relates_to.event_id)
+    }
+
+    /// Get the summary of the thread this event is the root of, as bundled by
+    /// the homeserver in the `unsigned` field of the event.
+    ///
+    /// The client updates this summary with the replies received in sync
+    /// responses, for the thread roots in the same sync response and the ones
+    /// in the stored timeline.
+    ///
+    /// Returns `None` if the event isn't a thread root or if the homeserver
+    /// didn't bundle the summary.
+    pub fn thread_summary(&self) -> Option<ThreadSummary> {
+        #[derive(Deserialize)]
+        struct Relations {
+            #[serde(rename = "m.thread")]
+            thread: Option<ThreadSummary>,
+        }
+
+        #[derive(Deserialize)]
+        struct Unsigned {
+            #[serde(rename = "m.relations")]
+            relations: Option<Relations>,
+        }
+
+        self.event.get_field::<Unsigned>("unsigned").ok().flatten()?.relations?.thread
+    }
+
+    /// Update the summary of the thread this event is the root of with new
+    /// replies.
+    ///
+    /// `replies` summarizes the new replies: its latest event replaces the one
+    /// of the current summary and its count is added to the current count. If
+    /// the event doesn't have a summary yet, `replies` becomes its summary.
+    pub fn add_thread_replies(&mut self, replies: &ThreadSummary) -> serde_json::Result<()> {
+        let summary = match self.thread_summary() {
+            Some(summary) => ThreadSummary {
+                latest_event: replies.latest_event.clone(),
+                count: summary.count.saturating_add(replies.count),
+                current_user_participated: summary.current_user_participated
+                    || replies.current_user_participated,
+            },
+            None => replies.clone(),
+        };
+
+        let mut event: serde_json::Map<String, JsonValue> = self.event.deserialize_as()?;
+        let unsigned =
+            event.entry("unsigned").or_insert_with(|| JsonValue::Object(Default::default()));
+
+        if let Some(relations) = unsigned.as_object_mut().and_then(|unsigned| {
+            unsigned
+                .entry("m.relations")
+                .or_insert_with(|| JsonValue::Object(Default::default()))
+                .as_object_mut()
+        }) {
+            relations.insert("m.thread".to_owned(), serde_json::to_value(&summary)?);
+            self.event = Raw::new(&event)?.cast();
+        }
+
+        Ok(())
+    }
+}
+
+/// The summary of a thread that the homeserver bundles with the thread root.
+///
+/// See [`SyncTimelineEvent::thread_summary()`].
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ThreadSummary {
+    /// The latest event of the thread.
+    pub latest_event: Raw<AnyTimelineEvent>,
+    /// The number of events in the thread, not counting the thread root.
+    pub count: UInt,
+    /// Whether the current user sent an event in the thread, or is the sender
+    /// of the thread root.
+    #[serde(default)]
+    pub current_user_participated: bool,
 }
 
 impl From<Raw<AnySyncTimelineEvent>> for SyncTimelineEvent {
@@ -374,10 +471,10 @@ mod tests {
         },
         room_id,
         serde::Raw,
-        user_id, MilliSecondsSinceUnixEpoch,
+        uint, user_id, MilliSecondsSinceUnixEpoch,
     };
 
-    use super::{SyncTimelineEvent, TimelineEvent};
+    use super::{SyncTimelineEvent, ThreadSummary, TimelineEvent};
 
     #[test]
     fn room_event_to_sync_room_event() {
@@ -410,4 +507,119 @@ mod tests {
         assert_eq!(converted_event.event_id(), sync_event.event_id());
         assert_eq!(converted_event.sender(), sync_event.sender());
     }
+
+    #[test]
+    fn thread_relations() {
+        let reply = SyncTimelineEvent::from(
+            Raw::from_json_string(
+                r#"{
+                    "content": {
+                        "body": "In the thread",
+                        "msgtype": "m.text",
+                        "m.relates_to": {
+                            "rel_type": "m.thread",
+                            "event_id": "$root:example.org",
+                            "is_falling_back": true,
+                            "m.in_reply_to": { "event_id": "$root:example.org" }
+                        }
+                    },
+                    "event_id": "$reply:example.org",
+                    "origin_server_ts": 1,
+                    "sender": "@carl:example.com",
+                    "type": "m.room.message"
+                }"#
+                .to_owned(),
+            )
+            .unwrap(),
+        );
+
+        assert_eq!(reply.thread_root().as_deref(), Some(event_id!("$root:example.org")));
+        assert!(reply.thread_summary().is_none());
+
+        let root = SyncTimelineEvent::from(
+            Raw::from_json_string(
+                r#"{
+                    "content": { "body": "Root", "msgtype": "m.text" },
+                    "event_id": "$root:example.org",
+                    "origin_server_ts": 0,
+                    "sender": "@carl:example.com",
+                    "type": "m.room.message",
+                    "unsigned": {
+                        "m.relations": {
+                            "m.thread": {
+                                "latest_event": {
+                                    "content": { "body": "In the thread", "msgtype": "m.text" },
+                                    "event_id": "$reply:example.org",
+                                    "origin_server_ts": 1,
+                                    "room_id": "!someroom:example.com",
+                                    "sender": "@carl:example.com",
+                                    "type": "m.room.message"
+                                },
+                                "count": 1,
+                                "current_user_participated": true
+                            }
+                        }
+                    }
+                }"#
+                .to_owned(),
+            )
+            .unwrap(),
+        );
+
+        assert!(root.thread_root().is_none());
+
+        let summary = root.thread_summary().unwrap();
+        assert_eq!(summary.count, uint!(1));
+        assert!(summary.current_user_participated);
+        assert_eq!(
+            summary.latest_event.get_field::<String>("event_id").unwrap().as_deref(),
+            Some("$reply:example.org")
+        );
+    }
+
+    #[test]
+    fn add_thread_replies() {
+        let mut root = SyncTimelineEvent::from(
+            Raw::from_json_string(
+                r#"{
+                    "content": { "body": "Root", "msgtype": "m.text" },
+                    "event_id": "$root:example.org",
+                    "origin_server_ts": 0,
+                    "sender": "@carl:example.com",
+                    "type": "m.room.message"
+                }"#
+                .to_owned(),
+            )
+            .unwrap(),
+        );
+
+        let replies = |event_id: &str, count| ThreadSummary {
+            latest_event: Raw::new(&serde_json::json!({
+                "content": { "body": "In the thread", "msgtype": "m.text" },
+                "event_id": event_id,
+                "origin_server_ts": 1,
+                "room_id": "!someroom:example.com",
+                "sender": "@carl:example.com",
+                "type": "m.room.message"
+            }))
+            .unwrap()
+            .cast(),
+            count,
+            current_user_participated: false,
+        };
+
+        root.add_thread_replies(&replies("$first:example.org", uint!(2))).unwrap();
+        let summary = root.thread_summary().unwrap();
+        assert_eq!(summary.count, uint!(2));
+
+        root.add_thread_replies(&replies("$second:example.org", uint!(1))).unwrap();
+        let summary = root.thread_summary().unwrap();
+        assert_eq!(summary.count, uint!(3));
+        assert!(!summary.current_user_participated);
+        assert_eq!(
+            summary.latest_event.get_field::<String>("event_id").unwrap().as_deref(),
+            Some("$second:example.org")
+        );
+        assert_eq!(root.event_id().as_deref(), Some(event_id!("$root:example.org")));
+    }
 }
@@ -14,8 +14,8 @@ macro_rules! cryptostore_integration_tests {
                     PrivateCrossSigningIdentity, ReadOnlyAccount, Session,
                 },
                 store::{
-                    Changes, CryptoStore, DeviceChanges, GossipRequest, IdentityChanges,
-                    RecoveryKey,
+                    migration::migrate_crypto_store, Changes, CryptoStore, DeviceChanges,
+                    GossipRequest, IdentityChanges, RecoveryKey,
                 },
                 testing::{get_device, get_other_identity, get_own_identity},
                 types::events::room_key_request::MegolmV1AesSha2Content,
@@ -668,6 +668,57 @@ macro_rules! cryptostore_integration_tests {
                     "The loaded version matches to the one we stored"
                 );
             }
+
+            #[async_test]
+            async fn migrate_to_other_store() {
+                let source = get_store("migrate_to_other_store_source", None).await;
+                let target = get_store("migrate_to_other_store_target", None).await;
+
+                let alice = get_account();
+                let bob = ReadOnlyAccount::new(&bob_id(), &bob_device_id());
+                bob.generate_one_time_keys_helper(1).await;
+                let one_time_key = *bob.one_time_keys().await.values().next().unwrap();
+                let session = alice
+                    .create_outbound_session_helper(
+                        Default::default(),
+                        bob.identity_keys().curve25519,
+                        one_time_key,
+                        false,
+                    )
+                    .await;
+                let bob_device = ReadOnlyDevice::from_account(&bob).await;
+                let (_, inbound) = alice
+                    .create_group_session_pair_with_defaults(room_id!("!test:localhost"))
+                    .await;
+
+                source.save_account(alice.clone()).await.unwrap();
+                source.update_tracked_user(bob_id(), true).await.unwrap();
+
+                let changes = Changes {
+                    sessions: vec![session.clone()],
+                    inbound_group_sessions: vec![inbound],
+                    devices: DeviceChanges { new: vec![bob_device], ..Default::default() },
+                    ..Default::default()
+                };
+                source.save_changes(changes).await.unwrap();
+
+                let report = migrate_crypto_store(&source, &target, |_, _, _| {})
+                    .await
+                    .expect("Can't migrate the store");
+
+                assert!(report.account);
+                assert_eq!(report.tracked_users, 1);
+                assert_eq!(report.devices, 1);
+                assert_eq!(report.sessions, 1);
+                assert_eq!(report.inbound_group_sessions, 1);
+
+                assert_eq!(target.load_account().await.unwrap().unwrap(), alice);
+                assert!(target.users_for_key_query().contains(bob_id()));
+
+                let sessions =
+                    target.get_sessions(&session.sender_key.to_base64()).await.unwrap().unwrap();
+                assert_eq!(sessions.lock().await[0].session_id(), session.session_id());
+            }
         }
     };
 }
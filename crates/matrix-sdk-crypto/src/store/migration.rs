@@ -0,0 +1,301 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Copy the contents of one [`CryptoStore`] into another one.
+//!
+//! This can be used to move an existing session from one storage backend to
+//! another, e.g. from Sled to SQLite, without having to log in again and
+//! without losing access to the room keys that were already received.
+//!
+//! The [`CryptoStore`] trait has no way to list Olm sessions, so only the
+//! sessions that belong to a device the store knows about are copied.
+//! Outbound group sessions aren't copied either, they will be rotated the
+//! next time a message is sent in an encrypted room.
+
+use std::collections::BTreeSet;
+
+use ruma::OwnedUserId;
+use thiserror::Error;
+use tracing::info;
+
+use super::{Changes, CryptoStore, CryptoStoreError, DeviceChanges, IdentityChanges};
+
+/// The number of inbound group sessions that are written to the target store
+/// in one go.
+const INBOUND_GROUP_SESSION_BATCH_SIZE: usize = 1000;
+
+/// The individual steps of a crypto store migration.
+///
+/// These are passed to the progress listener of [`migrate_crypto_store`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MigrationStep {
+    /// The account and the private cross signing identity are being copied.
+    Account,
+    /// The list of tracked users is being copied.
+    TrackedUsers,
+    /// The devices of the tracked users are being copied.
+    Devices,
+    /// The public cross signing identities of the tracked users are being
+    /// copied.
+    Identities,
+    /// The Olm sessions are being copied.
+    Sessions,
+    /// The inbound group sessions, i.e. the room keys, are being copied.
+    InboundGroupSessions,
+}
+
+/// Summary of the data that was copied by [`migrate_crypto_store`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MigrationReport {
+    /// Was an account copied.
+    pub account: bool,
+    /// Was a private cross signing identity copied.
+    pub private_identity: bool,
+    /// The number of tracked users that were copied.
+    pub tracked_users: usize,
+    /// The number of devices that were copied.
+    pub devices: usize,
+    /// The number of user identities that were copied.
+    pub identities: usize,
+    /// The number of Olm sessions that were copied.
+    pub sessions: usize,
+    /// The number of inbound group sessions that were copied.
+    pub inbound_group_sessions: usize,
+    /// The number of inbound group sessions that are marked as backed up.
+    pub backed_up_inbound_group_sessions: usize,
+}
+
+/// Error type for [`migrate_crypto_store`].
+#[derive(Debug, Error)]
+pub enum MigrationError {
+    /// Reading from the source store or writing to the target store failed.
+    #[error(transparent)]
+    Store(#[from] CryptoStoreError),
+
+    /// The target store doesn't contain the data that was copied into it.
+    #[error("The target store contains {found} entries for {step:?}, expected {expected}")]
+    CountMismatch {
+        /// The step that copied the missing data.
+        step: MigrationStep,
+        /// The number of entries that were copied.
+        expected: usize,
+        /// The number of entries the target store contains.
+        found: usize,
+    },
+}
+
+/// Copy all the data of the `source` store into the `target` store.
+///
+/// Once everything has been copied, the target store is read back and the
+/// number of entries it contains is compared to the number of entries that
+/// were copied. The target store should thus be empty before the migration
+/// starts.
+///
+/// # Arguments
+///
+/// * `source` - The store that should be copied, it must contain an account.
+///
+/// * `target` - The store that the data should be copied into.
+///
+/// * `progress_listener` - Closure that is called with the current step, the
+/// number of entries copied so far and the total number of entries of that
+/// step.
+pub async fn migrate_crypto_store(
+    source: &dyn CryptoStore,
+    target: &dyn CryptoStore,
+    progress_listener: impl Fn(MigrationStep, usize, usize),
+) -> Result<MigrationReport, MigrationError> {
+    let mut report = MigrationReport::default();
+
+    progress_listener(MigrationStep::Account, 0, 1);
+
+    let account = source.load_account().await?.ok_or(CryptoStoreError::AccountUnset)?;
+    let own_user_id = account.user_id().to_owned();
+    let private_identity = source.load_identity().await?;
+    let backup_keys = source.load_backup_keys().await?;
+
+    report.account = true;
+    report.private_identity = private_identity.is_some();
+
+    // Save the account on its own first, some stores need to know about it
+    // before they can store anything else.
+    target.save_account(account).await?;
+    target
+        .save_changes(Changes {
+            private_identity,
+            backup_version: backup_keys.backup_version,
+            recovery_key: backup_keys.recovery_key,
+            ..Default::default()
+        })
+        .await?;
+
+    progress_listener(MigrationStep::Account, 1, 1);
+
+    let tracked_users = source.tracked_users();
+    let users_for_key_query = source.users_for_key_query();
+    let total = tracked_users.len();
+
+    for (i, user_id) in tracked_users.iter().enumerate() {
+        target.update_tracked_user(user_id, users_for_key_query.contains(user_id)).await?;
+        progress_listener(MigrationStep::TrackedUsers, i + 1, total);
+    }
+
+    report.tracked_users = total;
+
+    let user_ids = user_ids(tracked_users, own_user_id);
+    let total = user_ids.len();
+    let mut sender_keys = Vec::new();
+
+    for (i, user_id) in user_ids.iter().enumerate() {
+        let devices: Vec<_> = source.get_user_devices(user_id).await?.into_values().collect();
+
+        sender_keys.extend(devices.iter().filter_map(|d| d.curve25519_key()));
+        report.devices += devices.len();
+
+        target
+            .save_changes(Changes {
+                devices: DeviceChanges { new: devices, ..Default::default() },
+                ..Default::default()
+            })
+            .await?;
+
+        progress_listener(MigrationStep::Devices, i + 1, total);
+    }
+
+    for (i, user_id) in user_ids.iter().enumerate() {
+        if let Some(identity) = source.get_user_identity(user_id).await? {
+            report.identities += 1;
+
+            target
+                .save_changes(Changes {
+                    identities: IdentityChanges { new: vec![identity], ..Default::default() },
+                    ..Default::default()
+                })
+                .await?;
+        }
+
+        progress_listener(MigrationStep::Identities, i + 1, total);
+    }
+
+    let total = sender_keys.len();
+
+    for (i, sender_key) in sender_keys.iter().enumerate() {
+        if let Some(sessions) = source.get_sessions(&sender_key.to_base64()).await? {
+            let sessions = sessions.lock().await.clone();
+            report.sessions += sessions.len();
+
+            target.save_changes(Changes { sessions, ..Default::default() }).await?;
+        }
+
+        progress_listener(MigrationStep::Sessions, i + 1, total);
+    }
+
+    let inbound_group_sessions = source.get_inbound_group_sessions().await?;
+    let total = inbound_group_sessions.len();
+
+    report.inbound_group_sessions = total;
+    report.backed_up_inbound_group_sessions =
+        inbound_group_sessions.iter().filter(|s| s.backed_up()).count();
+
+    let mut copied = 0;
+
+    for chunk in inbound_group_sessions.chunks(INBOUND_GROUP_SESSION_BATCH_SIZE) {
+        target
+            .save_changes(Changes { inbound_group_sessions: chunk.to_vec(), ..Default::default() })
+            .await?;
+
+        copied += chunk.len();
+        progress_listener(MigrationStep::InboundGroupSessions, copied, total);
+    }
+
+    verify(target, &report).await?;
+
+    info!(?report, "Successfully migrated the crypto store");
+
+    Ok(report)
+}
+
+/// Check that the `target` store contains as many entries as the `report`
+/// says were copied.
+async fn verify(target: &dyn CryptoStore, report: &MigrationReport) -> Result<(), MigrationError> {
+    fn check(step: MigrationStep, expected: usize, found: usize) -> Result<(), MigrationError> {
+        if expected == found {
+            Ok(())
+        } else {
+            Err(MigrationError::CountMismatch { step, expected, found })
+        }
+    }
+
+    let account = match target.load_account().await? {
+        Some(a) => a,
+        None => {
+            return Err(MigrationError::CountMismatch {
+                step: MigrationStep::Account,
+                expected: 1,
+                found: 0,
+            })
+        }
+    };
+
+    let private_identity = target.load_identity().await?;
+    check(
+        MigrationStep::Account,
+        report.private_identity.into(),
+        private_identity.is_some().into(),
+    )?;
+
+    let tracked_users = target.tracked_users();
+    check(MigrationStep::TrackedUsers, report.tracked_users, tracked_users.len())?;
+
+    let mut devices = 0;
+    let mut identities = 0;
+    let mut sessions = 0;
+
+    for user_id in user_ids(tracked_users, account.user_id().to_owned()) {
+        for device in target.get_user_devices(&user_id).await?.values() {
+            devices += 1;
+
+            if let Some(sender_key) = device.curve25519_key() {
+                if let Some(s) = target.get_sessions(&sender_key.to_base64()).await? {
+                    sessions += s.lock().await.len();
+                }
+            }
+        }
+
+        if target.get_user_identity(&user_id).await?.is_some() {
+            identities += 1;
+        }
+    }
+
+    check(MigrationStep::Devices, report.devices, devices)?;
+    check(MigrationStep::Identities, report.identities, identities)?;
+    check(MigrationStep::Sessions, report.sessions, sessions)?;
+
+    let counts = target.inbound_group_session_counts().await?;
+    check(MigrationStep::InboundGroupSessions, report.inbound_group_sessions, counts.total)?;
+    check(
+        MigrationStep::InboundGroupSessions,
+        report.backed_up_inbound_group_sessions,
+        counts.backed_up,
+    )
+}
+
+/// The users whose devices and identities should be copied, our own user is
+/// included even if it isn't tracked.
+fn user_ids(
+    tracked_users: impl IntoIterator<Item = OwnedUserId>,
+    own_user_id: OwnedUserId,
+) -> BTreeSet<OwnedUserId> {
+    tracked_users.into_iter().chain(std::iter::once(own_user_id)).collect()
+}
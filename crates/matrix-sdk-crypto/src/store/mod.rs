@@ -40,6 +40,7 @@
 
 pub mod caches;
 mod memorystore;
+pub mod migration;
 
 #[cfg(any(test, feature = "testing"))]
 #[macro_use]
@@ -16,7 +16,7 @@ use std::{
     collections::{BTreeSet, HashSet},
     sync::{
         atomic::{AtomicBool, Ordering},
-        Arc,
+        Arc, RwLock,
     },
 };
 
@@ -28,7 +28,7 @@ use futures_util::stream;
 use js_sys::Date as JsDate;
 use matrix_sdk_base::{
     deserialized_responses::MemberEvent,
-    media::{MediaRequest, UniqueKey},
+    media::{MediaCacheEntry, MediaCacheUsage, MediaRequest, MediaRetentionPolicy, UniqueKey},
     store::{Result as StoreResult, StateChanges, StateStore, StoreError},
     MinimalStateEvent, RoomInfo,
 };
@@ -45,7 +45,8 @@ use ruma::{
         GlobalAccountDataEventType, RoomAccountDataEventType, StateEventType,
     },
     serde::Raw,
-    CanonicalJsonObject, EventId, MxcUri, OwnedEventId, OwnedUserId, RoomId, RoomVersionId, UserId,
+    CanonicalJsonObject, EventId, MilliSecondsSinceUnixEpoch, MxcUri, OwnedEventId, OwnedUserId,
+    RoomId, RoomVersionId, UserId,
 };
 #[cfg(feature = "experimental-timeline")]
 use ruma::{
@@ -130,7 +131,7 @@ impl From<IndexeddbStateStoreError> for StoreError {
 mod KEYS {
     // STORES
 
-    pub const CURRENT_DB_VERSION: f64 = 1.1;
+    pub const CURRENT_DB_VERSION: f64 = 1.2;
     pub const CURRENT_META_DB_VERSION: f64 = 2.0;
 
     pub const INTERNAL_STATE: &str = "matrix-sdk-state";
@@ -167,6 +168,7 @@ mod KEYS {
     pub const ROOM_EVENT_ID_TO_POSITION: &str = "room_event_id_to_position";
 
     pub const MEDIA: &str = "media";
+    pub const MEDIA_METADATA: &str = "media_metadata";
 
     pub const CUSTOM: &str = "custom";
 
@@ -193,6 +195,7 @@ mod KEYS {
         ROOM_USER_RECEIPTS,
         ROOM_EVENT_RECEIPTS,
         MEDIA,
+        MEDIA_METADATA,
         CUSTOM,
         SYNC_TOKEN,
         #[cfg(feature = "experimental-timeline")]
@@ -401,13 +404,24 @@ impl IndexeddbStateStoreBuilder {
                 if recreate_stores {
                     drop_stores(evt.db())?;
                     create_stores(evt.db())?;
+                } else if evt.old_version() < 1.2
+                    && !evt.db().object_store_names().any(|n| n == KEYS::MEDIA_METADATA)
+                {
+                    // migrating to version 1.2
+                    evt.db().create_object_store(KEYS::MEDIA_METADATA)?;
                 }
                 Ok(())
             },
         ));
 
         let db = db_req.into_future().await?;
-        Ok(IndexeddbStateStore { name, inner: db, meta: meta_db, store_cipher })
+        Ok(IndexeddbStateStore {
+            name,
+            inner: db,
+            meta: meta_db,
+            store_cipher,
+            media_retention_policy: Default::default(),
+        })
     }
 }
 
@@ -416,6 +430,7 @@ pub struct IndexeddbStateStore {
     pub(crate) inner: IdbDatabase,
     pub(crate) meta: IdbDatabase,
     pub(crate) store_cipher: Option<Arc<StoreCipher>>,
+    media_retention_policy: RwLock<MediaRetentionPolicy>,
 }
 
 impl std::fmt::Debug for IndexeddbStateStore {
@@ -1036,6 +1051,36 @@ impl IndexeddbStateStore {
                             );
                             RoomVersionId::V9
                         });
+
+                    // Update the summaries of the thread roots already in store
+                    if let Some(thread_replies) = changes.thread_replies.get(room_id) {
+                        for (thread_root, replies) in thread_replies {
+                            let root_key = self.encode_key(
+                                KEYS::ROOM_EVENT_ID_TO_POSITION,
+                                (room_id, thread_root),
+                            );
+                            if let Some(position_key) =
+                                event_id_to_position_store.get_owned(root_key)?.await?
+                            {
+                                if let Some(mut root) = timeline_store
+                                    .get(&position_key)?
+                                    .await?
+                                    .map(|e| {
+                                        self.deserialize_event::<SyncTimelineEvent>(e)
+                                            .map_err(StoreError::from)
+                                    })
+                                    .transpose()?
+                                {
+                                    root.add_thread_replies(replies)?;
+                                    timeline_store.put_key_val_owned(
+                                        position_key,
+                                        &self.serialize_event(&root)?,
+                                    )?;
+                                }
+                            }
+                        }
+                    }
+
                     for event in &timeline.events {
                         // Redact events already in store only on sync response
                         if let Ok(AnySyncTimelineEvent::MessageLike(
@@ -1388,25 +1433,78 @@ impl IndexeddbStateStore {
     }
 
     async fn add_media_content(&self, request: &MediaRequest, data: Vec<u8>) -> Result<()> {
+        let policy = self.media_retention_policy();
+        let entry = MediaCacheEntry::new(data.len() as u64);
+
+        if policy.exceeds_max_file_size(entry.size) {
+            return Ok(());
+        }
+
         let key = self
             .encode_key(KEYS::MEDIA, (request.source.unique_key(), request.format.unique_key()));
-        let tx =
-            self.inner.transaction_on_one_with_mode(KEYS::MEDIA, IdbTransactionMode::Readwrite)?;
+        let tx = self.inner.transaction_on_multi_with_mode(
+            &[KEYS::MEDIA, KEYS::MEDIA_METADATA],
+            IdbTransactionMode::Readwrite,
+        )?;
 
         tx.object_store(KEYS::MEDIA)?.put_key_val(&key, &self.serialize_event(&data)?)?;
+        tx.object_store(KEYS::MEDIA_METADATA)?.put_key_val(&key, &self.serialize_event(&entry)?)?;
 
-        tx.await.into_result().map_err(|e| e.into())
+        tx.await.into_result()?;
+
+        self.clean_up_media(policy).await
     }
 
     async fn get_media_content(&self, request: &MediaRequest) -> Result<Option<Vec<u8>>> {
         let key = self
             .encode_key(KEYS::MEDIA, (request.source.unique_key(), request.format.unique_key()));
+        let tx = self.inner.transaction_on_multi_with_mode(
+            &[KEYS::MEDIA, KEYS::MEDIA_METADATA],
+            IdbTransactionMode::Readwrite,
+        )?;
+        let media = tx.object_store(KEYS::MEDIA)?;
+        let metadata = tx.object_store(KEYS::MEDIA_METADATA)?;
+
+        let data: Vec<u8> = match media.get(&key)?.await? {
+            Some(data) => self.deserialize_event(data)?,
+            None => return Ok(None),
+        };
+
+        let mut entry = metadata
+            .get(&key)?
+            .await?
+            .map(|entry| self.deserialize_event::<MediaCacheEntry>(entry))
+            .transpose()?
+            .unwrap_or_else(|| MediaCacheEntry::new(data.len() as u64));
+
+        let data = if self
+            .media_retention_policy()
+            .has_expired(MilliSecondsSinceUnixEpoch::now(), entry.last_access)
+        {
+            media.delete(&key)?;
+            metadata.delete(&key)?;
+            None
+        } else {
+            entry.touch();
+            metadata.put_key_val(&key, &self.serialize_event(&entry)?)?;
+            Some(data)
+        };
+
+        tx.await.into_result()?;
+
+        Ok(data)
+    }
+
+    async fn peek_media_content(&self, request: &MediaRequest) -> Result<Option<Vec<u8>>> {
+        let key = self
+            .encode_key(KEYS::MEDIA, (request.source.unique_key(), request.format.unique_key()));
+
         self.inner
             .transaction_on_one_with_mode(KEYS::MEDIA, IdbTransactionMode::Readonly)?
             .object_store(KEYS::MEDIA)?
             .get(&key)?
             .await?
-            .map(|f| self.deserialize_event(f))
+            .map(|data| self.deserialize_event(data))
             .transpose()
     }
 
@@ -1446,27 +1544,123 @@ impl IndexeddbStateStore {
     async fn remove_media_content(&self, request: &MediaRequest) -> Result<()> {
         let key = self
             .encode_key(KEYS::MEDIA, (request.source.unique_key(), request.format.unique_key()));
-        let tx =
-            self.inner.transaction_on_one_with_mode(KEYS::MEDIA, IdbTransactionMode::Readwrite)?;
+        let tx = self.inner.transaction_on_multi_with_mode(
+            &[KEYS::MEDIA, KEYS::MEDIA_METADATA],
+            IdbTransactionMode::Readwrite,
+        )?;
 
         tx.object_store(KEYS::MEDIA)?.delete(&key)?;
+        tx.object_store(KEYS::MEDIA_METADATA)?.delete(&key)?;
 
         tx.await.into_result().map_err(|e| e.into())
     }
 
     async fn remove_media_content_for_uri(&self, uri: &MxcUri) -> Result<()> {
         let range = self.encode_to_range(KEYS::MEDIA, uri)?;
-        let tx =
-            self.inner.transaction_on_one_with_mode(KEYS::MEDIA, IdbTransactionMode::Readwrite)?;
+        let tx = self.inner.transaction_on_multi_with_mode(
+            &[KEYS::MEDIA, KEYS::MEDIA_METADATA],
+            IdbTransactionMode::Readwrite,
+        )?;
         let store = tx.object_store(KEYS::MEDIA)?;
+        let metadata = tx.object_store(KEYS::MEDIA_METADATA)?;
 
         for k in store.get_all_keys_with_key(&range)?.await?.iter() {
             store.delete(&k)?;
+            metadata.delete(&k)?;
         }
 
         tx.await.into_result().map_err(|e| e.into())
     }
 
+    fn media_retention_policy(&self) -> MediaRetentionPolicy {
+        *self.media_retention_policy.read().unwrap()
+    }
+
+    /// Get the metadata of all the media files in the store.
+    async fn media_entries(&self) -> Result<Vec<(JsValue, MediaCacheEntry)>> {
+        let tx = self.inner.transaction_on_multi_with_mode(
+            &[KEYS::MEDIA, KEYS::MEDIA_METADATA],
+            IdbTransactionMode::Readwrite,
+        )?;
+        let media = tx.object_store(KEYS::MEDIA)?;
+        let metadata = tx.object_store(KEYS::MEDIA_METADATA)?;
+
+        let mut entries = Vec::new();
+
+        for key in media.get_all_keys()?.await?.iter() {
+            let entry = match metadata.get(&key)?.await? {
+                Some(entry) => self.deserialize_event(entry)?,
+                // The file was stored before its metadata was tracked.
+                None => {
+                    let size = media
+                        .get(&key)?
+                        .await?
+                        .map(|data| self.deserialize_event::<Vec<u8>>(data))
+                        .transpose()?
+                        .map_or(0, |data| data.len() as u64);
+                    let entry = MediaCacheEntry::new(size);
+                    metadata.put_key_val(&key, &self.serialize_event(&entry)?)?;
+                    entry
+                }
+            };
+
+            entries.push((key, entry));
+        }
+
+        tx.await.into_result()?;
+
+        Ok(entries)
+    }
+
+    /// Remove the media files that don't comply with the given policy.
+    async fn clean_up_media(&self, policy: MediaRetentionPolicy) -> Result<()> {
+        let evicted =
+            policy.entries_to_evict(self.media_entries().await?, MilliSecondsSinceUnixEpoch::now());
+
+        if evicted.is_empty() {
+            return Ok(());
+        }
+
+        let tx = self.inner.transaction_on_multi_with_mode(
+            &[KEYS::MEDIA, KEYS::MEDIA_METADATA],
+            IdbTransactionMode::Readwrite,
+        )?;
+        let media = tx.object_store(KEYS::MEDIA)?;
+        let metadata = tx.object_store(KEYS::MEDIA_METADATA)?;
+
+        for key in evicted {
+            media.delete(&key)?;
+            metadata.delete(&key)?;
+        }
+
+        tx.await.into_result().map_err(|e| e.into())
+    }
+
+    async fn set_media_retention_policy(&self, policy: MediaRetentionPolicy) -> Result<()> {
+        *self.media_retention_policy.write().unwrap() = policy;
+        self.clean_up_media_cache().await
+    }
+
+    async fn clean_up_media_cache(&self) -> Result<()> {
+        self.clean_up_media(self.media_retention_policy()).await
+    }
+
+    async fn media_cache_usage(&self) -> Result<MediaCacheUsage> {
+        Ok(self.media_entries().await?.into_iter().map(|(_, entry)| entry).collect())
+    }
+
+    async fn clear_media_cache(&self) -> Result<()> {
+        let tx = self.inner.transaction_on_multi_with_mode(
+            &[KEYS::MEDIA, KEYS::MEDIA_METADATA],
+            IdbTransactionMode::Readwrite,
+        )?;
+
+        tx.object_store(KEYS::MEDIA)?.clear()?;
+        tx.object_store(KEYS::MEDIA_METADATA)?.clear()?;
+
+        tx.await.into_result().map_err(|e| e.into())
+    }
+
     async fn remove_room(&self, room_id: &RoomId) -> Result<()> {
         let direct_stores = [KEYS::ROOM_INFOS, KEYS::STRIPPED_ROOM_INFOS];
 
@@ -1707,6 +1901,10 @@ impl StateStore for IndexeddbStateStore {
         self.get_media_content(request).await.map_err(|e| e.into())
     }
 
+    async fn peek_media_content(&self, request: &MediaRequest) -> StoreResult<Option<Vec<u8>>> {
+        self.peek_media_content(request).await.map_err(|e| e.into())
+    }
+
     async fn remove_media_content(&self, request: &MediaRequest) -> StoreResult<()> {
         self.remove_media_content(request).await.map_err(|e| e.into())
     }
@@ -1715,6 +1913,22 @@ impl StateStore for IndexeddbStateStore {
         self.remove_media_content_for_uri(uri).await.map_err(|e| e.into())
     }
 
+    async fn set_media_retention_policy(&self, policy: MediaRetentionPolicy) -> StoreResult<()> {
+        self.set_media_retention_policy(policy).await.map_err(|e| e.into())
+    }
+
+    async fn clean_up_media_cache(&self) -> StoreResult<()> {
+        self.clean_up_media_cache().await.map_err(|e| e.into())
+    }
+
+    async fn media_cache_usage(&self) -> StoreResult<MediaCacheUsage> {
+        self.media_cache_usage().await.map_err(|e| e.into())
+    }
+
+    async fn clear_media_cache(&self) -> StoreResult<()> {
+        self.clear_media_cache().await.map_err(|e| e.into())
+    }
+
     async fn remove_room(&self, room_id: &RoomId) -> StoreResult<()> {
         self.remove_room(room_id).await.map_err(|e| e.into())
     }
@@ -15,7 +15,7 @@
 use std::{
     collections::BTreeSet,
     path::{Path, PathBuf},
-    sync::Arc,
+    sync::{Arc, RwLock},
     time::{Instant, SystemTime, UNIX_EPOCH},
 };
 
@@ -27,7 +27,7 @@ use futures_core::stream::Stream;
 use futures_util::stream::{self, StreamExt, TryStreamExt};
 use matrix_sdk_base::{
     deserialized_responses::MemberEvent,
-    media::{MediaRequest, UniqueKey},
+    media::{MediaCacheEntry, MediaCacheUsage, MediaRequest, MediaRetentionPolicy, UniqueKey},
     store::{Result as StoreResult, StateChanges, StateStore, StoreError},
     MinimalStateEvent, RoomInfo,
 };
@@ -44,8 +44,8 @@ use ruma::{
         GlobalAccountDataEventType, RoomAccountDataEventType, StateEventType,
     },
     serde::Raw,
-    CanonicalJsonObject, EventId, IdParseError, MxcUri, OwnedEventId, OwnedUserId, RoomId,
-    RoomVersionId, UserId,
+    CanonicalJsonObject, EventId, IdParseError, MilliSecondsSinceUnixEpoch, MxcUri, OwnedEventId,
+    OwnedUserId, RoomId, RoomVersionId, UserId,
 };
 #[cfg(feature = "experimental-timeline")]
 use ruma::{
@@ -152,6 +152,7 @@ const DISPLAY_NAME: &str = "display-name";
 const INVITED_USER_ID: &str = "invited-user-id";
 const JOINED_USER_ID: &str = "joined-user-id";
 const MEDIA: &str = "media";
+const MEDIA_METADATA: &str = "media-metadata";
 const MEMBER: &str = "member";
 const PRESENCE: &str = "presence";
 const PROFILE: &str = "profile";
@@ -181,6 +182,7 @@ const ALL_DB_STORES: &[&str] = &[
     INVITED_USER_ID,
     JOINED_USER_ID,
     MEDIA,
+    MEDIA_METADATA,
     MEMBER,
     PRESENCE,
     PROFILE,
@@ -331,6 +333,11 @@ pub struct SledStateStore {
     room_user_receipts: Tree,
     room_event_receipts: Tree,
     media: Tree,
+    media_metadata: Tree,
+    media_retention_policy: Arc<RwLock<MediaRetentionPolicy>>,
+    /// The total size of the media files, `None` if it needs to be computed
+    /// again.
+    media_cache_size: Arc<RwLock<Option<u64>>>,
     custom: Tree,
     #[cfg(feature = "experimental-timeline")]
     room_timeline: Tree,
@@ -381,6 +388,7 @@ impl SledStateStore {
         let room_event_receipts = db.open_tree(ROOM_EVENT_RECEIPT)?;
 
         let media = db.open_tree(MEDIA)?;
+        let media_metadata = db.open_tree(MEDIA_METADATA)?;
 
         let custom = db.open_tree(CUSTOM)?;
 
@@ -414,6 +422,9 @@ impl SledStateStore {
             room_user_receipts,
             room_event_receipts,
             media,
+            media_metadata,
+            media_retention_policy: Default::default(),
+            media_cache_size: Default::default(),
             custom,
             #[cfg(feature = "experimental-timeline")]
             room_timeline,
@@ -1165,10 +1176,20 @@ impl SledStateStore {
     }
 
     async fn add_media_content(&self, request: &MediaRequest, data: Vec<u8>) -> Result<()> {
-        self.media.insert(
-            self.encode_key(MEDIA, (request.source.unique_key(), request.format.unique_key())),
-            self.serialize_value(&data)?,
-        )?;
+        let policy = self.media_retention_policy();
+        let size = data.len() as u64;
+
+        if policy.exceeds_max_file_size(size) {
+            return Ok(());
+        }
+
+        let key =
+            self.encode_key(MEDIA, (request.source.unique_key(), request.format.unique_key()));
+        self.media.insert(key.as_slice(), self.serialize_value(&data)?)?;
+        self.media_metadata.insert(key, self.serialize_value(&MediaCacheEntry::new(size))?)?;
+
+        let db = self.clone();
+        spawn_blocking(move || db.grow_media_cache(size, policy)).await??;
 
         self.inner.flush_async().await?;
 
@@ -1179,13 +1200,43 @@ impl SledStateStore {
         let db = self.clone();
         let key =
             self.encode_key(MEDIA, (request.source.unique_key(), request.format.unique_key()));
+        let policy = self.media_retention_policy();
 
         spawn_blocking(move || {
-            db.media.get(key)?.map(move |m| db.deserialize_value(&m)).transpose()
+            let data: Vec<u8> = match db.media.get(&key)? {
+                Some(data) => db.deserialize_value(&data)?,
+                None => return Ok(None),
+            };
+
+            let mut entry = db
+                .media_metadata
+                .get(&key)?
+                .map(|entry| db.deserialize_value::<MediaCacheEntry>(&entry))
+                .transpose()?
+                .unwrap_or_else(|| MediaCacheEntry::new(data.len() as u64));
+
+            if policy.has_expired(MilliSecondsSinceUnixEpoch::now(), entry.last_access) {
+                db.media.remove(&key)?;
+                db.media_metadata.remove(&key)?;
+                db.invalidate_media_cache_size();
+                return Ok(None);
+            }
+
+            entry.touch();
+            db.media_metadata.insert(key, db.serialize_value(&entry)?)?;
+
+            Ok(Some(data))
         })
         .await?
     }
 
+    async fn peek_media_content(&self, request: &MediaRequest) -> Result<Option<Vec<u8>>> {
+        let key =
+            self.encode_key(MEDIA, (request.source.unique_key(), request.format.unique_key()));
+
+        self.media.get(key)?.map(|data| self.deserialize_value(&data)).transpose()
+    }
+
     async fn get_custom_value(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
         let custom = self.custom.clone();
         let me = self.clone();
@@ -1208,9 +1259,11 @@ impl SledStateStore {
     }
 
     async fn remove_media_content(&self, request: &MediaRequest) -> Result<()> {
-        self.media.remove(
-            self.encode_key(MEDIA, (request.source.unique_key(), request.format.unique_key())),
-        )?;
+        let key =
+            self.encode_key(MEDIA, (request.source.unique_key(), request.format.unique_key()));
+        self.media.remove(key.as_slice())?;
+        self.media_metadata.remove(key)?;
+        self.invalidate_media_cache_size();
 
         Ok(())
     }
@@ -1223,7 +1276,121 @@ impl SledStateStore {
             batch.remove(key?);
         }
 
-        Ok(self.media.apply_batch(batch)?)
+        self.media.apply_batch(batch.clone())?;
+        self.media_metadata.apply_batch(batch)?;
+        self.invalidate_media_cache_size();
+
+        Ok(())
+    }
+
+    fn media_retention_policy(&self) -> MediaRetentionPolicy {
+        *self.media_retention_policy.read().unwrap()
+    }
+
+    fn invalidate_media_cache_size(&self) {
+        *self.media_cache_size.write().unwrap() = None;
+    }
+
+    /// Account for a new media file of the given size, that was already added
+    /// to the store, and clean up the media files if the cache exceeds its
+    /// maximum size.
+    ///
+    /// The total size is only computed from the stored metadata when it
+    /// isn't known yet, so adding a file doesn't require a full scan.
+    fn grow_media_cache(&self, size: u64, policy: MediaRetentionPolicy) -> Result<()> {
+        let cached_size = *self.media_cache_size.read().unwrap();
+        let cache_size = match cached_size {
+            Some(cache_size) => cache_size.saturating_add(size),
+            None => self.media_entries()?.iter().map(|(_, entry)| entry.size).sum(),
+        };
+
+        if policy.max_cache_size.map_or(false, |max| cache_size > max) {
+            self.clean_up_media(policy)
+        } else {
+            *self.media_cache_size.write().unwrap() = Some(cache_size);
+            Ok(())
+        }
+    }
+
+    /// Get the metadata of all the media files in the store.
+    fn media_entries(&self) -> Result<Vec<(sled::IVec, MediaCacheEntry)>> {
+        self.media
+            .iter()
+            .keys()
+            .map(|key| {
+                let key = key?;
+
+                let entry = match self.media_metadata.get(&key)? {
+                    Some(entry) => self.deserialize_value(&entry)?,
+                    // The file was stored before its metadata was tracked.
+                    None => {
+                        let size = self
+                            .media
+                            .get(&key)?
+                            .map(|data| self.deserialize_value::<Vec<u8>>(&data))
+                            .transpose()?
+                            .map_or(0, |data| data.len() as u64);
+                        let entry = MediaCacheEntry::new(size);
+                        self.media_metadata.insert(&key, self.serialize_value(&entry)?)?;
+                        entry
+                    }
+                };
+
+                Ok((key, entry))
+            })
+            .collect()
+    }
+
+    /// Remove the media files that don't comply with the given policy.
+    fn clean_up_media(&self, policy: MediaRetentionPolicy) -> Result<()> {
+        self.invalidate_media_cache_size();
+
+        let evicted =
+            policy.entries_to_evict(self.media_entries()?, MilliSecondsSinceUnixEpoch::now());
+
+        if evicted.is_empty() {
+            return Ok(());
+        }
+
+        let mut batch = sled::Batch::default();
+        for key in evicted {
+            batch.remove(key);
+        }
+
+        self.media.apply_batch(batch.clone())?;
+        Ok(self.media_metadata.apply_batch(batch)?)
+    }
+
+    async fn set_media_retention_policy(&self, policy: MediaRetentionPolicy) -> Result<()> {
+        *self.media_retention_policy.write().unwrap() = policy;
+        self.clean_up_media_cache().await
+    }
+
+    async fn clean_up_media_cache(&self) -> Result<()> {
+        let db = self.clone();
+        let policy = self.media_retention_policy();
+        spawn_blocking(move || db.clean_up_media(policy)).await??;
+
+        self.inner.flush_async().await?;
+
+        Ok(())
+    }
+
+    async fn media_cache_usage(&self) -> Result<MediaCacheUsage> {
+        let db = self.clone();
+        let entries = spawn_blocking(move || db.media_entries()).await??;
+
+        Ok(entries.into_iter().map(|(_, entry)| entry).collect())
+    }
+
+    async fn clear_media_cache(&self) -> Result<()> {
+        self.media.clear()?;
+        self.media_metadata.clear()?;
+        *self.media_cache_size.write().unwrap() = Some(0);
+
+        self.inner.flush_async().await?;
+
+        Ok(())
     }
 
     async fn remove_room(&self, room_id: &RoomId) -> Result<()> {
@@ -1544,6 +1711,25 @@ impl SledStateStore {
                 });
 
             if timeline.sync {
+                // Update the summaries of the thread roots already in store
+                if let Some(thread_replies) = changes.thread_replies.get(room_id) {
+                    for (thread_root, replies) in thread_replies {
+                        let root_key =
+                            self.encode_key(ROOM_EVENT_ID_POSITION, (room_id, thread_root));
+                        if let Some(position_key) = self.room_event_id_to_position.get(root_key)? {
+                            if let Some(mut root) = self
+                                .room_timeline
+                                .get(position_key.as_ref())?
+                                .map(|e| self.deserialize_value::<SyncTimelineEvent>(&e))
+                                .transpose()?
+                            {
+                                root.add_thread_replies(replies)?;
+                                timeline_batch.insert(position_key, self.serialize_value(&root)?);
+                            }
+                        }
+                    }
+                }
+
                 for event in &timeline.events {
                     // Redact events already in store only on sync response
                     if let Ok(AnySyncTimelineEvent::MessageLike(
@@ -1790,6 +1976,10 @@ impl StateStore for SledStateStore {
         self.get_media_content(request).await.map_err(Into::into)
     }
 
+    async fn peek_media_content(&self, request: &MediaRequest) -> StoreResult<Option<Vec<u8>>> {
+        self.peek_media_content(request).await.map_err(Into::into)
+    }
+
     async fn remove_media_content(&self, request: &MediaRequest) -> StoreResult<()> {
         self.remove_media_content(request).await.map_err(Into::into)
     }
@@ -1798,6 +1988,22 @@ impl StateStore for SledStateStore {
         self.remove_media_content_for_uri(uri).await.map_err(Into::into)
     }
 
+    async fn set_media_retention_policy(&self, policy: MediaRetentionPolicy) -> StoreResult<()> {
+        self.set_media_retention_policy(policy).await.map_err(Into::into)
+    }
+
+    async fn clean_up_media_cache(&self) -> StoreResult<()> {
+        self.clean_up_media_cache().await.map_err(Into::into)
+    }
+
+    async fn media_cache_usage(&self) -> StoreResult<MediaCacheUsage> {
+        self.media_cache_usage().await.map_err(Into::into)
+    }
+
+    async fn clear_media_cache(&self) -> StoreResult<()> {
+        self.clear_media_cache().await.map_err(Into::into)
+    }
+
     async fn remove_room(&self, room_id: &RoomId) -> StoreResult<()> {
         self.remove_room(room_id).await.map_err(Into::into)
     }
@@ -1822,7 +2028,12 @@ struct TimelineMetadata {
 
 #[cfg(test)]
 mod tests {
-    use matrix_sdk_base::statestore_integration_tests;
+    use matrix_sdk_base::{
+        media::{MediaFormat, MediaRequest},
+        statestore_integration_tests,
+    };
+    use matrix_sdk_test::async_test;
+    use ruma::events::room::MediaSource;
 
     use super::{SledStateStore, StateStore, StoreResult};
 
@@ -1831,6 +2042,22 @@ mod tests {
     }
 
     statestore_integration_tests!();
+
+    fn media_request(uri: &str) -> MediaRequest {
+        MediaRequest { source: MediaSource::Plain(uri.into()), format: MediaFormat::File }
+    }
+
+    #[async_test]
+    async fn media_without_metadata() {
+        let store = SledStateStore::builder().build().unwrap();
+
+        store.add_media_content(&media_request("mxc://localhost/a"), vec![0; 8]).await.unwrap();
+        store.media_metadata.clear().unwrap();
+
+        let usage = store.media_cache_usage().await.unwrap();
+        assert_eq!(usage.count, 1);
+        assert_eq!(usage.size, 8);
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,59 @@
+-- Generic key-value table, holds the store cipher, the account, the private
+-- cross-signing identity and the backup keys.
+CREATE TABLE "kv" (
+    "key" BLOB PRIMARY KEY NOT NULL,
+    "value" BLOB NOT NULL
+);
+
+CREATE TABLE "session" (
+    "sender_key" BLOB NOT NULL,
+    "session_id" BLOB NOT NULL,
+    "data" BLOB NOT NULL,
+
+    PRIMARY KEY ("sender_key", "session_id")
+);
+
+CREATE TABLE "inbound_group_session" (
+    "room_id" BLOB NOT NULL,
+    "sender_key" BLOB NOT NULL,
+    "session_id" BLOB NOT NULL,
+    "backed_up" BOOLEAN NOT NULL,
+    "data" BLOB NOT NULL,
+
+    PRIMARY KEY ("room_id", "sender_key", "session_id")
+);
+
+CREATE TABLE "outbound_group_session" (
+    "room_id" BLOB PRIMARY KEY NOT NULL,
+    "data" BLOB NOT NULL
+);
+
+CREATE TABLE "device" (
+    "user_id" BLOB NOT NULL,
+    "device_id" BLOB NOT NULL,
+    "data" BLOB NOT NULL,
+
+    PRIMARY KEY ("user_id", "device_id")
+);
+
+CREATE TABLE "identity" (
+    "user_id" BLOB PRIMARY KEY NOT NULL,
+    "data" BLOB NOT NULL
+);
+
+CREATE TABLE "tracked_user" (
+    "user_id" BLOB PRIMARY KEY NOT NULL,
+    "data" BLOB NOT NULL
+);
+
+CREATE TABLE "olm_hash" (
+    "hash" BLOB PRIMARY KEY NOT NULL
+);
+
+CREATE TABLE "secret_request" (
+    "request_id" BLOB PRIMARY KEY NOT NULL,
+    "info_key" BLOB NOT NULL,
+    "sent_out" BOOLEAN NOT NULL,
+    "data" BLOB NOT NULL
+);
+CREATE INDEX "secret_request_info_key_idx" ON "secret_request" ("info_key");
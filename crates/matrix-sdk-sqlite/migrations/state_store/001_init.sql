@@ -0,0 +1,108 @@
+-- Generic key-value table, holds the store cipher, the sync token and filters.
+CREATE TABLE "kv" (
+    "key" BLOB PRIMARY KEY NOT NULL,
+    "value" BLOB NOT NULL
+);
+
+-- Values set through `StateStore::set_custom_value()`.
+CREATE TABLE "custom" (
+    "key" BLOB PRIMARY KEY NOT NULL,
+    "value" BLOB NOT NULL
+);
+
+CREATE TABLE "account_data" (
+    "event_type" BLOB PRIMARY KEY NOT NULL,
+    "data" BLOB NOT NULL
+);
+
+CREATE TABLE "presence" (
+    "user_id" BLOB PRIMARY KEY NOT NULL,
+    "data" BLOB NOT NULL
+);
+
+CREATE TABLE "room_info" (
+    "room_id" BLOB NOT NULL,
+    "stripped" BOOLEAN NOT NULL,
+    "data" BLOB NOT NULL,
+
+    PRIMARY KEY ("room_id", "stripped")
+);
+
+CREATE TABLE "state_event" (
+    "room_id" BLOB NOT NULL,
+    "event_type" BLOB NOT NULL,
+    "state_key" BLOB NOT NULL,
+    "stripped" BOOLEAN NOT NULL,
+    "data" BLOB NOT NULL,
+
+    PRIMARY KEY ("room_id", "event_type", "state_key", "stripped")
+);
+
+CREATE TABLE "member" (
+    "room_id" BLOB NOT NULL,
+    "user_id" BLOB NOT NULL,
+    "membership" BLOB NOT NULL,
+    "stripped" BOOLEAN NOT NULL,
+    "data" BLOB NOT NULL,
+
+    PRIMARY KEY ("room_id", "user_id", "stripped")
+);
+
+CREATE TABLE "profile" (
+    "room_id" BLOB NOT NULL,
+    "user_id" BLOB NOT NULL,
+    "data" BLOB NOT NULL,
+
+    PRIMARY KEY ("room_id", "user_id")
+);
+
+CREATE TABLE "display_name" (
+    "room_id" BLOB NOT NULL,
+    "name" BLOB NOT NULL,
+    "data" BLOB NOT NULL,
+
+    PRIMARY KEY ("room_id", "name")
+);
+
+CREATE TABLE "room_account_data" (
+    "room_id" BLOB NOT NULL,
+    "event_type" BLOB NOT NULL,
+    "data" BLOB NOT NULL,
+
+    PRIMARY KEY ("room_id", "event_type")
+);
+
+CREATE TABLE "receipt" (
+    "room_id" BLOB NOT NULL,
+    "receipt_type" BLOB NOT NULL,
+    "user_id" BLOB NOT NULL,
+    "event_id" BLOB NOT NULL,
+    "data" BLOB NOT NULL,
+
+    PRIMARY KEY ("room_id", "receipt_type", "user_id")
+);
+CREATE INDEX "receipt_event_id_idx" ON "receipt" ("room_id", "receipt_type", "event_id");
+
+CREATE TABLE "media" (
+    "uri" BLOB NOT NULL,
+    "format" BLOB NOT NULL,
+    "data" BLOB NOT NULL,
+
+    PRIMARY KEY ("uri", "format")
+);
+
+CREATE TABLE "timeline_metadata" (
+    "room_id" BLOB PRIMARY KEY NOT NULL,
+    "data" BLOB NOT NULL
+);
+
+CREATE TABLE "timeline" (
+    "room_id" BLOB NOT NULL,
+    "position" INTEGER NOT NULL,
+    -- Events without an event ID, e.g. local echoes, are stored with a NULL ID.
+    "event_id" BLOB,
+    "data" BLOB NOT NULL,
+
+    PRIMARY KEY ("room_id", "position")
+);
+CREATE INDEX "timeline_event_id_idx" ON "timeline" ("room_id", "event_id");
@@ -0,0 +1,5 @@
+-- The metadata used to enforce the media retention policy. Both columns are
+-- NULL for the files that were cached before they were tracked, they are
+-- filled the first time the media cache is cleaned up.
+ALTER TABLE "media" ADD COLUMN "size" INTEGER;
+ALTER TABLE "media" ADD COLUMN "last_access" INTEGER;
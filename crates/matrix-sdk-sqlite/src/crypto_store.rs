@@ -0,0 +1,850 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex as StdMutex, MutexGuard, RwLock},
+};
+
+use async_trait::async_trait;
+use dashmap::DashSet;
+use matrix_sdk_common::locks::Mutex;
+use matrix_sdk_crypto::{
+    olm::{
+        IdentityKeys, InboundGroupSession, OlmMessageHash, OutboundGroupSession,
+        PickledInboundGroupSession, PrivateCrossSigningIdentity, Session,
+    },
+    store::{
+        caches::SessionStore, BackupKeys, Changes, CryptoStore, CryptoStoreError,
+        Result as CryptoStoreResult, RoomKeyCounts,
+    },
+    GossipRequest, ReadOnlyAccount, ReadOnlyDevice, ReadOnlyUserIdentities, SecretInfo,
+};
+use matrix_sdk_store_encryption::StoreCipher;
+use ruma::{DeviceId, OwnedDeviceId, OwnedUserId, RoomId, TransactionId, UserId};
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{Error, Result},
+    utils::{self, ConnectionExt, EncryptableStore},
+    OpenStoreError,
+};
+
+const DATABASE_NAME: &str = "matrix-sdk-crypto.sqlite3";
+
+/// The migrations of the crypto store schema, see [`utils::run_migrations`].
+const MIGRATIONS: &[&str] = &[include_str!("../migrations/crypto_store/001_init.sql")];
+
+// Table names, they are also used to derive a separate hash key for every
+// table, so the same ID doesn't end up as the same byte sequence in different
+// tables.
+const KV: &str = "kv";
+const SESSION: &str = "session";
+const INBOUND_GROUP_SESSION: &str = "inbound_group_session";
+const OUTBOUND_GROUP_SESSION: &str = "outbound_group_session";
+const DEVICE: &str = "device";
+const IDENTITY: &str = "identity";
+const TRACKED_USER: &str = "tracked_user";
+const OLM_HASH: &str = "olm_hash";
+const SECRET_REQUEST: &str = "secret_request";
+
+#[derive(Clone, Debug)]
+struct AccountInfo {
+    user_id: Arc<UserId>,
+    device_id: Arc<DeviceId>,
+    identity_keys: Arc<IdentityKeys>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct TrackedUser {
+    user_id: OwnedUserId,
+    dirty: bool,
+}
+
+/// A [SQLite] based cryptostore.
+///
+/// [SQLite]: https://www.sqlite.org/
+#[derive(Clone)]
+pub struct SqliteCryptoStore {
+    conn: Arc<StdMutex<Connection>>,
+    store_cipher: Option<Arc<StoreCipher>>,
+    path: Option<PathBuf>,
+
+    account_info: Arc<RwLock<Option<AccountInfo>>>,
+    session_cache: SessionStore,
+    tracked_users_cache: Arc<DashSet<OwnedUserId>>,
+    users_for_key_query_cache: Arc<DashSet<OwnedUserId>>,
+}
+
+impl std::fmt::Debug for SqliteCryptoStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(path) = &self.path {
+            f.debug_struct("SqliteCryptoStore").field("path", &path).finish()
+        } else {
+            f.debug_struct("SqliteCryptoStore").field("path", &"memory store").finish()
+        }
+    }
+}
+
+impl EncryptableStore for SqliteCryptoStore {
+    fn store_cipher(&self) -> Option<&StoreCipher> {
+        self.store_cipher.as_deref()
+    }
+}
+
+impl SqliteCryptoStore {
+    /// Open a crypto store that lives in memory, all the data is lost once the
+    /// store is dropped.
+    pub fn open() -> Result<Self, OpenStoreError> {
+        Self::open_helper(Connection::open_in_memory()?, None, None)
+    }
+
+    /// Open the SQLite-based crypto store in the given directory, using the
+    /// given passphrase to encrypt private data.
+    ///
+    /// The directory and the database are created if they don't exist yet.
+    pub fn open_with_passphrase(
+        path: impl AsRef<Path>,
+        passphrase: Option<&str>,
+    ) -> Result<Self, OpenStoreError> {
+        let path = path.as_ref();
+        std::fs::create_dir_all(path)?;
+        let conn = Connection::open(path.join(DATABASE_NAME))?;
+
+        Self::open_helper(conn, Some(path.to_owned()), passphrase)
+    }
+
+    fn open_helper(
+        mut conn: Connection,
+        path: Option<PathBuf>,
+        passphrase: Option<&str>,
+    ) -> Result<Self, OpenStoreError> {
+        conn.pragma_update_and_check(None, "journal_mode", "wal", |_| Ok(()))?;
+
+        utils::run_migrations(&mut conn, MIGRATIONS).map_err(CryptoStoreError::from)?;
+
+        let store_cipher = passphrase
+            .map(|p| utils::get_or_create_store_cipher(p, &conn))
+            .transpose()
+            .map_err(|e| match e {
+                Error::Encryption(_) => CryptoStoreError::UnpicklingError,
+                e => e.into(),
+            })?
+            .map(Arc::new);
+
+        Ok(Self {
+            conn: Arc::new(StdMutex::new(conn)),
+            store_cipher,
+            path,
+            account_info: RwLock::new(None).into(),
+            session_cache: SessionStore::new(),
+            tracked_users_cache: DashSet::new().into(),
+            users_for_key_query_cache: DashSet::new().into(),
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Connection> {
+        self.conn.lock().unwrap()
+    }
+
+    fn get_account_info(&self) -> Option<AccountInfo> {
+        self.account_info.read().unwrap().clone()
+    }
+
+    fn get_kv(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        Ok(self
+            .lock()
+            .query_value("SELECT value FROM kv WHERE key = ?", [self.encode_key(KV, key)])?)
+    }
+
+    fn set_kv(&self, conn: &Connection, key: &str, value: &impl Serialize) -> Result<()> {
+        conn.execute(
+            "INSERT OR REPLACE INTO kv (key, value) VALUES (?, ?)",
+            params![self.encode_key(KV, key), self.serialize_value(value)?],
+        )?;
+
+        Ok(())
+    }
+
+    fn load_tracked_users(&self) -> Result<()> {
+        for value in self.lock().query_values("SELECT data FROM tracked_user", [])? {
+            let user: TrackedUser = self.deserialize_value(&value)?;
+
+            self.tracked_users_cache.insert(user.user_id.to_owned());
+
+            if user.dirty {
+                self.users_for_key_query_cache.insert(user.user_id);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Save a batch of tracked users.
+    ///
+    /// # Arguments
+    ///
+    /// * `tracked_users` - A list of tuples. The first element of the tuple is
+    /// the user ID, the second element is if the user should be considered to
+    /// be dirty.
+    pub async fn save_tracked_users(
+        &self,
+        tracked_users: &[(&UserId, bool)],
+    ) -> Result<(), CryptoStoreError> {
+        self.save_tracked_users_inner(tracked_users).map_err(Into::into)
+    }
+
+    fn save_tracked_users_inner(&self, tracked_users: &[(&UserId, bool)]) -> Result<()> {
+        let mut conn = self.lock();
+        let txn = conn.transaction()?;
+
+        for (user_id, dirty) in tracked_users {
+            let user = TrackedUser { user_id: (*user_id).into(), dirty: *dirty };
+
+            txn.execute(
+                "INSERT OR REPLACE INTO tracked_user (user_id, data) VALUES (?, ?)",
+                params![
+                    self.encode_key(TRACKED_USER, user.user_id.as_str()),
+                    self.serialize_value(&user)?
+                ],
+            )?;
+        }
+
+        txn.commit()?;
+
+        Ok(())
+    }
+
+    async fn load_account(&self) -> Result<Option<ReadOnlyAccount>> {
+        let pickle = match self.get_kv("account")? {
+            Some(p) => self.deserialize_value(&p)?,
+            None => return Ok(None),
+        };
+
+        self.load_tracked_users()?;
+        let account = ReadOnlyAccount::from_pickle(pickle).map_err(CryptoStoreError::from)?;
+
+        let account_info = AccountInfo {
+            user_id: account.user_id.clone(),
+            device_id: account.device_id.clone(),
+            identity_keys: account.identity_keys.clone(),
+        };
+
+        *self.account_info.write().unwrap() = Some(account_info);
+
+        Ok(Some(account))
+    }
+
+    async fn load_identity(&self) -> Result<Option<PrivateCrossSigningIdentity>> {
+        if let Some(i) = self.get_kv("identity")? {
+            let pickle = self.deserialize_value(&i)?;
+            Ok(Some(
+                PrivateCrossSigningIdentity::from_pickle(pickle)
+                    .await
+                    .map_err(|_| CryptoStoreError::UnpicklingError)?,
+            ))
+        } else {
+            Ok(None)
+        }
+    }
+
+    async fn save_changes(&self, changes: Changes) -> Result<()> {
+        let account_pickle = if let Some(account) = changes.account {
+            let account_info = AccountInfo {
+                user_id: account.user_id.clone(),
+                device_id: account.device_id.clone(),
+                identity_keys: account.identity_keys.clone(),
+            };
+
+            *self.account_info.write().unwrap() = Some(account_info);
+            Some(account.pickle().await)
+        } else {
+            None
+        };
+
+        let private_identity_pickle =
+            if let Some(i) = changes.private_identity { Some(i.pickle().await?) } else { None };
+
+        let mut session_changes = Vec::new();
+
+        for session in changes.sessions {
+            let sender_key = session.sender_key().to_base64();
+            let session_id = session.session_id().to_owned();
+            let pickle = session.pickle().await;
+
+            self.session_cache.add(session).await;
+            session_changes.push((sender_key, session_id, pickle));
+        }
+
+        let mut inbound_session_changes = Vec::new();
+
+        for session in changes.inbound_group_sessions {
+            let session_id = session.session_id().to_owned();
+            let pickle = session.pickle().await;
+
+            inbound_session_changes.push((session_id, pickle));
+        }
+
+        let mut outbound_session_changes = Vec::new();
+
+        for session in changes.outbound_group_sessions {
+            let room_id = session.room_id().to_owned();
+            let pickle = session.pickle().await;
+
+            outbound_session_changes.push((room_id, pickle));
+        }
+
+        let mut conn = self.lock();
+        let txn = conn.transaction()?;
+
+        if let Some(a) = &account_pickle {
+            self.set_kv(&txn, "account", a)?;
+        }
+
+        if let Some(i) = &private_identity_pickle {
+            self.set_kv(&txn, "identity", i)?;
+        }
+
+        if let Some(r) = &changes.recovery_key {
+            self.set_kv(&txn, "recovery_key_v1", r)?;
+        }
+
+        if let Some(b) = &changes.backup_version {
+            self.set_kv(&txn, "backup_version_v1", b)?;
+        }
+
+        for device in changes.devices.new.iter().chain(&changes.devices.changed) {
+            txn.execute(
+                "INSERT OR REPLACE INTO device (user_id, device_id, data) VALUES (?, ?, ?)",
+                params![
+                    self.encode_key(DEVICE, device.user_id().as_str()),
+                    self.encode_key(DEVICE, device.device_id().as_str()),
+                    self.serialize_value(&device)?
+                ],
+            )?;
+        }
+
+        for device in &changes.devices.deleted {
+            txn.execute(
+                "DELETE FROM device WHERE user_id = ? AND device_id = ?",
+                [
+                    self.encode_key(DEVICE, device.user_id().as_str()),
+                    self.encode_key(DEVICE, device.device_id().as_str()),
+                ],
+            )?;
+        }
+
+        for identity in changes.identities.changed.iter().chain(&changes.identities.new) {
+            txn.execute(
+                "INSERT OR REPLACE INTO identity (user_id, data) VALUES (?, ?)",
+                params![
+                    self.encode_key(IDENTITY, identity.user_id().as_str()),
+                    self.serialize_value(&identity)?
+                ],
+            )?;
+        }
+
+        for (sender_key, session_id, pickle) in &session_changes {
+            txn.execute(
+                "INSERT OR REPLACE INTO session (sender_key, session_id, data) VALUES (?, ?, ?)",
+                params![
+                    self.encode_key(SESSION, sender_key),
+                    self.encode_key(SESSION, session_id),
+                    self.serialize_value(pickle)?
+                ],
+            )?;
+        }
+
+        for (session_id, pickle) in &inbound_session_changes {
+            txn.execute(
+                "INSERT OR REPLACE INTO inbound_group_session
+                 (room_id, sender_key, session_id, backed_up, data)
+                 VALUES (?, ?, ?, ?, ?)",
+                params![
+                    self.encode_key(INBOUND_GROUP_SESSION, pickle.room_id.as_str()),
+                    self.encode_key(INBOUND_GROUP_SESSION, pickle.sender_key.to_base64()),
+                    self.encode_key(INBOUND_GROUP_SESSION, session_id),
+                    pickle.backed_up,
+                    self.serialize_value(pickle)?
+                ],
+            )?;
+        }
+
+        for (room_id, pickle) in &outbound_session_changes {
+            txn.execute(
+                "INSERT OR REPLACE INTO outbound_group_session (room_id, data) VALUES (?, ?)",
+                params![
+                    self.encode_key(OUTBOUND_GROUP_SESSION, room_id.as_str()),
+                    self.serialize_value(pickle)?
+                ],
+            )?;
+        }
+
+        for hash in &changes.message_hashes {
+            txn.execute(
+                "INSERT OR IGNORE INTO olm_hash (hash) VALUES (?)",
+                [self.encode_key(OLM_HASH, serde_json::to_vec(hash)?)],
+            )?;
+        }
+
+        for request in &changes.key_requests {
+            txn.execute(
+                "INSERT OR REPLACE INTO secret_request (request_id, info_key, sent_out, data)
+                 VALUES (?, ?, ?, ?)",
+                params![
+                    self.encode_key(SECRET_REQUEST, request.request_id.as_str()),
+                    self.encode_key(SECRET_REQUEST, request.info.as_key()),
+                    request.sent_out,
+                    self.serialize_value(request)?
+                ],
+            )?;
+        }
+
+        txn.commit()?;
+
+        Ok(())
+    }
+
+    async fn get_sessions(&self, sender_key: &str) -> Result<Option<Arc<Mutex<Vec<Session>>>>> {
+        let account_info = self.get_account_info().ok_or(CryptoStoreError::AccountUnset)?;
+
+        if self.session_cache.get(sender_key).is_none() {
+            let sessions = self
+                .lock()
+                .query_values(
+                    "SELECT data FROM session WHERE sender_key = ?",
+                    [self.encode_key(SESSION, sender_key)],
+                )?
+                .iter()
+                .map(|p| {
+                    Ok(Session::from_pickle(
+                        account_info.user_id.clone(),
+                        account_info.device_id.clone(),
+                        account_info.identity_keys.clone(),
+                        self.deserialize_value(p)?,
+                    ))
+                })
+                .collect::<Result<_>>()?;
+
+            self.session_cache.set_for_sender(sender_key, sessions);
+        }
+
+        Ok(self.session_cache.get(sender_key))
+    }
+
+    async fn get_inbound_group_session(
+        &self,
+        room_id: &RoomId,
+        sender_key: &str,
+        session_id: &str,
+    ) -> Result<Option<InboundGroupSession>> {
+        let pickle = self.lock().query_value(
+            "SELECT data FROM inbound_group_session
+             WHERE room_id = ? AND sender_key = ? AND session_id = ?",
+            [
+                self.encode_key(INBOUND_GROUP_SESSION, room_id.as_str()),
+                self.encode_key(INBOUND_GROUP_SESSION, sender_key),
+                self.encode_key(INBOUND_GROUP_SESSION, session_id),
+            ],
+        )?;
+
+        if let Some(pickle) = pickle {
+            let pickle: PickledInboundGroupSession = self.deserialize_value(&pickle)?;
+            Ok(Some(InboundGroupSession::from_pickle(pickle).map_err(CryptoStoreError::from)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn get_inbound_group_session_pickles(
+        &self,
+        sql: &str,
+        params: impl rusqlite::Params,
+    ) -> Result<Vec<PickledInboundGroupSession>> {
+        self.lock().query_values(sql, params)?.iter().map(|p| self.deserialize_value(p)).collect()
+    }
+
+    async fn get_inbound_group_sessions(&self) -> Result<Vec<InboundGroupSession>> {
+        let pickles =
+            self.get_inbound_group_session_pickles("SELECT data FROM inbound_group_session", [])?;
+
+        Ok(pickles.into_iter().filter_map(|p| InboundGroupSession::from_pickle(p).ok()).collect())
+    }
+
+    async fn inbound_group_session_counts(&self) -> Result<RoomKeyCounts> {
+        let (total, backed_up): (i64, i64) = self.lock().query_row(
+            "SELECT COUNT(*), COUNT(*) FILTER (WHERE backed_up = TRUE) FROM inbound_group_session",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+
+        Ok(RoomKeyCounts { total: total as usize, backed_up: backed_up as usize })
+    }
+
+    async fn inbound_group_sessions_for_backup(
+        &self,
+        limit: usize,
+    ) -> Result<Vec<InboundGroupSession>> {
+        self.get_inbound_group_session_pickles(
+            "SELECT data FROM inbound_group_session WHERE backed_up = FALSE LIMIT ?",
+            [limit as i64],
+        )?
+        .into_iter()
+        .map(|p| Ok(InboundGroupSession::from_pickle(p).map_err(CryptoStoreError::from)?))
+        .collect()
+    }
+
+    async fn reset_backup_state(&self) -> Result<()> {
+        let mut conn = self.lock();
+        let txn = conn.transaction()?;
+
+        let mut statement =
+            txn.prepare("SELECT rowid, data FROM inbound_group_session WHERE backed_up = TRUE")?;
+        let pickles: Vec<(i64, Vec<u8>)> = statement
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<_>>()?;
+        drop(statement);
+
+        for (rowid, data) in pickles {
+            let mut pickle: PickledInboundGroupSession = self.deserialize_value(&data)?;
+            pickle.backed_up = false;
+
+            txn.execute(
+                "UPDATE inbound_group_session SET backed_up = FALSE, data = ? WHERE rowid = ?",
+                params![self.serialize_value(&pickle)?, rowid],
+            )?;
+        }
+
+        txn.commit()?;
+
+        Ok(())
+    }
+
+    async fn get_outbound_group_session(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Option<OutboundGroupSession>> {
+        let account_info = self.get_account_info().ok_or(CryptoStoreError::AccountUnset)?;
+
+        self.lock()
+            .query_value(
+                "SELECT data FROM outbound_group_session WHERE room_id = ?",
+                [self.encode_key(OUTBOUND_GROUP_SESSION, room_id.as_str())],
+            )?
+            .map(|p| {
+                Ok(OutboundGroupSession::from_pickle(
+                    account_info.device_id,
+                    account_info.identity_keys,
+                    self.deserialize_value(&p)?,
+                )
+                .map_err(CryptoStoreError::from)?)
+            })
+            .transpose()
+    }
+
+    async fn get_device(
+        &self,
+        user_id: &UserId,
+        device_id: &DeviceId,
+    ) -> Result<Option<ReadOnlyDevice>> {
+        self.lock()
+            .query_value(
+                "SELECT data FROM device WHERE user_id = ? AND device_id = ?",
+                [
+                    self.encode_key(DEVICE, user_id.as_str()),
+                    self.encode_key(DEVICE, device_id.as_str()),
+                ],
+            )?
+            .map(|d| self.deserialize_value(&d))
+            .transpose()
+    }
+
+    async fn get_user_devices(
+        &self,
+        user_id: &UserId,
+    ) -> Result<HashMap<OwnedDeviceId, ReadOnlyDevice>> {
+        self.lock()
+            .query_values(
+                "SELECT data FROM device WHERE user_id = ?",
+                [self.encode_key(DEVICE, user_id.as_str())],
+            )?
+            .iter()
+            .map(|d| {
+                let d: ReadOnlyDevice = self.deserialize_value(d)?;
+                Ok((d.device_id().to_owned(), d))
+            })
+            .collect()
+    }
+
+    async fn get_user_identity(&self, user_id: &UserId) -> Result<Option<ReadOnlyUserIdentities>> {
+        self.lock()
+            .query_value(
+                "SELECT data FROM identity WHERE user_id = ?",
+                [self.encode_key(IDENTITY, user_id.as_str())],
+            )?
+            .map(|i| self.deserialize_value(&i))
+            .transpose()
+    }
+
+    async fn is_message_known(&self, message_hash: &OlmMessageHash) -> Result<bool> {
+        Ok(self.lock().query_row(
+            "SELECT EXISTS(SELECT 1 FROM olm_hash WHERE hash = ?)",
+            [self.encode_key(OLM_HASH, serde_json::to_vec(message_hash)?)],
+            |row| row.get(0),
+        )?)
+    }
+
+    async fn get_outgoing_secret_requests(
+        &self,
+        request_id: &TransactionId,
+    ) -> Result<Option<GossipRequest>> {
+        self.lock()
+            .query_value(
+                "SELECT data FROM secret_request WHERE request_id = ?",
+                [self.encode_key(SECRET_REQUEST, request_id.as_str())],
+            )?
+            .map(|r| self.deserialize_value(&r))
+            .transpose()
+    }
+
+    async fn get_secret_request_by_info(
+        &self,
+        key_info: &SecretInfo,
+    ) -> Result<Option<GossipRequest>> {
+        self.lock()
+            .query_value(
+                "SELECT data FROM secret_request WHERE info_key = ?",
+                [self.encode_key(SECRET_REQUEST, key_info.as_key())],
+            )?
+            .map(|r| self.deserialize_value(&r))
+            .transpose()
+    }
+
+    async fn get_unsent_secret_requests(&self) -> Result<Vec<GossipRequest>> {
+        self.lock()
+            .query_values("SELECT data FROM secret_request WHERE sent_out = FALSE", [])?
+            .iter()
+            .map(|r| self.deserialize_value(r))
+            .collect()
+    }
+
+    async fn delete_outgoing_secret_requests(&self, request_id: &TransactionId) -> Result<()> {
+        self.lock().execute(
+            "DELETE FROM secret_request WHERE request_id = ?",
+            [self.encode_key(SECRET_REQUEST, request_id.as_str())],
+        )?;
+
+        Ok(())
+    }
+
+    async fn load_backup_keys(&self) -> Result<BackupKeys> {
+        let backup_version =
+            self.get_kv("backup_version_v1")?.map(|v| self.deserialize_value(&v)).transpose()?;
+        let recovery_key =
+            self.get_kv("recovery_key_v1")?.map(|p| self.deserialize_value(&p)).transpose()?;
+
+        Ok(BackupKeys { backup_version, recovery_key })
+    }
+}
+
+#[async_trait]
+impl CryptoStore for SqliteCryptoStore {
+    async fn load_account(&self) -> CryptoStoreResult<Option<ReadOnlyAccount>> {
+        self.load_account().await.map_err(Into::into)
+    }
+
+    async fn save_account(&self, account: ReadOnlyAccount) -> CryptoStoreResult<()> {
+        self.save_changes(Changes { account: Some(account), ..Default::default() })
+            .await
+            .map_err(Into::into)
+    }
+
+    async fn load_identity(&self) -> CryptoStoreResult<Option<PrivateCrossSigningIdentity>> {
+        self.load_identity().await.map_err(Into::into)
+    }
+
+    async fn save_changes(&self, changes: Changes) -> CryptoStoreResult<()> {
+        self.save_changes(changes).await.map_err(Into::into)
+    }
+
+    async fn get_sessions(
+        &self,
+        sender_key: &str,
+    ) -> CryptoStoreResult<Option<Arc<Mutex<Vec<Session>>>>> {
+        self.get_sessions(sender_key).await.map_err(Into::into)
+    }
+
+    async fn get_inbound_group_session(
+        &self,
+        room_id: &RoomId,
+        sender_key: &str,
+        session_id: &str,
+    ) -> CryptoStoreResult<Option<InboundGroupSession>> {
+        self.get_inbound_group_session(room_id, sender_key, session_id).await.map_err(Into::into)
+    }
+
+    async fn get_inbound_group_sessions(&self) -> CryptoStoreResult<Vec<InboundGroupSession>> {
+        self.get_inbound_group_sessions().await.map_err(Into::into)
+    }
+
+    async fn inbound_group_session_counts(&self) -> CryptoStoreResult<RoomKeyCounts> {
+        self.inbound_group_session_counts().await.map_err(Into::into)
+    }
+
+    async fn inbound_group_sessions_for_backup(
+        &self,
+        limit: usize,
+    ) -> CryptoStoreResult<Vec<InboundGroupSession>> {
+        self.inbound_group_sessions_for_backup(limit).await.map_err(Into::into)
+    }
+
+    async fn reset_backup_state(&self) -> CryptoStoreResult<()> {
+        self.reset_backup_state().await.map_err(Into::into)
+    }
+
+    async fn load_backup_keys(&self) -> CryptoStoreResult<BackupKeys> {
+        self.load_backup_keys().await.map_err(Into::into)
+    }
+
+    async fn get_outbound_group_sessions(
+        &self,
+        room_id: &RoomId,
+    ) -> CryptoStoreResult<Option<OutboundGroupSession>> {
+        self.get_outbound_group_session(room_id).await.map_err(Into::into)
+    }
+
+    fn is_user_tracked(&self, user_id: &UserId) -> bool {
+        self.tracked_users_cache.contains(user_id)
+    }
+
+    fn has_users_for_key_query(&self) -> bool {
+        !self.users_for_key_query_cache.is_empty()
+    }
+
+    fn users_for_key_query(&self) -> HashSet<OwnedUserId> {
+        self.users_for_key_query_cache.iter().map(|u| u.clone()).collect()
+    }
+
+    fn tracked_users(&self) -> HashSet<OwnedUserId> {
+        self.tracked_users_cache.iter().map(|u| u.clone()).collect()
+    }
+
+    async fn update_tracked_user(&self, user: &UserId, dirty: bool) -> CryptoStoreResult<bool> {
+        let already_added = self.tracked_users_cache.insert(user.to_owned());
+
+        if dirty {
+            self.users_for_key_query_cache.insert(user.to_owned());
+        } else {
+            self.users_for_key_query_cache.remove(user);
+        }
+
+        self.save_tracked_users(&[(user, dirty)]).await?;
+
+        Ok(already_added)
+    }
+
+    async fn get_device(
+        &self,
+        user_id: &UserId,
+        device_id: &DeviceId,
+    ) -> CryptoStoreResult<Option<ReadOnlyDevice>> {
+        self.get_device(user_id, device_id).await.map_err(Into::into)
+    }
+
+    async fn get_user_devices(
+        &self,
+        user_id: &UserId,
+    ) -> CryptoStoreResult<HashMap<OwnedDeviceId, ReadOnlyDevice>> {
+        self.get_user_devices(user_id).await.map_err(Into::into)
+    }
+
+    async fn get_user_identity(
+        &self,
+        user_id: &UserId,
+    ) -> CryptoStoreResult<Option<ReadOnlyUserIdentities>> {
+        self.get_user_identity(user_id).await.map_err(Into::into)
+    }
+
+    async fn is_message_known(&self, message_hash: &OlmMessageHash) -> CryptoStoreResult<bool> {
+        self.is_message_known(message_hash).await.map_err(Into::into)
+    }
+
+    async fn get_outgoing_secret_requests(
+        &self,
+        request_id: &TransactionId,
+    ) -> CryptoStoreResult<Option<GossipRequest>> {
+        self.get_outgoing_secret_requests(request_id).await.map_err(Into::into)
+    }
+
+    async fn get_secret_request_by_info(
+        &self,
+        key_info: &SecretInfo,
+    ) -> CryptoStoreResult<Option<GossipRequest>> {
+        self.get_secret_request_by_info(key_info).await.map_err(Into::into)
+    }
+
+    async fn get_unsent_secret_requests(&self) -> CryptoStoreResult<Vec<GossipRequest>> {
+        self.get_unsent_secret_requests().await.map_err(Into::into)
+    }
+
+    async fn delete_outgoing_secret_requests(
+        &self,
+        request_id: &TransactionId,
+    ) -> CryptoStoreResult<()> {
+        self.delete_outgoing_secret_requests(request_id).await.map_err(Into::into)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use matrix_sdk_crypto::cryptostore_integration_tests;
+    use once_cell::sync::Lazy;
+    use tempfile::{tempdir, TempDir};
+
+    use super::SqliteCryptoStore;
+
+    static TMP_DIR: Lazy<TempDir> = Lazy::new(|| tempdir().unwrap());
+
+    async fn get_store(name: &str, passphrase: Option<&str>) -> SqliteCryptoStore {
+        let tmpdir_path = TMP_DIR.path().join(name);
+
+        SqliteCryptoStore::open_with_passphrase(tmpdir_path.to_str().unwrap(), passphrase)
+            .expect("Can't create a passphrase protected store")
+    }
+
+    cryptostore_integration_tests!();
+}
+
+#[cfg(test)]
+mod encrypted_tests {
+    use matrix_sdk_crypto::cryptostore_integration_tests;
+    use once_cell::sync::Lazy;
+    use tempfile::{tempdir, TempDir};
+
+    use super::SqliteCryptoStore;
+
+    static TMP_DIR: Lazy<TempDir> = Lazy::new(|| tempdir().unwrap());
+
+    async fn get_store(name: &str, passphrase: Option<&str>) -> SqliteCryptoStore {
+        let tmpdir_path = TMP_DIR.path().join(name);
+        let pass = passphrase.unwrap_or("default_test_password");
+
+        SqliteCryptoStore::open_with_passphrase(tmpdir_path.to_str().unwrap(), Some(pass))
+            .expect("Can't create a passphrase protected store")
+    }
+
+    cryptostore_integration_tests!();
+}
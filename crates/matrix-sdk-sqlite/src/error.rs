@@ -0,0 +1,88 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#[cfg(feature = "state-store")]
+use matrix_sdk_base::store::StoreError;
+#[cfg(feature = "crypto-store")]
+use matrix_sdk_crypto::store::CryptoStoreError;
+use matrix_sdk_store_encryption::Error as KeyEncryptionError;
+use ruma::IdParseError;
+use thiserror::Error;
+
+/// The internal error type of the SQLite stores.
+///
+/// It is converted into the error type of the respective store trait at the
+/// API boundary.
+#[derive(Debug, Error)]
+pub(crate) enum Error {
+    #[error(transparent)]
+    Sqlite(#[from] rusqlite::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error(transparent)]
+    Encryption(#[from] KeyEncryptionError),
+    #[error(transparent)]
+    Identifier(#[from] IdParseError),
+    #[error("The database version {0} is newer than the latest supported version {1}")]
+    UnsupportedDatabaseVersion(usize, usize),
+    #[cfg(feature = "state-store")]
+    #[error(transparent)]
+    State(#[from] StoreError),
+    #[cfg(feature = "crypto-store")]
+    #[error(transparent)]
+    Crypto(#[from] CryptoStoreError),
+}
+
+pub(crate) type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[cfg(feature = "state-store")]
+impl From<Error> for StoreError {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::Json(e) => StoreError::Json(e),
+            Error::Identifier(e) => StoreError::Identifier(e),
+            Error::Encryption(e) => match e {
+                KeyEncryptionError::Random(e) => StoreError::Encryption(e.to_string()),
+                KeyEncryptionError::Serialization(e) => StoreError::Json(e),
+                KeyEncryptionError::Encryption(e) => StoreError::Encryption(e.to_string()),
+                KeyEncryptionError::Version(found, expected) => StoreError::Encryption(format!(
+                    "Bad Database Encryption Version: expected {expected}, found {found}",
+                )),
+                KeyEncryptionError::Length(found, expected) => StoreError::Encryption(format!(
+                    "The database key an invalid length: expected {expected}, found {found}",
+                )),
+            },
+            Error::UnsupportedDatabaseVersion(found, expected) => {
+                StoreError::UnsupportedDatabaseVersion(found, expected)
+            }
+            Error::State(e) => e,
+            _ => StoreError::backend(e),
+        }
+    }
+}
+
+#[cfg(feature = "crypto-store")]
+impl From<Error> for CryptoStoreError {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::Json(e) => CryptoStoreError::Serialization(e),
+            Error::Identifier(e) => CryptoStoreError::IdentifierValidation(e),
+            Error::UnsupportedDatabaseVersion(found, expected) => {
+                CryptoStoreError::UnsupportedDatabaseVersion(found, expected)
+            }
+            Error::Crypto(e) => e,
+            _ => CryptoStoreError::backend(e),
+        }
+    }
+}
@@ -0,0 +1,91 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#[cfg(any(feature = "state-store", feature = "crypto-store"))]
+use matrix_sdk_base::store::StoreConfig;
+#[cfg(feature = "state-store")]
+use matrix_sdk_base::store::StoreError;
+#[cfg(feature = "crypto-store")]
+use matrix_sdk_crypto::store::CryptoStoreError;
+use thiserror::Error;
+
+#[cfg(feature = "crypto-store")]
+mod crypto_store;
+mod error;
+#[cfg(feature = "state-store")]
+mod state_store;
+mod utils;
+
+#[cfg(feature = "crypto-store")]
+pub use crypto_store::SqliteCryptoStore;
+#[cfg(feature = "state-store")]
+pub use state_store::SqliteStateStore;
+
+/// All the errors that can occur when opening a SQLite store.
+#[derive(Error, Debug)]
+#[non_exhaustive]
+pub enum OpenStoreError {
+    /// An error occurred with the state store implementation.
+    #[cfg(feature = "state-store")]
+    #[error(transparent)]
+    State(#[from] StoreError),
+
+    /// An error occurred with the crypto store implementation.
+    #[cfg(feature = "crypto-store")]
+    #[error(transparent)]
+    Crypto(#[from] CryptoStoreError),
+
+    /// An error occurred with SQLite.
+    #[error(transparent)]
+    Sqlite(#[from] rusqlite::Error),
+
+    /// The directory of the store couldn't be created.
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+/// Create a [`StoreConfig`] with an opened [`SqliteStateStore`] that uses the
+/// given path and passphrase.
+///
+/// If the `e2e-encryption` Cargo feature is enabled, a [`SqliteCryptoStore`]
+/// with the same parameters is also opened. Both stores use their own database
+/// file inside the given directory.
+///
+/// [`StoreConfig`]: #StoreConfig
+#[cfg(any(feature = "state-store", feature = "crypto-store"))]
+pub fn make_store_config(
+    path: impl AsRef<std::path::Path>,
+    passphrase: Option<&str>,
+) -> Result<StoreConfig, OpenStoreError> {
+    let path = path.as_ref();
+
+    #[cfg(all(feature = "crypto-store", feature = "state-store"))]
+    {
+        let state_store = SqliteStateStore::open_with_passphrase(path, passphrase)?;
+        let crypto_store = SqliteCryptoStore::open_with_passphrase(path, passphrase)?;
+        Ok(StoreConfig::new().state_store(state_store).crypto_store(crypto_store))
+    }
+
+    #[cfg(all(feature = "crypto-store", not(feature = "state-store")))]
+    {
+        let crypto_store = SqliteCryptoStore::open_with_passphrase(path, passphrase)?;
+        Ok(StoreConfig::new().crypto_store(crypto_store))
+    }
+
+    #[cfg(not(feature = "crypto-store"))]
+    {
+        let state_store = SqliteStateStore::open_with_passphrase(path, passphrase)?;
+        Ok(StoreConfig::new().state_store(state_store))
+    }
+}
@@ -0,0 +1,1499 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard, RwLock},
+    time::Instant,
+};
+
+use async_trait::async_trait;
+#[cfg(feature = "experimental-timeline")]
+use futures_util::stream;
+use matrix_sdk_base::{
+    deserialized_responses::MemberEvent,
+    media::{MediaCacheEntry, MediaCacheUsage, MediaRequest, MediaRetentionPolicy, UniqueKey},
+    store::{Result as StoreResult, StateChanges, StateStore, StoreError},
+    MinimalStateEvent, RoomInfo,
+};
+#[cfg(feature = "experimental-timeline")]
+use matrix_sdk_base::{
+    deserialized_responses::{SyncTimelineEvent, ThreadSummary},
+    store::BoxStream,
+};
+use matrix_sdk_store_encryption::StoreCipher;
+use ruma::{
+    canonical_json::redact,
+    events::{
+        presence::PresenceEvent,
+        receipt::{Receipt, ReceiptType},
+        room::member::{MembershipState, RoomMemberEventContent},
+        AnyGlobalAccountDataEvent, AnyRoomAccountDataEvent, AnySyncStateEvent,
+        GlobalAccountDataEventType, RoomAccountDataEventType, StateEventType,
+    },
+    serde::Raw,
+    CanonicalJsonObject, EventId, MilliSecondsSinceUnixEpoch, MxcUri, OwnedEventId, OwnedUserId,
+    RoomId, RoomVersionId, UInt, UserId,
+};
+#[cfg(feature = "experimental-timeline")]
+use ruma::{
+    canonical_json::redact_in_place,
+    events::{
+        room::redaction::SyncRoomRedactionEvent, AnySyncMessageLikeEvent, AnySyncTimelineEvent,
+    },
+};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Deserialize;
+#[cfg(feature = "experimental-timeline")]
+use serde::Serialize;
+#[cfg(feature = "experimental-timeline")]
+use tracing::info;
+use tracing::{debug, warn};
+
+use crate::{
+    error::Result,
+    utils::{self, ConnectionExt, EncryptableStore},
+    OpenStoreError,
+};
+
+const DATABASE_NAME: &str = "matrix-sdk-state.sqlite3";
+
+/// The migrations of the state store schema, see [`utils::run_migrations`].
+const MIGRATIONS: &[&str] = &[
+    include_str!("../migrations/state_store/001_init.sql"),
+    include_str!("../migrations/state_store/002_media_retention.sql"),
+];
+
+// Table names, they are also used to derive a separate hash key for every
+// table, so the same ID doesn't end up as the same byte sequence in different
+// tables.
+const KV: &str = "kv";
+const CUSTOM: &str = "custom";
+const ACCOUNT_DATA: &str = "account_data";
+const PRESENCE: &str = "presence";
+const ROOM_INFO: &str = "room_info";
+const STATE_EVENT: &str = "state_event";
+const MEMBER: &str = "member";
+const PROFILE: &str = "profile";
+const DISPLAY_NAME: &str = "display_name";
+const ROOM_ACCOUNT_DATA: &str = "room_account_data";
+const RECEIPT: &str = "receipt";
+const MEDIA: &str = "media";
+const TIMELINE_METADATA: &str = "timeline_metadata";
+const TIMELINE: &str = "timeline";
+
+/// All the tables that contain data about a single room, every one of them has
+/// a `room_id` column.
+const ROOM_TABLES: &[&str] = &[
+    ROOM_INFO,
+    STATE_EVENT,
+    MEMBER,
+    PROFILE,
+    DISPLAY_NAME,
+    ROOM_ACCOUNT_DATA,
+    RECEIPT,
+    TIMELINE_METADATA,
+    TIMELINE,
+];
+
+const SYNC_TOKEN: &str = "sync_token";
+
+/// The encoded URI and format of a media file, the key of the media table.
+type MediaKey = (Vec<u8>, Vec<u8>);
+
+/// The part of a member event we need to list the members of a room.
+#[derive(Deserialize)]
+struct MemberStateKey {
+    state_key: OwnedUserId,
+}
+
+/// Convert a timestamp to the value stored in an `INTEGER` column.
+fn millis_to_sql(ts: MilliSecondsSinceUnixEpoch) -> i64 {
+    u64::from(ts.0) as i64
+}
+
+/// Convert the value stored in an `INTEGER` column to a timestamp.
+fn millis_from_sql(value: i64) -> MilliSecondsSinceUnixEpoch {
+    MilliSecondsSinceUnixEpoch(UInt::new_saturating(value.max(0) as u64))
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg(feature = "experimental-timeline")]
+struct TimelineMetadata {
+    pub start: String,
+    pub start_position: i64,
+    pub end: Option<String>,
+    pub end_position: i64,
+}
+
+/// A [SQLite] based state store.
+///
+/// [SQLite]: https://www.sqlite.org/
+#[derive(Clone)]
+pub struct SqliteStateStore {
+    conn: Arc<Mutex<Connection>>,
+    store_cipher: Option<Arc<StoreCipher>>,
+    path: Option<PathBuf>,
+    media_retention_policy: Arc<RwLock<MediaRetentionPolicy>>,
+}
+
+impl std::fmt::Debug for SqliteStateStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(path) = &self.path {
+            f.debug_struct("SqliteStateStore").field("path", &path).finish()
+        } else {
+            f.debug_struct("SqliteStateStore").field("path", &"memory store").finish()
+        }
+    }
+}
+
+impl EncryptableStore for SqliteStateStore {
+    fn store_cipher(&self) -> Option<&StoreCipher> {
+        self.store_cipher.as_deref()
+    }
+}
+
+impl SqliteStateStore {
+    /// Open a state store that lives in memory, all the data is lost once the
+    /// store is dropped.
+    pub fn open() -> Result<Self, OpenStoreError> {
+        Self::open_helper(Connection::open_in_memory()?, None, None)
+    }
+
+    /// Open the SQLite-based state store in the given directory.
+    ///
+    /// The directory and the database are created if they don't exist yet.
+    pub fn open_with_path(path: impl AsRef<Path>) -> Result<Self, OpenStoreError> {
+        Self::open_with_passphrase(path, None)
+    }
+
+    /// Open the SQLite-based state store in the given directory, using the
+    /// given passphrase to encrypt private data.
+    ///
+    /// The directory and the database are created if they don't exist yet.
+    pub fn open_with_passphrase(
+        path: impl AsRef<Path>,
+        passphrase: Option<&str>,
+    ) -> Result<Self, OpenStoreError> {
+        let path = path.as_ref();
+        std::fs::create_dir_all(path)?;
+        let conn = Connection::open(path.join(DATABASE_NAME))?;
+
+        Self::open_helper(conn, Some(path.to_owned()), passphrase)
+    }
+
+    fn open_helper(
+        mut conn: Connection,
+        path: Option<PathBuf>,
+        passphrase: Option<&str>,
+    ) -> Result<Self, OpenStoreError> {
+        conn.pragma_update_and_check(None, "journal_mode", "wal", |_| Ok(()))?;
+
+        utils::run_migrations(&mut conn, MIGRATIONS).map_err(StoreError::from)?;
+
+        let store_cipher = passphrase
+            .map(|p| utils::get_or_create_store_cipher(p, &conn))
+            .transpose()
+            .map_err(StoreError::from)?
+            .map(Arc::new);
+
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+            store_cipher,
+            path,
+            media_retention_policy: Default::default(),
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Connection> {
+        self.conn.lock().unwrap()
+    }
+
+    fn get_kv(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        Ok(self
+            .lock()
+            .query_value("SELECT value FROM kv WHERE key = ?", [self.encode_key(KV, key)])?)
+    }
+
+    fn set_kv(conn: &Connection, key: Vec<u8>, value: Vec<u8>) -> Result<()> {
+        conn.execute("INSERT OR REPLACE INTO kv (key, value) VALUES (?, ?)", params![key, value])?;
+        Ok(())
+    }
+
+    fn get_room_version(&self, conn: &Connection, room_id: &RoomId) -> RoomVersionId {
+        conn.query_value(
+            "SELECT data FROM room_info WHERE room_id = ? AND stripped = FALSE",
+            [self.encode_key(ROOM_INFO, room_id.as_str())],
+        )
+        .ok()
+        .flatten()
+        .and_then(|r| self.deserialize_value::<RoomInfo>(&r).ok())
+        .and_then(|info| info.room_version().cloned())
+        .unwrap_or_else(|| {
+            warn!(%room_id, "Unable to find the room version, assume version 9");
+            RoomVersionId::V9
+        })
+    }
+
+    async fn save_filter(&self, filter_name: &str, filter_id: &str) -> Result<()> {
+        let key = self.encode_key(KV, format!("filter:{filter_name}"));
+        Self::set_kv(&self.lock(), key, self.serialize_value(&filter_id)?)
+    }
+
+    async fn get_filter(&self, filter_name: &str) -> Result<Option<String>> {
+        self.get_kv(&format!("filter:{filter_name}"))?
+            .map(|f| self.deserialize_value(&f))
+            .transpose()
+    }
+
+    async fn get_sync_token(&self) -> Result<Option<String>> {
+        self.get_kv(SYNC_TOKEN)?.map(|t| self.deserialize_value(&t)).transpose()
+    }
+
+    async fn save_changes(&self, changes: &StateChanges) -> Result<()> {
+        let now = Instant::now();
+
+        {
+            let mut conn = self.lock();
+            let txn = conn.transaction()?;
+            self.save_changes_inner(&txn, changes)?;
+            txn.commit()?;
+        }
+
+        debug!("Saved changes in {:?}", now.elapsed());
+
+        Ok(())
+    }
+
+    fn save_changes_inner(&self, txn: &Connection, changes: &StateChanges) -> Result<()> {
+        if let Some(s) = &changes.sync_token {
+            Self::set_kv(txn, self.encode_key(KV, SYNC_TOKEN), self.serialize_value(s)?)?;
+        }
+
+        for (event_type, event) in &changes.account_data {
+            txn.execute(
+                "INSERT OR REPLACE INTO account_data (event_type, data) VALUES (?, ?)",
+                params![
+                    self.encode_key(ACCOUNT_DATA, event_type.to_string()),
+                    self.serialize_value(&event)?
+                ],
+            )?;
+        }
+
+        for (user_id, event) in &changes.presence {
+            txn.execute(
+                "INSERT OR REPLACE INTO presence (user_id, data) VALUES (?, ?)",
+                params![self.encode_key(PRESENCE, user_id.as_str()), self.serialize_value(&event)?],
+            )?;
+        }
+
+        for (room_id, events) in &changes.members {
+            let profile_changes = changes.profiles.get(room_id);
+
+            for event in events.values() {
+                let user_id = event.state_key();
+                let room_key = self.encode_key(MEMBER, room_id.as_str());
+                let user_key = self.encode_key(MEMBER, user_id.as_str());
+
+                txn.execute(
+                    "DELETE FROM member WHERE room_id = ? AND user_id = ? AND stripped = TRUE",
+                    params![room_key, user_key],
+                )?;
+                txn.execute(
+                    "INSERT OR REPLACE INTO member (room_id, user_id, membership, stripped, data)
+                     VALUES (?, ?, ?, FALSE, ?)",
+                    params![
+                        room_key,
+                        user_key,
+                        self.encode_key(MEMBER, event.membership().as_str()),
+                        self.serialize_value(&event)?
+                    ],
+                )?;
+
+                if let Some(profile) = profile_changes.and_then(|p| p.get(user_id)) {
+                    txn.execute(
+                        "INSERT OR REPLACE INTO profile (room_id, user_id, data) VALUES (?, ?, ?)",
+                        params![
+                            self.encode_key(PROFILE, room_id.as_str()),
+                            self.encode_key(PROFILE, user_id.as_str()),
+                            self.serialize_value(&profile)?
+                        ],
+                    )?;
+                }
+            }
+        }
+
+        for (room_id, ambiguity_maps) in &changes.ambiguity_maps {
+            for (display_name, map) in ambiguity_maps {
+                txn.execute(
+                    "INSERT OR REPLACE INTO display_name (room_id, name, data) VALUES (?, ?, ?)",
+                    params![
+                        self.encode_key(DISPLAY_NAME, room_id.as_str()),
+                        self.encode_key(DISPLAY_NAME, display_name),
+                        self.serialize_value(&map)?
+                    ],
+                )?;
+            }
+        }
+
+        for (room_id, events) in &changes.room_account_data {
+            for (event_type, event) in events {
+                txn.execute(
+                    "INSERT OR REPLACE INTO room_account_data (room_id, event_type, data)
+                     VALUES (?, ?, ?)",
+                    params![
+                        self.encode_key(ROOM_ACCOUNT_DATA, room_id.as_str()),
+                        self.encode_key(ROOM_ACCOUNT_DATA, event_type.to_string()),
+                        self.serialize_value(&event)?
+                    ],
+                )?;
+            }
+        }
+
+        for (room_id, event_types) in &changes.state {
+            for (event_type, events) in event_types {
+                for (state_key, event) in events {
+                    let key = (
+                        self.encode_key(STATE_EVENT, room_id.as_str()),
+                        self.encode_key(STATE_EVENT, event_type.to_string()),
+                        self.encode_key(STATE_EVENT, state_key),
+                    );
+
+                    txn.execute(
+                        "INSERT OR REPLACE INTO state_event
+                         (room_id, event_type, state_key, stripped, data)
+                         VALUES (?, ?, ?, FALSE, ?)",
+                        params![key.0, key.1, key.2, self.serialize_value(&event)?],
+                    )?;
+                    txn.execute(
+                        "DELETE FROM state_event
+                         WHERE room_id = ? AND event_type = ? AND state_key = ? AND stripped = TRUE",
+                        params![key.0, key.1, key.2],
+                    )?;
+                }
+            }
+        }
+
+        for (room_id, room_info) in &changes.room_infos {
+            self.save_room_info(txn, room_id, room_info, false)?;
+        }
+
+        for (room_id, room_info) in &changes.stripped_room_infos {
+            self.save_room_info(txn, room_id, room_info, true)?;
+        }
+
+        for (room_id, events) in &changes.stripped_members {
+            for event in events.values() {
+                txn.execute(
+                    "INSERT OR REPLACE INTO member (room_id, user_id, membership, stripped, data)
+                     VALUES (?, ?, ?, TRUE, ?)",
+                    params![
+                        self.encode_key(MEMBER, room_id.as_str()),
+                        self.encode_key(MEMBER, event.state_key.as_str()),
+                        self.encode_key(MEMBER, event.content.membership.as_str()),
+                        self.serialize_value(&event)?
+                    ],
+                )?;
+            }
+        }
+
+        for (room_id, event_types) in &changes.stripped_state {
+            for (event_type, events) in event_types {
+                for (state_key, event) in events {
+                    txn.execute(
+                        "INSERT OR REPLACE INTO state_event
+                         (room_id, event_type, state_key, stripped, data)
+                         VALUES (?, ?, ?, TRUE, ?)",
+                        params![
+                            self.encode_key(STATE_EVENT, room_id.as_str()),
+                            self.encode_key(STATE_EVENT, event_type.to_string()),
+                            self.encode_key(STATE_EVENT, state_key),
+                            self.serialize_value(&event)?
+                        ],
+                    )?;
+                }
+            }
+        }
+
+        for (room_id, redactions) in &changes.redactions {
+            let mut room_version = None;
+
+            // Go through all the saved state events of the room and check
+            // whether they are among the redacted ones.
+            let mut statement = txn.prepare_cached(
+                "SELECT rowid, data FROM state_event WHERE room_id = ? AND stripped = FALSE",
+            )?;
+            let events: Vec<(i64, Vec<u8>)> = statement
+                .query_map([self.encode_key(STATE_EVENT, room_id.as_str())], |row| {
+                    Ok((row.get(0)?, row.get(1)?))
+                })?
+                .collect::<rusqlite::Result<_>>()?;
+
+            for (rowid, data) in events {
+                let raw_event = self.deserialize_value::<Raw<AnySyncStateEvent>>(&data)?;
+
+                if let Ok(Some(event_id)) = raw_event.get_field::<OwnedEventId>("event_id") {
+                    if redactions.contains_key(&event_id) {
+                        let redacted = redact(
+                            &raw_event.deserialize_as::<CanonicalJsonObject>()?,
+                            room_version.get_or_insert_with(|| self.get_room_version(txn, room_id)),
+                        )
+                        .map_err(StoreError::Redaction)?;
+
+                        txn.execute(
+                            "UPDATE state_event SET data = ? WHERE rowid = ?",
+                            params![self.serialize_value(&redacted)?, rowid],
+                        )?;
+                    }
+                }
+            }
+        }
+
+        for (room_id, content) in &changes.receipts {
+            for (event_id, receipts) in &content.0 {
+                for (receipt_type, receipts) in receipts {
+                    for (user_id, receipt) in receipts {
+                        // Replacing the row moves the receipt of the user
+                        // away from the event it was previously attached to.
+                        txn.execute(
+                            "INSERT OR REPLACE INTO receipt
+                             (room_id, receipt_type, user_id, event_id, data)
+                             VALUES (?, ?, ?, ?, ?)",
+                            params![
+                                self.encode_key(RECEIPT, room_id.as_str()),
+                                self.encode_key(RECEIPT, receipt_type.as_str()),
+                                self.encode_key(RECEIPT, user_id.as_str()),
+                                self.encode_key(RECEIPT, event_id.as_str()),
+                                self.serialize_value(&(event_id, user_id, receipt))?
+                            ],
+                        )?;
+                    }
+                }
+            }
+        }
+
+        #[cfg(feature = "experimental-timeline")]
+        self.save_room_timeline(txn, changes)?;
+
+        Ok(())
+    }
+
+    fn save_room_info(
+        &self,
+        txn: &Connection,
+        room_id: &RoomId,
+        room_info: &RoomInfo,
+        stripped: bool,
+    ) -> Result<()> {
+        let room_key = self.encode_key(ROOM_INFO, room_id.as_str());
+
+        txn.execute(
+            "INSERT OR REPLACE INTO room_info (room_id, stripped, data) VALUES (?, ?, ?)",
+            params![room_key, stripped, self.serialize_value(room_info)?],
+        )?;
+        txn.execute(
+            "DELETE FROM room_info WHERE room_id = ? AND stripped = ?",
+            params![room_key, !stripped],
+        )?;
+
+        Ok(())
+    }
+
+    async fn get_presence_event(&self, user_id: &UserId) -> Result<Option<Raw<PresenceEvent>>> {
+        self.lock()
+            .query_value(
+                "SELECT data FROM presence WHERE user_id = ?",
+                [self.encode_key(PRESENCE, user_id.as_str())],
+            )?
+            .map(|e| self.deserialize_value(&e))
+            .transpose()
+    }
+
+    async fn get_state_event(
+        &self,
+        room_id: &RoomId,
+        event_type: StateEventType,
+        state_key: &str,
+    ) -> Result<Option<Raw<AnySyncStateEvent>>> {
+        self.lock()
+            .query_value(
+                "SELECT data FROM state_event
+                 WHERE room_id = ? AND event_type = ? AND state_key = ? AND stripped = FALSE",
+                [
+                    self.encode_key(STATE_EVENT, room_id.as_str()),
+                    self.encode_key(STATE_EVENT, event_type.to_string()),
+                    self.encode_key(STATE_EVENT, state_key),
+                ],
+            )?
+            .map(|e| self.deserialize_value(&e))
+            .transpose()
+    }
+
+    async fn get_state_events(
+        &self,
+        room_id: &RoomId,
+        event_type: StateEventType,
+    ) -> Result<Vec<Raw<AnySyncStateEvent>>> {
+        self.lock()
+            .query_values(
+                "SELECT data FROM state_event
+                 WHERE room_id = ? AND event_type = ? AND stripped = FALSE",
+                [
+                    self.encode_key(STATE_EVENT, room_id.as_str()),
+                    self.encode_key(STATE_EVENT, event_type.to_string()),
+                ],
+            )?
+            .iter()
+            .map(|e| self.deserialize_value(e))
+            .collect()
+    }
+
+    async fn get_profile(
+        &self,
+        room_id: &RoomId,
+        user_id: &UserId,
+    ) -> Result<Option<MinimalStateEvent<RoomMemberEventContent>>> {
+        self.lock()
+            .query_value(
+                "SELECT data FROM profile WHERE room_id = ? AND user_id = ?",
+                [
+                    self.encode_key(PROFILE, room_id.as_str()),
+                    self.encode_key(PROFILE, user_id.as_str()),
+                ],
+            )?
+            .map(|p| self.deserialize_value(&p))
+            .transpose()
+    }
+
+    async fn get_member_event(
+        &self,
+        room_id: &RoomId,
+        state_key: &UserId,
+    ) -> Result<Option<MemberEvent>> {
+        let row: Option<(bool, Vec<u8>)> = self
+            .lock()
+            .query_row(
+                "SELECT stripped, data FROM member WHERE room_id = ? AND user_id = ?
+                 ORDER BY stripped DESC LIMIT 1",
+                [
+                    self.encode_key(MEMBER, room_id.as_str()),
+                    self.encode_key(MEMBER, state_key.as_str()),
+                ],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+
+        Ok(match row {
+            Some((true, data)) => Some(MemberEvent::Stripped(self.deserialize_value(&data)?)),
+            Some((false, data)) => Some(MemberEvent::Sync(self.deserialize_value(&data)?)),
+            None => None,
+        })
+    }
+
+    /// Get the IDs of the members of the given room that have one of the given
+    /// memberships.
+    fn get_user_ids_with_memberships(
+        &self,
+        room_id: &RoomId,
+        memberships: &[MembershipState],
+        stripped: bool,
+    ) -> Result<Vec<OwnedUserId>> {
+        let room_key = self.encode_key(MEMBER, room_id.as_str());
+        let mut user_ids = Vec::new();
+
+        for membership in memberships {
+            let values = self.lock().query_values(
+                "SELECT data FROM member WHERE room_id = ? AND membership = ? AND stripped = ?",
+                params![room_key, self.encode_key(MEMBER, membership.as_str()), stripped],
+            )?;
+
+            for value in values {
+                user_ids.push(self.deserialize_value::<MemberStateKey>(&value)?.state_key);
+            }
+        }
+
+        Ok(user_ids)
+    }
+
+    /// Get the user IDs of the room from the stripped members if there are
+    /// any, falling back to the regular members otherwise.
+    fn get_user_ids_inner(
+        &self,
+        room_id: &RoomId,
+        memberships: &[MembershipState],
+    ) -> Result<Vec<OwnedUserId>> {
+        let user_ids = self.get_user_ids_with_memberships(room_id, memberships, true)?;
+
+        if !user_ids.is_empty() {
+            return Ok(user_ids);
+        }
+
+        self.get_user_ids_with_memberships(room_id, memberships, false)
+    }
+
+    fn get_room_infos_inner(&self, stripped: bool) -> Result<Vec<RoomInfo>> {
+        self.lock()
+            .query_values("SELECT data FROM room_info WHERE stripped = ?", [stripped])?
+            .iter()
+            .map(|r| self.deserialize_value(r))
+            .collect()
+    }
+
+    async fn get_users_with_display_name(
+        &self,
+        room_id: &RoomId,
+        display_name: &str,
+    ) -> Result<BTreeSet<OwnedUserId>> {
+        Ok(self
+            .lock()
+            .query_value(
+                "SELECT data FROM display_name WHERE room_id = ? AND name = ?",
+                [
+                    self.encode_key(DISPLAY_NAME, room_id.as_str()),
+                    self.encode_key(DISPLAY_NAME, display_name),
+                ],
+            )?
+            .map(|m| self.deserialize_value(&m))
+            .transpose()?
+            .unwrap_or_default())
+    }
+
+    async fn get_account_data_event(
+        &self,
+        event_type: GlobalAccountDataEventType,
+    ) -> Result<Option<Raw<AnyGlobalAccountDataEvent>>> {
+        self.lock()
+            .query_value(
+                "SELECT data FROM account_data WHERE event_type = ?",
+                [self.encode_key(ACCOUNT_DATA, event_type.to_string())],
+            )?
+            .map(|m| self.deserialize_value(&m))
+            .transpose()
+    }
+
+    async fn get_room_account_data_event(
+        &self,
+        room_id: &RoomId,
+        event_type: RoomAccountDataEventType,
+    ) -> Result<Option<Raw<AnyRoomAccountDataEvent>>> {
+        self.lock()
+            .query_value(
+                "SELECT data FROM room_account_data WHERE room_id = ? AND event_type = ?",
+                [
+                    self.encode_key(ROOM_ACCOUNT_DATA, room_id.as_str()),
+                    self.encode_key(ROOM_ACCOUNT_DATA, event_type.to_string()),
+                ],
+            )?
+            .map(|m| self.deserialize_value(&m))
+            .transpose()
+    }
+
+    async fn get_user_room_receipt_event(
+        &self,
+        room_id: &RoomId,
+        receipt_type: ReceiptType,
+        user_id: &UserId,
+    ) -> Result<Option<(OwnedEventId, Receipt)>> {
+        let value = self.lock().query_value(
+            "SELECT data FROM receipt WHERE room_id = ? AND receipt_type = ? AND user_id = ?",
+            [
+                self.encode_key(RECEIPT, room_id.as_str()),
+                self.encode_key(RECEIPT, receipt_type.as_str()),
+                self.encode_key(RECEIPT, user_id.as_str()),
+            ],
+        )?;
+
+        Ok(value
+            .map(|r| self.deserialize_value::<(OwnedEventId, OwnedUserId, Receipt)>(&r))
+            .transpose()?
+            .map(|(event_id, _, receipt)| (event_id, receipt)))
+    }
+
+    async fn get_event_room_receipt_events(
+        &self,
+        room_id: &RoomId,
+        receipt_type: ReceiptType,
+        event_id: &EventId,
+    ) -> Result<Vec<(OwnedUserId, Receipt)>> {
+        self.lock()
+            .query_values(
+                "SELECT data FROM receipt WHERE room_id = ? AND receipt_type = ? AND event_id = ?",
+                [
+                    self.encode_key(RECEIPT, room_id.as_str()),
+                    self.encode_key(RECEIPT, receipt_type.as_str()),
+                    self.encode_key(RECEIPT, event_id.as_str()),
+                ],
+            )?
+            .iter()
+            .map(|r| {
+                let (_, user_id, receipt) =
+                    self.deserialize_value::<(OwnedEventId, OwnedUserId, Receipt)>(r)?;
+                Ok((user_id, receipt))
+            })
+            .collect()
+    }
+
+    async fn add_media_content(&self, request: &MediaRequest, data: Vec<u8>) -> Result<()> {
+        let policy = self.media_retention_policy();
+        let entry = MediaCacheEntry::new(data.len() as u64);
+
+        if policy.exceeds_max_file_size(entry.size) {
+            return Ok(());
+        }
+
+        let mut conn = self.lock();
+        let txn = conn.transaction()?;
+
+        txn.execute(
+            "INSERT OR REPLACE INTO media (uri, format, data, size, last_access)
+             VALUES (?, ?, ?, ?, ?)",
+            params![
+                self.encode_key(MEDIA, request.source.unique_key()),
+                self.encode_key(MEDIA, request.format.unique_key()),
+                self.serialize_value(&data)?,
+                entry.size as i64,
+                millis_to_sql(entry.last_access),
+            ],
+        )?;
+        self.clean_up_media(&txn, policy)?;
+
+        txn.commit()?;
+
+        Ok(())
+    }
+
+    async fn get_media_content(&self, request: &MediaRequest) -> Result<Option<Vec<u8>>> {
+        let uri = self.encode_key(MEDIA, request.source.unique_key());
+        let format = self.encode_key(MEDIA, request.format.unique_key());
+        let policy = self.media_retention_policy();
+        let now = MilliSecondsSinceUnixEpoch::now();
+
+        let mut conn = self.lock();
+        let txn = conn.transaction()?;
+
+        let row: Option<(Vec<u8>, Option<i64>)> = txn
+            .query_row(
+                "SELECT data, last_access FROM media WHERE uri = ? AND format = ?",
+                [&uri, &format],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+
+        let data = match row {
+            Some((_, Some(last_access)))
+                if policy.has_expired(now, millis_from_sql(last_access)) =>
+            {
+                txn.execute("DELETE FROM media WHERE uri = ? AND format = ?", [&uri, &format])?;
+                None
+            }
+            Some((data, _)) => {
+                txn.execute(
+                    "UPDATE media SET last_access = ? WHERE uri = ? AND format = ?",
+                    params![millis_to_sql(now), uri, format],
+                )?;
+                Some(data)
+            }
+            None => None,
+        };
+
+        txn.commit()?;
+
+        data.map(|data| self.deserialize_value(&data)).transpose()
+    }
+
+    async fn peek_media_content(&self, request: &MediaRequest) -> Result<Option<Vec<u8>>> {
+        let uri = self.encode_key(MEDIA, request.source.unique_key());
+        let format = self.encode_key(MEDIA, request.format.unique_key());
+
+        let data: Option<Vec<u8>> = self
+            .lock()
+            .query_row(
+                "SELECT data FROM media WHERE uri = ? AND format = ?",
+                [&uri, &format],
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        data.map(|data| self.deserialize_value(&data)).transpose()
+    }
+
+    async fn get_custom_value(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.lock()
+            .query_value("SELECT value FROM custom WHERE key = ?", [self.encode_key(CUSTOM, key)])?
+            .map(|v| self.deserialize_value(&v))
+            .transpose()
+    }
+
+    async fn set_custom_value(&self, key: &[u8], value: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        let key = self.encode_key(CUSTOM, key);
+        let value = self.serialize_value(&value)?;
+
+        let previous = {
+            let mut conn = self.lock();
+            let txn = conn.transaction()?;
+            let previous = txn.query_value("SELECT value FROM custom WHERE key = ?", [&key])?;
+            txn.execute(
+                "INSERT OR REPLACE INTO custom (key, value) VALUES (?, ?)",
+                params![key, value],
+            )?;
+            txn.commit()?;
+            previous
+        };
+
+        previous.map(|v| self.deserialize_value(&v)).transpose()
+    }
+
+    async fn remove_media_content(&self, request: &MediaRequest) -> Result<()> {
+        self.lock().execute(
+            "DELETE FROM media WHERE uri = ? AND format = ?",
+            [
+                self.encode_key(MEDIA, request.source.unique_key()),
+                self.encode_key(MEDIA, request.format.unique_key()),
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    async fn remove_media_content_for_uri(&self, uri: &MxcUri) -> Result<()> {
+        self.lock()
+            .execute("DELETE FROM media WHERE uri = ?", [self.encode_key(MEDIA, uri.as_str())])?;
+
+        Ok(())
+    }
+
+    fn media_retention_policy(&self) -> MediaRetentionPolicy {
+        *self.media_retention_policy.read().unwrap()
+    }
+
+    /// Get the metadata of all the media files in the store.
+    fn media_entries(&self, conn: &Connection) -> Result<Vec<(MediaKey, MediaCacheEntry)>> {
+        let rows = conn
+            .prepare("SELECT uri, format, size, last_access FROM media")?
+            .query_map([], |row| {
+                Ok((
+                    (row.get::<_, Vec<u8>>(0)?, row.get::<_, Vec<u8>>(1)?),
+                    row.get::<_, Option<i64>>(2)?,
+                    row.get::<_, Option<i64>>(3)?,
+                ))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        rows.into_iter()
+            .map(|((uri, format), size, last_access)| {
+                let entry = match (size, last_access) {
+                    (Some(size), Some(last_access)) => MediaCacheEntry {
+                        size: size as u64,
+                        last_access: millis_from_sql(last_access),
+                    },
+                    // The file was stored before its metadata was tracked.
+                    _ => {
+                        let data = conn
+                            .query_value(
+                                "SELECT data FROM media WHERE uri = ? AND format = ?",
+                                [&uri, &format],
+                            )?
+                            .map(|data| self.deserialize_value::<Vec<u8>>(&data))
+                            .transpose()?;
+                        let entry = MediaCacheEntry::new(data.map_or(0, |data| data.len() as u64));
+
+                        conn.execute(
+                            "UPDATE media SET size = ?, last_access = ?
+                             WHERE uri = ? AND format = ?",
+                            params![
+                                entry.size as i64,
+                                millis_to_sql(entry.last_access),
+                                uri,
+                                format
+                            ],
+                        )?;
+
+                        entry
+                    }
+                };
+
+                Ok(((uri, format), entry))
+            })
+            .collect()
+    }
+
+    /// Remove the media files that don't comply with the given policy.
+    fn clean_up_media(&self, conn: &Connection, policy: MediaRetentionPolicy) -> Result<()> {
+        let evicted =
+            policy.entries_to_evict(self.media_entries(conn)?, MilliSecondsSinceUnixEpoch::now());
+
+        for (uri, format) in evicted {
+            conn.execute("DELETE FROM media WHERE uri = ? AND format = ?", [uri, format])?;
+        }
+
+        Ok(())
+    }
+
+    async fn set_media_retention_policy(&self, policy: MediaRetentionPolicy) -> Result<()> {
+        *self.media_retention_policy.write().unwrap() = policy;
+        self.clean_up_media_cache().await
+    }
+
+    async fn clean_up_media_cache(&self) -> Result<()> {
+        let policy = self.media_retention_policy();
+
+        let mut conn = self.lock();
+        let txn = conn.transaction()?;
+        self.clean_up_media(&txn, policy)?;
+        txn.commit()?;
+
+        Ok(())
+    }
+
+    async fn media_cache_usage(&self) -> Result<MediaCacheUsage> {
+        let mut conn = self.lock();
+        let txn = conn.transaction()?;
+        let entries = self.media_entries(&txn)?;
+        txn.commit()?;
+
+        Ok(entries.into_iter().map(|(_, entry)| entry).collect())
+    }
+
+    async fn clear_media_cache(&self) -> Result<()> {
+        self.lock().execute("DELETE FROM media", [])?;
+
+        Ok(())
+    }
+
+    async fn remove_room(&self, room_id: &RoomId) -> Result<()> {
+        let mut conn = self.lock();
+        let txn = conn.transaction()?;
+
+        for table in ROOM_TABLES {
+            txn.execute(
+                &format!("DELETE FROM {table} WHERE room_id = ?"),
+                [self.encode_key(table, room_id.as_str())],
+            )?;
+        }
+
+        txn.commit()?;
+
+        Ok(())
+    }
+
+    #[cfg(feature = "experimental-timeline")]
+    async fn room_timeline(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Option<(BoxStream<StoreResult<SyncTimelineEvent>>, Option<String>)>> {
+        let (metadata, events) = {
+            let conn = self.lock();
+
+            let metadata = match self.get_timeline_metadata(&conn, room_id)? {
+                Some(m) => m,
+                None => {
+                    info!(%room_id, "Couldn't find a previously stored timeline");
+                    return Ok(None);
+                }
+            };
+
+            let events = conn.query_values(
+                "SELECT data FROM timeline WHERE room_id = ? ORDER BY position ASC",
+                [self.encode_key(TIMELINE, room_id.as_str())],
+            )?;
+
+            (metadata, events)
+        };
+
+        let end_token = metadata.end;
+
+        info!(%room_id, ?end_token, "Found previously stored timeline");
+
+        let store = self.clone();
+        let stream = stream::iter(events.into_iter().map(move |e| {
+            store.deserialize_value::<SyncTimelineEvent>(&e).map_err(StoreError::from)
+        }));
+
+        Ok(Some((Box::pin(stream), end_token)))
+    }
+
+    #[cfg(feature = "experimental-timeline")]
+    fn get_timeline_metadata(
+        &self,
+        conn: &Connection,
+        room_id: &RoomId,
+    ) -> Result<Option<TimelineMetadata>> {
+        conn.query_value(
+            "SELECT data FROM timeline_metadata WHERE room_id = ?",
+            [self.encode_key(TIMELINE_METADATA, room_id.as_str())],
+        )?
+        .map(|m| self.deserialize_value(&m))
+        .transpose()
+    }
+
+    #[cfg(feature = "experimental-timeline")]
+    fn remove_room_timeline(&self, txn: &Connection, room_id: &RoomId) -> Result<()> {
+        info!(%room_id, "Removing stored timeline");
+
+        txn.execute(
+            "DELETE FROM timeline_metadata WHERE room_id = ?",
+            [self.encode_key(TIMELINE_METADATA, room_id.as_str())],
+        )?;
+        txn.execute(
+            "DELETE FROM timeline WHERE room_id = ?",
+            [self.encode_key(TIMELINE, room_id.as_str())],
+        )?;
+
+        Ok(())
+    }
+
+    #[cfg(feature = "experimental-timeline")]
+    fn save_timeline_event(
+        &self,
+        txn: &Connection,
+        room_key: &[u8],
+        position: i64,
+        event: &SyncTimelineEvent,
+    ) -> Result<()> {
+        // Only events with an ID can be found by their ID later on.
+        let event_key = event.event_id().map(|id| self.encode_key(TIMELINE, id.as_str()));
+
+        txn.execute(
+            "INSERT OR REPLACE INTO timeline (room_id, position, event_id, data)
+             VALUES (?, ?, ?, ?)",
+            params![room_key, position, event_key, self.serialize_value(event)?],
+        )?;
+
+        Ok(())
+    }
+
+    #[cfg(feature = "experimental-timeline")]
+    fn redact_timeline_event(
+        &self,
+        txn: &Connection,
+        room_key: &[u8],
+        event_id: &EventId,
+        room_version: &RoomVersionId,
+    ) -> Result<()> {
+        let row: Option<(i64, Vec<u8>)> = txn
+            .query_row(
+                "SELECT position, data FROM timeline WHERE room_id = ? AND event_id = ?",
+                params![room_key, self.encode_key(TIMELINE, event_id.as_str())],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+
+        if let Some((position, data)) = row {
+            let mut full_event: SyncTimelineEvent = self.deserialize_value(&data)?;
+            let mut event_json: CanonicalJsonObject = full_event.event.deserialize_as()?;
+            redact_in_place(&mut event_json, room_version).map_err(StoreError::Redaction)?;
+            full_event.event = Raw::new(&event_json)?.cast();
+
+            txn.execute(
+                "UPDATE timeline SET data = ? WHERE room_id = ? AND position = ?",
+                params![self.serialize_value(&full_event)?, room_key, position],
+            )?;
+        }
+
+        Ok(())
+    }
+
+    #[cfg(feature = "experimental-timeline")]
+    fn add_thread_replies(
+        &self,
+        txn: &Connection,
+        room_key: &[u8],
+        thread_root: &EventId,
+        replies: &ThreadSummary,
+    ) -> Result<()> {
+        let row: Option<(i64, Vec<u8>)> = txn
+            .query_row(
+                "SELECT position, data FROM timeline WHERE room_id = ? AND event_id = ?",
+                params![room_key, self.encode_key(TIMELINE, thread_root.as_str())],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+
+        if let Some((position, data)) = row {
+            let mut root: SyncTimelineEvent = self.deserialize_value(&data)?;
+            root.add_thread_replies(replies)?;
+
+            txn.execute(
+                "UPDATE timeline SET data = ? WHERE room_id = ? AND position = ?",
+                params![self.serialize_value(&root)?, room_key, position],
+            )?;
+        }
+
+        Ok(())
+    }
+
+    #[cfg(feature = "experimental-timeline")]
+    fn save_room_timeline(&self, txn: &Connection, changes: &StateChanges) -> Result<()> {
+        for (room_id, timeline) in &changes.timeline {
+            if timeline.sync {
+                info!(%room_id, "Saving new timeline batch from sync response");
+            } else {
+                info!(%room_id, "Saving new timeline batch from messages response");
+            }
+
+            let room_key = self.encode_key(TIMELINE, room_id.as_str());
+
+            let metadata = if timeline.limited {
+                info!(%room_id, "Deleting stored timeline because the sync response was limited");
+                self.remove_room_timeline(txn, room_id)?;
+                None
+            } else if let Some(mut metadata) = self.get_timeline_metadata(txn, room_id)? {
+                if !timeline.sync && Some(&timeline.start) != metadata.end.as_ref() {
+                    // This should only happen when a developer adds a wrong timeline
+                    // batch to the `StateChanges` or the server returns a wrong response
+                    // to our request.
+                    warn!(%room_id, "Dropping unexpected timeline batch");
+                    continue;
+                }
+
+                // Check if the event already exists in the store
+                let mut delete_timeline = false;
+                for event_id in timeline.events.iter().filter_map(|e| e.event_id()) {
+                    let exists: bool = txn.query_row(
+                        "SELECT EXISTS(SELECT 1 FROM timeline WHERE room_id = ? AND event_id = ?)",
+                        params![room_key, self.encode_key(TIMELINE, event_id.as_str())],
+                        |row| row.get(0),
+                    )?;
+
+                    if exists {
+                        delete_timeline = true;
+                        break;
+                    }
+                }
+
+                if delete_timeline {
+                    info!(%room_id, "Deleting stored timeline because of duplicated events");
+                    self.remove_room_timeline(txn, room_id)?;
+                    None
+                } else if timeline.sync {
+                    metadata.start = timeline.start.clone();
+                    Some(metadata)
+                } else {
+                    metadata.end = timeline.end.clone();
+                    Some(metadata)
+                }
+            } else {
+                None
+            };
+
+            let mut metadata = metadata.unwrap_or_else(|| TimelineMetadata {
+                start: timeline.start.clone(),
+                end: timeline.end.clone(),
+                start_position: 0,
+                end_position: -1,
+            });
+
+            if timeline.sync {
+                // Update the summaries of the thread roots already in store
+                if let Some(thread_replies) = changes.thread_replies.get(room_id) {
+                    for (thread_root, replies) in thread_replies {
+                        self.add_thread_replies(txn, &room_key, thread_root, replies)?;
+                    }
+                }
+
+                let room_version = self.get_room_version(txn, room_id);
+
+                for event in &timeline.events {
+                    // Redact events already in store only on sync response
+                    if let Ok(AnySyncTimelineEvent::MessageLike(
+                        AnySyncMessageLikeEvent::RoomRedaction(SyncRoomRedactionEvent::Original(
+                            redaction,
+                        )),
+                    )) = event.event.deserialize()
+                    {
+                        self.redact_timeline_event(
+                            txn,
+                            &room_key,
+                            &redaction.redacts,
+                            &room_version,
+                        )?;
+                    }
+
+                    metadata.start_position -= 1;
+                    self.save_timeline_event(txn, &room_key, metadata.start_position, event)?;
+                }
+            } else {
+                for event in &timeline.events {
+                    metadata.end_position += 1;
+                    self.save_timeline_event(txn, &room_key, metadata.end_position, event)?;
+                }
+            }
+
+            txn.execute(
+                "INSERT OR REPLACE INTO timeline_metadata (room_id, data) VALUES (?, ?)",
+                params![
+                    self.encode_key(TIMELINE_METADATA, room_id.as_str()),
+                    self.serialize_value(&metadata)?
+                ],
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl StateStore for SqliteStateStore {
+    async fn save_filter(&self, filter_name: &str, filter_id: &str) -> StoreResult<()> {
+        self.save_filter(filter_name, filter_id).await.map_err(Into::into)
+    }
+
+    async fn save_changes(&self, changes: &StateChanges) -> StoreResult<()> {
+        self.save_changes(changes).await.map_err(Into::into)
+    }
+
+    async fn get_filter(&self, filter_id: &str) -> StoreResult<Option<String>> {
+        self.get_filter(filter_id).await.map_err(Into::into)
+    }
+
+    async fn get_sync_token(&self) -> StoreResult<Option<String>> {
+        self.get_sync_token().await.map_err(Into::into)
+    }
+
+    async fn get_presence_event(
+        &self,
+        user_id: &UserId,
+    ) -> StoreResult<Option<Raw<PresenceEvent>>> {
+        self.get_presence_event(user_id).await.map_err(Into::into)
+    }
+
+    async fn get_state_event(
+        &self,
+        room_id: &RoomId,
+        event_type: StateEventType,
+        state_key: &str,
+    ) -> StoreResult<Option<Raw<AnySyncStateEvent>>> {
+        self.get_state_event(room_id, event_type, state_key).await.map_err(Into::into)
+    }
+
+    async fn get_state_events(
+        &self,
+        room_id: &RoomId,
+        event_type: StateEventType,
+    ) -> StoreResult<Vec<Raw<AnySyncStateEvent>>> {
+        self.get_state_events(room_id, event_type).await.map_err(Into::into)
+    }
+
+    async fn get_profile(
+        &self,
+        room_id: &RoomId,
+        user_id: &UserId,
+    ) -> StoreResult<Option<MinimalStateEvent<RoomMemberEventContent>>> {
+        self.get_profile(room_id, user_id).await.map_err(Into::into)
+    }
+
+    async fn get_member_event(
+        &self,
+        room_id: &RoomId,
+        state_key: &UserId,
+    ) -> StoreResult<Option<MemberEvent>> {
+        self.get_member_event(room_id, state_key).await.map_err(Into::into)
+    }
+
+    async fn get_user_ids(&self, room_id: &RoomId) -> StoreResult<Vec<OwnedUserId>> {
+        self.get_user_ids_inner(room_id, &[MembershipState::Join, MembershipState::Invite])
+            .map_err(Into::into)
+    }
+
+    async fn get_invited_user_ids(&self, room_id: &RoomId) -> StoreResult<Vec<OwnedUserId>> {
+        self.get_user_ids_inner(room_id, &[MembershipState::Invite]).map_err(Into::into)
+    }
+
+    async fn get_joined_user_ids(&self, room_id: &RoomId) -> StoreResult<Vec<OwnedUserId>> {
+        self.get_user_ids_inner(room_id, &[MembershipState::Join]).map_err(Into::into)
+    }
+
+    async fn get_room_infos(&self) -> StoreResult<Vec<RoomInfo>> {
+        self.get_room_infos_inner(false).map_err(Into::into)
+    }
+
+    async fn get_stripped_room_infos(&self) -> StoreResult<Vec<RoomInfo>> {
+        self.get_room_infos_inner(true).map_err(Into::into)
+    }
+
+    async fn get_users_with_display_name(
+        &self,
+        room_id: &RoomId,
+        display_name: &str,
+    ) -> StoreResult<BTreeSet<OwnedUserId>> {
+        self.get_users_with_display_name(room_id, display_name).await.map_err(Into::into)
+    }
+
+    async fn get_account_data_event(
+        &self,
+        event_type: GlobalAccountDataEventType,
+    ) -> StoreResult<Option<Raw<AnyGlobalAccountDataEvent>>> {
+        self.get_account_data_event(event_type).await.map_err(Into::into)
+    }
+
+    async fn get_room_account_data_event(
+        &self,
+        room_id: &RoomId,
+        event_type: RoomAccountDataEventType,
+    ) -> StoreResult<Option<Raw<AnyRoomAccountDataEvent>>> {
+        self.get_room_account_data_event(room_id, event_type).await.map_err(Into::into)
+    }
+
+    async fn get_user_room_receipt_event(
+        &self,
+        room_id: &RoomId,
+        receipt_type: ReceiptType,
+        user_id: &UserId,
+    ) -> StoreResult<Option<(OwnedEventId, Receipt)>> {
+        self.get_user_room_receipt_event(room_id, receipt_type, user_id).await.map_err(Into::into)
+    }
+
+    async fn get_event_room_receipt_events(
+        &self,
+        room_id: &RoomId,
+        receipt_type: ReceiptType,
+        event_id: &EventId,
+    ) -> StoreResult<Vec<(OwnedUserId, Receipt)>> {
+        self.get_event_room_receipt_events(room_id, receipt_type, event_id)
+            .await
+            .map_err(Into::into)
+    }
+
+    async fn get_custom_value(&self, key: &[u8]) -> StoreResult<Option<Vec<u8>>> {
+        self.get_custom_value(key).await.map_err(Into::into)
+    }
+
+    async fn set_custom_value(&self, key: &[u8], value: Vec<u8>) -> StoreResult<Option<Vec<u8>>> {
+        self.set_custom_value(key, value).await.map_err(Into::into)
+    }
+
+    async fn add_media_content(&self, request: &MediaRequest, data: Vec<u8>) -> StoreResult<()> {
+        self.add_media_content(request, data).await.map_err(Into::into)
+    }
+
+    async fn get_media_content(&self, request: &MediaRequest) -> StoreResult<Option<Vec<u8>>> {
+        self.get_media_content(request).await.map_err(Into::into)
+    }
+
+    async fn peek_media_content(&self, request: &MediaRequest) -> StoreResult<Option<Vec<u8>>> {
+        self.peek_media_content(request).await.map_err(Into::into)
+    }
+
+    async fn remove_media_content(&self, request: &MediaRequest) -> StoreResult<()> {
+        self.remove_media_content(request).await.map_err(Into::into)
+    }
+
+    async fn remove_media_content_for_uri(&self, uri: &MxcUri) -> StoreResult<()> {
+        self.remove_media_content_for_uri(uri).await.map_err(Into::into)
+    }
+
+    async fn set_media_retention_policy(&self, policy: MediaRetentionPolicy) -> StoreResult<()> {
+        self.set_media_retention_policy(policy).await.map_err(Into::into)
+    }
+
+    async fn clean_up_media_cache(&self) -> StoreResult<()> {
+        self.clean_up_media_cache().await.map_err(Into::into)
+    }
+
+    async fn media_cache_usage(&self) -> StoreResult<MediaCacheUsage> {
+        self.media_cache_usage().await.map_err(Into::into)
+    }
+
+    async fn clear_media_cache(&self) -> StoreResult<()> {
+        self.clear_media_cache().await.map_err(Into::into)
+    }
+
+    async fn remove_room(&self, room_id: &RoomId) -> StoreResult<()> {
+        self.remove_room(room_id).await.map_err(Into::into)
+    }
+
+    #[cfg(feature = "experimental-timeline")]
+    async fn room_timeline(
+        &self,
+        room_id: &RoomId,
+    ) -> StoreResult<Option<(BoxStream<StoreResult<SyncTimelineEvent>>, Option<String>)>> {
+        self.room_timeline(room_id).await.map_err(Into::into)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use matrix_sdk_base::statestore_integration_tests;
+
+    use super::{SqliteStateStore, StateStore, StoreResult};
+
+    async fn get_store() -> StoreResult<impl StateStore> {
+        Ok(SqliteStateStore::open().unwrap())
+    }
+
+    statestore_integration_tests!();
+}
+
+#[cfg(test)]
+mod encrypted_tests {
+    use matrix_sdk_base::statestore_integration_tests;
+    use once_cell::sync::Lazy;
+    use tempfile::{tempdir, TempDir};
+
+    use super::{SqliteStateStore, StateStore, StoreResult};
+
+    static TMP_DIR: Lazy<TempDir> = Lazy::new(|| tempdir().unwrap());
+
+    async fn get_store() -> StoreResult<impl StateStore> {
+        // Every test gets its own database.
+        let path = tempfile::Builder::new().tempdir_in(TMP_DIR.path()).unwrap().into_path();
+
+        Ok(SqliteStateStore::open_with_passphrase(path, Some("secret")).unwrap())
+    }
+
+    statestore_integration_tests!();
+}
+
+#[cfg(test)]
+mod migration {
+    use matrix_sdk_test::async_test;
+    use rusqlite::Connection;
+    use tempfile::TempDir;
+
+    use super::{SqliteStateStore, DATABASE_NAME, MIGRATIONS};
+    use crate::OpenStoreError;
+
+    #[async_test]
+    async fn reopening_keeps_the_schema_version() {
+        let folder = TempDir::new().unwrap();
+
+        let store = SqliteStateStore::open_with_path(folder.path()).unwrap();
+        store.save_filter("filter", "filter_id").await.unwrap();
+        drop(store);
+
+        let store = SqliteStateStore::open_with_path(folder.path()).unwrap();
+        assert_eq!(store.get_filter("filter").await.unwrap().as_deref(), Some("filter_id"));
+
+        let conn = Connection::open(folder.path().join(DATABASE_NAME)).unwrap();
+        let version: u32 = conn.pragma_query_value(None, "user_version", |r| r.get(0)).unwrap();
+        assert_eq!(version as usize, MIGRATIONS.len());
+    }
+
+    #[async_test]
+    async fn opening_a_newer_database_fails() {
+        let folder = TempDir::new().unwrap();
+
+        drop(SqliteStateStore::open_with_path(folder.path()).unwrap());
+
+        let conn = Connection::open(folder.path().join(DATABASE_NAME)).unwrap();
+        conn.pragma_update(None, "user_version", MIGRATIONS.len() as u32 + 1).unwrap();
+        drop(conn);
+
+        assert!(matches!(
+            SqliteStateStore::open_with_path(folder.path()),
+            Err(OpenStoreError::State(_))
+        ));
+    }
+}
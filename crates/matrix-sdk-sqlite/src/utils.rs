@@ -0,0 +1,133 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use matrix_sdk_store_encryption::StoreCipher;
+use rusqlite::{params, Connection, OptionalExtension, Params};
+use serde::{de::DeserializeOwned, Serialize};
+use tracing::debug;
+
+use crate::error::{Error, Result};
+
+/// The key under which the exported store cipher lives in the `kv` table.
+///
+/// This key is never hashed, we need to read it before we have a cipher.
+const STORE_CIPHER_KEY: &[u8] = b"store_cipher";
+
+/// Bring the schema of the given database up to date.
+///
+/// The schema version is tracked with SQLite's `user_version` pragma, the
+/// migration at index `n` upgrades the database from version `n` to `n + 1`.
+/// Every migration is applied in its own transaction.
+pub(crate) fn run_migrations(conn: &mut Connection, migrations: &[&str]) -> Result<()> {
+    let version: u32 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    let version = version as usize;
+
+    if version > migrations.len() {
+        return Err(Error::UnsupportedDatabaseVersion(version, migrations.len()));
+    }
+
+    for (from, migration) in migrations.iter().enumerate().skip(version) {
+        debug!(from, to = from + 1, "Migrating the SQLite store");
+
+        let txn = conn.transaction()?;
+        txn.execute_batch(migration)?;
+        txn.pragma_update(None, "user_version", (from + 1) as u32)?;
+        txn.commit()?;
+    }
+
+    Ok(())
+}
+
+/// Load the store cipher from the `kv` table, or create and persist a new one
+/// if the database doesn't contain one yet.
+pub(crate) fn get_or_create_store_cipher(
+    passphrase: &str,
+    conn: &Connection,
+) -> Result<StoreCipher> {
+    let encrypted: Option<Vec<u8>> = conn
+        .query_row("SELECT value FROM kv WHERE key = ?", [STORE_CIPHER_KEY], |row| row.get(0))
+        .optional()?;
+
+    let cipher = if let Some(encrypted) = encrypted {
+        StoreCipher::import(passphrase, &encrypted)?
+    } else {
+        let cipher = StoreCipher::new()?;
+        #[cfg(not(test))]
+        let export = cipher.export(passphrase)?;
+        #[cfg(test)]
+        let export = cipher._insecure_export_fast_for_testing(passphrase)?;
+        conn.execute(
+            "INSERT INTO kv (key, value) VALUES (?, ?)",
+            params![STORE_CIPHER_KEY, export],
+        )?;
+        cipher
+    };
+
+    Ok(cipher)
+}
+
+/// Helpers to encode keys and values for a store that may be protected by a
+/// [`StoreCipher`].
+pub(crate) trait EncryptableStore {
+    fn store_cipher(&self) -> Option<&StoreCipher>;
+
+    /// Encode a key for the given table, the key is hashed if the store is
+    /// encrypted.
+    fn encode_key(&self, table_name: &str, key: impl AsRef<[u8]>) -> Vec<u8> {
+        let key = key.as_ref();
+
+        if let Some(store_cipher) = self.store_cipher() {
+            store_cipher.hash_key(table_name, key).to_vec()
+        } else {
+            key.to_owned()
+        }
+    }
+
+    fn serialize_value(&self, value: &impl Serialize) -> Result<Vec<u8>> {
+        if let Some(store_cipher) = self.store_cipher() {
+            Ok(store_cipher.encrypt_value(value)?)
+        } else {
+            Ok(serde_json::to_vec(value)?)
+        }
+    }
+
+    fn deserialize_value<T: DeserializeOwned>(&self, value: &[u8]) -> Result<T> {
+        if let Some(store_cipher) = self.store_cipher() {
+            Ok(store_cipher.decrypt_value(value)?)
+        } else {
+            Ok(serde_json::from_slice(value)?)
+        }
+    }
+}
+
+/// Shorthands for queries that select a single blob column.
+pub(crate) trait ConnectionExt {
+    /// Get the value of the first row returned by the query, if any.
+    fn query_value(&self, sql: &str, params: impl Params) -> rusqlite::Result<Option<Vec<u8>>>;
+
+    /// Get the values of all the rows returned by the query.
+    fn query_values(&self, sql: &str, params: impl Params) -> rusqlite::Result<Vec<Vec<u8>>>;
+}
+
+impl ConnectionExt for Connection {
+    fn query_value(&self, sql: &str, params: impl Params) -> rusqlite::Result<Option<Vec<u8>>> {
+        self.query_row(sql, params, |row| row.get(0)).optional()
+    }
+
+    fn query_values(&self, sql: &str, params: impl Params) -> rusqlite::Result<Vec<Vec<u8>>> {
+        let mut statement = self.prepare_cached(sql)?;
+        let values = statement.query_map(params, |row| row.get(0))?.collect();
+        values
+    }
+}
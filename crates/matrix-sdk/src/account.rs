@@ -26,6 +26,7 @@ use ruma::{
             request_3pid_management_token_via_email, request_3pid_management_token_via_msisdn,
         },
         config::set_global_account_data,
+        presence::set_presence,
         profile::{
             get_avatar_url, get_display_name, get_profile, set_avatar_url, set_display_name,
         },
@@ -33,12 +34,14 @@ use ruma::{
     },
     assign,
     events::{
-        room::MediaSource, AnyGlobalAccountDataEventContent, GlobalAccountDataEventContent,
+        ignored_user_list::IgnoredUserListEventContent, room::MediaSource,
+        AnyGlobalAccountDataEventContent, GlobalAccountDataEventContent,
         GlobalAccountDataEventType, StaticEventContent,
     },
+    presence::PresenceState,
     serde::Raw,
     thirdparty::Medium,
-    ClientSecret, MxcUri, OwnedMxcUri, SessionId, UInt,
+    ClientSecret, MxcUri, OwnedMxcUri, OwnedUserId, SessionId, UInt, UserId,
 };
 use serde::Deserialize;
 
@@ -106,6 +109,46 @@ impl Account {
         Ok(())
     }
 
+    /// Set the presence of the account.
+    ///
+    /// Note that the presence is also updated by the homeserver when the
+    /// client syncs, according to [`SyncSettings::set_presence()`].
+    ///
+    /// # Arguments
+    ///
+    /// * `presence` - The new presence state.
+    ///
+    /// * `status_msg` - The status message to attach to the presence.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use futures::executor::block_on;
+    /// # use matrix_sdk::Client;
+    /// # use url::Url;
+    /// # block_on(async {
+    /// # let homeserver = Url::parse("http://example.com")?;
+    /// use matrix_sdk::ruma::presence::PresenceState;
+    ///
+    /// let user = "example";
+    /// let client = Client::new(homeserver).await?;
+    /// client.login(user, "password", None, None).await?;
+    ///
+    /// client.account().set_presence(PresenceState::Unavailable, Some("Out for lunch")).await?;
+    /// # anyhow::Ok(()) });
+    /// ```
+    ///
+    /// [`SyncSettings::set_presence()`]: crate::config::SyncSettings::set_presence
+    pub async fn set_presence(
+        &self,
+        presence: PresenceState,
+        status_msg: Option<&str>,
+    ) -> Result<()> {
+        let user_id = self.client.user_id().ok_or(Error::AuthenticationRequired)?;
+        let request = assign!(set_presence::v3::Request::new(user_id, presence), { status_msg });
+        self.client.send(request, None).await?;
+        Ok(())
+    }
+
     /// Get the MXC URI of the account's avatar, if set.
     ///
     /// # Example
@@ -715,6 +758,65 @@ impl Account {
 
         Ok(self.client.send(request, None).await?)
     }
+
+    /// Get the users ignored by this account, read from the
+    /// `m.ignored_user_list` account data event in the store.
+    ///
+    /// Events from these users are not included in the sync responses.
+    pub async fn ignored_users(&self) -> Result<Vec<OwnedUserId>> {
+        Ok(self.ignored_user_list().await?.ignored_users)
+    }
+
+    /// Ignore the given user.
+    ///
+    /// Events from this user will not be included anymore in the sync
+    /// responses. Does nothing if the user is already ignored.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use matrix_sdk::Client;
+    /// # async {
+    /// # let client = Client::new("http://localhost:8080".parse()?).await?;
+    /// use matrix_sdk::ruma::user_id;
+    ///
+    /// client.account().ignore_user(user_id!("@spammer:example.org")).await?;
+    /// # anyhow::Ok(()) };
+    /// ```
+    pub async fn ignore_user(&self, user_id: &UserId) -> Result<()> {
+        let mut content = self.ignored_user_list().await?;
+
+        if !content.ignored_users.iter().any(|ignored| *ignored == *user_id) {
+            content.ignored_users.push(user_id.to_owned());
+            self.set_account_data(content).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Stop ignoring the given user.
+    ///
+    /// Does nothing if the user isn't ignored.
+    pub async fn unignore_user(&self, user_id: &UserId) -> Result<()> {
+        let mut content = self.ignored_user_list().await?;
+        let count = content.ignored_users.len();
+        content.ignored_users.retain(|ignored| *ignored != *user_id);
+
+        if content.ignored_users.len() != count {
+            self.set_account_data(content).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn ignored_user_list(&self) -> Result<IgnoredUserListEventContent> {
+        Ok(self
+            .account_data::<IgnoredUserListEventContent>()
+            .await?
+            .map(|content| content.deserialize())
+            .transpose()?
+            .unwrap_or_default())
+    }
 }
 
 fn get_raw_content<Ev, C>(raw: Option<Raw<Ev>>) -> Result<Option<Raw<C>>> {
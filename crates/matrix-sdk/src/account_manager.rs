@@ -0,0 +1,330 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Management of several accounts logged in on the same device.
+
+use std::{
+    collections::BTreeMap,
+    fmt, io,
+    path::{Path, PathBuf},
+    sync::{Arc, Weak},
+};
+
+use matrix_sdk_base::{locks::Mutex, StoreError};
+use ruma::{OwnedUserId, TransactionId, UserId};
+use thiserror::Error;
+use tracing::{instrument, warn};
+
+use crate::{
+    client::ClientInner,
+    session_persistence::{FileSessionPersister, SessionPersister},
+    Client, ClientBuildError, ClientBuilder, SessionState,
+};
+
+const ACCOUNTS_DIR: &str = "accounts";
+const ACTIVE_ACCOUNT_FILE: &str = "active_account";
+const SESSION_FILE: &str = "session.json";
+const STORE_DIR: &str = "store";
+
+/// Errors that can happen when using an [`AccountManager`].
+#[derive(Debug, Error)]
+pub enum AccountManagerError {
+    /// No account with the given user ID was found.
+    #[error("no account found for {0}")]
+    UnknownAccount(OwnedUserId),
+
+    /// Error reading or writing the files of the accounts.
+    #[error(transparent)]
+    Io(#[from] io::Error),
+
+    /// Error loading a persisted session.
+    #[error(transparent)]
+    SessionPersistence(#[from] StoreError),
+
+    /// Error building the `Client` of an account.
+    #[error(transparent)]
+    Build(#[from] ClientBuildError),
+}
+
+/// Manager for several accounts, each with its own [`Client`].
+///
+/// Every account lives in its own directory under the base path of the
+/// manager, containing the session of the account, persisted with a
+/// [`FileSessionPersister`], and the state and crypto stores of its `Client`.
+/// The user ID of the active account is remembered across restarts.
+///
+/// A `Client` is only built once per account, subsequent calls return a clone
+/// of the same `Client`.
+///
+/// # Example
+///
+/// ```no_run
+/// # use futures::executor::block_on;
+/// use matrix_sdk::{AccountManager, Client};
+///
+/// # block_on(async {
+/// let manager = AccountManager::new(
+///     "/home/example/.local/share/my-app",
+///     Client::builder().handle_refresh_tokens(),
+/// );
+///
+/// // Log in with a new account.
+/// let client = manager.add_account("https://example.com").await?;
+/// client.login_username("user", "wordpass").request_refresh_token().send().await?;
+///
+/// // The account is persisted and can be used later.
+/// for user_id in manager.accounts().await? {
+///     println!("{user_id}");
+/// }
+///
+/// let user_id = client.user_id().unwrap().to_owned();
+/// let client = manager.switch_account(&user_id).await?;
+/// # anyhow::Ok(()) });
+/// ```
+pub struct AccountManager {
+    base_path: PathBuf,
+    client_builder: ClientBuilder,
+    passphrase: Option<String>,
+    /// The clients that were built, by account directory.
+    clients: Mutex<BTreeMap<PathBuf, Client>>,
+    /// The clients of the added accounts that were not logged in yet, by
+    /// account directory.
+    new_clients: Mutex<BTreeMap<PathBuf, Weak<ClientInner>>>,
+}
+
+impl fmt::Debug for AccountManager {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AccountManager").field("base_path", &self.base_path).finish_non_exhaustive()
+    }
+}
+
+impl AccountManager {
+    /// Create a new `AccountManager` storing the accounts under the given
+    /// directory.
+    ///
+    /// The `Client` of every account is built with `client_builder`, which
+    /// shouldn't have a store or a session persister set since they are
+    /// configured by the manager.
+    pub fn new(base_path: impl Into<PathBuf>, client_builder: ClientBuilder) -> Self {
+        Self {
+            base_path: base_path.into(),
+            client_builder,
+            passphrase: None,
+            clients: Default::default(),
+            new_clients: Default::default(),
+        }
+    }
+
+    /// Set the passphrase used to encrypt the stores of the accounts.
+    #[must_use]
+    pub fn passphrase(mut self, passphrase: impl Into<String>) -> Self {
+        self.passphrase = Some(passphrase.into());
+        self
+    }
+
+    /// Get the user IDs of the accounts with a persisted session.
+    pub async fn accounts(&self) -> Result<Vec<OwnedUserId>, AccountManagerError> {
+        Ok(self.account_dirs().await?.into_keys().collect())
+    }
+
+    /// Create a `Client` for a new account on the given homeserver.
+    ///
+    /// The account is only added to the [accounts](Self::accounts) of the
+    /// manager once the returned `Client` is logged in, for example with
+    /// [`Client::login_username()`] or [`Client::register_username()`]. If the
+    /// `Client` is dropped before that, the local data of the account is
+    /// deleted the next time the accounts are listed.
+    #[instrument(skip(self, homeserver_url), fields(homeserver = homeserver_url.as_ref()))]
+    pub async fn add_account(
+        &self,
+        homeserver_url: impl AsRef<str>,
+    ) -> Result<Client, AccountManagerError> {
+        let dir = self.base_path.join(ACCOUNTS_DIR).join(TransactionId::new().as_str());
+
+        let builder = self.client_builder.clone().homeserver_url(homeserver_url);
+        let client = self.build_client(builder, &dir).await?;
+        self.new_clients.lock().await.insert(dir, Arc::downgrade(&client.inner));
+
+        Ok(client)
+    }
+
+    /// Get the `Client` of the account with the given user ID.
+    ///
+    /// The persisted session of the account is restored if the `Client` was
+    /// not built yet.
+    #[instrument(skip(self))]
+    pub async fn open_account(&self, user_id: &UserId) -> Result<Client, AccountManagerError> {
+        let dir = self.account_dir(user_id).await?;
+
+        let mut clients = self.clients.lock().await;
+        if let Some(client) = clients.get(&dir) {
+            return Ok(client.clone());
+        }
+
+        let new_client = self.new_clients.lock().await.remove(&dir).and_then(|w| w.upgrade());
+        let client = match new_client {
+            Some(inner) => Client { inner },
+            None => self.build_client(self.client_builder.clone(), &dir).await?,
+        };
+        clients.insert(dir, client.clone());
+
+        Ok(client)
+    }
+
+    /// Make the account with the given user ID the active one, and get its
+    /// `Client`.
+    pub async fn switch_account(&self, user_id: &UserId) -> Result<Client, AccountManagerError> {
+        let client = self.open_account(user_id).await?;
+        tokio::fs::create_dir_all(&self.base_path).await?;
+        tokio::fs::write(self.base_path.join(ACTIVE_ACCOUNT_FILE), user_id.as_str()).await?;
+
+        Ok(client)
+    }
+
+    /// Get the `Client` of the active account.
+    ///
+    /// Returns `None` if no account was [switched to](Self::switch_account),
+    /// or if the active account was removed or logged out.
+    pub async fn active_account(&self) -> Result<Option<Client>, AccountManagerError> {
+        let user_id =
+            match tokio::fs::read_to_string(self.base_path.join(ACTIVE_ACCOUNT_FILE)).await {
+                Ok(user_id) => user_id,
+                Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+                Err(error) => return Err(error.into()),
+            };
+        let user_id = UserId::parse(user_id.trim()).map_err(StoreError::from)?;
+
+        match self.open_account(&user_id).await {
+            Ok(client) => Ok(Some(client)),
+            Err(AccountManagerError::UnknownAccount(_)) => Ok(None),
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Remove the account with the given user ID.
+    ///
+    /// The session is logged out on the homeserver, and all the local data of
+    /// the account is deleted. The local data is deleted even if logging out
+    /// fails.
+    ///
+    /// The `Client` of the account must not be used anymore after this call.
+    #[instrument(skip(self))]
+    pub async fn remove_account(&self, user_id: &UserId) -> Result<(), AccountManagerError> {
+        let dir = self.account_dir(user_id).await?;
+        let client = self.open_account(user_id).await?;
+
+        if client.logged_in() {
+            if let Err(error) = client.logout().await {
+                warn!("Failed to log out before removing the account: {error}");
+            }
+        }
+
+        // The persisted session is removed by the client in the background,
+        // it must be done before the directory is removed, or the session
+        // could be written again in a new directory.
+        client.set_session_state(SessionState::LoggedOut);
+        client.wait_for_session_persistence().await;
+
+        self.clients.lock().await.remove(&dir);
+        drop(client);
+        tokio::fs::remove_dir_all(&dir).await?;
+
+        let active_path = self.base_path.join(ACTIVE_ACCOUNT_FILE);
+        if let Ok(active) = tokio::fs::read_to_string(&active_path).await {
+            if active.trim() == user_id.as_str() {
+                tokio::fs::remove_file(&active_path).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Get the directory of the account with the given user ID.
+    async fn account_dir(&self, user_id: &UserId) -> Result<PathBuf, AccountManagerError> {
+        self.account_dirs()
+            .await?
+            .remove(user_id)
+            .ok_or_else(|| AccountManagerError::UnknownAccount(user_id.to_owned()))
+    }
+
+    /// Get the directories of the accounts with a persisted session, by user
+    /// ID.
+    ///
+    /// The directories without a persisted session whose `Client` was dropped
+    /// are removed.
+    async fn account_dirs(&self) -> Result<BTreeMap<OwnedUserId, PathBuf>, AccountManagerError> {
+        let mut dirs = BTreeMap::new();
+
+        let mut entries = match tokio::fs::read_dir(self.base_path.join(ACCOUNTS_DIR)).await {
+            Ok(entries) => entries,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(dirs),
+            Err(error) => return Err(error.into()),
+        };
+
+        while let Some(entry) = entries.next_entry().await? {
+            let dir = entry.path();
+            if !entry.file_type().await?.is_dir() {
+                continue;
+            }
+
+            let persister = FileSessionPersister::new(dir.join(SESSION_FILE));
+            if let Some(persisted) = persister.load().await? {
+                dirs.insert(persisted.session.user_id, dir);
+            } else if !self.is_in_use(&dir).await {
+                if let Err(error) = tokio::fs::remove_dir_all(&dir).await {
+                    warn!("Failed to remove the directory of an unused account: {error}");
+                }
+            }
+        }
+
+        Ok(dirs)
+    }
+
+    /// Whether a `Client` using the given account directory is still alive.
+    async fn is_in_use(&self, dir: &Path) -> bool {
+        if self.clients.lock().await.contains_key(dir) {
+            return true;
+        }
+
+        let mut new_clients = self.new_clients.lock().await;
+        let is_alive = new_clients.get(dir).map_or(false, |client| client.strong_count() > 0);
+        if !is_alive {
+            new_clients.remove(dir);
+        }
+
+        is_alive
+    }
+
+    async fn build_client(
+        &self,
+        builder: ClientBuilder,
+        dir: &Path,
+    ) -> Result<Client, AccountManagerError> {
+        let store_path = dir.join(STORE_DIR);
+        let passphrase = self.passphrase.as_deref();
+
+        #[cfg(feature = "sled")]
+        let builder = builder.sled_store(store_path, passphrase).map_err(ClientBuildError::from)?;
+        #[cfg(all(feature = "sqlite", not(feature = "sled")))]
+        let builder =
+            builder.sqlite_store(store_path, passphrase).map_err(ClientBuildError::from)?;
+
+        let client = builder
+            .session_persister(FileSessionPersister::new(dir.join(SESSION_FILE)))
+            .build()
+            .await?;
+
+        Ok(client)
+    }
+}
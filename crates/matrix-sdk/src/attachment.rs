@@ -16,10 +16,14 @@
 
 #[cfg(feature = "image-proc")]
 use std::io::{BufRead, Cursor, Seek};
-use std::time::Duration;
+use std::{fmt, sync::Arc, time::Duration};
 
 #[cfg(feature = "image-proc")]
-use image::GenericImageView;
+use image::{
+    codecs::gif::GifDecoder, AnimationDecoder, DynamicImage, GenericImageView, ImageFormat,
+};
+use matrix_sdk_base::{SendOutsideWasm, SyncOutsideWasm};
+use mime::Mime;
 use ruma::{
     assign,
     events::room::{
@@ -31,6 +35,10 @@ use ruma::{
 
 #[cfg(feature = "image-proc")]
 use crate::ImageError;
+use crate::ThumbnailError;
+
+/// The default size of generated thumbnails, as a `(width, height)` tuple.
+const DEFAULT_THUMBNAIL_SIZE: (u32, u32) = (800, 600);
 
 /// Base metadata about an image.
 #[derive(Debug, Clone)]
@@ -173,16 +181,108 @@ pub struct Thumbnail<'a> {
     pub info: Option<BaseThumbnailInfo>,
 }
 
+/// A thumbnail generated by a [`Thumbnailer`].
+#[derive(Debug, Clone)]
+pub struct GeneratedThumbnail {
+    /// The raw bytes of the thumbnail.
+    pub data: Vec<u8>,
+    /// The type of the thumbnail.
+    pub content_type: Mime,
+    /// The metadata of the thumbnail.
+    pub info: BaseThumbnailInfo,
+    /// The [BlurHash](https://blurha.sh/) of the media, if it was computed.
+    pub blurhash: Option<String>,
+}
+
+/// A type that generates thumbnails for attachments.
+///
+/// Implement this trait to generate thumbnails for media that the SDK doesn't
+/// support, e.g. to extract a frame of a video, and use it with
+/// [`AttachmentConfig::thumbnailer()`].
+pub trait Thumbnailer: fmt::Debug + SendOutsideWasm + SyncOutsideWasm {
+    /// Generate a thumbnail for the given media.
+    ///
+    /// Returns `Ok(None)` if this thumbnailer doesn't support the media.
+    ///
+    /// # Arguments
+    ///
+    /// * `content_type` - The type of the media.
+    ///
+    /// * `data` - The raw bytes of the media.
+    ///
+    /// * `size` - The maximum size of the thumbnail in pixels as a
+    /// `(width, height)` tuple.
+    fn generate_thumbnail(
+        &self,
+        content_type: &Mime,
+        data: &[u8],
+        size: (u32, u32),
+    ) -> Result<Option<GeneratedThumbnail>, ThumbnailError>;
+}
+
+/// A [`Thumbnailer`] for images, that uses the
+/// [image](https://github.com/image-rs/image) crate.
+///
+/// Only the first frame of animated images is used. The thumbnail is encoded
+/// as JPEG for JPEG images and as PNG for the other formats, and the BlurHash
+/// of the image is computed.
+#[cfg(feature = "image-proc")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ImageThumbnailer;
+
+#[cfg(feature = "image-proc")]
+impl Thumbnailer for ImageThumbnailer {
+    fn generate_thumbnail(
+        &self,
+        content_type: &Mime,
+        data: &[u8],
+        size: (u32, u32),
+    ) -> Result<Option<GeneratedThumbnail>, ThumbnailError> {
+        let (image, format) = match load_first_frame(content_type, data) {
+            Ok(image) => image,
+            Err(ImageError::FormatNotSupported) => return Ok(None),
+            Err(error) => return Err(error.into()),
+        };
+
+        let thumbnail = match thumbnail_image(&image, size) {
+            Ok(thumbnail) => thumbnail,
+            Err(ImageError::ThumbnailBiggerThanOriginal) => return Ok(None),
+            Err(error) => return Err(error.into()),
+        };
+        let (width, height) = thumbnail.dimensions();
+
+        let (format, content_type) = if format == ImageFormat::Jpeg {
+            (ImageFormat::Jpeg, mime::IMAGE_JPEG)
+        } else {
+            (ImageFormat::Png, mime::IMAGE_PNG)
+        };
+
+        let mut data = Vec::new();
+        thumbnail.write_to(&mut Cursor::new(&mut data), format).map_err(ImageError::from)?;
+        let data_size = data.len() as u32;
+
+        Ok(Some(GeneratedThumbnail {
+            data,
+            content_type,
+            info: BaseThumbnailInfo {
+                width: Some(width.into()),
+                height: Some(height.into()),
+                size: Some(data_size.into()),
+            },
+            blurhash: Some(blurhash(&thumbnail)),
+        }))
+    }
+}
+
 /// Configuration for sending an attachment.
 #[derive(Debug)]
 pub struct AttachmentConfig<'a> {
     pub(crate) txn_id: Option<&'a TransactionId>,
     pub(crate) info: Option<AttachmentInfo>,
     pub(crate) thumbnail: Option<Thumbnail<'a>>,
-    #[cfg(feature = "image-proc")]
     pub(crate) generate_thumbnail: bool,
-    #[cfg(feature = "image-proc")]
     pub(crate) thumbnail_size: Option<(u32, u32)>,
+    pub(crate) thumbnailer: Option<Arc<dyn Thumbnailer>>,
 }
 
 impl AttachmentConfig<'static> {
@@ -194,20 +294,21 @@ impl AttachmentConfig<'static> {
             txn_id: Default::default(),
             info: Default::default(),
             thumbnail: None,
-            #[cfg(feature = "image-proc")]
             generate_thumbnail: Default::default(),
-            #[cfg(feature = "image-proc")]
             thumbnail_size: Default::default(),
+            thumbnailer: None,
         }
     }
 
     /// Generate the thumbnail to send for this media.
     ///
-    /// Uses [`generate_image_thumbnail()`].
+    /// Uses [`ImageThumbnailer`], which also computes the BlurHash of the
+    /// image.
     ///
     /// Thumbnails can only be generated for supported image attachments. For
     /// more information, see the [image](https://github.com/image-rs/image)
-    /// crate.
+    /// crate. To generate thumbnails for other media, use
+    /// [`AttachmentConfig::thumbnailer()`].
     ///
     /// # Arguments
     ///
@@ -220,6 +321,32 @@ impl AttachmentConfig<'static> {
         self.thumbnail_size = size;
         self
     }
+
+    /// Generate the thumbnail to send for this media with the given
+    /// [`Thumbnailer`].
+    ///
+    /// If the thumbnailer doesn't support the media, the `ImageThumbnailer`
+    /// is used when the `image-proc` feature is enabled. In that case, the
+    /// BlurHash of the thumbnail is also computed if the thumbnailer didn't
+    /// provide one.
+    ///
+    /// # Arguments
+    ///
+    /// * `thumbnailer` - The thumbnailer to use.
+    ///
+    /// * `size` - The size of the thumbnail in pixels as a `(width, height)`
+    /// tuple. If set to `None`, defaults to `(800, 600)`.
+    #[must_use]
+    pub fn thumbnailer(
+        mut self,
+        thumbnailer: impl Thumbnailer + 'static,
+        size: Option<(u32, u32)>,
+    ) -> Self {
+        self.generate_thumbnail = true;
+        self.thumbnail_size = size;
+        self.thumbnailer = Some(Arc::new(thumbnailer));
+        self
+    }
 }
 
 impl Default for AttachmentConfig<'static> {
@@ -244,10 +371,9 @@ impl<'a> AttachmentConfig<'a> {
             txn_id: Default::default(),
             info: Default::default(),
             thumbnail: Some(thumbnail),
-            #[cfg(feature = "image-proc")]
             generate_thumbnail: Default::default(),
-            #[cfg(feature = "image-proc")]
             thumbnail_size: Default::default(),
+            thumbnailer: None,
         }
     }
 
@@ -341,25 +467,11 @@ pub fn generate_image_thumbnail<R: BufRead + Seek>(
     reader: R,
     size: Option<(u32, u32)>,
 ) -> Result<(Vec<u8>, BaseThumbnailInfo), ImageError> {
-    let image_format = image::ImageFormat::from_mime_type(content_type);
-    if image_format.is_none() {
-        return Err(ImageError::FormatNotSupported);
-    }
-
-    let image_format = image_format.unwrap();
+    let image_format =
+        ImageFormat::from_mime_type(content_type).ok_or(ImageError::FormatNotSupported)?;
 
     let image = image::load(reader, image_format)?;
-    let (original_width, original_height) = image.dimensions();
-
-    let (width, height) = size.unwrap_or((800, 600));
-
-    // Don't generate a thumbnail if it would be bigger than or equal to the
-    // original.
-    if height >= original_height && width >= original_width {
-        return Err(ImageError::ThumbnailBiggerThanOriginal);
-    }
-
-    let thumbnail = image.thumbnail(width, height);
+    let thumbnail = thumbnail_image(&image, size.unwrap_or(DEFAULT_THUMBNAIL_SIZE))?;
     let (thumbnail_width, thumbnail_height) = thumbnail.dimensions();
 
     let mut data: Vec<u8> = vec![];
@@ -375,3 +487,132 @@ pub fn generate_image_thumbnail<R: BufRead + Seek>(
         },
     ))
 }
+
+/// Compute the [BlurHash](https://blurha.sh/) of an image.
+///
+/// Only the first frame of animated images is used.
+///
+/// # Arguments
+///
+/// * `content_type` - The type of the image.
+///
+/// * `data` - The raw bytes of the image.
+#[cfg(feature = "image-proc")]
+pub fn generate_blurhash(content_type: &Mime, data: &[u8]) -> Result<String, ImageError> {
+    let (image, _) = load_first_frame(content_type, data)?;
+    Ok(blurhash(&image))
+}
+
+/// Load an image, or the first frame of an animated image.
+#[cfg(feature = "image-proc")]
+fn load_first_frame(
+    content_type: &Mime,
+    data: &[u8],
+) -> Result<(DynamicImage, ImageFormat), ImageError> {
+    let format = ImageFormat::from_mime_type(content_type).ok_or(ImageError::FormatNotSupported)?;
+
+    let image = if format == ImageFormat::Gif {
+        match GifDecoder::new(Cursor::new(data))?.into_frames().next() {
+            Some(frame) => DynamicImage::ImageRgba8(frame?.into_buffer()),
+            None => image::load_from_memory_with_format(data, format)?,
+        }
+    } else {
+        image::load_from_memory_with_format(data, format)?
+    };
+
+    Ok((image, format))
+}
+
+/// Resize an image to fit in the given `(width, height)`.
+#[cfg(feature = "image-proc")]
+fn thumbnail_image(image: &DynamicImage, size: (u32, u32)) -> Result<DynamicImage, ImageError> {
+    let (original_width, original_height) = image.dimensions();
+    let (width, height) = size;
+
+    // Don't generate a thumbnail if it would be bigger than or equal to the
+    // original.
+    if height >= original_height && width >= original_width {
+        return Err(ImageError::ThumbnailBiggerThanOriginal);
+    }
+
+    Ok(image.thumbnail(width, height))
+}
+
+/// Compute the BlurHash of an image.
+#[cfg(feature = "image-proc")]
+fn blurhash(image: &DynamicImage) -> String {
+    // The BlurHash only keeps a few components, computing it on a small
+    // version of the image is much faster and gives the same result.
+    let image = image.thumbnail(100, 100);
+    let (width, height) = image.dimensions();
+
+    blurhash::encode(4, 3, width, height, &image.to_rgba8().into_raw())
+}
+
+/// Generate a thumbnail for an attachment with the given thumbnailer, falling
+/// back to the [`ImageThumbnailer`] if the `image-proc` feature is enabled.
+pub(crate) fn generate_thumbnail(
+    thumbnailer: Option<&dyn Thumbnailer>,
+    content_type: &Mime,
+    data: &[u8],
+    size: Option<(u32, u32)>,
+) -> Result<Option<GeneratedThumbnail>, ThumbnailError> {
+    let size = size.unwrap_or(DEFAULT_THUMBNAIL_SIZE);
+
+    if let Some(thumbnailer) = thumbnailer {
+        if let Some(thumbnail) = thumbnailer.generate_thumbnail(content_type, data, size)? {
+            #[cfg(feature = "image-proc")]
+            let thumbnail = with_blurhash(thumbnail);
+            return Ok(Some(thumbnail));
+        }
+    }
+
+    #[cfg(feature = "image-proc")]
+    return ImageThumbnailer.generate_thumbnail(content_type, data, size);
+
+    #[cfg(not(feature = "image-proc"))]
+    Ok(None)
+}
+
+/// Compute the BlurHash of a thumbnail that doesn't have one.
+#[cfg(feature = "image-proc")]
+fn with_blurhash(mut thumbnail: GeneratedThumbnail) -> GeneratedThumbnail {
+    if thumbnail.blurhash.is_none() {
+        thumbnail.blurhash = generate_blurhash(&thumbnail.content_type, &thumbnail.data).ok();
+    }
+
+    thumbnail
+}
+
+/// Add a BlurHash to the metadata of an image or a video attachment, unless it
+/// already has one.
+pub(crate) fn add_blurhash(
+    info: Option<AttachmentInfo>,
+    content_type: &Mime,
+    blurhash: String,
+) -> Option<AttachmentInfo> {
+    match (info, content_type.type_()) {
+        (Some(AttachmentInfo::Image(mut info)), _) => {
+            info.blurhash.get_or_insert(blurhash);
+            Some(AttachmentInfo::Image(info))
+        }
+        (Some(AttachmentInfo::Video(mut info)), _) => {
+            info.blurhash.get_or_insert(blurhash);
+            Some(AttachmentInfo::Video(info))
+        }
+        (None, mime::IMAGE) => Some(AttachmentInfo::Image(BaseImageInfo {
+            height: None,
+            width: None,
+            size: None,
+            blurhash: Some(blurhash),
+        })),
+        (None, mime::VIDEO) => Some(AttachmentInfo::Video(BaseVideoInfo {
+            duration: None,
+            height: None,
+            width: None,
+            size: None,
+            blurhash: Some(blurhash),
+        })),
+        (info, _) => info,
+    }
+}
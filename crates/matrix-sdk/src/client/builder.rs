@@ -13,14 +13,16 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::sync::Arc;
+use std::{collections::HashMap, num::NonZeroUsize, sync::Arc};
 
 #[cfg(target_arch = "wasm32")]
 use async_once_cell::OnceCell;
+use futures_signals::signal::Mutable;
 use matrix_sdk_base::{
     locks::{Mutex, RwLock},
+    media::MediaRetentionPolicy,
     store::StoreConfig,
-    BaseClient, StateStore,
+    BaseClient, StateStore, StoreError,
 };
 use ruma::{
     api::{client::discovery::discover_homeserver, error::FromHttpResponseError, MatrixVersion},
@@ -31,11 +33,12 @@ use thiserror::Error;
 use tokio::sync::OnceCell;
 use url::Url;
 
-use super::{Client, ClientInner};
+use super::{Client, ClientInner, SessionState, SupportedVersions};
 use crate::{
     config::RequestConfig,
     error::RumaApiError,
-    http_client::{HttpClient, HttpSend, HttpSettings},
+    http_client::{HttpClient, HttpSend, HttpSettings, RequestCategory},
+    session_persistence::SessionPersister,
     HttpError,
 };
 
@@ -81,26 +84,36 @@ use crate::{
 #[derive(Clone, Debug)]
 pub struct ClientBuilder {
     homeserver_cfg: Option<HomeserverConfig>,
+    authentication_issuer: Option<Url>,
     http_cfg: Option<HttpConfig>,
     store_config: StoreConfig,
+    media_retention_policy: Option<MediaRetentionPolicy>,
     request_config: RequestConfig,
+    concurrency_limits: HashMap<RequestCategory, NonZeroUsize>,
     respect_login_well_known: bool,
     appservice_mode: bool,
     server_versions: Option<Box<[MatrixVersion]>>,
     handle_refresh_tokens: bool,
+    auto_join_room_successors: bool,
+    session_persister: Option<Arc<dyn SessionPersister>>,
 }
 
 impl ClientBuilder {
     pub(crate) fn new() -> Self {
         Self {
             homeserver_cfg: None,
+            authentication_issuer: None,
             http_cfg: None,
             store_config: Default::default(),
+            media_retention_policy: None,
             request_config: Default::default(),
+            concurrency_limits: Default::default(),
             respect_login_well_known: true,
             appservice_mode: false,
             server_versions: None,
             handle_refresh_tokens: false,
+            auto_join_room_successors: false,
+            session_persister: None,
         }
     }
 
@@ -136,6 +149,16 @@ impl ClientBuilder {
         self
     }
 
+    /// Set the OpenID Connect Provider trusted by the homeserver.
+    ///
+    /// By default, it is discovered with the homeserver when using
+    /// [`server_name()`][Self::server_name]. Setting it overrides the
+    /// discovered one.
+    pub fn authentication_issuer(mut self, issuer: Url) -> Self {
+        self.authentication_issuer = Some(issuer);
+        self
+    }
+
     /// Set up the store configuration for a sled store.
     ///
     /// This is a shorthand for
@@ -150,6 +173,20 @@ impl ClientBuilder {
         Ok(self.store_config(config))
     }
 
+    /// Set up the store configuration for a SQLite store.
+    ///
+    /// This is a shorthand for
+    /// <code>.[store_config](Self::store_config)([matrix_sdk_sqlite]::[make_store_config](matrix_sdk_sqlite::make_store_config)(path, passphrase)?)</code>.
+    #[cfg(feature = "sqlite")]
+    pub fn sqlite_store(
+        self,
+        path: impl AsRef<std::path::Path>,
+        passphrase: Option<&str>,
+    ) -> Result<Self, matrix_sdk_sqlite::OpenStoreError> {
+        let config = matrix_sdk_sqlite::make_store_config(path, passphrase)?;
+        Ok(self.store_config(config))
+    }
+
     /// Set up the store configuration for a IndexedDB store.
     ///
     /// This is a shorthand for
@@ -196,7 +233,8 @@ impl ClientBuilder {
     /// The state store should be opened before being set.
     #[deprecated = "\
         Use [`store_config`](#method.store_config), \
-        [`sled_store`](#method.sled_store) or \
+        [`sled_store`](#method.sled_store), \
+        [`sqlite_store`](#method.sqlite_store) or \
         [`indexeddb_store`](#method.indexeddb_store) instead
     "]
     pub fn state_store(mut self, store: impl StateStore + 'static) -> Self {
@@ -209,7 +247,8 @@ impl ClientBuilder {
     /// The crypto store should be opened before being set.
     #[deprecated = "\
         Use [`store_config`](#method.store_config), \
-        [`sled_store`](#method.sled_store) or \
+        [`sled_store`](#method.sled_store), \
+        [`sqlite_store`](#method.sqlite_store) or \
         [`indexeddb_store`](#method.indexeddb_store) instead
     "]
     #[cfg(feature = "e2e-encryption")]
@@ -221,6 +260,23 @@ impl ClientBuilder {
         self
     }
 
+    /// Set the policy deciding which media files are kept in the media cache
+    /// of the state store.
+    ///
+    /// By default, the cache uses the default [`MediaRetentionPolicy`]. The
+    /// policy can also be changed later with
+    /// [`Media::set_retention_policy()`].
+    ///
+    /// # Arguments
+    ///
+    /// * `policy` - The policy of the media cache.
+    ///
+    /// [`Media::set_retention_policy()`]: crate::Media::set_retention_policy
+    pub fn media_retention_policy(mut self, policy: MediaRetentionPolicy) -> Self {
+        self.media_retention_policy = Some(policy);
+        self
+    }
+
     /// Update the client's homeserver URL with the discovery information
     /// present in the login response, if any.
     pub fn respect_login_well_known(mut self, value: bool) -> Self {
@@ -234,6 +290,33 @@ impl ClientBuilder {
         self
     }
 
+    /// Limit the number of requests of the given category that can be in
+    /// flight at the same time.
+    ///
+    /// Requests over the limit wait until a previous request of the same
+    /// category finished. By default the number of concurrent requests isn't
+    /// limited.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::num::NonZeroUsize;
+    ///
+    /// use matrix_sdk::{Client, RequestCategory};
+    ///
+    /// // Upload a single file at a time.
+    /// let client_builder = Client::builder()
+    ///     .max_concurrent_requests(RequestCategory::MediaUpload, NonZeroUsize::new(1).unwrap());
+    /// ```
+    pub fn max_concurrent_requests(
+        mut self,
+        category: RequestCategory,
+        limit: NonZeroUsize,
+    ) -> Self {
+        self.concurrency_limits.insert(category, limit);
+        self
+    }
+
     /// Set the proxy through which all the HTTP requests should go.
     ///
     /// Note, only HTTP proxies are supported.
@@ -317,6 +400,9 @@ impl ClientBuilder {
     /// than `build()` doing it using a `get_supported_versions` request.
     ///
     /// This is helpful for test code that doesn't care to mock that endpoint.
+    ///
+    /// The unstable features of the homeserver are not known in that case, so
+    /// they are not taken into account in [`Client::server_features()`].
     pub fn server_versions(mut self, value: impl IntoIterator<Item = MatrixVersion>) -> Self {
         self.server_versions = Some(value.into_iter().collect());
         self
@@ -345,14 +431,55 @@ impl ClientBuilder {
     ///   using [`Client::session_tokens_signal()`] for example, to be able to
     ///   [restore the session] later.
     ///
+    /// * If the homeserver doesn't support [`Feature::RefreshTokens`], the
+    ///   tokens are not refreshed and the [`UnknownToken`] error is forwarded.
+    ///
     /// [refreshing access tokens]: https://spec.matrix.org/v1.3/client-server-api/#refreshing-access-tokens
     /// [`UnknownToken`]: ruma::api::client::error::ErrorKind::UnknownToken
     /// [restore the session]: Client::restore_login
+    /// [`Feature::RefreshTokens`]: crate::Feature::RefreshTokens
     pub fn handle_refresh_tokens(mut self) -> Self {
         self.handle_refresh_tokens = true;
         self
     }
 
+    /// Join the rooms that replace the joined rooms automatically.
+    ///
+    /// By default, when a joined room is upgraded the `Client` doesn't join the
+    /// new room, [`Common::join_successor()`] needs to be called manually.
+    ///
+    /// Enabling this setting means that the `Client` joins the new room as soon
+    /// as the `m.room.tombstone` event of the old room is received in a sync.
+    /// A tombstone that is only part of the state of the old room, like during
+    /// an initial sync, doesn't make the `Client` join a new room that it has
+    /// already joined or left.
+    ///
+    /// [`Common::join_successor()`]: crate::room::Common::join_successor
+    pub fn auto_join_room_successors(mut self) -> Self {
+        self.auto_join_room_successors = true;
+        self
+    }
+
+    /// Persist the session of the `Client` with the given persister.
+    ///
+    /// When the `Client` is built, the session saved by the persister, if
+    /// any, is restored. The homeserver URL of the persisted session is used,
+    /// so it is not necessary to set [`homeserver_url()`][Self::homeserver_url]
+    /// or [`server_name()`][Self::server_name] in that case.
+    ///
+    /// Afterwards, the session is saved every time the client logs in and the
+    /// tokens change, so this should be used with
+    /// [`handle_refresh_tokens()`][Self::handle_refresh_tokens] if refresh
+    /// tokens are requested. The session is removed from the persister when
+    /// the client is logged out.
+    ///
+    /// See the [`session_persistence`](crate::session_persistence) module for
+    /// more details.
+    pub fn session_persister(mut self, persister: impl SessionPersister + 'static) -> Self {
+        self.session_persister = Some(Arc::new(persister));
+        self
+    }
+
     /// Create a [`Client`] with the options set on this builder.
     ///
     /// # Errors
@@ -365,8 +492,21 @@ impl ClientBuilder {
     ///   server discovery request is made which can fail; if you didn't set
     ///   [`server_versions(false)`][Self::server_versions], that amounts to
     ///   another request that can fail
+    /// * Session persistence: if a [session persister][Self::session_persister]
+    ///   was set, loading or restoring the persisted session can fail
     pub async fn build(self) -> Result<Client, ClientBuildError> {
-        let homeserver_cfg = self.homeserver_cfg.ok_or(ClientBuildError::MissingHomeserver)?;
+        let persisted_session = match &self.session_persister {
+            Some(persister) => {
+                persister.load().await.map_err(ClientBuildError::SessionPersistence)?
+            }
+            None => None,
+        };
+
+        let homeserver_cfg = match (self.homeserver_cfg, &persisted_session) {
+            (Some(homeserver_cfg), _) => homeserver_cfg,
+            (None, Some(persisted)) => HomeserverConfig::Url(persisted.homeserver_url.to_string()),
+            (None, None) => return Err(ClientBuildError::MissingHomeserver),
+        };
 
         let inner_http_client = match self.http_cfg.unwrap_or_default() {
             #[allow(unused_mut)]
@@ -382,9 +522,18 @@ impl ClientBuilder {
         };
 
         let base_client = BaseClient::with_store_config(self.store_config);
-        let http_client = HttpClient::new(inner_http_client.clone(), self.request_config);
+        if let Some(policy) = self.media_retention_policy {
+            base_client.store().set_media_retention_policy(policy).await?;
+        }
+
+        let http_client = HttpClient::new(
+            inner_http_client.clone(),
+            self.request_config,
+            &self.concurrency_limits,
+        );
 
-        let mut authentication_issuer: Option<Url> = None;
+        let mut authentication_issuer = self.authentication_issuer;
+        let mut account_management_url = None;
         let homeserver = match homeserver_cfg {
             HomeserverConfig::Url(url) => url,
             HomeserverConfig::ServerName(server_name) => {
@@ -404,43 +553,79 @@ impl ClientBuilder {
                         err => ClientBuildError::Http(err),
                     })?;
 
-                if let Some(issuer) = well_known.authentication.map(|auth| auth.issuer) {
-                    authentication_issuer = Url::parse(&issuer).ok();
+                if let Some(authentication) = well_known.authentication {
+                    if authentication_issuer.is_none() {
+                        authentication_issuer = Url::parse(&authentication.issuer).ok();
+                    }
+                    account_management_url =
+                        authentication.account.and_then(|account| Url::parse(&account).ok());
                 };
 
                 well_known.homeserver.base_url
             }
         };
 
-        let homeserver = RwLock::new(Url::parse(&homeserver)?);
+        // The persisted session is only valid on the homeserver it was created
+        // on.
+        let homeserver = match &persisted_session {
+            Some(persisted) => persisted.homeserver_url.clone(),
+            None => Url::parse(&homeserver)?,
+        };
+        let homeserver = RwLock::new(homeserver);
         let authentication_issuer = authentication_issuer.map(RwLock::new);
 
         let inner = Arc::new(ClientInner {
             homeserver,
             authentication_issuer,
+            account_management_url,
+            #[cfg(feature = "experimental-oidc")]
+            oidc: Default::default(),
             http_client,
             base_client,
-            server_versions: OnceCell::new_with(self.server_versions),
+            server_versions: OnceCell::new_with(
+                self.server_versions.map(SupportedVersions::from_versions),
+            ),
+            server_features: Default::default(),
             #[cfg(feature = "e2e-encryption")]
             group_session_locks: Default::default(),
             #[cfg(feature = "e2e-encryption")]
             key_claim_lock: Default::default(),
             members_request_locks: Default::default(),
             typing_notice_times: Default::default(),
+            send_queues: Default::default(),
             event_handlers: Default::default(),
             notification_handlers: Default::default(),
             appservice_mode: self.appservice_mode,
             respect_login_well_known: self.respect_login_well_known,
             sync_beat: event_listener::Event::new(),
+            session_state: Mutable::new(SessionState::LoggedOut),
+            persisted_session_state: Mutable::new(None),
             handle_refresh_tokens: self.handle_refresh_tokens,
+            auto_join_room_successors: self.auto_join_room_successors,
+            space_tree: Default::default(),
+            presences: Default::default(),
+            ephemeral_states: Default::default(),
+            notification_settings_lock: Default::default(),
             refresh_token_lock: Mutex::new(Ok(())),
         });
+        let client = Client { inner };
+
+        if let Some(persister) = self.session_persister {
+            if let Some(persisted) = persisted_session {
+                client
+                    .restore_login(persisted.session)
+                    .await
+                    .map_err(|error| ClientBuildError::RestoreSession(Box::new(error)))?;
+            }
 
-        Ok(Client { inner })
+            client.spawn_session_persistence(persister);
+        }
+
+        Ok(client)
     }
 }
 
-fn homeserver_from_name(server_name: &ServerName) -> String {
+pub(super) fn homeserver_from_name(server_name: &ServerName) -> String {
     #[cfg(not(test))]
     return format!("https://{server_name}");
 
@@ -503,6 +688,18 @@ pub enum ClientBuildError {
     #[error(transparent)]
     Http(#[from] HttpError),
 
+    /// Error applying the configuration to the state store.
+    #[error(transparent)]
+    StateStore(#[from] StoreError),
+
+    /// Error loading the session from the session persister.
+    #[error("failed to load the persisted session: {0}")]
+    SessionPersistence(#[source] StoreError),
+
+    /// Error restoring the session loaded from the session persister.
+    #[error("failed to restore the persisted session: {0}")]
+    RestoreSession(#[source] Box<crate::Error>),
+
     /// Error opening the indexeddb store.
     #[cfg(feature = "indexeddb")]
     #[error(transparent)]
@@ -512,6 +709,11 @@ pub enum ClientBuildError {
     #[cfg(feature = "sled")]
     #[error(transparent)]
     SledStore(#[from] matrix_sdk_sled::OpenStoreError),
+
+    /// Error opening the sqlite store.
+    #[cfg(feature = "sqlite")]
+    #[error(transparent)]
+    SqliteStore(#[from] matrix_sdk_sqlite::OpenStoreError),
 }
 
 impl ClientBuildError {
@@ -18,13 +18,16 @@
 use std::future::Future;
 
 use ruma::{
-    api::client::{session::login, uiaa::UserIdentifier},
+    api::client::{
+        session::{login, logout},
+        uiaa::UserIdentifier,
+    },
     assign,
 };
-use tracing::{info, instrument};
+use tracing::{info, instrument, warn};
 
 use super::Client;
-use crate::{config::RequestConfig, Result};
+use crate::{config::RequestConfig, Error, Result, SessionState};
 
 /// The login method.
 ///
@@ -99,9 +102,13 @@ impl<'a> LoginBuilder<'a> {
     /// Set the device ID.
     ///
     /// The device ID is a unique ID that will be associated with this session.
-    /// If not set, the homeserver will create one. Can be an existing device ID
-    /// from a previous login call. Note that this should be done only if the
-    /// client also holds the corresponding encryption keys.
+    /// If not set, the homeserver will create one, unless the client is logging
+    /// in again after a [soft logout], in which case the device ID of the
+    /// current session is used. Can be an existing device ID from a previous
+    /// login call. Note that this should be done only if the client also holds
+    /// the corresponding encryption keys.
+    ///
+    /// [soft logout]: crate::SessionState::SoftLoggedOut
     pub fn device_id(mut self, value: &'a str) -> Self {
         self.device_id = Some(value);
         self
@@ -146,13 +153,63 @@ impl<'a> LoginBuilder<'a> {
         let homeserver = self.client.homeserver().await;
         info!(homeserver = homeserver.as_str(), identifier = ?self.login_method.id(), "Logging in");
 
+        let soft_logged_out = self.client.session_state() == SessionState::SoftLoggedOut;
+
+        let device_id = if soft_logged_out {
+            // When logging in again after a soft logout, the user and device of
+            // the current session must be kept.
+            let user_id = self.client.user_id().ok_or(Error::AuthenticationRequired)?;
+            let device_id = self.client.device_id().ok_or(Error::AuthenticationRequired)?;
+
+            let is_other_user = match self.login_method.id() {
+                Some(UserIdentifier::UserIdOrLocalpart(id)) => {
+                    *id != user_id.as_str() && *id != user_id.localpart()
+                }
+                _ => false,
+            };
+            if is_other_user || self.device_id.map_or(false, |id| id != device_id.as_str()) {
+                return Err(Error::SessionMismatch);
+            }
+
+            Some(device_id)
+        } else {
+            self.device_id.map(Into::into)
+        };
         let request = assign!(login::v3::Request::new(self.login_method.to_login_info()), {
-            device_id: self.device_id.map(Into::into),
+            device_id,
             initial_device_display_name: self.initial_device_display_name,
             refresh_token: self.request_refresh_token,
         });
 
         let response = self.client.send(request, Some(RequestConfig::short_retry())).await?;
+
+        // The user can't be checked before the request with every login method,
+        // and the homeserver might not have reused the device.
+        if soft_logged_out
+            && (self.client.user_id() != Some(&*response.user_id)
+                || self.client.device_id() != Some(&*response.device_id))
+        {
+            // Don't leave the new session behind on the homeserver.
+            let result = self
+                .client
+                .http_client()
+                .send(
+                    logout::v3::Request::new(),
+                    None,
+                    homeserver.to_string(),
+                    Some(&response.access_token),
+                    Some(&response.user_id),
+                    self.client.server_versions().await?,
+                )
+                .await;
+
+            if let Err(error) = result {
+                warn!(?error, "Failed to log out the session that doesn't match the current one");
+            }
+
+            return Err(Error::SessionMismatch);
+        }
+
         self.client.receive_login_response(&response).await?;
 
         Ok(response)
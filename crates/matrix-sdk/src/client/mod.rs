@@ -15,6 +15,8 @@
 // limitations under the License.
 
 use std::{
+    borrow::Cow,
+    collections::BTreeSet,
     fmt::{self, Debug},
     future::Future,
     pin::Pin,
@@ -25,7 +27,11 @@ use std::{
 use async_once_cell::OnceCell;
 use dashmap::DashMap;
 use futures_core::stream::Stream;
-use futures_signals::signal::Signal;
+use futures_signals::{
+    map_ref,
+    signal::{Mutable, ReadOnlyMutable, Signal, SignalExt},
+};
+use futures_util::{future, StreamExt};
 use matrix_sdk_base::{
     deserialized_responses::SyncResponse, BaseClient, SendOutsideWasm, Session, SessionMeta,
     SessionTokens, StateStore, SyncOutsideWasm,
@@ -39,17 +45,21 @@ use ruma::TransactionId;
 use ruma::{
     api::{
         client::{
-            account::{register, whoami},
+            account::{
+                get_username_availability, register, request_registration_token_via_email, whoami,
+            },
             alias::get_alias,
             device::{delete_devices, get_devices},
             directory::{get_public_rooms, get_public_rooms_filtered},
             discovery::{
+                discover_homeserver,
                 get_capabilities::{self, Capabilities},
                 get_supported_versions,
             },
             error::ErrorKind,
             filter::{create_filter::v3::Request as FilterUploadRequest, FilterDefinition},
             membership::{join_room_by_id, join_room_by_id_or_alias},
+            presence::get_presence,
             push::get_notifications::v3::Notification,
             room::create_room,
             session::{
@@ -59,23 +69,24 @@ use ruma::{
             uiaa::{AuthData, UserIdentifier},
         },
         error::{FromHttpResponseError, ServerError},
-        MatrixVersion, OutgoingRequest, SendAccessToken,
+        MatrixVersion, Metadata, OutgoingRequest, SendAccessToken,
     },
     assign,
-    presence::PresenceState,
-    DeviceId, OwnedDeviceId, OwnedRoomId, OwnedServerName, RoomAliasId, RoomId, RoomOrAliasId,
-    ServerName, UInt, UserId,
+    directory::{Filter, PublicRoomsChunk},
+    events::presence::PresenceEvent,
+    ClientSecret, DeviceId, OwnedDeviceId, OwnedRoomId, OwnedServerName, OwnedUserId, RoomAliasId,
+    RoomId, RoomOrAliasId, ServerName, UInt, UserId,
 };
 use serde::de::DeserializeOwned;
 #[cfg(not(target_arch = "wasm32"))]
 use tokio::sync::OnceCell;
-#[cfg(feature = "e2e-encryption")]
-use tracing::error;
-use tracing::{debug, info, instrument};
+use tracing::{debug, error, info, instrument, warn};
 use url::Url;
 
 #[cfg(feature = "e2e-encryption")]
 use crate::encryption::Encryption;
+#[cfg(feature = "experimental-oidc")]
+use crate::oidc::{Oidc, OidcState};
 use crate::{
     config::RequestConfig,
     error::{HttpError, HttpResult},
@@ -83,18 +94,27 @@ use crate::{
         EventHandler, EventHandlerDropGuard, EventHandlerHandle, EventHandlerResult,
         EventHandlerStore, SyncEvent,
     },
-    http_client::HttpClient,
-    room, Account, Error, Media, RefreshTokenError, Result, RumaApiError,
+    http_client::{BackoffState, ByteStream, HttpClient},
+    room::{self, EphemeralState, SendQueueState},
+    session_persistence::{PersistedSession, SessionPersister},
+    space::SpaceTree,
+    uiaa::{Uiaa, UiaaHandler},
+    Account, Error, Media, NotificationSettings, RefreshTokenError, Result, RumaApiError,
 };
 
 mod builder;
 mod login_builder;
+mod registration_builder;
+mod server_features;
 
 #[cfg(feature = "sso-login")]
 pub use self::login_builder::SsoLoginBuilder;
+use self::server_features::SupportedVersions;
 pub use self::{
     builder::{ClientBuildError, ClientBuilder},
     login_builder::LoginBuilder,
+    registration_builder::RegistrationBuilder,
+    server_features::{Feature, ServerFeatures},
 };
 
 #[cfg(not(target_arch = "wasm32"))]
@@ -123,6 +143,24 @@ pub enum LoopCtrl {
     Break,
 }
 
+/// The state of the session of a [`Client`].
+///
+/// Can be observed with [`Client::session_state_signal()`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionState {
+    /// The client is logged in.
+    LoggedIn,
+    /// The homeserver invalidated the access token with a [soft logout].
+    ///
+    /// The session can be recovered by logging in again with the same device
+    /// ID, which keeps the state and encryption keys of the client.
+    ///
+    /// [soft logout]: https://spec.matrix.org/v1.3/client-server-api/#soft-logout
+    SoftLoggedOut,
+    /// The client is not logged in, or the homeserver invalidated the session.
+    LoggedOut,
+}
+
 /// An async/await enabled Matrix client.
 ///
 /// All of the state is held in an `Arc` so the `Client` can be cloned freely.
@@ -136,12 +174,22 @@ pub(crate) struct ClientInner {
     homeserver: RwLock<Url>,
     /// The OIDC Provider that is trusted by the homeserver.
     authentication_issuer: Option<RwLock<Url>>,
+    /// The URL where the user can manage their account, advertised by the
+    /// homeserver.
+    pub(crate) account_management_url: Option<Url>,
+    /// The state of the OpenID Connect API. See `Oidc`.
+    #[cfg(feature = "experimental-oidc")]
+    pub(crate) oidc: OidcState,
     /// The underlying HTTP client.
     http_client: HttpClient,
     /// User session data.
     base_client: BaseClient,
-    /// The Matrix versions the server supports (well-known ones only)
-    server_versions: OnceCell<Box<[MatrixVersion]>>,
+    /// The Matrix versions (well-known ones only) and unstable features the
+    /// server supports.
+    server_versions: OnceCell<SupportedVersions>,
+    /// The features of the server, with the user they were fetched for. See
+    /// `server_features`.
+    server_features: Mutex<Option<(Option<OwnedUserId>, ServerFeatures)>>,
     /// Locks making sure we only have one group session sharing request in
     /// flight per room.
     #[cfg(feature = "e2e-encryption")]
@@ -151,6 +199,8 @@ pub(crate) struct ClientInner {
     pub(crate) key_claim_lock: Mutex<()>,
     pub(crate) members_request_locks: DashMap<OwnedRoomId, Arc<Mutex<()>>>,
     pub(crate) typing_notice_times: DashMap<OwnedRoomId, Instant>,
+    /// The state of the send queues, per room. See `Joined::send_queue`.
+    pub(crate) send_queues: DashMap<OwnedRoomId, Arc<SendQueueState>>,
     /// Event handlers. See `add_event_handler`.
     pub(crate) event_handlers: EventHandlerStore,
     /// Notification handlers. See `register_notification_handler`.
@@ -165,8 +215,27 @@ pub(crate) struct ClientInner {
     /// Whether to try to refresh the access token automatically when an
     /// `M_UNKNOWN_TOKEN` error is encountered.
     handle_refresh_tokens: bool,
+    /// Whether to join the successor of a joined room automatically when its
+    /// `m.room.tombstone` event is received.
+    pub(crate) auto_join_room_successors: bool,
+    /// The relations between the joined spaces and rooms, built lazily. See
+    /// `space_tree`.
+    pub(crate) space_tree: Mutex<Option<SpaceTree>>,
+    /// The presence of the users observed with `presence_stream`.
+    pub(crate) presences: DashMap<OwnedUserId, Mutable<Option<PresenceEvent>>>,
+    /// The typing users and read receipts of the rooms observed with
+    /// `Common::typing_users()` and `Common::read_receipts()`.
+    pub(crate) ephemeral_states: DashMap<OwnedRoomId, Arc<EphemeralState>>,
+    /// Lock making sure we're only changing the push rules once at a time.
+    /// See `NotificationSettings`.
+    pub(crate) notification_settings_lock: Mutex<()>,
     /// Lock making sure we're only doing one token refresh at a time.
     refresh_token_lock: Mutex<Result<(), RefreshTokenError>>,
+    /// The state of the session. See `session_state_signal`.
+    pub(crate) session_state: Mutable<SessionState>,
+    /// The state of the session that was last handled by the session
+    /// persister. See `spawn_session_persistence`.
+    persisted_session_state: Mutable<Option<SessionState>>,
     /// An event that can be listened on to wait for a successful sync. The
     /// event will only be fired if a sync loop is running. Can be used for
     /// synchronization, e.g. if we send out a request to create a room, we can
@@ -205,6 +274,10 @@ impl Client {
         &self.inner.base_client
     }
 
+    pub(crate) fn http_client(&self) -> &HttpClient {
+        &self.inner.http_client
+    }
+
     /// Change the homeserver URL used by this client.
     ///
     /// # Arguments
@@ -242,6 +315,93 @@ impl Client {
         Ok(res.capabilities)
     }
 
+    /// Get the features supported by the homeserver.
+    ///
+    /// This combines the versions and unstable features advertised by the
+    /// homeserver, its [capabilities](Self::get_capabilities) and the client
+    /// discovery information of the server. The result is cached, it is only
+    /// fetched again when the user of the client changes, since the
+    /// capabilities depend on the user.
+    ///
+    /// The stable or unstable endpoints used by the `Client` are already
+    /// selected according to the versions and unstable features of the
+    /// homeserver.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use futures::executor::block_on;
+    /// # use matrix_sdk::Client;
+    /// # use url::Url;
+    /// # block_on(async {
+    /// # let homeserver = Url::parse("http://example.com")?;
+    /// use matrix_sdk::Feature;
+    ///
+    /// let client = Client::new(homeserver).await?;
+    ///
+    /// if client.server_features().await?.supports(Feature::Threads) {
+    ///     // Show the threads of the rooms.
+    /// }
+    /// # anyhow::Ok(()) });
+    /// ```
+    pub async fn server_features(&self) -> HttpResult<ServerFeatures> {
+        let mut cache = self.inner.server_features.lock().await;
+        let user_id = self.user_id().map(ToOwned::to_owned);
+
+        if let Some((cached_user_id, features)) = &*cache {
+            if *cached_user_id == user_id {
+                return Ok(features.clone());
+            }
+        }
+
+        let supported_versions = self.supported_versions().await?.clone();
+        let capabilities =
+            if user_id.is_some() { self.get_capabilities().await? } else { Default::default() };
+        let well_known = self.well_known().await;
+
+        let features = ServerFeatures { supported_versions, capabilities, well_known };
+        *cache = Some((user_id, features.clone()));
+
+        Ok(features)
+    }
+
+    /// Get the client discovery information of the server of the user, or of
+    /// the homeserver if the client is not logged in.
+    async fn well_known(&self) -> Option<discover_homeserver::Response> {
+        let server_name = match self.user_id() {
+            Some(user_id) => user_id.server_name().to_owned(),
+            None => {
+                let homeserver = self.homeserver().await;
+                let authority = match homeserver.port() {
+                    Some(port) => format!("{}:{port}", homeserver.host_str()?),
+                    None => homeserver.host_str()?.to_owned(),
+                };
+                ServerName::parse(authority).ok()?
+            }
+        };
+
+        let response = self
+            .inner
+            .http_client
+            .send(
+                discover_homeserver::Request::new(),
+                // The discovery information is optional, don't insist.
+                Some(RequestConfig::new().disable_retry()),
+                builder::homeserver_from_name(&server_name),
+                None,
+                None,
+                &[MatrixVersion::V1_0],
+            )
+            .await;
+
+        match response {
+            Ok(response) => Some(response),
+            Err(error) => {
+                debug!(%server_name, "No client discovery information: {error}");
+                None
+            }
+        }
+    }
+
     /// Process a [transaction] received from the homeserver which has been
     /// converted into a sync response.
     ///
@@ -299,6 +459,124 @@ impl Client {
         self.inner.http_client.request_config
     }
 
+    /// Get the current rate limiting state of the client.
+    ///
+    /// When the homeserver responds with a `M_LIMIT_EXCEEDED` error, requests
+    /// to rate limited endpoints are paused until the delay it asked for has
+    /// passed. The returned value can be used to observe this, e.g. to show
+    /// it in the UI.
+    pub fn request_backoff(&self) -> ReadOnlyMutable<BackoffState> {
+        self.inner.http_client.scheduler.backoff()
+    }
+
+    /// The current state of the session.
+    pub fn session_state(&self) -> SessionState {
+        self.inner.session_state.get()
+    }
+
+    /// Get a signal of the state of the session.
+    ///
+    /// This can be used to react to a [soft logout], by asking the user to log
+    /// in again, or to a logout initiated by the homeserver.
+    ///
+    /// The sync loops stop when the session is logged out.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use futures::executor::block_on;
+    /// # use url::Url;
+    /// # block_on(async {
+    /// # let homeserver = Url::parse("http://localhost:8080")?;
+    /// use futures::StreamExt;
+    /// use futures_signals::signal::SignalExt;
+    /// use matrix_sdk::{Client, SessionState};
+    ///
+    /// let client = Client::new(homeserver).await?;
+    /// let mut session_states = client.session_state_signal().to_stream();
+    ///
+    /// while let Some(state) = session_states.next().await {
+    ///     if state == SessionState::SoftLoggedOut {
+    ///         // Ask the user for their password and log in again, the device
+    ///         // ID of the current session is used automatically.
+    ///         client.login_username("user", "password").send().await?;
+    ///     }
+    /// }
+    /// # anyhow::Ok(()) });
+    /// ```
+    ///
+    /// [soft logout]: https://spec.matrix.org/v1.3/client-server-api/#soft-logout
+    pub fn session_state_signal(&self) -> impl Signal<Item = SessionState> {
+        self.inner.session_state.signal()
+    }
+
+    pub(crate) fn set_session_state(&self, state: SessionState) {
+        if self.inner.session_state.get() != state {
+            self.inner.session_state.set(state);
+        }
+    }
+
+    /// Save the session with the given persister every time the tokens
+    /// change, and remove it when the client is logged out.
+    ///
+    /// The spawned task stops when the client is dropped.
+    pub(crate) fn spawn_session_persistence(&self, persister: Arc<dyn SessionPersister>) {
+        let inner = Arc::downgrade(&self.inner);
+        let signal = map_ref! {
+            let _tokens = self.session_tokens_changed_signal(),
+            let state = self.session_state_signal() => *state
+        };
+
+        matrix_sdk_common::executor::spawn(signal.for_each(move |state| {
+            let inner = inner.clone();
+            let persister = persister.clone();
+
+            async move {
+                let client = match inner.upgrade() {
+                    Some(inner) => Client { inner },
+                    None => return,
+                };
+
+                let result = if state == SessionState::LoggedOut {
+                    persister.remove().await
+                } else if let Some(session) = client.session() {
+                    let homeserver_url = client.homeserver().await;
+                    persister.save(&PersistedSession { homeserver_url, session }).await
+                } else {
+                    Ok(())
+                };
+
+                if let Err(error) = result {
+                    error!("Failed to persist the session: {error}");
+                }
+
+                client.inner.persisted_session_state.set(Some(state));
+            }
+        }));
+    }
+
+    /// Wait until the session persister handled the current state of the
+    /// session.
+    ///
+    /// Must only be called if the session is persisted, otherwise it never
+    /// returns.
+    #[cfg(all(not(target_arch = "wasm32"), any(feature = "sled", feature = "sqlite")))]
+    pub(crate) async fn wait_for_session_persistence(&self) {
+        let state = self.session_state();
+        self.inner.persisted_session_state.signal().wait_for(Some(state)).await;
+    }
+
+    /// Update the state of the session if the given result is an
+    /// `M_UNKNOWN_TOKEN` error.
+    fn handle_unknown_token<T>(&self, res: &HttpResult<T>) {
+        if let Some(soft_logout) = res.as_ref().err().and_then(unknown_token_soft_logout) {
+            let state =
+                if soft_logout { SessionState::SoftLoggedOut } else { SessionState::LoggedOut };
+            warn!(?state, "The access token was invalidated by the homeserver");
+            self.set_session_state(state);
+        }
+    }
+
     /// Is the client logged in.
     pub fn logged_in(&self) -> bool {
         self.inner.base_client.logged_in()
@@ -318,6 +596,14 @@ impl Client {
         }
     }
 
+    /// The URL where the user can manage their account, as advertised by the
+    /// homeserver alongside the [`authentication_issuer()`].
+    ///
+    /// [`authentication_issuer()`]: Self::authentication_issuer
+    pub fn account_management_url(&self) -> Option<&Url> {
+        self.inner.account_management_url.as_ref()
+    }
+
     fn session_meta(&self) -> Option<&SessionMeta> {
         self.base_client().session_meta()
     }
@@ -515,6 +801,25 @@ impl Client {
         Media::new(self.clone())
     }
 
+    /// Get the OpenID Connect API of the client.
+    #[cfg(feature = "experimental-oidc")]
+    pub fn oidc(&self) -> Oidc {
+        Oidc::new(self.clone())
+    }
+
+    /// Get a driver for a [User-Interactive Authentication] session, that uses
+    /// the given handler to get the credentials required by each stage.
+    ///
+    /// [User-Interactive Authentication]: crate::uiaa
+    pub fn uiaa<H: UiaaHandler>(&self, handler: H) -> Uiaa<H> {
+        Uiaa::new(self.clone(), handler)
+    }
+
+    /// Get the notification settings of the current owner of the client.
+    pub fn notification_settings(&self) -> NotificationSettings {
+        NotificationSettings::new(self.clone())
+    }
+
     /// Register a handler for a specific event type.
     ///
     /// The handler is a function or closure with one or more arguments. The
@@ -892,6 +1197,27 @@ impl Client {
         self.base_client().get_room(room_id).and_then(|room| room::Left::new(self, room))
     }
 
+    /// Send the events that are left in the send queues of the joined rooms.
+    ///
+    /// Events that were queued with [`room::RoomSendQueue::push()`] are
+    /// persisted until the homeserver acknowledges them. This should be called
+    /// after the client was restored, to send the events that were still queued
+    /// when the client was last shut down.
+    ///
+    /// Resolves once every queue is either empty or blocked by an event that
+    /// failed to be sent.
+    pub async fn resume_send_queues(&self) -> Result<()> {
+        for room in self.joined_rooms() {
+            let queue = room.send_queue();
+
+            if !queue.is_empty().await? {
+                queue.retry().await?;
+            }
+        }
+
+        Ok(())
+    }
+
     /// Resolve a room alias to a room id and a list of servers which know
     /// about it.
     ///
@@ -1241,7 +1567,20 @@ impl Client {
             }
         }
 
-        self.inner.base_client.receive_login_response(response).await?;
+        if self.session_state() == SessionState::SoftLoggedOut {
+            // The state and encryption keys of the session are kept, only the
+            // tokens change.
+            self.base_client().set_session_tokens(SessionTokens {
+                access_token: response.access_token.clone(),
+                refresh_token: response.refresh_token.clone(),
+            });
+        } else {
+            self.inner.base_client.receive_login_response(response).await?;
+        }
+
+        #[cfg(feature = "experimental-oidc")]
+        self.oidc().set_logged_in(false);
+        self.set_session_state(SessionState::LoggedIn);
 
         Ok(())
     }
@@ -1306,7 +1645,12 @@ impl Client {
     ///
     /// [`login`]: #method.login
     pub async fn restore_login(&self, session: Session) -> Result<()> {
-        Ok(self.inner.base_client.restore_login(session).await?)
+        self.inner.base_client.restore_login(session).await?;
+        #[cfg(feature = "experimental-oidc")]
+        self.oidc().set_logged_in(false);
+        self.set_session_state(SessionState::LoggedIn);
+
+        Ok(())
     }
 
     /// Refresh the access token.
@@ -1328,6 +1672,10 @@ impl Client {
     /// It can also be called at any time when a refresh token is available, it
     /// will invalidate the previous access token.
     ///
+    /// If the homeserver doesn't support [`Feature::RefreshTokens`], this
+    /// returns a [`RefreshTokenError::NotSupported`] error without sending a
+    /// request.
+    ///
     /// The new tokens in the response will be used by the `Client` and should
     /// be persisted to be able to [restore the session]. The response will
     /// always contain an access token that replaces the previous one. It
@@ -1412,20 +1760,20 @@ impl Client {
                 .as_ref()
                 .ok_or(RefreshTokenError::RefreshTokenRequired)?
                 .clone();
-            let request = refresh_token::v3::Request::new(&refresh_token);
-
-            let res = self
-                .inner
-                .http_client
-                .send(
-                    request,
-                    None,
-                    self.homeserver().await.to_string(),
-                    self.access_token().as_deref(),
-                    self.user_id(),
-                    self.server_versions().await?,
-                )
-                .await;
+
+            #[cfg(feature = "experimental-oidc")]
+            let res = if self.oidc().logged_in() {
+                // The session was created with OpenID Connect, so the tokens
+                // are refreshed with the issuer.
+                self.oidc()
+                    .refresh_access_token(&refresh_token)
+                    .await
+                    .map_err(|error| HttpError::from(RefreshTokenError::Oidc(Arc::new(error))))
+            } else {
+                self.send_refresh_token_request(&refresh_token).await
+            };
+            #[cfg(not(feature = "experimental-oidc"))]
+            let res = self.send_refresh_token_request(&refresh_token).await;
 
             match res {
                 Ok(res) => {
@@ -1445,6 +1793,8 @@ impl Client {
                     )) = &error
                     {
                         Err(RefreshTokenError::ClientApi(api_error.to_owned()))
+                    } else if let HttpError::RefreshToken(refresh_error) = &error {
+                        Err(refresh_error.clone())
                     } else {
                         Err(RefreshTokenError::UnableToRefreshToken)
                     };
@@ -1460,6 +1810,29 @@ impl Client {
         }
     }
 
+    async fn send_refresh_token_request(
+        &self,
+        refresh_token: &str,
+    ) -> HttpResult<refresh_token::v3::Response> {
+        if !self.supports_feature(Feature::RefreshTokens).await? {
+            return Err(RefreshTokenError::NotSupported.into());
+        }
+
+        let request = refresh_token::v3::Request::new(refresh_token);
+
+        self.inner
+            .http_client
+            .send(
+                request,
+                None,
+                self.homeserver().await.to_string(),
+                self.access_token().as_deref(),
+                self.user_id(),
+                &self.request_versions(&refresh_token::v3::Request::METADATA).await?,
+            )
+            .await
+    }
+
     /// Register a user to the server.
     ///
     /// # Arguments
@@ -1512,6 +1885,145 @@ impl Client {
         self.send(request, config).await
     }
 
+    /// Register a new user account with a username and password.
+    ///
+    /// The homeserver might require to complete some [User-Interactive
+    /// Authentication] stages, like providing a registration token or
+    /// validating an email address. The credentials for those stages are
+    /// provided with [`RegistrationBuilder::uiaa_handler()`].
+    ///
+    /// On success, the client is logged in with the new account.
+    ///
+    /// # Arguments
+    ///
+    /// * `username` - The localpart of the user ID of the new account.
+    ///
+    /// * `password` - The password of the new account.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use futures::executor::block_on;
+    /// # use matrix_sdk::{
+    /// #     async_trait,
+    /// #     ruma::api::client::uiaa::UiaaInfo,
+    /// #     uiaa::UiaaHandler,
+    /// #     Client,
+    /// # };
+    /// # use url::Url;
+    /// #[derive(Debug)]
+    /// struct TokenHandler;
+    ///
+    /// #[async_trait]
+    /// impl UiaaHandler for TokenHandler {
+    ///     async fn registration_token(&self, _info: &UiaaInfo) -> Option<String> {
+    ///         Some("my-registration-token".to_owned())
+    ///     }
+    /// }
+    ///
+    /// # block_on(async {
+    /// # let homeserver = Url::parse("http://localhost:8080")?;
+    /// let client = Client::new(homeserver).await?;
+    ///
+    /// if client.is_username_available("user").await? {
+    ///     let session = client
+    ///         .register_username("user", "password")
+    ///         .uiaa_handler(TokenHandler)
+    ///         .initial_device_display_name("My app")
+    ///         .send()
+    ///         .await?;
+    ///
+    ///     println!("Registered {}", session.user_id);
+    /// }
+    /// # anyhow::Ok(()) });
+    /// ```
+    ///
+    /// [User-Interactive Authentication]: crate::uiaa
+    pub fn register_username<'a>(
+        &self,
+        username: &'a str,
+        password: &'a str,
+    ) -> RegistrationBuilder<'a> {
+        RegistrationBuilder::new_user(self.clone(), username, password)
+    }
+
+    /// Register a new guest account.
+    ///
+    /// Guest accounts have limited access to the homeserver, and can later be
+    /// upgraded to user accounts with [`Client::upgrade_guest()`].
+    ///
+    /// On success, the client is logged in with the new guest account.
+    pub fn register_guest(&self) -> RegistrationBuilder<'static> {
+        RegistrationBuilder::new_guest(self.clone())
+    }
+
+    /// Upgrade the guest account the client is logged in with to a user
+    /// account with the given username and password.
+    ///
+    /// The upgraded account keeps the device of the guest, so the state of the
+    /// client is kept too. Only the access token changes.
+    pub fn upgrade_guest<'a>(
+        &self,
+        username: &'a str,
+        password: &'a str,
+    ) -> RegistrationBuilder<'a> {
+        RegistrationBuilder::new_guest_upgrade(self.clone(), username, password)
+    }
+
+    /// Check whether the given username is available for registration on the
+    /// homeserver.
+    ///
+    /// Returns `false` if the username is already taken. Other problems with
+    /// the username, like [`ErrorKind::InvalidUsername`], are returned as
+    /// errors.
+    ///
+    /// # Arguments
+    ///
+    /// * `username` - The localpart of the user ID to check.
+    pub async fn is_username_available(&self, username: &str) -> HttpResult<bool> {
+        let request = get_username_availability::v3::Request::new(username);
+
+        match self.send(request, None).await {
+            Ok(response) => Ok(response.available),
+            Err(error) if matches!(error.client_api_error_kind(), Some(ErrorKind::UserInUse)) => {
+                Ok(false)
+            }
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Request a token to validate an email address before registering a new
+    /// account with it.
+    ///
+    /// The returned `sid` and the `client_secret` are the credentials for the
+    /// `m.login.email.identity` stage, once the user followed the instructions
+    /// in the email. See [`Account::request_3pid_email_token()`] for the
+    /// equivalent method for accounts that already exist.
+    ///
+    /// # Arguments
+    ///
+    /// * `client_secret` - A client-generated secret string used to protect
+    /// this session.
+    ///
+    /// * `email` - The email address to validate.
+    ///
+    /// * `send_attempt` - The attempt number. This number needs to be
+    /// incremented if you want to request another token for the same
+    /// validation.
+    pub async fn request_registration_email_token(
+        &self,
+        client_secret: &ClientSecret,
+        email: &str,
+        send_attempt: UInt,
+    ) -> HttpResult<request_registration_token_via_email::v3::Response> {
+        let request = request_registration_token_via_email::v3::Request::new(
+            client_secret,
+            email,
+            send_attempt,
+        );
+        self.send(request, None).await
+    }
+
     /// Get or upload a sync filter.
     ///
     /// This method will either get a filter ID from the store or upload the
@@ -1737,6 +2249,81 @@ impl Client {
         self.send(request, None).await
     }
 
+    /// Get a stream over the rooms published in the room directory of a
+    /// server.
+    ///
+    /// The rooms are fetched with [`public_rooms_filtered()`], a page at a
+    /// time, as the stream is polled. The stream ends after the last page, or
+    /// after the first error.
+    ///
+    /// # Arguments
+    ///
+    /// * `server` - The name of the server, if `None` the homeserver is used.
+    ///
+    /// * `search_term` - A string to search for in the name, topic or alias
+    ///   of the rooms.
+    ///
+    /// * `page_size` - The number of rooms to fetch per request, if `None`
+    ///   the server picks a default.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use url::Url;
+    /// # use matrix_sdk::Client;
+    /// # use futures::executor::block_on;
+    /// # block_on(async {
+    /// # let homeserver = Url::parse("http://example.com")?;
+    /// use futures::{pin_mut, StreamExt};
+    /// # let client = Client::new(homeserver).await?;
+    ///
+    /// let rooms = client.public_rooms_stream(None, Some("rust".to_owned()), Some(20));
+    /// pin_mut!(rooms);
+    ///
+    /// while let Some(room) = rooms.next().await {
+    ///     println!("Found room {:?}", room?.name);
+    /// }
+    /// # anyhow::Ok(()) });
+    /// ```
+    ///
+    /// [`public_rooms_filtered()`]: Self::public_rooms_filtered
+    pub fn public_rooms_stream(
+        &self,
+        server: Option<OwnedServerName>,
+        search_term: Option<String>,
+        page_size: Option<u32>,
+    ) -> impl Stream<Item = HttpResult<PublicRoomsChunk>> + '_ {
+        async_stream::try_stream! {
+            let mut since: Option<String> = None;
+
+            loop {
+                let mut filter = Filter::new();
+                filter.generic_search_term = search_term.as_deref();
+
+                let request = assign!(get_public_rooms_filtered::v3::Request::new(), {
+                    server: server.as_deref(),
+                    limit: page_size.map(UInt::from),
+                    since: since.as_deref(),
+                    filter,
+                });
+                let response = self.public_rooms_filtered(request).await?;
+                let is_empty = response.chunk.is_empty();
+
+                for room in response.chunk {
+                    yield room;
+                }
+
+                match response.next_batch {
+                    // Don't loop forever if the server keeps sending the same token.
+                    Some(next_batch) if !is_empty && since.as_ref() != Some(&next_batch) => {
+                        since = Some(next_batch);
+                    }
+                    _ => break,
+                }
+            }
+        }
+    }
+
     /// Send an arbitrary request to the server, without updating client state.
     ///
     /// **Warning:** Because this method *does not* update the client state, it
@@ -1785,34 +2372,8 @@ impl Client {
         Request: OutgoingRequest + Clone + Debug,
         HttpError: From<FromHttpResponseError<Request::EndpointError>>,
     {
-        let res = self.send_inner(request.clone(), config, None).await;
-
-        // If this is an `M_UNKNOWN_TOKEN` error and refresh token handling is active,
-        // try to refresh the token and retry the request.
-        if self.inner.handle_refresh_tokens {
-            if let Err(HttpError::Api(FromHttpResponseError::Server(ServerError::Known(
-                RumaApiError::ClientApi(error),
-            )))) = &res
-            {
-                if matches!(error.kind, ErrorKind::UnknownToken { .. }) {
-                    let refresh_res = self.refresh_access_token().await;
-
-                    if let Err(refresh_error) = refresh_res {
-                        match &refresh_error {
-                            HttpError::RefreshToken(RefreshTokenError::RefreshTokenRequired) => {
-                                // Refreshing access tokens is not supported by
-                                // this `Session`, ignore.
-                            }
-                            _ => {
-                                return Err(refresh_error);
-                            }
-                        }
-                    } else {
-                        return self.send_inner(request, config, None).await;
-                    }
-                }
-            }
-        }
+        let res = self.send_and_refresh(request, config, None).await;
+        self.handle_unknown_token(&res);
 
         res
     }
@@ -1826,6 +2387,22 @@ impl Client {
         config: Option<RequestConfig>,
         homeserver: Option<String>,
     ) -> HttpResult<Request::IncomingResponse>
+    where
+        Request: OutgoingRequest + Clone + Debug,
+        HttpError: From<FromHttpResponseError<Request::EndpointError>>,
+    {
+        let res = self.send_and_refresh(request, config, homeserver).await;
+        self.handle_unknown_token(&res);
+
+        res
+    }
+
+    async fn send_and_refresh<Request>(
+        &self,
+        request: Request,
+        config: Option<RequestConfig>,
+        homeserver: Option<String>,
+    ) -> HttpResult<Request::IncomingResponse>
     where
         Request: OutgoingRequest + Clone + Debug,
         HttpError: From<FromHttpResponseError<Request::EndpointError>>,
@@ -1844,9 +2421,12 @@ impl Client {
 
                     if let Err(refresh_error) = refresh_res {
                         match &refresh_error {
-                            HttpError::RefreshToken(RefreshTokenError::RefreshTokenRequired) => {
+                            HttpError::RefreshToken(
+                                RefreshTokenError::RefreshTokenRequired
+                                | RefreshTokenError::NotSupported,
+                            ) => {
                                 // Refreshing access tokens is not supported by
-                                // this `Session`, ignore.
+                                // this `Session` or the homeserver, ignore.
                             }
                             _ => {
                                 return Err(refresh_error);
@@ -1883,13 +2463,67 @@ impl Client {
                 homeserver,
                 self.access_token().as_deref(),
                 self.user_id(),
-                self.server_versions().await?,
+                &self.request_versions(&Request::METADATA).await?,
+            )
+            .await
+    }
+
+    /// Send the given request with `body` as its streamed body, of
+    /// `content_length` bytes.
+    ///
+    /// Contrary to [`Client::send()`], the request is not retried after
+    /// refreshing the access token, since the body can only be read once.
+    pub(crate) async fn send_with_body_stream<Request>(
+        &self,
+        request: Request,
+        body: ByteStream,
+        content_length: u64,
+        config: Option<RequestConfig>,
+    ) -> HttpResult<Request::IncomingResponse>
+    where
+        Request: OutgoingRequest + Debug,
+        HttpError: From<FromHttpResponseError<Request::EndpointError>>,
+    {
+        self.inner
+            .http_client
+            .send_with_body_stream(
+                request,
+                body,
+                content_length,
+                config,
+                self.homeserver().await.to_string(),
+                self.access_token().as_deref(),
+                self.user_id(),
+                &self.request_versions(&Request::METADATA).await?,
+            )
+            .await
+    }
+
+    /// Send the given request and stream the body of its response.
+    pub(crate) async fn send_with_response_stream<Request>(
+        &self,
+        request: Request,
+        config: Option<RequestConfig>,
+    ) -> HttpResult<http::Response<ByteStream>>
+    where
+        Request: OutgoingRequest + Debug,
+        HttpError: From<FromHttpResponseError<Request::EndpointError>>,
+    {
+        self.inner
+            .http_client
+            .send_with_response_stream(
+                request,
+                config,
+                self.homeserver().await.to_string(),
+                self.access_token().as_deref(),
+                self.user_id(),
+                &self.request_versions(&Request::METADATA).await?,
             )
             .await
     }
 
-    async fn request_server_versions(&self) -> HttpResult<Box<[MatrixVersion]>> {
-        let server_versions: Box<[MatrixVersion]> = self
+    async fn request_supported_versions(&self) -> HttpResult<SupportedVersions> {
+        let response = self
             .inner
             .http_client
             .send(
@@ -1900,28 +2534,59 @@ impl Client {
                 None,
                 &[MatrixVersion::V1_0],
             )
-            .await?
-            .known_versions()
-            .into_iter()
-            .collect();
+            .await?;
 
-        if server_versions.is_empty() {
-            Ok(vec![MatrixVersion::V1_0].into())
-        } else {
-            Ok(server_versions)
-        }
+        Ok(SupportedVersions::from_response(response))
     }
 
-    pub(crate) async fn server_versions(&self) -> HttpResult<&[MatrixVersion]> {
+    async fn supported_versions(&self) -> HttpResult<&SupportedVersions> {
         #[cfg(target_arch = "wasm32")]
-        let server_versions =
-            self.inner.server_versions.get_or_try_init(self.request_server_versions()).await?;
+        let supported_versions =
+            self.inner.server_versions.get_or_try_init(self.request_supported_versions()).await?;
 
         #[cfg(not(target_arch = "wasm32"))]
-        let server_versions =
-            self.inner.server_versions.get_or_try_init(|| self.request_server_versions()).await?;
+        let supported_versions = self
+            .inner
+            .server_versions
+            .get_or_try_init(|| self.request_supported_versions())
+            .await?;
+
+        Ok(supported_versions)
+    }
 
-        Ok(server_versions)
+    pub(crate) async fn server_versions(&self) -> HttpResult<&[MatrixVersion]> {
+        Ok(&self.supported_versions().await?.versions)
+    }
+
+    /// Whether the homeserver supports the given feature, according to the
+    /// versions and unstable features it advertises.
+    ///
+    /// Unlike [`Client::server_features()`], this doesn't request the
+    /// capabilities of the homeserver, so it can be used while refreshing the
+    /// access token.
+    pub(crate) async fn supports_feature(&self, feature: Feature) -> HttpResult<bool> {
+        Ok(self.supported_versions().await?.supports(feature))
+    }
+
+    /// Make sure that the homeserver supports the given feature, according to
+    /// [`Client::supports_feature()`].
+    pub(crate) async fn ensure_feature(&self, feature: Feature) -> Result<()> {
+        if self.supports_feature(feature.clone()).await? {
+            Ok(())
+        } else {
+            Err(Error::UnsupportedFeature(feature))
+        }
+    }
+
+    /// The versions to use to build a request for the endpoint with the given
+    /// metadata, to select its stable or unstable path.
+    async fn request_versions(&self, metadata: &Metadata) -> HttpResult<Cow<'_, [MatrixVersion]>> {
+        let supported_versions = self.supported_versions().await?;
+
+        Ok(match supported_versions.versions_for_endpoint(metadata) {
+            Some(versions) => Cow::Owned(versions.into_vec()),
+            None => Cow::Borrowed(&supported_versions.versions),
+        })
     }
 
     /// Get information of all our own devices.
@@ -1963,42 +2628,40 @@ impl Client {
     /// request needs to set this to `None` and will always fail with an
     /// `UiaaResponse`. The response will contain information for the
     /// interactive auth and the same request needs to be made but this time
-    /// with some `auth_data` provided.
+    /// with some `auth_data` provided. [`Uiaa::send()`] takes care of it.
     ///
     /// ```no_run
     /// # use matrix_sdk::{
-    /// #    ruma::{
-    /// #        api::{
-    /// #            client::uiaa,
-    /// #            error::{FromHttpResponseError, ServerError},
-    /// #        },
-    /// #        device_id,
-    /// #    },
-    /// #    Client, Error, config::SyncSettings,
+    /// #    async_trait,
+    /// #    ruma::{api::client::uiaa::UiaaInfo, device_id},
+    /// #    uiaa::UiaaHandler,
+    /// #    Client,
     /// # };
     /// # use futures::executor::block_on;
-    /// # use serde_json::json;
     /// # use url::Url;
-    /// # use std::collections::BTreeMap;
+    /// #[derive(Debug)]
+    /// struct PasswordHandler;
+    ///
+    /// #[async_trait]
+    /// impl UiaaHandler for PasswordHandler {
+    ///     async fn password(&self, _info: &UiaaInfo) -> Option<String> {
+    ///         Some("wordpass".to_owned())
+    ///     }
+    /// }
+    ///
     /// # block_on(async {
     /// # let homeserver = Url::parse("http://localhost:8080")?;
-    /// # let mut client = Client::new(homeserver).await?;
+    /// # let client = Client::new(homeserver).await?;
     /// let devices = &[device_id!("DEVICEID").to_owned()];
     ///
-    /// if let Err(e) = client.delete_devices(devices, None).await {
-    ///     if let Some(info) = e.uiaa_response() {
-    ///         let mut password = uiaa::Password::new(
-    ///             uiaa::UserIdentifier::UserIdOrLocalpart("example"),
-    ///             "wordpass",
-    ///         );
-    ///         password.session = info.session.as_deref();
-    ///
-    ///         client
-    ///             .delete_devices(devices, Some(uiaa::AuthData::Password(password)))
-    ///             .await?;
-    ///     }
-    /// }
+    /// client
+    ///     .uiaa(PasswordHandler)
+    ///     .send(|client, auth| async move {
+    ///         client.delete_devices(devices, auth.auth_data()).await
+    ///     })
+    ///     .await?;
     /// # anyhow::Ok(()) });
+    /// ```
     pub async fn delete_devices(
         &self,
         devices: &[OwnedDeviceId],
@@ -2121,7 +2784,7 @@ impl Client {
             filter: sync_settings.filter.as_ref(),
             since: sync_settings.token.as_deref(),
             full_state: sync_settings.full_state,
-            set_presence: &PresenceState::Online,
+            set_presence: &sync_settings.set_presence,
             timeout: sync_settings.timeout,
         });
         let mut request_config = self.request_config();
@@ -2300,6 +2963,10 @@ impl Client {
     /// asked for a regular stop, the result will be `Ok(())` otherwise the
     /// `Err(Error)` is returned.
     ///
+    /// The sync also stops with `Ok(())` after the callback handled the error
+    /// caused by the homeserver logging the session out, see
+    /// [`Client::session_state()`].
+    ///
     /// _Note_: Lower-level configuration (e.g. for retries) are not changed by
     /// this, and are handled first without sending the result to the
     /// callback. Only after they have exceeded is the `Result` handed to
@@ -2360,8 +3027,9 @@ impl Client {
 
         loop {
             let result = self.sync_loop_helper(&mut sync_settings).await;
+            let is_logged_out = is_unknown_token(&result);
 
-            if callback(result).await? == LoopCtrl::Break {
+            if callback(result).await? == LoopCtrl::Break || is_logged_out {
                 break;
             }
 
@@ -2377,6 +3045,9 @@ impl Client {
     /// equivalent to the [`Client::sync`] method but the responses are provided
     /// as an async stream.
     ///
+    /// The stream ends after yielding the error caused by the homeserver
+    /// logging the session out, see [`Client::session_state()`].
+    ///
     /// # Arguments
     ///
     /// * `sync_settings` - Settings for the sync call. *Note* that those
@@ -2426,7 +3097,14 @@ impl Client {
 
         async_stream::stream! {
             loop {
-                yield self.sync_loop_helper(&mut sync_settings).await;
+                let result = self.sync_loop_helper(&mut sync_settings).await;
+                let is_logged_out = is_unknown_token(&result);
+
+                yield result;
+
+                if is_logged_out {
+                    break;
+                }
 
                 Client::delay_sync(&mut last_sync_time).await
             }
@@ -2439,6 +3117,87 @@ impl Client {
         self.inner.base_client.sync_token().await
     }
 
+    /// Get the presence of the given user from the homeserver.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The ID of the user.
+    pub async fn get_presence(&self, user_id: &UserId) -> HttpResult<get_presence::v3::Response> {
+        let request = get_presence::v3::Request::new(user_id);
+        self.send(request, None).await
+    }
+
+    /// Get a stream of the presence of the given user.
+    ///
+    /// The stream yields the presence known locally first, if any, and then
+    /// the presence updates of the user received during sync.
+    ///
+    /// If updates are received faster than the stream is polled, only the
+    /// latest one is yielded. The stream ends once the user doesn't share a
+    /// joined room with the current user anymore.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The ID of the user.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use futures::executor::block_on;
+    /// # use matrix_sdk::{ruma::user_id, Client};
+    /// # use url::Url;
+    /// # block_on(async {
+    /// # let homeserver = Url::parse("http://example.com")?;
+    /// use futures::StreamExt;
+    ///
+    /// let client = Client::new(homeserver).await?;
+    ///
+    /// let mut presence = Box::pin(client.presence_stream(user_id!("@alice:example.org")).await?);
+    ///
+    /// while let Some(event) = presence.next().await {
+    ///     println!("Alice is now {}", event.content.presence);
+    /// }
+    /// # anyhow::Ok(()) });
+    /// ```
+    pub async fn presence_stream(
+        &self,
+        user_id: &UserId,
+    ) -> Result<impl Stream<Item = PresenceEvent>> {
+        let presence = self.inner.presences.get(user_id).map(|presence| presence.clone());
+        let presence = match presence {
+            Some(presence) => presence,
+            None => {
+                let event = self
+                    .store()
+                    .get_presence_event(user_id)
+                    .await?
+                    .and_then(|event| event.deserialize().ok());
+
+                self.inner
+                    .presences
+                    .entry(user_id.to_owned())
+                    .or_insert_with(|| Mutable::new(event))
+                    .clone()
+            }
+        };
+
+        Ok(presence.signal_cloned().to_stream().filter_map(future::ready))
+    }
+
+    /// Stop observing the presence of the users that don't share a joined room
+    /// with the current user anymore, which ends their presence streams.
+    pub(crate) async fn prune_presences(&self) -> Result<()> {
+        let mut user_ids = BTreeSet::new();
+
+        for room in self.joined_rooms() {
+            user_ids.extend(room.joined_user_ids().await?);
+        }
+
+        self.inner.presences.retain(|user_id, _| user_ids.contains(user_id));
+
+        Ok(())
+    }
+
     /// Gets information about the owner of a given access token.
     pub async fn whoami(&self) -> HttpResult<whoami::v3::Response> {
         let request = whoami::v3::Request::new();
@@ -2448,10 +3207,36 @@ impl Client {
     /// Log out the current user
     pub async fn logout(&self) -> HttpResult<logout::v3::Response> {
         let request = logout::v3::Request::new();
-        self.send(request, None).await
+        let response = self.send(request, None).await?;
+        self.set_session_state(SessionState::LoggedOut);
+
+        Ok(response)
+    }
+}
+
+/// Whether the given error is an `M_UNKNOWN_TOKEN` error, and if so, whether
+/// the session was only soft logged out.
+fn unknown_token_soft_logout(error: &HttpError) -> Option<bool> {
+    let error = match error {
+        HttpError::Api(FromHttpResponseError::Server(ServerError::Known(
+            RumaApiError::ClientApi(error),
+        )))
+        | HttpError::RefreshToken(RefreshTokenError::ClientApi(error)) => error,
+        _ => return None,
+    };
+
+    match error.kind {
+        ErrorKind::UnknownToken { soft_logout } => Some(soft_logout),
+        _ => None,
     }
 }
 
+/// Whether the given sync result is an error caused by the homeserver logging
+/// the session out.
+fn is_unknown_token<T>(result: &Result<T>) -> bool {
+    matches!(result, Err(Error::Http(error)) if unknown_token_soft_logout(error).is_some())
+}
+
 // The http mocking library is not supported for wasm32
 #[cfg(all(test, not(target_arch = "wasm32")))]
 pub(crate) mod tests {
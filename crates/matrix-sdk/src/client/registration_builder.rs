@@ -0,0 +1,235 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use async_trait::async_trait;
+use matrix_sdk_base::{Session, SessionTokens};
+use ruma::{
+    api::client::account::register::{self, RegistrationKind},
+    assign,
+};
+use tracing::{info, instrument};
+
+use super::Client;
+use crate::{
+    config::RequestConfig,
+    uiaa::{Uiaa, UiaaHandler},
+    Error, Result,
+};
+
+/// How the account is registered.
+#[derive(Debug)]
+enum RegistrationMethod<'a> {
+    /// A new user account.
+    User { username: &'a str, password: &'a str },
+    /// A new guest account.
+    Guest,
+    /// An upgrade of the guest account the client is logged in with to a user
+    /// account.
+    GuestUpgrade { username: &'a str, password: &'a str },
+}
+
+impl<'a> RegistrationMethod<'a> {
+    fn kind(&self) -> RegistrationKind {
+        match self {
+            RegistrationMethod::Guest => RegistrationKind::Guest,
+            _ => RegistrationKind::User,
+        }
+    }
+
+    fn credentials(&self) -> Option<(&'a str, &'a str)> {
+        match *self {
+            RegistrationMethod::User { username, password }
+            | RegistrationMethod::GuestUpgrade { username, password } => Some((username, password)),
+            RegistrationMethod::Guest => None,
+        }
+    }
+
+    fn tracing_desc(&self) -> &'static str {
+        match self {
+            RegistrationMethod::User { .. } => "user",
+            RegistrationMethod::Guest => "guest",
+            RegistrationMethod::GuestUpgrade { .. } => "guest upgrade",
+        }
+    }
+}
+
+/// A [`UiaaHandler`] that doesn't provide any credentials.
+#[derive(Debug)]
+struct NoCredentials;
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl UiaaHandler for NoCredentials {}
+
+/// Builder type used to configure optional settings for registering an
+/// account.
+///
+/// Created with [`Client::register_username`], [`Client::register_guest`] or
+/// [`Client::upgrade_guest`]. Finalized with [`.send()`](Self::send).
+#[allow(missing_debug_implementations)]
+pub struct RegistrationBuilder<'a> {
+    client: Client,
+    method: RegistrationMethod<'a>,
+    uiaa_handler: Box<dyn UiaaHandler>,
+    device_id: Option<&'a str>,
+    initial_device_display_name: Option<&'a str>,
+    request_refresh_token: bool,
+}
+
+impl<'a> RegistrationBuilder<'a> {
+    fn new(client: Client, method: RegistrationMethod<'a>) -> Self {
+        Self {
+            client,
+            method,
+            uiaa_handler: Box::new(NoCredentials),
+            device_id: None,
+            initial_device_display_name: None,
+            request_refresh_token: false,
+        }
+    }
+
+    pub(super) fn new_user(client: Client, username: &'a str, password: &'a str) -> Self {
+        Self::new(client, RegistrationMethod::User { username, password })
+    }
+
+    pub(super) fn new_guest(client: Client) -> Self {
+        Self::new(client, RegistrationMethod::Guest)
+    }
+
+    pub(super) fn new_guest_upgrade(client: Client, username: &'a str, password: &'a str) -> Self {
+        Self::new(client, RegistrationMethod::GuestUpgrade { username, password })
+    }
+
+    /// Set the handler providing the credentials for the [User-Interactive
+    /// Authentication] stages required by the homeserver, like a registration
+    /// token, a validated email address or the acceptance of the terms of
+    /// service.
+    ///
+    /// The `m.login.dummy` stage is always completed automatically.
+    ///
+    /// [User-Interactive Authentication]: crate::uiaa
+    pub fn uiaa_handler(mut self, handler: impl UiaaHandler + 'static) -> Self {
+        self.uiaa_handler = Box::new(handler);
+        self
+    }
+
+    /// Set the device ID.
+    ///
+    /// The device ID is a unique ID that will be associated with this session.
+    /// If not set, the homeserver will create one.
+    ///
+    /// This is ignored when upgrading a guest account, the device of the guest
+    /// is kept.
+    pub fn device_id(mut self, value: &'a str) -> Self {
+        self.device_id = Some(value);
+        self
+    }
+
+    /// Set the initial device display name.
+    ///
+    /// The device display name is the public name that will be associated with
+    /// the device ID.
+    pub fn initial_device_display_name(mut self, value: &'a str) -> Self {
+        self.initial_device_display_name = Some(value);
+        self
+    }
+
+    /// Advertise support for [refreshing access tokens].
+    ///
+    /// See [`LoginBuilder::request_refresh_token()`] for more details.
+    ///
+    /// [refreshing access tokens]: https://spec.matrix.org/v1.3/client-server-api/#refreshing-access-tokens
+    /// [`LoginBuilder::request_refresh_token()`]: crate::LoginBuilder::request_refresh_token
+    pub fn request_refresh_token(mut self) -> Self {
+        self.request_refresh_token = true;
+        self
+    }
+
+    /// Send the registration request, completing the authentication stages
+    /// required by the homeserver.
+    ///
+    /// On success, the `Client` is logged in with the new account and the
+    /// returned [`Session`] can be persisted to [restore the login] later.
+    ///
+    /// [restore the login]: Client::restore_login
+    #[instrument(
+        target = "matrix_sdk::client",
+        name = "register",
+        skip_all,
+        fields(method = self.method.tracing_desc()),
+    )]
+    pub async fn send(self) -> Result<Session> {
+        let homeserver = self.client.homeserver().await;
+        info!(homeserver = homeserver.as_str(), "Registering");
+
+        let is_upgrade = matches!(self.method, RegistrationMethod::GuestUpgrade { .. });
+        let device_id = if is_upgrade {
+            Some(self.client.device_id().ok_or(Error::AuthenticationRequired)?.as_str())
+        } else {
+            self.device_id
+        };
+        let config = if is_upgrade || self.client.inner.appservice_mode {
+            Some(RequestConfig::short_retry().force_auth())
+        } else {
+            None
+        };
+
+        let credentials = self.method.credentials();
+        let username = credentials.map(|(username, _)| username);
+        let password = credentials.map(|(_, password)| password);
+        let initial_device_display_name = self.initial_device_display_name;
+        let method = &self.method;
+        let refresh_token = self.request_refresh_token;
+
+        let response = Uiaa::new(self.client.clone(), self.uiaa_handler)
+            .send(|client, auth| async move {
+                let request = assign!(register::v3::Request::new(), {
+                    username,
+                    password,
+                    device_id: device_id.map(Into::into),
+                    initial_device_display_name,
+                    auth: auth.auth_data(),
+                    kind: method.kind(),
+                    refresh_token,
+                });
+
+                client.send(request, config).await
+            })
+            .await?;
+
+        // The homeserver always logs the new account in, since login wasn't
+        // inhibited.
+        let (access_token, device_id) =
+            response.access_token.zip(response.device_id).ok_or(Error::AuthenticationRequired)?;
+        let session = Session {
+            access_token,
+            refresh_token: response.refresh_token,
+            user_id: response.user_id,
+            device_id,
+        };
+
+        if is_upgrade {
+            // The user ID and device ID of the guest are kept, only the tokens
+            // change.
+            self.client.base_client().set_session_tokens(SessionTokens {
+                access_token: session.access_token.clone(),
+                refresh_token: session.refresh_token.clone(),
+            });
+        } else {
+            self.client.restore_login(session.clone()).await?;
+        }
+
+        Ok(session)
+    }
+}
@@ -0,0 +1,260 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::BTreeMap;
+
+use ruma::api::{
+    client::discovery::{
+        discover_homeserver, get_capabilities::Capabilities, get_supported_versions,
+    },
+    MatrixVersion, Metadata,
+};
+
+use crate::room::get_threads;
+
+/// The unstable feature of refresh tokens.
+const REFRESH_TOKENS_UNSTABLE_FEATURE: &str = "org.matrix.msc2918";
+/// The unstable feature of sliding sync.
+const SLIDING_SYNC_UNSTABLE_FEATURE: &str = "org.matrix.msc3575";
+
+/// The versions and unstable features advertised by the homeserver, as
+/// returned by the `/versions` endpoint.
+#[derive(Clone, Debug)]
+pub(crate) struct SupportedVersions {
+    pub(crate) versions: Box<[MatrixVersion]>,
+    pub(crate) unstable_features: BTreeMap<String, bool>,
+}
+
+impl SupportedVersions {
+    /// Versions set manually, without unstable features.
+    pub(crate) fn from_versions(versions: Box<[MatrixVersion]>) -> Self {
+        Self { versions, unstable_features: BTreeMap::new() }
+    }
+
+    pub(crate) fn from_response(response: get_supported_versions::Response) -> Self {
+        let mut versions: Box<[MatrixVersion]> = response.known_versions().collect();
+        if versions.is_empty() {
+            versions = vec![MatrixVersion::V1_0].into();
+        }
+
+        Self { versions, unstable_features: response.unstable_features }
+    }
+
+    fn has_unstable_feature(&self, feature: &str) -> bool {
+        self.unstable_features.get(feature).copied().unwrap_or(false)
+    }
+
+    /// Whether the given feature is supported according to these versions and
+    /// unstable features.
+    ///
+    /// Features that depend on the capabilities of the homeserver are never
+    /// supported here.
+    pub(crate) fn supports(&self, feature: Feature) -> bool {
+        match feature {
+            Feature::Version(version) => self.versions.contains(&version),
+            Feature::Unstable(feature) => self.has_unstable_feature(&feature),
+            Feature::RefreshTokens => {
+                self.versions.contains(&MatrixVersion::V1_3)
+                    || self.has_unstable_feature(REFRESH_TOKENS_UNSTABLE_FEATURE)
+            }
+            Feature::Threads => {
+                let prefix = get_threads::UNSTABLE_PREFIX;
+                self.has_unstable_feature(prefix)
+                    || self.has_unstable_feature(&format!("{prefix}.stable"))
+            }
+            Feature::SlidingSync => self.has_unstable_feature(SLIDING_SYNC_UNSTABLE_FEATURE),
+            Feature::ChangePassword => false,
+        }
+    }
+
+    /// The versions to build a request for the endpoint with the given
+    /// metadata with.
+    ///
+    /// Ruma only uses the stable path of an endpoint if one of the versions
+    /// where it is stable is supported. Homeservers can also advertise that
+    /// they support the stable path of an endpoint before such a version, with
+    /// the `{unstable prefix}.stable` unstable feature. In this case, the
+    /// version where the endpoint was added is returned with the supported
+    /// ones.
+    ///
+    /// Returns `None` if the supported versions should be used as-is.
+    pub(crate) fn versions_for_endpoint(
+        &self,
+        metadata: &Metadata,
+    ) -> Option<Box<[MatrixVersion]>> {
+        let added = metadata.added?;
+        let prefix = metadata.unstable_path.and_then(unstable_prefix)?;
+
+        if self.versions.contains(&added) || !self.has_unstable_feature(&format!("{prefix}.stable"))
+        {
+            return None;
+        }
+
+        Some(self.versions.iter().copied().chain([added]).collect())
+    }
+}
+
+/// Get the prefix of the MSC of the given unstable path, e.g.
+/// `org.matrix.msc3856` for `/_matrix/client/unstable/org.matrix.msc3856/…`.
+fn unstable_prefix(path: &str) -> Option<&str> {
+    let mut segments = path.split('/');
+    segments.find(|segment| *segment == "unstable")?;
+    segments.next().filter(|segment| segment.contains('.'))
+}
+
+/// A feature of the Matrix specification or of a [Matrix Spec Change] that the
+/// homeserver can support.
+///
+/// [Matrix Spec Change]: https://spec.matrix.org/proposals/
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Feature {
+    /// A version of the Matrix client-server API.
+    Version(MatrixVersion),
+
+    /// A feature advertised in the `unstable_features` of the `/versions`
+    /// endpoint, like `org.matrix.e2e_cross_signing`.
+    Unstable(String),
+
+    /// [Refreshing access tokens], stable since Matrix 1.3 and previously
+    /// [MSC2918].
+    ///
+    /// [Refreshing access tokens]: https://spec.matrix.org/v1.3/client-server-api/#refreshing-access-tokens
+    /// [MSC2918]: https://github.com/matrix-org/matrix-spec-proposals/pull/2918
+    RefreshTokens,
+
+    /// Listing the threads of a room ([MSC3856]), with
+    /// [`Common::threads()`](crate::room::Common::threads).
+    ///
+    /// [MSC3856]: https://github.com/matrix-org/matrix-spec-proposals/pull/3856
+    Threads,
+
+    /// [Sliding sync] natively on the homeserver.
+    ///
+    /// This is not needed when using a sliding sync proxy, set with
+    /// `SlidingSyncBuilder::homeserver()`.
+    ///
+    /// [Sliding sync]: https://github.com/matrix-org/matrix-spec-proposals/pull/3575
+    SlidingSync,
+
+    /// Changing the password of the account, according to the capabilities of
+    /// the homeserver.
+    ChangePassword,
+}
+
+/// The features supported by the homeserver.
+///
+/// Combines the versions and unstable features advertised by the `/versions`
+/// endpoint, the capabilities of the homeserver for the current user and the
+/// client discovery information of the server. Obtained with
+/// [`Client::server_features()`](crate::Client::server_features).
+#[derive(Clone, Debug)]
+pub struct ServerFeatures {
+    pub(crate) supported_versions: SupportedVersions,
+    pub(crate) capabilities: Capabilities,
+    pub(crate) well_known: Option<discover_homeserver::Response>,
+}
+
+impl ServerFeatures {
+    /// The versions of the Matrix client-server API supported by the
+    /// homeserver.
+    ///
+    /// If the versions were set with
+    /// [`ClientBuilder::server_versions()`](crate::ClientBuilder::server_versions),
+    /// these are returned instead.
+    pub fn versions(&self) -> &[MatrixVersion] {
+        &self.supported_versions.versions
+    }
+
+    /// The unstable features advertised by the homeserver, and whether they
+    /// are enabled.
+    ///
+    /// This is empty if the versions were set with
+    /// [`ClientBuilder::server_versions()`](crate::ClientBuilder::server_versions).
+    pub fn unstable_features(&self) -> &BTreeMap<String, bool> {
+        &self.supported_versions.unstable_features
+    }
+
+    /// The capabilities of the homeserver for the current user.
+    ///
+    /// These are the default capabilities if the client was not logged in
+    /// when the features were fetched.
+    pub fn capabilities(&self) -> &Capabilities {
+        &self.capabilities
+    }
+
+    /// The client discovery information of the server, from
+    /// `/.well-known/matrix/client`, if it is available.
+    pub fn well_known(&self) -> Option<&discover_homeserver::Response> {
+        self.well_known.as_ref()
+    }
+
+    /// Whether the homeserver supports the given feature.
+    pub fn supports(&self, feature: Feature) -> bool {
+        match feature {
+            Feature::ChangePassword => self.capabilities.change_password.enabled,
+            feature => self.supported_versions.supports(feature),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use ruma::api::{client::discovery::get_capabilities::Capabilities, MatrixVersion};
+
+    use super::{unstable_prefix, Feature, ServerFeatures, SupportedVersions};
+
+    #[test]
+    fn unstable_path_prefix() {
+        assert_eq!(
+            unstable_prefix("/_matrix/client/unstable/org.matrix.msc3856/rooms/:room_id/threads"),
+            Some("org.matrix.msc3856")
+        );
+        assert_eq!(unstable_prefix("/_matrix/client/unstable/rooms/:room_id/state"), None);
+        assert_eq!(unstable_prefix("/_matrix/client/v3/rooms/:room_id/state"), None);
+    }
+
+    #[test]
+    fn supports() {
+        let features = ServerFeatures {
+            supported_versions: SupportedVersions {
+                versions: vec![MatrixVersion::V1_0].into(),
+                unstable_features: BTreeMap::from([
+                    ("org.matrix.msc3856".to_owned(), true),
+                    ("org.matrix.e2e_cross_signing".to_owned(), false),
+                ]),
+            },
+            capabilities: Capabilities::default(),
+            well_known: None,
+        };
+
+        assert!(features.supports(Feature::Version(MatrixVersion::V1_0)));
+        assert!(!features.supports(Feature::RefreshTokens));
+        assert!(features.supports(Feature::Threads));
+        assert!(!features.supports(Feature::SlidingSync));
+        assert!(!features.supports(Feature::Unstable("org.matrix.e2e_cross_signing".to_owned())));
+
+        let features = ServerFeatures {
+            supported_versions: SupportedVersions {
+                versions: vec![MatrixVersion::V1_0].into(),
+                unstable_features: BTreeMap::from([("org.matrix.msc2918".to_owned(), true)]),
+            },
+            capabilities: Capabilities::default(),
+            well_known: None,
+        };
+        assert!(features.supports(Feature::RefreshTokens));
+    }
+}
@@ -14,7 +14,7 @@
 
 use std::{fmt, time::Duration};
 
-use ruma::api::client::sync::sync_events;
+use ruma::{api::client::sync::sync_events, presence::PresenceState};
 
 const DEFAULT_SYNC_TIMEOUT: Duration = Duration::from_secs(30);
 
@@ -25,6 +25,7 @@ pub struct SyncSettings<'a> {
     pub(crate) timeout: Option<Duration>,
     pub(crate) token: Option<String>,
     pub(crate) full_state: bool,
+    pub(crate) set_presence: PresenceState,
 }
 
 impl<'a> Default for SyncSettings<'a> {
@@ -49,7 +50,7 @@ impl<'a> fmt::Debug for SyncSettings<'a> {
         opt_field!(timeout);
         opt_field!(token);
 
-        s.field("full_state", &self.full_state).finish()
+        s.field("full_state", &self.full_state).field("set_presence", &self.set_presence).finish()
     }
 }
 
@@ -57,7 +58,13 @@ impl<'a> SyncSettings<'a> {
     /// Create new default sync settings.
     #[must_use]
     pub fn new() -> Self {
-        Self { filter: None, timeout: Some(DEFAULT_SYNC_TIMEOUT), token: None, full_state: false }
+        Self {
+            filter: None,
+            timeout: Some(DEFAULT_SYNC_TIMEOUT),
+            token: None,
+            full_state: false,
+            set_presence: PresenceState::Online,
+        }
     }
 
     /// Set the sync token.
@@ -108,4 +115,21 @@ impl<'a> SyncSettings<'a> {
         self.full_state = full_state;
         self
     }
+
+    /// Set the presence of the user while the client is syncing.
+    ///
+    /// The homeserver updates the presence of the user with each sync
+    /// request. Use [`PresenceState::Offline`] to sync without marking the
+    /// user as online.
+    ///
+    /// Default: [`PresenceState::Online`].
+    ///
+    /// # Arguments
+    ///
+    /// * `presence` - The presence the homeserver should set for the user.
+    #[must_use]
+    pub fn set_presence(mut self, presence: PresenceState) -> Self {
+        self.set_presence = presence;
+        self
+    }
 }
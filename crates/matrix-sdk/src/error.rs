@@ -15,6 +15,8 @@
 //! Error conditions.
 
 use std::io::Error as IoError;
+#[cfg(feature = "experimental-oidc")]
+use std::sync::Arc;
 
 use http::StatusCode;
 #[cfg(feature = "qrcode")]
@@ -27,16 +29,21 @@ use matrix_sdk_base::{Error as SdkBaseError, StoreError};
 use reqwest::Error as ReqwestError;
 use ruma::{
     api::{
-        client::uiaa::{UiaaInfo, UiaaResponse as UiaaError},
+        client::{
+            error::ErrorKind,
+            uiaa::{AuthType, UiaaInfo, UiaaResponse as UiaaError},
+        },
         error::{FromHttpResponseError, IntoHttpError, ServerError},
     },
     events::tag::InvalidUserTagName,
-    IdParseError,
+    IdParseError, OwnedRoomAliasId, OwnedRoomId,
 };
 use serde_json::Error as JsonError;
 use thiserror::Error;
 use url::ParseError as UrlParseError;
 
+use crate::Feature;
+
 /// Result type of the matrix-sdk.
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
@@ -98,6 +105,10 @@ pub enum HttpError {
     #[error("The request cannot be cloned")]
     UnableToCloneRequest,
 
+    /// An error occurred while reading or writing a streamed body.
+    #[error(transparent)]
+    Io(#[from] IoError),
+
     /// An error occurred while refreshing the access token.
     #[error(transparent)]
     RefreshToken(#[from] RefreshTokenError),
@@ -181,6 +192,38 @@ pub enum Error {
     #[error(transparent)]
     ImageError(#[from] ImageError),
 
+    /// An error while generating the thumbnail of an attachment.
+    #[error(transparent)]
+    Thumbnail(#[from] ThumbnailError),
+
+    /// An error while composing a message that relates to another event.
+    #[error(transparent)]
+    Relation(#[from] RelationError),
+
+    /// An error while validating the aliases of a room.
+    #[error(transparent)]
+    RoomAlias(#[from] RoomAliasError),
+
+    /// An error while changing the power levels of a room.
+    #[error(transparent)]
+    PowerLevels(#[from] PowerLevelsError),
+
+    /// The session of a new login doesn't have the same user ID and device ID
+    /// as the current session of the client.
+    ///
+    /// After a soft logout, the client must log in again with the same user
+    /// and device ID.
+    #[error("the new session doesn't match the current session")]
+    SessionMismatch,
+
+    /// The homeserver doesn't support a feature needed for the request.
+    #[error("the homeserver doesn't support the {0:?} feature")]
+    UnsupportedFeature(Feature),
+
+    /// An error occurred during User-Interactive Authentication.
+    #[error(transparent)]
+    InteractiveAuth(#[from] InteractiveAuthError),
+
     /// An other error was raised
     /// this might happen because encryption was enabled on the base-crate
     /// but not here and that raised.
@@ -238,6 +281,19 @@ impl HttpError {
             None
         }
     }
+
+    /// Get the kind of the client API error returned by the homeserver, if
+    /// this is one.
+    pub fn client_api_error_kind(&self) -> Option<&ErrorKind> {
+        if let HttpError::Api(FromHttpResponseError::Server(ServerError::Known(
+            RumaApiError::ClientApi(error),
+        ))) = self
+        {
+            Some(&error.kind)
+        } else {
+            None
+        }
+    }
 }
 
 impl Error {
@@ -327,6 +383,97 @@ pub enum ImageError {
     ThumbnailBiggerThanOriginal,
 }
 
+/// All possible errors that can happen when generating the thumbnail of an
+/// attachment.
+#[derive(Error, Debug)]
+pub enum ThumbnailError {
+    /// Error processing an image.
+    #[cfg(feature = "image-proc")]
+    #[error(transparent)]
+    Image(#[from] ImageError),
+
+    /// Error raised by a custom [`Thumbnailer`](crate::attachment::Thumbnailer).
+    #[error(transparent)]
+    Other(Box<dyn std::error::Error + Send + Sync>),
+}
+
+/// Errors that can happen when validating the aliases of a room before setting
+/// them in its `m.room.canonical_alias` event.
+#[derive(Error, Debug)]
+pub enum RoomAliasError {
+    /// The alias doesn't exist.
+    #[error("the alias {0} doesn't exist")]
+    NotFound(OwnedRoomAliasId),
+
+    /// The alias points to another room.
+    #[error("the alias {alias} points to another room: {room_id}")]
+    WrongRoom {
+        /// The alias.
+        alias: OwnedRoomAliasId,
+        /// The room the alias points to.
+        room_id: OwnedRoomId,
+    },
+}
+
+/// Errors that can happen when changing the `m.room.power_levels` event of a
+/// room.
+#[derive(Error, Debug)]
+pub enum PowerLevelsError {
+    /// The room doesn't have a usable `m.room.power_levels` event in the
+    /// store.
+    #[error("the room doesn't have a power levels event")]
+    Missing,
+
+    /// The own user doesn't have a high enough power level to make this
+    /// change.
+    #[error("the own user doesn't have a high enough power level for this change")]
+    Forbidden,
+}
+
+/// Errors that can happen when driving a [User-Interactive Authentication]
+/// session.
+///
+/// [User-Interactive Authentication]: crate::uiaa
+#[derive(Error, Debug)]
+pub enum InteractiveAuthError {
+    /// None of the flows offered by the homeserver can be completed.
+    #[error("no supported authentication flow")]
+    NoSupportedFlow,
+
+    /// The handler didn't provide the credentials for a stage.
+    #[error("the authentication was cancelled")]
+    Cancelled,
+
+    /// A stage that doesn't require any input was not accepted by the
+    /// homeserver.
+    #[error("the {} authentication stage failed", .0.as_str())]
+    StageFailed(AuthType),
+
+    /// The credentials provided for a stage were rejected too many times by
+    /// the homeserver.
+    #[error("too many failed attempts of the {} authentication stage", .0.as_str())]
+    TooManyAttempts(AuthType),
+
+    /// The homeserver didn't provide the session ID required to complete a
+    /// stage with the fallback.
+    #[error("the homeserver didn't provide an authentication session")]
+    MissingSession,
+}
+
+/// Errors that can happen when composing a reply to, or an edit of, another
+/// event.
+#[derive(Error, Debug)]
+pub enum RelationError {
+    /// The event isn't a room message, or it was redacted.
+    #[error("the event isn't a room message")]
+    NotARoomMessage,
+
+    /// The event was sent by another user, only the sender of an event can
+    /// edit it.
+    #[error("only the sender of an event can edit it")]
+    NotOwnEvent,
+}
+
 /// Errors that can happen when refreshing an access token.
 ///
 /// This is usually only returned by [`Client::refresh_access_token()`], unless
@@ -344,8 +491,70 @@ pub enum RefreshTokenError {
     #[error("missing refresh token")]
     RefreshTokenRequired,
 
+    /// The homeserver doesn't support refreshing access tokens.
+    #[error("the homeserver doesn't support refreshing access tokens")]
+    NotSupported,
+
     /// There was an ongoing refresh token call that failed and the error could
     /// not be forwarded.
     #[error("the access token could not be refreshed")]
     UnableToRefreshToken,
+
+    /// The OpenID Connect issuer returned an error.
+    #[cfg(feature = "experimental-oidc")]
+    #[error(transparent)]
+    Oidc(Arc<OidcError>),
+}
+
+/// Errors that can happen when using the [OpenID Connect API].
+///
+/// [OpenID Connect API]: crate::oidc
+#[cfg(feature = "experimental-oidc")]
+#[derive(Error, Debug)]
+pub enum OidcError {
+    /// The homeserver doesn't advertise an OpenID Connect issuer.
+    #[error("the homeserver doesn't advertise an OpenID Connect issuer")]
+    NoIssuer,
+
+    /// A field of the metadata of the issuer is invalid.
+    #[error("the `{0}` field of the metadata of the issuer is invalid")]
+    InvalidMetadata(&'static str),
+
+    /// The issuer doesn't advertise the endpoint needed for this action.
+    #[error("the issuer doesn't support the `{0}`")]
+    MissingEndpoint(&'static str),
+
+    /// The client isn't registered with the issuer.
+    #[error("the client isn't registered with the issuer")]
+    NotRegistered,
+
+    /// The state in the callback URL doesn't match an authorization in
+    /// progress.
+    #[error("the callback URL doesn't match an authorization in progress")]
+    InvalidState,
+
+    /// A parameter is missing in the callback URL.
+    #[error("the callback URL is missing the `{0}` parameter")]
+    MissingParameter(&'static str),
+
+    /// The issuer returned an error.
+    #[error("the issuer returned an error: {error}")]
+    Issuer {
+        /// The error code.
+        error: String,
+        /// The human-readable description of the error, if any.
+        description: Option<String>,
+    },
+
+    /// An error occurred while sending a request.
+    #[error(transparent)]
+    Http(#[from] HttpError),
+
+    /// An error occurred while (de)serializing a request or a response.
+    #[error(transparent)]
+    Json(#[from] JsonError),
+
+    /// An error occurred while restoring the session.
+    #[error(transparent)]
+    Base(#[from] SdkBaseError),
 }
@@ -0,0 +1,594 @@
+// Copyright 2020 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{
+    any::type_name, collections::HashMap, fmt::Debug, num::NonZeroUsize, pin::Pin, sync::Arc,
+    time::Duration,
+};
+#[cfg(not(target_arch = "wasm32"))]
+use std::{
+    sync::Mutex as SyncMutex,
+    task::{Context, Poll},
+};
+
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
+use futures_core::stream::Stream;
+use futures_util::{future, stream, StreamExt};
+use http::{
+    header::{HeaderValue, CONTENT_LENGTH},
+    Response as HttpResponse,
+};
+use matrix_sdk_common::AsyncTraitDeps;
+use reqwest::Response;
+use ruma::{
+    api::{
+        error::FromHttpResponseError, AuthScheme, IncomingResponse, MatrixVersion, OutgoingRequest,
+        OutgoingRequestAppserviceExt, SendAccessToken,
+    },
+    UserId,
+};
+use tracing::trace;
+
+use crate::{config::RequestConfig, error::HttpError};
+
+mod scheduler;
+
+pub(crate) use self::scheduler::RequestScheduler;
+pub use self::scheduler::{BackoffState, RequestCategory};
+
+pub(crate) const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A streamed HTTP body, as a stream of chunks.
+#[cfg(not(target_arch = "wasm32"))]
+pub type ByteStream = Pin<Box<dyn Stream<Item = std::io::Result<Bytes>> + Send>>;
+/// A streamed HTTP body, as a stream of chunks.
+#[cfg(target_arch = "wasm32")]
+pub type ByteStream = Pin<Box<dyn Stream<Item = std::io::Result<Bytes>>>>;
+
+/// Abstraction around the http layer. The allows implementors to use different
+/// http libraries.
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+pub trait HttpSend: AsyncTraitDeps {
+    /// The method abstracting sending request types and receiving response
+    /// types.
+    ///
+    /// This is called by the client every time it wants to send anything to a
+    /// homeserver.
+    ///
+    /// # Arguments
+    ///
+    /// * `request` - The http request that has been converted from a ruma
+    ///   `Request`.
+    ///
+    /// * `request_config` - The config used for this request.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use matrix_sdk::{
+    ///     async_trait, bytes::Bytes, config::RequestConfig, HttpError, HttpSend,
+    /// };
+    ///
+    /// #[derive(Debug)]
+    /// struct Client(reqwest::Client);
+    ///
+    /// impl Client {
+    ///     async fn response_to_http_response(
+    ///         &self,
+    ///         mut response: reqwest::Response,
+    ///     ) -> Result<http::Response<Bytes>, HttpError> {
+    ///         // Convert the reqwest response to a http one.
+    ///         todo!()
+    ///     }
+    /// }
+    ///
+    /// #[async_trait]
+    /// impl HttpSend for Client {
+    ///     async fn send_request(
+    ///         &self,
+    ///         request: http::Request<Bytes>,
+    ///         config: RequestConfig,
+    ///     ) -> Result<http::Response<Bytes>, HttpError> {
+    ///         Ok(self
+    ///             .response_to_http_response(
+    ///                 self.0
+    ///                     .execute(reqwest::Request::try_from(request)?)
+    ///                     .await?,
+    ///             )
+    ///             .await?)
+    ///     }
+    /// }
+    /// ```
+    async fn send_request(
+        &self,
+        request: http::Request<Bytes>,
+        config: RequestConfig,
+    ) -> Result<http::Response<Bytes>, HttpError>;
+
+    /// Send a request whose body is streamed.
+    ///
+    /// This is used to upload media without loading it in memory. Requests
+    /// with a streamed body are never retried.
+    ///
+    /// The default implementation collects the body and calls
+    /// [`send_request()`](Self::send_request), it should be overridden if the
+    /// http library supports streaming request bodies.
+    async fn send_request_with_body_stream(
+        &self,
+        request: http::Request<ByteStream>,
+        config: RequestConfig,
+    ) -> Result<http::Response<Bytes>, HttpError> {
+        let (parts, body) = request.into_parts();
+        let body = collect_body(body).await?;
+
+        self.send_request(http::Request::from_parts(parts, body), config).await
+    }
+
+    /// Send a request and stream the body of its response.
+    ///
+    /// This is used to download media without loading it in memory.
+    ///
+    /// The default implementation calls [`send_request()`](Self::send_request)
+    /// and returns the whole body as a single chunk, it should be overridden if
+    /// the http library supports streaming response bodies.
+    async fn send_request_with_response_stream(
+        &self,
+        request: http::Request<Bytes>,
+        config: RequestConfig,
+    ) -> Result<http::Response<ByteStream>, HttpError> {
+        let response = self.send_request(request, config).await?;
+
+        Ok(response.map(|body| -> ByteStream { Box::pin(stream::once(future::ready(Ok(body)))) }))
+    }
+}
+
+/// Collect all the chunks of a streamed body.
+async fn collect_body(mut body: ByteStream) -> std::io::Result<Bytes> {
+    let mut bytes = BytesMut::new();
+
+    while let Some(chunk) = body.next().await {
+        bytes.extend_from_slice(&chunk?);
+    }
+
+    Ok(bytes.freeze())
+}
+
+#[derive(Debug)]
+pub(crate) struct HttpClient {
+    pub(crate) inner: Arc<dyn HttpSend>,
+    pub(crate) request_config: RequestConfig,
+    pub(crate) scheduler: RequestScheduler,
+}
+
+impl HttpClient {
+    pub(crate) fn new(
+        inner: Arc<dyn HttpSend>,
+        request_config: RequestConfig,
+        concurrency_limits: &HashMap<RequestCategory, NonZeroUsize>,
+    ) -> Self {
+        HttpClient { inner, request_config, scheduler: RequestScheduler::new(concurrency_limits) }
+    }
+
+    #[tracing::instrument(skip(self, request), fields(request_type = type_name::<Request>()))]
+    pub async fn send<Request>(
+        &self,
+        request: Request,
+        config: Option<RequestConfig>,
+        homeserver: String,
+        access_token: Option<&str>,
+        user_id: Option<&UserId>,
+        server_versions: &[MatrixVersion],
+    ) -> Result<Request::IncomingResponse, HttpError>
+    where
+        Request: OutgoingRequest + Debug,
+        HttpError: From<FromHttpResponseError<Request::EndpointError>>,
+    {
+        let config = config.unwrap_or(self.request_config);
+        let request = Self::serialize_request(
+            request,
+            config,
+            homeserver,
+            access_token,
+            user_id,
+            server_versions,
+        )?;
+
+        trace!("Sending request");
+        let response = self
+            .scheduler
+            .send(&*self.inner, request, config, Request::METADATA.rate_limited)
+            .await?;
+
+        trace!("Got response: {:?}", response);
+
+        let response = Request::IncomingResponse::try_from_http_response(response)?;
+        Ok(response)
+    }
+
+    /// Send the given request with `body` as its streamed body, of
+    /// `content_length` bytes.
+    ///
+    /// The body of the serialized request is replaced, so the request should be
+    /// created with an empty body.
+    #[allow(clippy::too_many_arguments)]
+    #[tracing::instrument(skip(self, request, body), fields(request_type = type_name::<Request>()))]
+    pub(crate) async fn send_with_body_stream<Request>(
+        &self,
+        request: Request,
+        body: ByteStream,
+        content_length: u64,
+        config: Option<RequestConfig>,
+        homeserver: String,
+        access_token: Option<&str>,
+        user_id: Option<&UserId>,
+        server_versions: &[MatrixVersion],
+    ) -> Result<Request::IncomingResponse, HttpError>
+    where
+        Request: OutgoingRequest + Debug,
+        HttpError: From<FromHttpResponseError<Request::EndpointError>>,
+    {
+        let config = config.unwrap_or(self.request_config);
+        let mut request = Self::serialize_request(
+            request,
+            config,
+            homeserver,
+            access_token,
+            user_id,
+            server_versions,
+        )?
+        .map(|_| body);
+        request.headers_mut().insert(CONTENT_LENGTH, HeaderValue::from(content_length));
+
+        let _permit = self
+            .scheduler
+            .wait_for_slot(request.uri().path(), Request::METADATA.rate_limited)
+            .await;
+
+        trace!("Sending request with a streamed body");
+        let response = self.inner.send_request_with_body_stream(request, config).await?;
+
+        trace!("Got response: {:?}", response);
+        self.scheduler.check_rate_limited(&response);
+
+        let response = Request::IncomingResponse::try_from_http_response(response)?;
+        Ok(response)
+    }
+
+    /// Send the given request and stream the body of its response.
+    ///
+    /// If the request fails, the error of the endpoint is returned.
+    #[tracing::instrument(skip(self, request), fields(request_type = type_name::<Request>()))]
+    pub(crate) async fn send_with_response_stream<Request>(
+        &self,
+        request: Request,
+        config: Option<RequestConfig>,
+        homeserver: String,
+        access_token: Option<&str>,
+        user_id: Option<&UserId>,
+        server_versions: &[MatrixVersion],
+    ) -> Result<http::Response<ByteStream>, HttpError>
+    where
+        Request: OutgoingRequest + Debug,
+        HttpError: From<FromHttpResponseError<Request::EndpointError>>,
+    {
+        let config = config.unwrap_or(self.request_config);
+        let request = Self::serialize_request(
+            request,
+            config,
+            homeserver,
+            access_token,
+            user_id,
+            server_versions,
+        )?;
+
+        let permit = self
+            .scheduler
+            .wait_for_slot(request.uri().path(), Request::METADATA.rate_limited)
+            .await;
+
+        trace!("Sending request with a streamed response");
+        let response = self.inner.send_request_with_response_stream(request, config).await?;
+        let status = response.status();
+
+        if !status.is_success() {
+            let (parts, body) = response.into_parts();
+            let response = http::Response::from_parts(parts, collect_body(body).await?);
+
+            trace!("Got response: {:?}", response);
+            self.scheduler.check_rate_limited(&response);
+
+            Request::IncomingResponse::try_from_http_response(response)?;
+            return Err(HttpError::Server(status));
+        }
+
+        // Keep the slot of the request until the whole body was received.
+        Ok(response.map(|body| -> ByteStream {
+            Box::pin(body.map(move |chunk| {
+                let _permit = &permit;
+                chunk
+            }))
+        }))
+    }
+
+    fn serialize_request<Request>(
+        request: Request,
+        config: RequestConfig,
+        homeserver: String,
+        access_token: Option<&str>,
+        user_id: Option<&UserId>,
+        server_versions: &[MatrixVersion],
+    ) -> Result<http::Request<Bytes>, HttpError>
+    where
+        Request: OutgoingRequest + Debug,
+    {
+        let auth_scheme = Request::METADATA.authentication;
+        if !matches!(auth_scheme, AuthScheme::AccessToken | AuthScheme::None) {
+            return Err(HttpError::NotClientRequest);
+        }
+
+        trace!("Serializing request");
+        // We can't assert the identity without a user_id.
+        let request = if let Some((access_token, user_id)) =
+            access_token.filter(|_| config.assert_identity).zip(user_id)
+        {
+            request.try_into_http_request_with_user_id::<BytesMut>(
+                &homeserver,
+                SendAccessToken::Always(access_token),
+                user_id,
+                server_versions,
+            )?
+        } else {
+            let send_access_token = match access_token {
+                Some(access_token) => {
+                    if config.force_auth {
+                        SendAccessToken::Always(access_token)
+                    } else {
+                        SendAccessToken::IfRequired(access_token)
+                    }
+                }
+                None => SendAccessToken::None,
+            };
+
+            request.try_into_http_request::<BytesMut>(
+                &homeserver,
+                send_access_token,
+                server_versions,
+            )?
+        };
+
+        Ok(request.map(|body| body.freeze()))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct HttpSettings {
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) disable_ssl_verification: bool,
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) proxy: Option<String>,
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) user_agent: Option<String>,
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) timeout: Duration,
+}
+
+#[allow(clippy::derivable_impls)]
+impl Default for HttpSettings {
+    fn default() -> Self {
+        Self {
+            #[cfg(not(target_arch = "wasm32"))]
+            disable_ssl_verification: false,
+            #[cfg(not(target_arch = "wasm32"))]
+            proxy: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            user_agent: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            timeout: DEFAULT_REQUEST_TIMEOUT,
+        }
+    }
+}
+
+impl HttpSettings {
+    /// Build a client with the specified configuration.
+    pub(crate) fn make_client(&self) -> Result<reqwest::Client, HttpError> {
+        #[allow(unused_mut)]
+        let mut http_client = reqwest::Client::builder();
+
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            if self.disable_ssl_verification {
+                http_client = http_client.danger_accept_invalid_certs(true)
+            }
+
+            if let Some(p) = &self.proxy {
+                http_client = http_client.proxy(reqwest::Proxy::all(p.as_str())?);
+            }
+
+            let user_agent =
+                self.user_agent.clone().unwrap_or_else(|| "matrix-rust-sdk".to_owned());
+
+            http_client = http_client.user_agent(user_agent).timeout(self.timeout);
+        };
+
+        Ok(http_client.build()?)
+    }
+}
+
+/// Create a response builder with the status and the headers of the given
+/// response.
+fn http_response_builder(response: &mut Response) -> http::response::Builder {
+    let mut http_builder = HttpResponse::builder().status(response.status());
+    let headers = http_builder.headers_mut().expect("Can't get the response builder headers");
+
+    for (k, v) in response.headers_mut().drain() {
+        if let Some(key) = k {
+            headers.insert(key, v);
+        }
+    }
+
+    http_builder
+}
+
+async fn response_to_http_response(
+    mut response: Response,
+) -> Result<http::Response<Bytes>, reqwest::Error> {
+    let http_builder = http_response_builder(&mut response);
+    let body = response.bytes().await?;
+
+    Ok(http_builder.body(body).expect("Can't construct a response using the given body"))
+}
+
+#[cfg(any(target_arch = "wasm32"))]
+async fn send_request(
+    client: &reqwest::Client,
+    request: http::Request<Bytes>,
+    _: RequestConfig,
+) -> Result<http::Response<Bytes>, HttpError> {
+    let request = reqwest::Request::try_from(request)?;
+    let response = client.execute(request).await?;
+
+    Ok(response_to_http_response(response).await?)
+}
+
+#[cfg(all(not(target_arch = "wasm32")))]
+async fn send_request(
+    client: &reqwest::Client,
+    request: http::Request<Bytes>,
+    config: RequestConfig,
+) -> Result<http::Response<Bytes>, HttpError> {
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    use backoff::{future::retry, Error as RetryError, ExponentialBackoff};
+
+    let mut backoff = ExponentialBackoff::default();
+    let mut request = reqwest::Request::try_from(request)?;
+    let retry_limit = config.retry_limit;
+    let retry_count = AtomicU64::new(1);
+
+    *request.timeout_mut() = Some(config.timeout);
+
+    backoff.max_elapsed_time = config.retry_timeout;
+
+    let request = &request;
+    let retry_count = &retry_count;
+
+    let request = || async move {
+        let stop = if let Some(retry_limit) = retry_limit {
+            retry_count.fetch_add(1, Ordering::Relaxed) >= retry_limit
+        } else {
+            false
+        };
+
+        // Turn errors into permanent errors when the retry limit is reached
+        let error_type = if stop {
+            RetryError::Permanent
+        } else {
+            |err| RetryError::Transient { err, retry_after: None }
+        };
+
+        let request = request.try_clone().ok_or(HttpError::UnableToCloneRequest)?;
+
+        let response =
+            client.execute(request).await.map_err(|e| error_type(HttpError::Reqwest(e)))?;
+
+        let status_code = response.status();
+        // Rate limited requests are retried by the `RequestScheduler`, which
+        // knows how long the homeserver wants us to wait.
+        if !stop && status_code.is_server_error() {
+            return Err(error_type(HttpError::Server(status_code)));
+        }
+
+        let response = response_to_http_response(response)
+            .await
+            .map_err(|e| RetryError::Permanent(HttpError::Reqwest(e)))?;
+
+        Ok(response)
+    };
+
+    let response = retry(backoff, request).await?;
+
+    Ok(response)
+}
+
+/// A [`ByteStream`] that is `Sync`, as required by reqwest.
+///
+/// The stream is only ever polled through a mutable reference, so the mutex is
+/// never actually locked.
+#[cfg(not(target_arch = "wasm32"))]
+struct SyncByteStream(SyncMutex<ByteStream>);
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Stream for SyncByteStream {
+    type Item = std::io::Result<Bytes>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        match self.get_mut().0.get_mut() {
+            Ok(stream) => stream.as_mut().poll_next(cx),
+            Err(poisoned) => poisoned.into_inner().as_mut().poll_next(cx),
+        }
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl HttpSend for reqwest::Client {
+    async fn send_request(
+        &self,
+        request: http::Request<Bytes>,
+        config: RequestConfig,
+    ) -> Result<http::Response<Bytes>, HttpError> {
+        send_request(self, request, config).await
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    async fn send_request_with_body_stream(
+        &self,
+        request: http::Request<ByteStream>,
+        config: RequestConfig,
+    ) -> Result<http::Response<Bytes>, HttpError> {
+        let (parts, body) = request.into_parts();
+        let mut request =
+            reqwest::Request::try_from(http::Request::from_parts(parts, Bytes::new()))?;
+
+        *request.body_mut() =
+            Some(reqwest::Body::wrap_stream(SyncByteStream(SyncMutex::new(body))));
+        *request.timeout_mut() = Some(config.timeout);
+
+        let response = self.execute(request).await?;
+
+        Ok(response_to_http_response(response).await?)
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    async fn send_request_with_response_stream(
+        &self,
+        request: http::Request<Bytes>,
+        config: RequestConfig,
+    ) -> Result<http::Response<ByteStream>, HttpError> {
+        let mut request = reqwest::Request::try_from(request)?;
+        *request.timeout_mut() = Some(config.timeout);
+
+        let mut response = self.execute(request).await?;
+        let http_builder = http_response_builder(&mut response);
+        let body: ByteStream = Box::pin(
+            response
+                .bytes_stream()
+                .map(|chunk| chunk.map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))),
+        );
+
+        Ok(http_builder.body(body).expect("Can't construct a response using the given body"))
+    }
+}
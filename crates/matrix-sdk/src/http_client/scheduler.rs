@@ -0,0 +1,376 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Client-wide scheduling of the requests that are sent to the homeserver.
+//!
+//! The scheduler limits the number of requests of a given
+//! [`RequestCategory`] that are in flight at the same time, and pauses the
+//! requests to rate limited endpoints once the homeserver responded with a
+//! `M_LIMIT_EXCEEDED` error.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    num::NonZeroUsize,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use bytes::Bytes;
+use event_listener::Event;
+use futures_signals::signal::{Mutable, ReadOnlyMutable};
+use http::StatusCode;
+use matrix_sdk_common::instant::Instant;
+use serde::Deserialize;
+use tracing::warn;
+
+use super::HttpSend;
+use crate::{config::RequestConfig, error::HttpError};
+
+/// How long to wait before retrying a request if the homeserver rate limited
+/// us without telling us for how long.
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(5);
+
+/// The number of attempts of a rate limited request if the [`RequestConfig`]
+/// sets neither a retry limit nor a retry timeout.
+const DEFAULT_RATE_LIMITED_ATTEMPTS: u64 = 3;
+
+/// The categories of requests that can have their own concurrency limit, see
+/// [`ClientBuilder::max_concurrent_requests()`].
+///
+/// [`ClientBuilder::max_concurrent_requests()`]: crate::ClientBuilder::max_concurrent_requests
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RequestCategory {
+    /// Requests to the `/sync` endpoint, including sliding sync.
+    Sync,
+    /// Requests to the `/keys/query` endpoint.
+    KeysQuery,
+    /// Requests uploading media to the content repository.
+    MediaUpload,
+    /// Requests downloading media or thumbnails from the content repository.
+    MediaDownload,
+    /// Any other request.
+    Other,
+}
+
+impl RequestCategory {
+    fn from_path(path: &str) -> Self {
+        if path.ends_with("/sync") {
+            Self::Sync
+        } else if path.ends_with("/keys/query") {
+            Self::KeysQuery
+        } else if let Some((_, endpoint)) = path.split_once("/_matrix/media/") {
+            // The rest of the path looks like `{version}/{endpoint}/…`.
+            if endpoint.split('/').nth(1) == Some("upload") {
+                Self::MediaUpload
+            } else {
+                Self::MediaDownload
+            }
+        } else {
+            Self::Other
+        }
+    }
+}
+
+/// Whether the homeserver is currently rate limiting the client.
+///
+/// See [`Client::request_backoff()`].
+///
+/// [`Client::request_backoff()`]: crate::Client::request_backoff
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BackoffState {
+    /// Requests are sent as soon as possible.
+    Idle,
+    /// The homeserver rate limited a request, requests to rate limited
+    /// endpoints are paused until the given instant.
+    ///
+    /// The state goes back to [`BackoffState::Idle`] once a paused request is
+    /// sent again, so `until` may already be in the past if no request was
+    /// waiting.
+    RateLimited {
+        /// The instant after which requests will be sent again.
+        until: Instant,
+    },
+}
+
+pub(crate) struct RequestScheduler {
+    limiters: HashMap<RequestCategory, Arc<Limiter>>,
+    backoff: Mutable<BackoffState>,
+}
+
+#[cfg(not(tarpaulin_include))]
+impl fmt::Debug for RequestScheduler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RequestScheduler")
+            .field("limits", &self.limiters.iter().map(|(c, l)| (c, l.limit)).collect::<Vec<_>>())
+            .field("backoff", &self.backoff.get())
+            .finish()
+    }
+}
+
+impl RequestScheduler {
+    pub(crate) fn new(limits: &HashMap<RequestCategory, NonZeroUsize>) -> Self {
+        let limiters = limits
+            .iter()
+            .map(|(category, limit)| (*category, Arc::new(Limiter::new(limit.get()))))
+            .collect();
+
+        Self { limiters, backoff: Mutable::new(BackoffState::Idle) }
+    }
+
+    pub(crate) fn backoff(&self) -> ReadOnlyMutable<BackoffState> {
+        self.backoff.read_only()
+    }
+
+    /// Send the given request through `inner` once the concurrency limit of its
+    /// category and the current backoff allow it.
+    ///
+    /// Requests that are rate limited by the homeserver are retried after the
+    /// delay it asked for, as long as the retry limit and retry timeout of
+    /// `config` allow it. Otherwise the rate limiting response is returned. If
+    /// `config` sets neither, rate limited requests are attempted
+    /// [`DEFAULT_RATE_LIMITED_ATTEMPTS`] times.
+    ///
+    /// The concurrency slot of the request is released while it waits to be
+    /// retried.
+    pub(crate) async fn send(
+        &self,
+        inner: &dyn HttpSend,
+        request: http::Request<Bytes>,
+        config: RequestConfig,
+        rate_limited: bool,
+    ) -> Result<http::Response<Bytes>, HttpError> {
+        let category = RequestCategory::from_path(request.uri().path());
+
+        let start = Instant::now();
+        // Like for other errors, the retry limit is the total number of
+        // attempts.
+        let retry_limit = match (config.retry_limit, config.retry_timeout) {
+            (None, None) => Some(DEFAULT_RATE_LIMITED_ATTEMPTS),
+            (retry_limit, _) => retry_limit,
+        };
+        let mut attempts = 1;
+
+        loop {
+            if rate_limited || attempts > 1 {
+                self.wait_for_backoff().await;
+            }
+
+            let permit = self.acquire(category).await;
+            let response = inner.send_request(clone_request(&request), config).await?;
+            drop(permit);
+
+            if response.status() != StatusCode::TOO_MANY_REQUESTS {
+                return Ok(response);
+            }
+
+            let retry_after = retry_after(&response).unwrap_or(DEFAULT_RETRY_AFTER);
+            self.set_rate_limited(retry_after);
+
+            let can_retry = retry_limit.map_or(true, |limit| attempts < limit)
+                && config
+                    .retry_timeout
+                    .map_or(true, |timeout| start.elapsed() + retry_after < timeout);
+
+            if !can_retry {
+                return Ok(response);
+            }
+
+            warn!(?category, ?retry_after, "The homeserver rate limited a request, retrying later");
+            attempts += 1;
+        }
+    }
+
+    /// Wait until a request to the given path can be sent according to the
+    /// concurrency limit of its category and the current backoff.
+    ///
+    /// This is used for requests with a streamed body, that can't be retried.
+    /// The returned permit must be kept until the request is done.
+    pub(crate) async fn wait_for_slot(&self, path: &str, rate_limited: bool) -> Option<Permit> {
+        if rate_limited {
+            self.wait_for_backoff().await;
+        }
+
+        self.acquire(RequestCategory::from_path(path)).await
+    }
+
+    /// Update the backoff if the given response of a request that wasn't sent
+    /// with [`RequestScheduler::send()`] says that the homeserver rate limited
+    /// us.
+    pub(crate) fn check_rate_limited(&self, response: &http::Response<Bytes>) {
+        if response.status() == StatusCode::TOO_MANY_REQUESTS {
+            self.set_rate_limited(retry_after(response).unwrap_or(DEFAULT_RETRY_AFTER));
+        }
+    }
+
+    async fn acquire(&self, category: RequestCategory) -> Option<Permit> {
+        match self.limiters.get(&category) {
+            Some(limiter) => Some(limiter.acquire().await),
+            None => None,
+        }
+    }
+
+    fn set_rate_limited(&self, retry_after: Duration) {
+        let until = Instant::now() + retry_after;
+
+        self.backoff.replace_with(|state| match *state {
+            BackoffState::RateLimited { until: current } if current > until => *state,
+            _ => BackoffState::RateLimited { until },
+        });
+    }
+
+    async fn wait_for_backoff(&self) {
+        loop {
+            let until = match self.backoff.get() {
+                BackoffState::Idle => return,
+                BackoffState::RateLimited { until } => until,
+            };
+
+            let now = Instant::now();
+
+            if until <= now {
+                // Only go back to idle if nobody got rate limited again in the
+                // meantime.
+                self.backoff.replace_with(|state| match *state {
+                    BackoffState::RateLimited { until: current } if current > now => *state,
+                    _ => BackoffState::Idle,
+                });
+
+                return;
+            }
+
+            sleep(until - now).await;
+        }
+    }
+}
+
+/// Limits the number of requests that are in flight at the same time.
+struct Limiter {
+    limit: usize,
+    in_flight: AtomicUsize,
+    released: Event,
+}
+
+impl Limiter {
+    fn new(limit: usize) -> Self {
+        Self { limit, in_flight: AtomicUsize::new(0), released: Event::new() }
+    }
+
+    fn try_acquire(&self) -> bool {
+        self.in_flight
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| (n < self.limit).then(|| n + 1))
+            .is_ok()
+    }
+
+    async fn acquire(self: &Arc<Self>) -> Permit {
+        loop {
+            if self.try_acquire() {
+                return Permit(self.clone());
+            }
+
+            // Check again after we started listening, a permit might have been
+            // released in between.
+            let listener = self.released.listen();
+
+            if self.try_acquire() {
+                return Permit(self.clone());
+            }
+
+            listener.await;
+        }
+    }
+}
+
+/// A slot for a request that is in flight, released when dropped.
+pub(crate) struct Permit(Arc<Limiter>);
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::AcqRel);
+        self.0.released.notify(1);
+    }
+}
+
+/// `http::Request` isn't `Clone`, but we need to be able to send the same
+/// request multiple times.
+fn clone_request(request: &http::Request<Bytes>) -> http::Request<Bytes> {
+    let mut builder = http::Request::builder()
+        .method(request.method().clone())
+        .uri(request.uri().clone())
+        .version(request.version());
+
+    *builder.headers_mut().expect("Can't get the request builder headers") =
+        request.headers().clone();
+
+    builder.body(request.body().clone()).expect("Can't construct a request from a valid one")
+}
+
+/// The delay the homeserver asked us to wait before retrying, either in the
+/// body of the `M_LIMIT_EXCEEDED` error or in the `Retry-After` header.
+fn retry_after(response: &http::Response<Bytes>) -> Option<Duration> {
+    #[derive(Deserialize)]
+    struct LimitExceeded {
+        retry_after_ms: Option<u64>,
+    }
+
+    serde_json::from_slice::<LimitExceeded>(response.body())
+        .ok()
+        .and_then(|body| body.retry_after_ms)
+        .map(Duration::from_millis)
+        .or_else(|| {
+            let header = response.headers().get(http::header::RETRY_AFTER)?;
+            header.to_str().ok()?.parse().ok().map(Duration::from_secs)
+        })
+}
+
+async fn sleep(duration: Duration) {
+    #[cfg(target_arch = "wasm32")]
+    let _ = wasm_timer::Delay::new(duration).await;
+
+    #[cfg(not(target_arch = "wasm32"))]
+    tokio::time::sleep(duration).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RequestCategory;
+
+    #[test]
+    fn request_categories() {
+        assert_eq!(RequestCategory::from_path("/_matrix/client/r0/sync"), RequestCategory::Sync);
+        assert_eq!(
+            RequestCategory::from_path("/_matrix/client/unstable/org.matrix.msc3575/sync"),
+            RequestCategory::Sync
+        );
+        assert_eq!(
+            RequestCategory::from_path("/_matrix/client/r0/keys/query"),
+            RequestCategory::KeysQuery
+        );
+        assert_eq!(
+            RequestCategory::from_path("/_matrix/media/r0/upload"),
+            RequestCategory::MediaUpload
+        );
+        assert_eq!(
+            RequestCategory::from_path("/_matrix/media/r0/download/localhost/abc"),
+            RequestCategory::MediaDownload
+        );
+        assert_eq!(
+            RequestCategory::from_path("/_matrix/client/r0/rooms/!a:b/send/m.room.message/1"),
+            RequestCategory::Other
+        );
+    }
+}
@@ -28,6 +28,8 @@ pub use reqwest;
 pub use ruma;
 
 mod account;
+#[cfg(all(not(target_arch = "wasm32"), any(feature = "sled", feature = "sqlite")))]
+mod account_manager;
 pub mod attachment;
 mod client;
 pub mod config;
@@ -35,9 +37,15 @@ mod error;
 pub mod event_handler;
 mod http_client;
 pub mod media;
+pub mod notification_settings;
+#[cfg(feature = "experimental-oidc")]
+pub mod oidc;
 pub mod room;
+pub mod session_persistence;
+pub mod space;
 pub mod store;
 mod sync;
+pub mod uiaa;
 
 #[cfg(feature = "sliding-sync")]
 mod sliding_sync;
@@ -46,14 +54,27 @@ mod sliding_sync;
 pub mod encryption;
 
 pub use account::Account;
+#[cfg(all(not(target_arch = "wasm32"), any(feature = "sled", feature = "sqlite")))]
+pub use account_manager::{AccountManager, AccountManagerError};
 #[cfg(feature = "sso-login")]
 pub use client::SsoLoginBuilder;
-pub use client::{Client, ClientBuildError, ClientBuilder, LoginBuilder, LoopCtrl};
+pub use client::{
+    Client, ClientBuildError, ClientBuilder, Feature, LoginBuilder, LoopCtrl, RegistrationBuilder,
+    ServerFeatures, SessionState,
+};
 #[cfg(feature = "image-proc")]
 pub use error::ImageError;
-pub use error::{Error, HttpError, HttpResult, RefreshTokenError, Result, RumaApiError};
-pub use http_client::HttpSend;
+#[cfg(feature = "experimental-oidc")]
+pub use error::OidcError;
+pub use error::{
+    Error, HttpError, HttpResult, InteractiveAuthError, PowerLevelsError, RefreshTokenError,
+    RelationError, Result, RoomAliasError, RumaApiError, ThumbnailError,
+};
+pub use http_client::{BackoffState, ByteStream, HttpSend, RequestCategory};
 pub use media::Media;
+pub use notification_settings::NotificationSettings;
+#[cfg(feature = "experimental-oidc")]
+pub use oidc::Oidc;
 #[cfg(feature = "sliding-sync")]
 pub use sliding_sync::{
     RoomListEntry, SlidingSync, SlidingSyncBuilder, SlidingSyncMode, SlidingSyncRoom,
@@ -15,12 +15,33 @@
 
 //! High-level media API.
 
+use std::{io, time::Duration};
 #[cfg(feature = "e2e-encryption")]
-use std::io::Read;
-use std::time::Duration;
+use std::{
+    io::{Cursor, Read},
+    sync::{Arc, Mutex as SyncMutex},
+};
+#[cfg(not(target_arch = "wasm32"))]
+use std::{
+    path::Path,
+    pin::Pin,
+    task::{Context, Poll},
+};
 
+use bytes::Bytes;
+use futures_signals::signal::Mutable;
+use futures_util::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    StreamExt,
+};
+use http::header::CONTENT_LENGTH;
+#[cfg(feature = "e2e-encryption")]
+use matrix_sdk_base::crypto::{AttachmentDecryptor, AttachmentEncryptor, MediaEncryptionInfo};
 pub use matrix_sdk_base::media::*;
+use matrix_sdk_base::SendOutsideWasm;
 use mime::Mime;
+#[cfg(feature = "e2e-encryption")]
+use ruma::events::room::{EncryptedFile, EncryptedFileInit};
 use ruma::{
     api::client::media::{create_content, get_content, get_content_thumbnail},
     assign,
@@ -30,13 +51,27 @@ use ruma::{
 
 use crate::{
     attachment::{AttachmentInfo, Thumbnail},
-    Client, Result,
+    ByteStream, Client, Result,
 };
 
 /// A conservative upload speed of 1Mbps
 const DEFAULT_UPLOAD_SPEED: u64 = 125_000;
 /// 5 min minimal upload request timeout, used to clamp the request timeout.
 const MIN_UPLOAD_REQUEST_TIMEOUT: Duration = Duration::from_secs(60 * 5);
+/// The size of the chunks read from the source of a streamed upload.
+const UPLOAD_CHUNK_SIZE: usize = 64 * 1024;
+/// The timeout of streamed downloads, since their size is unknown when the
+/// request is sent.
+const STREAMED_DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+
+/// The progress of a streamed media upload or download.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TransmissionProgress {
+    /// The number of bytes that were transferred so far.
+    pub current: u64,
+    /// The total number of bytes to transfer, if it is known.
+    pub total: Option<u64>,
+}
 
 /// A high-level API to interact with the media API.
 #[derive(Debug, Clone)]
@@ -83,19 +118,162 @@ impl Media {
         content_type: &Mime,
         data: &[u8],
     ) -> Result<create_content::v3::Response> {
-        let timeout = std::cmp::max(
-            Duration::from_secs(data.len() as u64 / DEFAULT_UPLOAD_SPEED),
-            MIN_UPLOAD_REQUEST_TIMEOUT,
-        );
-
         let request = assign!(create_content::v3::Request::new(data), {
             content_type: Some(content_type.essence_str()),
         });
 
-        let request_config = self.client.request_config().timeout(timeout);
+        let request_config =
+            self.client.request_config().timeout(upload_timeout(data.len() as u64));
         Ok(self.client.send(request, Some(request_config)).await?)
     }
 
+    /// Upload some media to the server, reading it from `reader` as it is
+    /// sent.
+    ///
+    /// Contrary to [`upload()`](#method.upload), the media is never fully
+    /// loaded in memory. The upload can be cancelled by dropping the returned
+    /// future.
+    ///
+    /// # Arguments
+    ///
+    /// * `content_type` - The type of the media, this will be used as the
+    /// content-type header.
+    ///
+    /// * `reader` - The reader of the raw bytes of the media.
+    ///
+    /// * `size` - The number of bytes that `reader` will return.
+    ///
+    /// * `progress` - A [`Mutable`] that is updated every time a chunk of the
+    /// media is sent, to be observed with its signal.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use matrix_sdk::Client;
+    /// # use url::Url;
+    /// # use futures::executor::block_on;
+    /// # use mime;
+    /// # block_on(async {
+    /// # let homeserver = Url::parse("http://localhost:8080")?;
+    /// # let mut client = Client::new(homeserver).await?;
+    /// use futures_signals::signal::{Mutable, SignalExt};
+    /// use matrix_sdk::media::TransmissionProgress;
+    ///
+    /// let video = vec![0; 1024];
+    /// let progress = Mutable::new(TransmissionProgress::default());
+    ///
+    /// tokio::spawn(progress.signal().for_each(|progress| {
+    ///     println!("Sent {} bytes", progress.current);
+    ///     async {}
+    /// }));
+    ///
+    /// let response = client
+    ///     .media()
+    ///     .upload_stream(
+    ///         &mime::VIDEO_MP4,
+    ///         futures_util::io::Cursor::new(video),
+    ///         1024,
+    ///         Some(progress.clone()),
+    ///     )
+    ///     .await?;
+    ///
+    /// println!("Video URI: {}", response.content_uri);
+    /// # anyhow::Ok(()) });
+    /// ```
+    pub async fn upload_stream(
+        &self,
+        content_type: &Mime,
+        reader: impl AsyncRead + Unpin + SendOutsideWasm + 'static,
+        size: u64,
+        progress: Option<Mutable<TransmissionProgress>>,
+    ) -> Result<create_content::v3::Response> {
+        self.upload_chunks(content_type, read_chunks(reader, size, progress), size).await
+    }
+
+    /// Upload the file at the given path to the server, reading it as it is
+    /// sent.
+    ///
+    /// This is a convenience method that calls the
+    /// [`upload_stream`](#method.upload_stream) method.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn upload_file(
+        &self,
+        content_type: &Mime,
+        path: impl AsRef<Path>,
+        progress: Option<Mutable<TransmissionProgress>>,
+    ) -> Result<create_content::v3::Response> {
+        let file = tokio::fs::File::open(path).await?;
+        let size = file.metadata().await?.len();
+
+        self.upload_stream(content_type, FileReader(file), size, progress).await
+    }
+
+    /// Encrypt some media and upload it to the server, reading it from
+    /// `reader` as it is encrypted and sent.
+    ///
+    /// The returned [`EncryptedFile`] contains the URI of the media and the
+    /// keys needed to decrypt it. See [`upload_stream`](#method.upload_stream)
+    /// for the arguments.
+    #[cfg(feature = "e2e-encryption")]
+    pub async fn upload_encrypted_stream(
+        &self,
+        content_type: &Mime,
+        reader: impl AsyncRead + Unpin + SendOutsideWasm + 'static,
+        size: u64,
+        progress: Option<Mutable<TransmissionProgress>>,
+    ) -> Result<EncryptedFile> {
+        let keys = Arc::new(SyncMutex::new(None));
+        let chunks = encrypt_chunks(read_chunks(reader, size, progress), size, keys.clone());
+
+        let response = self.upload_chunks(content_type, chunks, size).await?;
+
+        let keys = keys.lock().unwrap().take().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::UnexpectedEof, "The media was not fully uploaded")
+        })?;
+
+        Ok(EncryptedFileInit {
+            url: response.content_uri,
+            key: keys.key,
+            iv: keys.iv,
+            hashes: keys.hashes,
+            v: keys.version,
+        }
+        .into())
+    }
+
+    /// Encrypt the file at the given path and upload it to the server, reading
+    /// it as it is encrypted and sent.
+    ///
+    /// This is a convenience method that calls the
+    /// [`upload_encrypted_stream`](#method.upload_encrypted_stream) method.
+    #[cfg(all(feature = "e2e-encryption", not(target_arch = "wasm32")))]
+    pub async fn upload_encrypted_file(
+        &self,
+        content_type: &Mime,
+        path: impl AsRef<Path>,
+        progress: Option<Mutable<TransmissionProgress>>,
+    ) -> Result<EncryptedFile> {
+        let file = tokio::fs::File::open(path).await?;
+        let size = file.metadata().await?.len();
+
+        self.upload_encrypted_stream(content_type, FileReader(file), size, progress).await
+    }
+
+    async fn upload_chunks(
+        &self,
+        content_type: &Mime,
+        chunks: ByteStream,
+        size: u64,
+    ) -> Result<create_content::v3::Response> {
+        // The body is replaced by the chunks.
+        let request = assign!(create_content::v3::Request::new(&[]), {
+            content_type: Some(content_type.essence_str()),
+        });
+
+        let request_config = self.client.request_config().timeout(upload_timeout(size));
+        Ok(self.client.send_with_body_stream(request, chunks, size, Some(request_config)).await?)
+    }
+
     /// Get a media file's content.
     ///
     /// If the content is encrypted and encryption is enabled, the content will
@@ -161,6 +339,105 @@ impl Media {
         }
     }
 
+    /// Download a media file's content and write it to `writer` as it is
+    /// received.
+    ///
+    /// If the content is encrypted and encryption is enabled, the content will
+    /// be decrypted on the fly. Its hash can only be checked once everything
+    /// was received, so if an error is returned the data that was written must
+    /// be discarded.
+    ///
+    /// Contrary to [`get_media_content`](#method.get_media_content), the
+    /// content is never fully loaded in memory, and the media cache is not
+    /// used. The download can be cancelled by dropping the returned future.
+    ///
+    /// # Arguments
+    ///
+    /// * `request` - The `MediaRequest` of the content.
+    ///
+    /// * `writer` - The writer where the content is written.
+    ///
+    /// * `progress` - A [`Mutable`] that is updated every time a chunk of the
+    /// content is received, to be observed with its signal.
+    pub async fn download_to<W>(
+        &self,
+        request: &MediaRequest,
+        writer: &mut W,
+        progress: Option<Mutable<TransmissionProgress>>,
+    ) -> Result<()>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        let request_config = Some(self.client.request_config().timeout(STREAMED_DOWNLOAD_TIMEOUT));
+
+        let response = match &request.source {
+            MediaSource::Encrypted(file) => {
+                let request = get_content::v3::Request::from_url(&file.url)?;
+                self.client.send_with_response_stream(request, request_config).await?
+            }
+            MediaSource::Plain(uri) => {
+                if let MediaFormat::Thumbnail(size) = &request.format {
+                    let request =
+                        get_content_thumbnail::v3::Request::from_url(uri, size.width, size.height)?;
+                    self.client.send_with_response_stream(request, request_config).await?
+                } else {
+                    let request = get_content::v3::Request::from_url(uri)?;
+                    self.client.send_with_response_stream(request, request_config).await?
+                }
+            }
+        };
+
+        let total = response
+            .headers()
+            .get(CONTENT_LENGTH)
+            .and_then(|length| length.to_str().ok()?.parse().ok());
+        let mut current = 0;
+        let mut chunks = response.into_body();
+
+        if let Some(progress) = &progress {
+            progress.set(TransmissionProgress { current, total });
+        }
+
+        #[cfg(feature = "e2e-encryption")]
+        let feeder = ChunkReader::default();
+        #[cfg(feature = "e2e-encryption")]
+        let mut reader = feeder.clone();
+        #[cfg(feature = "e2e-encryption")]
+        let mut decryptor = match &request.source {
+            MediaSource::Encrypted(file) => {
+                Some(AttachmentDecryptor::new(&mut reader, file.as_ref().clone().into())?)
+            }
+            MediaSource::Plain(_) => None,
+        };
+
+        while let Some(chunk) = chunks.next().await {
+            let chunk = chunk?;
+            current += chunk.len() as u64;
+
+            #[cfg(feature = "e2e-encryption")]
+            let chunk = match &mut decryptor {
+                Some(decryptor) => feeder.process(chunk, decryptor)?,
+                None => chunk,
+            };
+
+            writer.write_all(&chunk).await?;
+
+            if let Some(progress) = &progress {
+                progress.set(TransmissionProgress { current, total });
+            }
+        }
+
+        // Reaching the end of the content checks its hash.
+        #[cfg(feature = "e2e-encryption")]
+        if let Some(decryptor) = &mut decryptor {
+            decryptor.read_to_end(&mut Vec::new())?;
+        }
+
+        writer.flush().await?;
+
+        Ok(())
+    }
+
     /// Remove a media file's content from the store.
     ///
     /// # Arguments
@@ -180,6 +457,35 @@ impl Media {
         Ok(self.client.store().remove_media_content_for_uri(uri).await?)
     }
 
+    /// Set the policy deciding which media files are kept in the store, and
+    /// remove the files that don't comply with it.
+    ///
+    /// # Arguments
+    ///
+    /// * `policy` - The new `MediaRetentionPolicy`.
+    pub async fn set_retention_policy(&self, policy: MediaRetentionPolicy) -> Result<()> {
+        Ok(self.client.store().set_media_retention_policy(policy).await?)
+    }
+
+    /// Remove the media files that don't comply with the retention policy
+    /// anymore from the store.
+    ///
+    /// Expired files are otherwise only removed when a file is added to the
+    /// cache or accessed, so this can be called periodically to free space.
+    pub async fn clean_up_cache(&self) -> Result<()> {
+        Ok(self.client.store().clean_up_media_cache().await?)
+    }
+
+    /// Get the space used by the media files in the store.
+    pub async fn cache_usage(&self) -> Result<MediaCacheUsage> {
+        Ok(self.client.store().media_cache_usage().await?)
+    }
+
+    /// Remove all the media files from the store.
+    pub async fn clear_cache(&self) -> Result<()> {
+        Ok(self.client.store().clear_media_cache().await?)
+    }
+
     /// Get the file of the given media event content.
     ///
     /// If the content is encrypted and encryption is enabled, the content will
@@ -373,3 +679,134 @@ impl Media {
         })
     }
 }
+
+/// The timeout of an upload of `size` bytes.
+fn upload_timeout(size: u64) -> Duration {
+    std::cmp::max(Duration::from_secs(size / DEFAULT_UPLOAD_SPEED), MIN_UPLOAD_REQUEST_TIMEOUT)
+}
+
+/// Read the chunks of a streamed upload from `reader`, updating `progress`.
+fn read_chunks(
+    mut reader: impl AsyncRead + Unpin + SendOutsideWasm + 'static,
+    size: u64,
+    progress: Option<Mutable<TransmissionProgress>>,
+) -> ByteStream {
+    Box::pin(async_stream::stream! {
+        let mut buf = vec![0; UPLOAD_CHUNK_SIZE];
+        let mut current = 0;
+
+        loop {
+            match reader.read(&mut buf).await {
+                Ok(0) => break,
+                Ok(read) => {
+                    current += read as u64;
+
+                    if let Some(progress) = &progress {
+                        progress.set(TransmissionProgress { current, total: Some(size) });
+                    }
+
+                    yield Ok(Bytes::copy_from_slice(&buf[..read]));
+                }
+                Err(e) => {
+                    yield Err(e);
+                    break;
+                }
+            }
+        }
+    })
+}
+
+/// Encrypt the chunks of a streamed upload.
+///
+/// The encryption info is only complete once all the chunks were encrypted, so
+/// it is put in `keys` before the last chunk is yielded. The HTTP client
+/// doesn't poll the body again once `size` bytes were sent, so the end of the
+/// stream might never be reached.
+#[cfg(feature = "e2e-encryption")]
+fn encrypt_chunks(
+    mut chunks: ByteStream,
+    size: u64,
+    keys: Arc<SyncMutex<Option<MediaEncryptionInfo>>>,
+) -> ByteStream {
+    Box::pin(async_stream::stream! {
+        let feeder = ChunkReader::default();
+        let mut reader = feeder.clone();
+        let mut encryptor = AttachmentEncryptor::new(&mut reader);
+        let mut current = 0;
+        let mut last_chunk = None;
+
+        while let Some(chunk) = chunks.next().await {
+            match chunk.and_then(|chunk| feeder.process(chunk, &mut encryptor)) {
+                Ok(chunk) => {
+                    current += chunk.len() as u64;
+
+                    if current >= size {
+                        last_chunk = Some(chunk);
+                        break;
+                    }
+
+                    yield Ok(chunk);
+                }
+                Err(e) => {
+                    yield Err(e);
+                    return;
+                }
+            }
+        }
+
+        *keys.lock().unwrap() = Some(encryptor.finish());
+
+        if let Some(chunk) = last_chunk {
+            yield Ok(chunk);
+        }
+    })
+}
+
+/// A reader that is fed chunk by chunk.
+///
+/// [`AttachmentEncryptor`] and [`AttachmentDecryptor`] wrap a reader for their
+/// whole lifetime, a clone of this reader allows to pass them the chunks of a
+/// streamed body.
+#[cfg(feature = "e2e-encryption")]
+#[derive(Clone, Default)]
+struct ChunkReader(Arc<SyncMutex<Cursor<Bytes>>>);
+
+#[cfg(feature = "e2e-encryption")]
+impl ChunkReader {
+    /// Pass `chunk` through `reader`, that must wrap a clone of this reader.
+    fn process(&self, chunk: Bytes, reader: &mut impl Read) -> io::Result<Bytes> {
+        let mut output = vec![0; chunk.len()];
+        *self.0.lock().unwrap() = Cursor::new(chunk);
+
+        // The chunk is read in a single call, so the wrapping reader never
+        // sees the end of the chunk as the end of the body.
+        reader.read_exact(&mut output)?;
+
+        Ok(output.into())
+    }
+}
+
+#[cfg(feature = "e2e-encryption")]
+impl Read for ChunkReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().read(buf)
+    }
+}
+
+/// A tokio `File` that implements the `AsyncRead` trait of `futures`.
+#[cfg(not(target_arch = "wasm32"))]
+struct FileReader(tokio::fs::File);
+
+#[cfg(not(target_arch = "wasm32"))]
+impl AsyncRead for FileReader {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut read_buf = tokio::io::ReadBuf::new(buf);
+
+        tokio::io::AsyncRead::poll_read(Pin::new(&mut self.0), cx, &mut read_buf)
+            .map_ok(|()| read_buf.filled().len())
+    }
+}
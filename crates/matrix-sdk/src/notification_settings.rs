@@ -0,0 +1,446 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! High-level API to manage the notification settings of the user.
+//!
+//! The notification settings are stored on the homeserver as [push rules].
+//! Changes are applied to the push rules in the local store right away, so
+//! they are taken into account for the notifications of the next syncs, and
+//! reverted if the homeserver rejects them.
+//!
+//! [push rules]: https://spec.matrix.org/v1.4/client-server-api/#push-rules
+
+use std::future::Future;
+
+use matrix_sdk_base::StateChanges;
+use ruma::{
+    api::client::push::{delete_pushrule, set_pushrule, set_pushrule_enabled, RuleKind},
+    assign,
+    events::{
+        push_rules::{PushRulesEvent, PushRulesEventContent},
+        AnyGlobalAccountDataEvent,
+    },
+    push::{
+        Action, ConditionalPushRuleInit, PatternedPushRuleInit, PushCondition, Ruleset,
+        SimplePushRuleInit, Tweak,
+    },
+    serde::Raw,
+    RoomId,
+};
+
+use crate::{Client, Result};
+
+/// The scope of the push rules of the user, the only one defined in the spec.
+const GLOBAL_SCOPE: &str = "global";
+
+/// The notification mode of a room.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RoomNotificationMode {
+    /// Notify for all the messages of the room.
+    AllMessages,
+
+    /// Only notify for the messages that mention the user or contain one of
+    /// their keywords.
+    MentionsAndKeywords,
+
+    /// Never notify.
+    Mute,
+}
+
+/// A high-level API to manage the notification settings of the user.
+///
+/// Get one with [`Client::notification_settings()`].
+#[derive(Debug, Clone)]
+pub struct NotificationSettings {
+    /// The underlying HTTP client.
+    client: Client,
+}
+
+impl NotificationSettings {
+    pub(crate) fn new(client: Client) -> Self {
+        Self { client }
+    }
+
+    /// Get the push rules of the user, as they are known locally.
+    ///
+    /// If the push rules were never received from the homeserver, the
+    /// server-default push rules are returned.
+    pub async fn ruleset(&self) -> Result<Ruleset> {
+        Ok(self.client.base_client().get_push_rules(&StateChanges::default()).await?)
+    }
+
+    /// Get the notification mode of the given room.
+    ///
+    /// Returns `None` if the room uses the default notification mode, which
+    /// depends on the server-default push rules.
+    pub async fn room_notification_mode(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Option<RoomNotificationMode>> {
+        Ok(room_notification_mode(&self.ruleset().await?, room_id))
+    }
+
+    /// Set the notification mode of the given room.
+    ///
+    /// # Arguments
+    ///
+    /// * `room_id` - The ID of the room.
+    ///
+    /// * `mode` - The new notification mode of the room, or `None` to use
+    ///   the default notification mode.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use futures::executor::block_on;
+    /// # use matrix_sdk::{ruma::room_id, Client};
+    /// # use url::Url;
+    /// # block_on(async {
+    /// # let homeserver = Url::parse("http://localhost:8080")?;
+    /// # let client = Client::new(homeserver).await?;
+    /// use matrix_sdk::notification_settings::RoomNotificationMode;
+    ///
+    /// client
+    ///     .notification_settings()
+    ///     .set_room_notification_mode(
+    ///         room_id!("!test:localhost"),
+    ///         Some(RoomNotificationMode::Mute),
+    ///     )
+    ///     .await?;
+    /// # anyhow::Ok(()) });
+    /// ```
+    pub async fn set_room_notification_mode(
+        &self,
+        room_id: &RoomId,
+        mode: Option<RoomNotificationMode>,
+    ) -> Result<()> {
+        let _guard = self.client.inner.notification_settings_lock.lock().await;
+        let original = self.ruleset().await?;
+
+        if room_notification_mode(&original, room_id) == mode {
+            return Ok(());
+        }
+
+        let mut ruleset = original.clone();
+        let has_override = remove_rule(&mut ruleset, RuleKind::Override, room_id.as_str());
+        let has_room = remove_rule(&mut ruleset, RuleKind::Room, room_id.as_str());
+
+        let new_rule = match mode {
+            Some(RoomNotificationMode::Mute) => {
+                let rule = ConditionalPushRuleInit {
+                    actions: vec![Action::DontNotify],
+                    default: false,
+                    enabled: true,
+                    rule_id: room_id.to_string(),
+                    conditions: vec![PushCondition::EventMatch {
+                        key: "room_id".to_owned(),
+                        pattern: room_id.to_string(),
+                    }],
+                };
+                // The master rule is always evaluated first.
+                insert_first(&mut ruleset.override_, rule.into(), |r| {
+                    r.rule_id == ".m.rule.master"
+                });
+                Some((RuleKind::Override, vec![Action::DontNotify]))
+            }
+            Some(RoomNotificationMode::MentionsAndKeywords) => {
+                let rule = SimplePushRuleInit {
+                    actions: vec![Action::DontNotify],
+                    default: false,
+                    enabled: true,
+                    rule_id: room_id.to_string(),
+                };
+                insert_first(&mut ruleset.room, rule.into(), |_| false);
+                Some((RuleKind::Room, vec![Action::DontNotify]))
+            }
+            Some(RoomNotificationMode::AllMessages) => {
+                let actions =
+                    vec![Action::Notify, Action::SetTweak(Tweak::Sound("default".to_owned()))];
+                let rule = SimplePushRuleInit {
+                    actions: actions.clone(),
+                    default: false,
+                    enabled: true,
+                    rule_id: room_id.to_string(),
+                };
+                insert_first(&mut ruleset.room, rule.into(), |_| false);
+                Some((RuleKind::Room, actions))
+            }
+            None => None,
+        };
+
+        self.update_ruleset(original, ruleset, async {
+            if has_override {
+                self.delete_rule(RuleKind::Override, room_id.as_str()).await?;
+            }
+            if has_room {
+                self.delete_rule(RuleKind::Room, room_id.as_str()).await?;
+            }
+
+            if let Some((kind, actions)) = new_rule {
+                let conditions = if kind == RuleKind::Override {
+                    vec![PushCondition::EventMatch {
+                        key: "room_id".to_owned(),
+                        pattern: room_id.to_string(),
+                    }]
+                } else {
+                    Vec::new()
+                };
+
+                let request =
+                    set_pushrule::v3::Request::new(GLOBAL_SCOPE, kind, room_id.as_str(), &actions);
+                let request = assign!(request, { conditions: &conditions });
+                self.client.send(request, None).await?;
+            }
+
+            Ok(())
+        })
+        .await
+    }
+
+    /// Get the keywords that trigger a notification, in addition to the
+    /// mentions of the user.
+    pub async fn keywords(&self) -> Result<Vec<String>> {
+        Ok(self
+            .ruleset()
+            .await?
+            .content
+            .iter()
+            .filter(|rule| !rule.default)
+            .map(|rule| rule.pattern.clone())
+            .collect())
+    }
+
+    /// Add a keyword that triggers a notification.
+    ///
+    /// Does nothing if the keyword already exists.
+    pub async fn add_keyword(&self, keyword: &str) -> Result<()> {
+        let _guard = self.client.inner.notification_settings_lock.lock().await;
+        let original = self.ruleset().await?;
+
+        if original.content.iter().any(|rule| !rule.default && rule.pattern == keyword) {
+            return Ok(());
+        }
+
+        let actions = vec![
+            Action::Notify,
+            Action::SetTweak(Tweak::Sound("default".to_owned())),
+            Action::SetTweak(Tweak::Highlight(true)),
+        ];
+
+        let mut ruleset = original.clone();
+        let rule = PatternedPushRuleInit {
+            actions: actions.clone(),
+            default: false,
+            enabled: true,
+            rule_id: keyword.to_owned(),
+            pattern: keyword.to_owned(),
+        };
+        insert_first(&mut ruleset.content, rule.into(), |_| false);
+
+        self.update_ruleset(original, ruleset, async {
+            let request =
+                set_pushrule::v3::Request::new(GLOBAL_SCOPE, RuleKind::Content, keyword, &actions);
+            let request = assign!(request, { pattern: Some(keyword) });
+
+            self.client.send(request, None).await?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Remove a keyword that triggers a notification.
+    ///
+    /// Does nothing if the keyword doesn't exist.
+    pub async fn remove_keyword(&self, keyword: &str) -> Result<()> {
+        let _guard = self.client.inner.notification_settings_lock.lock().await;
+        let original = self.ruleset().await?;
+
+        let rule_ids: Vec<String> = original
+            .content
+            .iter()
+            .filter(|rule| !rule.default && rule.pattern == keyword)
+            .map(|rule| rule.rule_id.clone())
+            .collect();
+
+        if rule_ids.is_empty() {
+            return Ok(());
+        }
+
+        let mut ruleset = original.clone();
+        for rule_id in &rule_ids {
+            remove_rule(&mut ruleset, RuleKind::Content, rule_id);
+        }
+
+        self.update_ruleset(original, ruleset, async {
+            for rule_id in &rule_ids {
+                self.delete_rule(RuleKind::Content, rule_id).await?;
+            }
+
+            Ok(())
+        })
+        .await
+    }
+
+    /// Enable or disable a push rule, usually one of the server-default push
+    /// rules like `.m.rule.contains_display_name`.
+    ///
+    /// # Arguments
+    ///
+    /// * `kind` - The kind of the push rule.
+    ///
+    /// * `rule_id` - The ID of the push rule.
+    ///
+    /// * `enabled` - Whether the push rule should be enabled.
+    pub async fn set_rule_enabled(
+        &self,
+        kind: RuleKind,
+        rule_id: &str,
+        enabled: bool,
+    ) -> Result<()> {
+        let _guard = self.client.inner.notification_settings_lock.lock().await;
+        let original = self.ruleset().await?;
+
+        let mut ruleset = original.clone();
+        set_rule_enabled(&mut ruleset, kind.clone(), rule_id, enabled);
+
+        self.update_ruleset(original, ruleset, async {
+            let request =
+                set_pushrule_enabled::v3::Request::new(GLOBAL_SCOPE, kind, rule_id, enabled);
+            self.client.send(request, None).await?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn delete_rule(&self, kind: RuleKind, rule_id: &str) -> Result<()> {
+        let request = delete_pushrule::v3::Request::new(GLOBAL_SCOPE, kind, rule_id);
+        self.client.send(request, None).await?;
+        Ok(())
+    }
+
+    /// Save `ruleset` in the store, then run `requests` to update the push
+    /// rules on the homeserver.
+    ///
+    /// If the requests fail, `original` is saved back in the store.
+    async fn update_ruleset(
+        &self,
+        original: Ruleset,
+        ruleset: Ruleset,
+        requests: impl Future<Output = Result<()>>,
+    ) -> Result<()> {
+        self.save_ruleset(ruleset).await?;
+
+        if let Err(error) = requests.await {
+            self.save_ruleset(original).await?;
+            return Err(error);
+        }
+
+        Ok(())
+    }
+
+    async fn save_ruleset(&self, ruleset: Ruleset) -> Result<()> {
+        let event = PushRulesEvent { content: PushRulesEventContent::new(ruleset) };
+        let raw_event = Raw::new(&event)?.cast();
+
+        let mut changes = StateChanges::default();
+        changes.add_account_data(AnyGlobalAccountDataEvent::PushRules(event), raw_event);
+
+        self.client.store().save_changes(&changes).await?;
+        Ok(())
+    }
+}
+
+/// Get the notification mode of the given room from the given push rules.
+fn room_notification_mode(ruleset: &Ruleset, room_id: &RoomId) -> Option<RoomNotificationMode> {
+    let is_muted = ruleset.override_.iter().any(|rule| {
+        rule.enabled
+            && rule.rule_id == room_id.as_str()
+            && !rule.actions.iter().any(|a| matches!(a, Action::Notify))
+            && matches!(
+                rule.conditions.as_slice(),
+                [PushCondition::EventMatch { key, pattern }]
+                    if key == "room_id" && pattern == room_id.as_str()
+            )
+    });
+
+    if is_muted {
+        return Some(RoomNotificationMode::Mute);
+    }
+
+    let room_rule = ruleset.room.iter().find(|rule| rule.enabled && *rule.rule_id == *room_id)?;
+
+    if room_rule.actions.iter().any(|a| matches!(a, Action::Notify)) {
+        Some(RoomNotificationMode::AllMessages)
+    } else {
+        Some(RoomNotificationMode::MentionsAndKeywords)
+    }
+}
+
+/// Insert `rule` in `rules`, before all the rules except the ones that match
+/// `keep_first`.
+fn insert_first<S, T>(rules: &mut S, rule: T, keep_first: impl Fn(&T) -> bool)
+where
+    S: Default + IntoIterator<Item = T> + FromIterator<T>,
+{
+    let (first, rest): (Vec<T>, Vec<T>) = std::mem::take(rules).into_iter().partition(keep_first);
+    *rules = first.into_iter().chain(std::iter::once(rule)).chain(rest).collect();
+}
+
+/// Remove the user-defined push rule with the given kind and ID.
+///
+/// Returns `true` if a push rule was removed.
+fn remove_rule(ruleset: &mut Ruleset, kind: RuleKind, rule_id: &str) -> bool {
+    macro_rules! remove {
+        ($rules:expr) => {{
+            let len = $rules.len();
+            $rules.retain(|rule| rule.default || rule.rule_id.as_str() != rule_id);
+            $rules.len() != len
+        }};
+    }
+
+    match kind {
+        RuleKind::Override => remove!(ruleset.override_),
+        RuleKind::Underride => remove!(ruleset.underride),
+        RuleKind::Sender => remove!(ruleset.sender),
+        RuleKind::Room => remove!(ruleset.room),
+        RuleKind::Content => remove!(ruleset.content),
+        _ => false,
+    }
+}
+
+/// Enable or disable the push rule with the given kind and ID.
+fn set_rule_enabled(ruleset: &mut Ruleset, kind: RuleKind, rule_id: &str, enabled: bool) {
+    macro_rules! set_enabled {
+        ($rules:expr) => {
+            $rules = std::mem::take(&mut $rules)
+                .into_iter()
+                .map(|mut rule| {
+                    if rule.rule_id.as_str() == rule_id {
+                        rule.enabled = enabled;
+                    }
+                    rule
+                })
+                .collect()
+        };
+    }
+
+    match kind {
+        RuleKind::Override => set_enabled!(ruleset.override_),
+        RuleKind::Underride => set_enabled!(ruleset.underride),
+        RuleKind::Sender => set_enabled!(ruleset.sender),
+        RuleKind::Room => set_enabled!(ruleset.room),
+        RuleKind::Content => set_enabled!(ruleset.content),
+        _ => {}
+    }
+}
@@ -0,0 +1,624 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! High-level OpenID Connect API, as proposed in [MSC3861].
+//!
+//! With OpenID Connect, the user doesn't log in with the homeserver but with
+//! the OpenID Provider it trusts, called the issuer. The login happens in a
+//! browser, using the authorization code flow with [PKCE], then the client
+//! exchanges the code it receives for an access token and a refresh token that
+//! can be used with the homeserver.
+//!
+//! The steps to log in are:
+//!
+//! 1. Register the client with the issuer, once, with
+//!    [`Oidc::register_client()`]. The client ID should be persisted and
+//!    restored with [`Oidc::restore_registered_client()`].
+//! 2. Get the URL to open in a browser with [`Oidc::url_for_login()`].
+//! 3. Finish the login with the URL the browser was redirected to, with
+//!    [`Oidc::finish_login()`].
+//!
+//! The access token is refreshed with the issuer by
+//! [`Client::refresh_access_token()`], so [handling refresh tokens]
+//! automatically works the same way as with the Matrix login API.
+//!
+//! [MSC3861]: https://github.com/matrix-org/matrix-spec-proposals/pull/3861
+//! [PKCE]: https://datatracker.ietf.org/doc/html/rfc7636
+//! [handling refresh tokens]: crate::ClientBuilder::handle_refresh_tokens
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex as StdMutex, RwLock as StdRwLock,
+    },
+    time::Duration,
+};
+
+use bytes::Bytes;
+use http::{
+    header::{ACCEPT, CONTENT_TYPE},
+    Method,
+};
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use ruma::{
+    api::{
+        client::{account::whoami, session::refresh_token},
+        error::IntoHttpError,
+    },
+    assign, DeviceId, OwnedDeviceId,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use url::{form_urlencoded, Url};
+
+use crate::{error::OidcError, Client, HttpError, Session, SessionState};
+
+/// The length of the random strings used as PKCE code verifier and state.
+const RANDOM_STRING_LENGTH: usize = 64;
+
+/// The metadata of an OpenID Provider, as defined in [OpenID Connect
+/// Discovery].
+///
+/// Only the fields that are used by the SDK are included.
+///
+/// [OpenID Connect Discovery]: https://openid.net/specs/openid-connect-discovery-1_0.html#ProviderMetadata
+#[derive(Clone, Debug, Deserialize)]
+#[non_exhaustive]
+pub struct ProviderMetadata {
+    /// The URL of the issuer.
+    pub issuer: Url,
+
+    /// The URL of the authorization endpoint.
+    pub authorization_endpoint: Url,
+
+    /// The URL of the token endpoint.
+    pub token_endpoint: Url,
+
+    /// The URL of the dynamic client registration endpoint, if supported.
+    #[serde(default)]
+    pub registration_endpoint: Option<Url>,
+
+    /// The URL of the token revocation endpoint, if supported.
+    #[serde(default)]
+    pub revocation_endpoint: Option<Url>,
+
+    /// The URL where the user can manage their account, if any.
+    #[serde(default)]
+    pub account_management_uri: Option<Url>,
+}
+
+/// The kind of application of a client.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ApplicationType {
+    /// A web application, that uses `https` redirect URIs.
+    Web,
+
+    /// A native application, that uses a custom URI scheme or `localhost` as
+    /// redirect URIs.
+    Native,
+}
+
+/// The metadata of a client to register with an issuer, as defined in
+/// [RFC 7591].
+///
+/// [RFC 7591]: https://datatracker.ietf.org/doc/html/rfc7591#section-2
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct ClientMetadata {
+    /// The kind of application of the client.
+    pub application_type: ApplicationType,
+
+    /// The URIs the issuer is allowed to redirect to after the authorization.
+    pub redirect_uris: Vec<Url>,
+
+    /// The human-readable name of the client.
+    pub client_name: Option<String>,
+
+    /// The URL of the home page of the client.
+    pub client_uri: Option<Url>,
+
+    /// The URL of the logo of the client.
+    pub logo_uri: Option<Url>,
+
+    /// The URL of the privacy policy of the client.
+    pub policy_uri: Option<Url>,
+
+    /// The URL of the terms of service of the client.
+    pub tos_uri: Option<Url>,
+}
+
+impl ClientMetadata {
+    /// Create a new `ClientMetadata` with the given application type and
+    /// redirect URIs.
+    pub fn new(application_type: ApplicationType, redirect_uris: Vec<Url>) -> Self {
+        Self {
+            application_type,
+            redirect_uris,
+            client_name: None,
+            client_uri: None,
+            logo_uri: None,
+            policy_uri: None,
+            tos_uri: None,
+        }
+    }
+}
+
+/// The data needed to perform an authorization with an issuer.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct OidcAuthorizationData {
+    /// The URL to open in a browser to log in.
+    pub url: Url,
+
+    /// The unique identifier of this authorization, it is found in the URL
+    /// the browser is redirected to.
+    pub state: String,
+}
+
+/// The data of an authorization in progress, used to validate the callback.
+#[derive(Debug)]
+struct AuthorizationValidationData {
+    redirect_uri: Url,
+    code_verifier: String,
+    device_id: OwnedDeviceId,
+}
+
+/// The state of the OpenID Connect API of a [`Client`].
+#[derive(Debug, Default)]
+pub(crate) struct OidcState {
+    provider_metadata: StdRwLock<Option<ProviderMetadata>>,
+    client_id: StdRwLock<Option<String>>,
+    authorizations: StdMutex<HashMap<String, AuthorizationValidationData>>,
+    /// Whether the current session of the `Client` was created with OpenID
+    /// Connect.
+    logged_in: AtomicBool,
+}
+
+/// The body of a successful response of the token endpoint.
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    #[serde(default)]
+    refresh_token: Option<String>,
+    #[serde(default)]
+    expires_in: Option<u64>,
+}
+
+/// The body of an error response of the issuer.
+#[derive(Debug, Deserialize)]
+struct ErrorResponse {
+    error: String,
+    #[serde(default)]
+    error_description: Option<String>,
+}
+
+/// A high-level API to log in with OpenID Connect.
+///
+/// Get an instance with [`Client::oidc()`].
+#[derive(Debug, Clone)]
+pub struct Oidc {
+    client: Client,
+}
+
+impl Oidc {
+    pub(crate) fn new(client: Client) -> Self {
+        Self { client }
+    }
+
+    fn state(&self) -> &OidcState {
+        &self.client.inner.oidc
+    }
+
+    /// Get the metadata of the issuer trusted by the homeserver.
+    ///
+    /// The metadata is discovered the first time this is called, from the
+    /// [`Client::authentication_issuer()`], and cached afterwards.
+    pub async fn provider_metadata(&self) -> Result<ProviderMetadata, OidcError> {
+        if let Some(metadata) = self.state().provider_metadata.read().unwrap().clone() {
+            return Ok(metadata);
+        }
+
+        let issuer = self.client.authentication_issuer().await.ok_or(OidcError::NoIssuer)?;
+        let mut url = issuer.clone();
+        url.path_segments_mut()
+            .map_err(|_| OidcError::InvalidMetadata("issuer"))?
+            .pop_if_empty()
+            .extend([".well-known", "openid-configuration"]);
+
+        let request = http::Request::builder()
+            .method(Method::GET)
+            .uri(url.as_str())
+            .header(ACCEPT, "application/json")
+            .body(Bytes::new())
+            .map_err(into_http_error)?;
+        let metadata: ProviderMetadata = serde_json::from_slice(&self.send(request).await?)?;
+
+        if metadata.issuer != issuer {
+            return Err(OidcError::InvalidMetadata("issuer"));
+        }
+
+        *self.state().provider_metadata.write().unwrap() = Some(metadata.clone());
+
+        Ok(metadata)
+    }
+
+    /// The URL where the user can manage their account, if any.
+    ///
+    /// It is advertised by the homeserver, or by the issuer as a fallback.
+    pub async fn account_management_url(&self) -> Result<Option<Url>, OidcError> {
+        if let Some(url) = &self.client.inner.account_management_url {
+            return Ok(Some(url.clone()));
+        }
+
+        Ok(self.provider_metadata().await?.account_management_uri)
+    }
+
+    /// The ID of the client registered with the issuer, if any.
+    pub fn client_id(&self) -> Option<String> {
+        self.state().client_id.read().unwrap().clone()
+    }
+
+    /// Register the client with the issuer, with [dynamic client
+    /// registration].
+    ///
+    /// This only needs to be done once per issuer, the returned client ID
+    /// should be persisted and restored with
+    /// [`Oidc::restore_registered_client()`] afterwards.
+    ///
+    /// [dynamic client registration]: https://datatracker.ietf.org/doc/html/rfc7591
+    pub async fn register_client(&self, metadata: &ClientMetadata) -> Result<String, OidcError> {
+        #[derive(Serialize)]
+        struct RegistrationRequest<'a> {
+            application_type: ApplicationType,
+            redirect_uris: &'a [Url],
+            #[serde(skip_serializing_if = "Option::is_none")]
+            client_name: Option<&'a str>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            client_uri: Option<&'a Url>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            logo_uri: Option<&'a Url>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            policy_uri: Option<&'a Url>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            tos_uri: Option<&'a Url>,
+            grant_types: [&'static str; 2],
+            response_types: [&'static str; 1],
+            token_endpoint_auth_method: &'static str,
+        }
+
+        #[derive(Deserialize)]
+        struct RegistrationResponse {
+            client_id: String,
+        }
+
+        let endpoint = self
+            .provider_metadata()
+            .await?
+            .registration_endpoint
+            .ok_or(OidcError::MissingEndpoint("registration_endpoint"))?;
+
+        let body = serde_json::to_vec(&RegistrationRequest {
+            application_type: metadata.application_type,
+            redirect_uris: &metadata.redirect_uris,
+            client_name: metadata.client_name.as_deref(),
+            client_uri: metadata.client_uri.as_ref(),
+            logo_uri: metadata.logo_uri.as_ref(),
+            policy_uri: metadata.policy_uri.as_ref(),
+            tos_uri: metadata.tos_uri.as_ref(),
+            grant_types: ["authorization_code", "refresh_token"],
+            response_types: ["code"],
+            token_endpoint_auth_method: "none",
+        })?;
+
+        let request = http::Request::builder()
+            .method(Method::POST)
+            .uri(endpoint.as_str())
+            .header(CONTENT_TYPE, "application/json")
+            .header(ACCEPT, "application/json")
+            .body(Bytes::from(body))
+            .map_err(into_http_error)?;
+        let response: RegistrationResponse = serde_json::from_slice(&self.send(request).await?)?;
+
+        self.restore_registered_client(response.client_id.clone());
+
+        Ok(response.client_id)
+    }
+
+    /// Whether the current session of the `Client` was created with OpenID
+    /// Connect, with [`Oidc::finish_login()`] or [`Oidc::restore_session()`].
+    ///
+    /// The tokens of such a session are refreshed and revoked with the issuer
+    /// rather than with the homeserver.
+    pub fn logged_in(&self) -> bool {
+        self.state().logged_in.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn set_logged_in(&self, logged_in: bool) {
+        self.state().logged_in.store(logged_in, Ordering::SeqCst);
+    }
+
+    /// Restore the ID of a client that was previously registered with the
+    /// issuer.
+    pub fn restore_registered_client(&self, client_id: String) {
+        *self.state().client_id.write().unwrap() = Some(client_id);
+    }
+
+    /// Get the URL to open in a browser to log in with the issuer.
+    ///
+    /// A new device ID is generated for the session.
+    ///
+    /// # Arguments
+    ///
+    /// * `redirect_uri` - The URI the browser should be redirected to after
+    ///   the authorization. It must be one of the `redirect_uris` of the
+    ///   registered client.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use futures::executor::block_on;
+    /// # block_on(async {
+    /// # let homeserver = url::Url::parse("http://localhost:8080")?;
+    /// # let client = matrix_sdk::Client::new(homeserver).await?;
+    /// # fn open_and_wait_for_redirect(url: &url::Url) -> url::Url { todo!() }
+    /// use matrix_sdk::oidc::{ApplicationType, ClientMetadata};
+    /// use url::Url;
+    ///
+    /// let redirect_uri = Url::parse("com.example.app:/callback")?;
+    /// let oidc = client.oidc();
+    ///
+    /// let metadata = ClientMetadata::new(ApplicationType::Native, vec![redirect_uri.clone()]);
+    /// oidc.register_client(&metadata).await?;
+    ///
+    /// let authorization_data = oidc.url_for_login(&redirect_uri).await?;
+    /// let callback_url = open_and_wait_for_redirect(&authorization_data.url);
+    /// oidc.finish_login(&callback_url).await?;
+    ///
+    /// println!("Logged in as {}", client.user_id().unwrap());
+    /// # anyhow::Ok(()) });
+    /// ```
+    pub async fn url_for_login(
+        &self,
+        redirect_uri: &Url,
+    ) -> Result<OidcAuthorizationData, OidcError> {
+        let client_id = self.client_id().ok_or(OidcError::NotRegistered)?;
+        let mut url = self.provider_metadata().await?.authorization_endpoint;
+
+        let device_id = DeviceId::new();
+        let scope = format!(
+            "openid urn:matrix:org.matrix.msc2967.client:api:* \
+             urn:matrix:org.matrix.msc2967.client:device:{device_id}"
+        );
+        let state = random_string();
+        let code_verifier = random_string();
+        let code_challenge = base64::encode_config(
+            Sha256::digest(code_verifier.as_bytes()),
+            base64::URL_SAFE_NO_PAD,
+        );
+
+        url.query_pairs_mut()
+            .append_pair("response_type", "code")
+            .append_pair("client_id", &client_id)
+            .append_pair("redirect_uri", redirect_uri.as_str())
+            .append_pair("scope", &scope)
+            .append_pair("state", &state)
+            .append_pair("code_challenge", &code_challenge)
+            .append_pair("code_challenge_method", "S256");
+
+        self.state().authorizations.lock().unwrap().insert(
+            state.clone(),
+            AuthorizationValidationData {
+                redirect_uri: redirect_uri.clone(),
+                code_verifier,
+                device_id,
+            },
+        );
+
+        Ok(OidcAuthorizationData { url, state })
+    }
+
+    /// Finish the login with the URL the browser was redirected to after the
+    /// authorization.
+    ///
+    /// The authorization code is exchanged for the tokens of the session, and
+    /// the `Client` is logged in. The session should be persisted with
+    /// [`Client::session()`] and restored with [`Oidc::restore_session()`].
+    pub async fn finish_login(&self, callback_url: &Url) -> Result<(), OidcError> {
+        let mut params: HashMap<_, _> = callback_url.query_pairs().collect();
+        let state = params.remove("state").ok_or(OidcError::MissingParameter("state"))?;
+
+        let validation_data = self
+            .state()
+            .authorizations
+            .lock()
+            .unwrap()
+            .remove(&*state)
+            .ok_or(OidcError::InvalidState)?;
+
+        if let Some(error) = params.remove("error") {
+            return Err(OidcError::Issuer {
+                error: error.into_owned(),
+                description: params.remove("error_description").map(Into::into),
+            });
+        }
+
+        let code = params.remove("code").ok_or(OidcError::MissingParameter("code"))?;
+        let client_id = self.client_id().ok_or(OidcError::NotRegistered)?;
+
+        let response = self
+            .request_token(&[
+                ("grant_type", "authorization_code"),
+                ("code", &code),
+                ("redirect_uri", validation_data.redirect_uri.as_str()),
+                ("client_id", &client_id),
+                ("code_verifier", &validation_data.code_verifier),
+            ])
+            .await?;
+
+        let whoami = self
+            .client
+            .http_client()
+            .send(
+                whoami::v3::Request::new(),
+                None,
+                self.client.homeserver().await.to_string(),
+                Some(&response.access_token),
+                None,
+                self.client.server_versions().await?,
+            )
+            .await?;
+
+        let session = Session {
+            access_token: response.access_token,
+            refresh_token: response.refresh_token,
+            user_id: whoami.user_id,
+            device_id: validation_data.device_id,
+        };
+        self.client.base_client().restore_login(session).await?;
+        self.set_logged_in(true);
+        self.client.set_session_state(SessionState::LoggedIn);
+
+        Ok(())
+    }
+
+    /// Restore a session that was previously logged in with
+    /// [`Oidc::finish_login()`].
+    ///
+    /// # Arguments
+    ///
+    /// * `client_id` - The ID of the client registered with the issuer.
+    ///
+    /// * `session` - The session, as returned by [`Client::session()`].
+    pub async fn restore_session(
+        &self,
+        client_id: String,
+        session: Session,
+    ) -> Result<(), OidcError> {
+        self.restore_registered_client(client_id);
+        self.client.base_client().restore_login(session).await?;
+        self.set_logged_in(true);
+        self.client.set_session_state(SessionState::LoggedIn);
+
+        Ok(())
+    }
+
+    /// Refresh the access token with the issuer.
+    pub(crate) async fn refresh_access_token(
+        &self,
+        refresh_token: &str,
+    ) -> Result<refresh_token::v3::Response, OidcError> {
+        let client_id = self.client_id().ok_or(OidcError::NotRegistered)?;
+
+        let response = self
+            .request_token(&[
+                ("grant_type", "refresh_token"),
+                ("refresh_token", refresh_token),
+                ("client_id", &client_id),
+            ])
+            .await?;
+
+        Ok(assign!(refresh_token::v3::Response::new(response.access_token), {
+            refresh_token: response.refresh_token,
+            expires_in_ms: response.expires_in.map(Duration::from_secs),
+        }))
+    }
+
+    /// Log out by revoking the tokens of the session with the issuer.
+    ///
+    /// The local state of the `Client` is left untouched.
+    pub async fn logout(&self) -> Result<(), OidcError> {
+        let client_id = self.client_id().ok_or(OidcError::NotRegistered)?;
+        let tokens = self.client.session_tokens().ok_or(HttpError::AuthenticationRequired)?;
+        let endpoint = self
+            .provider_metadata()
+            .await?
+            .revocation_endpoint
+            .ok_or(OidcError::MissingEndpoint("revocation_endpoint"))?;
+
+        if let Some(refresh_token) = &tokens.refresh_token {
+            self.revoke_token(&endpoint, &client_id, refresh_token, "refresh_token").await?;
+        }
+        self.revoke_token(&endpoint, &client_id, &tokens.access_token, "access_token").await?;
+
+        Ok(())
+    }
+
+    async fn revoke_token(
+        &self,
+        endpoint: &Url,
+        client_id: &str,
+        token: &str,
+        token_type_hint: &str,
+    ) -> Result<(), OidcError> {
+        let request = form_request(
+            endpoint,
+            &[("token", token), ("token_type_hint", token_type_hint), ("client_id", client_id)],
+        )?;
+        self.send(request).await?;
+
+        Ok(())
+    }
+
+    async fn request_token(&self, params: &[(&str, &str)]) -> Result<TokenResponse, OidcError> {
+        let endpoint = self.provider_metadata().await?.token_endpoint;
+        let request = form_request(&endpoint, params)?;
+
+        Ok(serde_json::from_slice(&self.send(request).await?)?)
+    }
+
+    /// Send the given request to the issuer and return the body of the
+    /// response.
+    async fn send(&self, request: http::Request<Bytes>) -> Result<Bytes, OidcError> {
+        let http_client = self.client.http_client();
+        let response = http_client.inner.send_request(request, http_client.request_config).await?;
+
+        if response.status().is_success() {
+            return Ok(response.into_body());
+        }
+
+        Err(parse_error_response(response.body())
+            .unwrap_or_else(|| HttpError::Server(response.status()).into()))
+    }
+}
+
+/// Build a `POST` request with the given parameters as form-encoded body.
+fn form_request(
+    endpoint: &Url,
+    params: &[(&str, &str)],
+) -> Result<http::Request<Bytes>, OidcError> {
+    let body = form_urlencoded::Serializer::new(String::new()).extend_pairs(params).finish();
+
+    Ok(http::Request::builder()
+        .method(Method::POST)
+        .uri(endpoint.as_str())
+        .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+        .header(ACCEPT, "application/json")
+        .body(Bytes::from(body))
+        .map_err(into_http_error)?)
+}
+
+fn into_http_error(error: http::Error) -> HttpError {
+    HttpError::IntoHttp(IntoHttpError::from(error))
+}
+
+fn parse_error_response(body: &[u8]) -> Option<OidcError> {
+    let response: ErrorResponse = serde_json::from_slice(body).ok()?;
+    Some(OidcError::Issuer { error: response.error, description: response.error_description })
+}
+
+fn random_string() -> String {
+    thread_rng().sample_iter(Alphanumeric).take(RANDOM_STRING_LENGTH).map(char::from).collect()
+}
@@ -0,0 +1,815 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Aggregation of edits and reactions into the events of a room timeline.
+
+#[cfg(feature = "experimental-timeline")]
+use std::future::Future;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex as SyncMutex,
+    },
+};
+
+#[cfg(feature = "experimental-timeline")]
+use futures_core::stream::Stream;
+use futures_signals::{
+    signal::{Mutable, Signal, SignalExt},
+    signal_vec::{MutableVec, SignalVec},
+};
+#[cfg(feature = "experimental-timeline")]
+use futures_util::{future, pin_mut, StreamExt};
+use matrix_sdk_base::deserialized_responses::SyncTimelineEvent;
+use ruma::{
+    events::AnyMessageLikeEventContent, serde::Raw, EventId, MilliSecondsSinceUnixEpoch,
+    OwnedEventId, OwnedUserId, UserId,
+};
+use serde::Deserialize;
+#[cfg(feature = "experimental-timeline")]
+use tracing::warn;
+
+/// An event of an [`AggregatedTimeline`], together with the edits and
+/// reactions that relate to it.
+#[derive(Clone, Debug)]
+pub struct TimelineItem {
+    /// The ID of the event.
+    pub event_id: OwnedEventId,
+    /// The sender of the event.
+    pub sender: OwnedUserId,
+    /// The original event, as it was received.
+    pub event: SyncTimelineEvent,
+    /// The latest valid `m.replace` edit of the event, if any.
+    pub edit: Option<SyncTimelineEvent>,
+    /// The `m.annotation` reactions to the event, grouped by their key.
+    pub reactions: BTreeMap<String, ReactionGroup>,
+}
+
+impl TimelineItem {
+    /// The content of the event with the latest edit applied, i.e. the
+    /// `m.new_content` of the edit if there is one, the content of the
+    /// original event otherwise.
+    ///
+    /// Returns `None` if the content is missing or malformed.
+    pub fn content(&self) -> Option<Raw<AnyMessageLikeEventContent>> {
+        #[derive(Deserialize)]
+        struct EditContent {
+            #[serde(rename = "m.new_content")]
+            new_content: Raw<AnyMessageLikeEventContent>,
+        }
+
+        match &self.edit {
+            Some(edit) => Some(edit.event.get_field::<EditContent>("content").ok()??.new_content),
+            None => self.event.event.get_field("content").ok().flatten(),
+        }
+    }
+
+    /// Whether the event was edited.
+    pub fn is_edited(&self) -> bool {
+        self.edit.is_some()
+    }
+}
+
+/// The reactions with the same key to a [`TimelineItem`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ReactionGroup {
+    /// The IDs of the reaction events, mapped to their sender.
+    pub reactions: BTreeMap<OwnedEventId, OwnedUserId>,
+}
+
+impl ReactionGroup {
+    /// The number of users that reacted with this key.
+    ///
+    /// Users that reacted multiple times with the same key are only counted
+    /// once.
+    pub fn count(&self) -> usize {
+        self.senders().count()
+    }
+
+    /// The users that reacted with this key, without duplicates.
+    pub fn senders(&self) -> impl Iterator<Item = &UserId> {
+        self.reactions.values().map(|s| &**s).collect::<BTreeSet<_>>().into_iter()
+    }
+}
+
+/// The fields of an event that are needed to aggregate it.
+#[derive(Deserialize)]
+struct EventFields {
+    event_id: OwnedEventId,
+    sender: OwnedUserId,
+    origin_server_ts: MilliSecondsSinceUnixEpoch,
+    #[serde(rename = "type")]
+    event_type: String,
+    state_key: Option<String>,
+    /// The redacted event, for redactions in room versions before 11.
+    redacts: Option<OwnedEventId>,
+    #[serde(default)]
+    content: ContentFields,
+}
+
+#[derive(Default, Deserialize)]
+struct ContentFields {
+    #[serde(rename = "m.relates_to")]
+    relates_to: Option<RelatesTo>,
+    /// The redacted event, for redactions in room versions 11 and later.
+    redacts: Option<OwnedEventId>,
+}
+
+#[derive(Deserialize)]
+struct RelatesTo {
+    rel_type: Option<String>,
+    event_id: Option<OwnedEventId>,
+    key: Option<String>,
+}
+
+/// What an event does to the timeline.
+enum Action {
+    /// The event is shown in the timeline.
+    Add,
+    /// The event edits the event with the given ID.
+    Edit(OwnedEventId),
+    /// The event reacts to the event with the given ID.
+    React { target: OwnedEventId, key: String },
+    /// The event redacts the event with the given ID.
+    Redact(OwnedEventId),
+    /// The event is ignored, e.g. a reaction without a valid relation.
+    Ignore,
+}
+
+impl EventFields {
+    fn action(&self) -> Action {
+        if self.state_key.is_some() {
+            return Action::Add;
+        }
+
+        if self.event_type == "m.room.redaction" {
+            return match self.redacts.as_ref().or(self.content.redacts.as_ref()) {
+                Some(redacts) => Action::Redact(redacts.clone()),
+                None => Action::Ignore,
+            };
+        }
+
+        let relates_to = self.content.relates_to.as_ref();
+
+        match relates_to.map(|r| (r.rel_type.as_deref(), &r.event_id, &r.key)) {
+            Some((Some("m.replace"), Some(target), _)) => Action::Edit(target.clone()),
+            Some((Some("m.annotation"), Some(target), Some(key)))
+                if self.event_type == "m.reaction" =>
+            {
+                Action::React { target: target.clone(), key: key.clone() }
+            }
+            // Reactions that were redacted before we received them lost their
+            // relation.
+            _ if self.event_type == "m.reaction" => Action::Ignore,
+            _ => Action::Add,
+        }
+    }
+}
+
+/// An edit that was received for an event.
+#[derive(Clone, Debug)]
+struct Edit {
+    event_id: OwnedEventId,
+    sender: OwnedUserId,
+    event_type: String,
+    origin_server_ts: MilliSecondsSinceUnixEpoch,
+    event: SyncTimelineEvent,
+}
+
+/// The relations of a target event, kept even if the target event itself
+/// wasn't received yet.
+#[derive(Debug, Default)]
+struct Aggregations {
+    edits: Vec<Edit>,
+    reactions: BTreeMap<String, ReactionGroup>,
+}
+
+#[derive(Debug, Default)]
+struct AggregationState {
+    /// The aggregations, by the ID of the event they relate to.
+    aggregations: BTreeMap<OwnedEventId, Aggregations>,
+    /// The IDs of all the edits and reactions that were received, mapped to the
+    /// ID of the event they relate to, to be able to undo them when they get
+    /// redacted.
+    relations: BTreeMap<OwnedEventId, OwnedEventId>,
+    /// The type of the events that are in the timeline, to validate edits.
+    event_types: BTreeMap<OwnedEventId, String>,
+    /// The IDs of the events that were redacted, the redaction might be
+    /// received before the event when paginating backward.
+    redacted: BTreeSet<OwnedEventId>,
+}
+
+#[derive(Debug, Default)]
+struct AggregatedTimelineInner {
+    items: MutableVec<TimelineItem>,
+    state: SyncMutex<AggregationState>,
+    /// The number of events that should still be fetched backward.
+    backward_requests: Mutable<usize>,
+    /// Whether a future fetches the events requested with
+    /// `paginate_backward()`.
+    fetches_backward: AtomicBool,
+    /// The error that stopped the current backward pagination, if any.
+    backward_error: SyncMutex<Option<crate::Error>>,
+    /// Whether the start of the room was reached when paginating backward.
+    start_reached: Mutable<bool>,
+}
+
+/// A room timeline where edits and reactions are applied to the events they
+/// relate to.
+///
+/// Events that are neither edits, reactions nor redactions become
+/// [`TimelineItem`]s, in the order they were received. An `m.replace` edit is
+/// only applied if it was sent by the sender of the original event and doesn't
+/// change the event type, the most recent of the valid edits wins.
+/// `m.annotation` reactions are grouped by their key. Redacting an edit or a
+/// reaction undoes it.
+///
+/// Edits and reactions can be received before the event they relate to, e.g.
+/// when paginating backward, they are applied once the event is received.
+///
+/// Every time an item is added or its aggregations change, an update is sent to
+/// the [`SignalVec`] returned by [`AggregatedTimeline::signal()`].
+///
+/// The timeline can be created with `Common::aggregated_timeline()` when the
+/// `experimental-timeline` feature is enabled, or be fed manually with events
+/// from any source.
+#[derive(Debug, Clone, Default)]
+pub struct AggregatedTimeline {
+    inner: Arc<AggregatedTimelineInner>,
+}
+
+impl AggregatedTimeline {
+    /// Create a new, empty `AggregatedTimeline`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get a copy of the current items of the timeline, from the oldest to the
+    /// most recent.
+    pub fn items(&self) -> Vec<TimelineItem> {
+        self.inner.items.lock_ref().to_vec()
+    }
+
+    /// Get the item of the event with the given ID, if it is in the timeline.
+    pub fn item(&self, event_id: &EventId) -> Option<TimelineItem> {
+        self.inner.items.lock_ref().iter().find(|i| i.event_id == event_id).cloned()
+    }
+
+    /// Get the index of the item a read receipt for the given event points to,
+    /// if it is in the timeline.
+    ///
+    /// Edits and reactions are not items of the timeline, so a receipt on one
+    /// of them resolves to the item of the event it relates to.
+    pub fn receipt_position(&self, event_id: &EventId) -> Option<usize> {
+        let target = self.inner.state.lock().unwrap().relations.get(event_id).cloned();
+        let event_id = target.as_deref().unwrap_or(event_id);
+
+        self.inner.items.lock_ref().iter().position(|i| i.event_id == event_id)
+    }
+
+    /// Get the items of the timeline as a [`SignalVec`].
+    ///
+    /// The signal sends an update every time an item is added, or its edit or
+    /// reactions change.
+    pub fn signal(&self) -> impl SignalVec<Item = TimelineItem> {
+        self.inner.items.signal_vec_cloned()
+    }
+
+    /// Fetch `count` more events backward.
+    ///
+    /// Returns once the events were added to the timeline, or the start of the
+    /// room was reached. If fetching an event fails, the pagination stops and
+    /// the error is returned.
+    ///
+    /// This only has an effect on timelines created with
+    /// `Common::aggregated_timeline()`, whose future fetches the events, so
+    /// that future must be polled for this to return.
+    pub async fn paginate_backward(&self, count: usize) -> crate::Result<()> {
+        if !self.inner.fetches_backward.load(Ordering::Acquire) || self.inner.start_reached.get() {
+            return Ok(());
+        }
+
+        self.inner.backward_error.lock().unwrap().take();
+        self.inner.backward_requests.replace_with(|n| *n + count);
+        self.inner.backward_requests.signal().wait_for(0).await;
+
+        match self.inner.backward_error.lock().unwrap().take() {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+
+    /// Whether the start of the room was reached when paginating backward, as
+    /// a [`Signal`].
+    pub fn start_reached_signal(&self) -> impl Signal<Item = bool> {
+        self.inner.start_reached.signal()
+    }
+
+    /// Add an event that is more recent than all the events of the timeline.
+    pub fn handle_forward_event(&self, event: SyncTimelineEvent) {
+        self.handle_event(event, false);
+    }
+
+    /// Add an event that is older than all the events of the timeline.
+    pub fn handle_backward_event(&self, event: SyncTimelineEvent) {
+        self.handle_event(event, true);
+    }
+
+    fn handle_event(&self, event: SyncTimelineEvent, backward: bool) {
+        let fields = match event.event.deserialize_as::<EventFields>() {
+            Ok(f) => f,
+            // Events without an ID or a sender can't be aggregated nor be
+            // related to.
+            Err(_) => return,
+        };
+
+        let mut state = self.inner.state.lock().unwrap();
+
+        if state.relations.contains_key(&fields.event_id)
+            || state.event_types.contains_key(&fields.event_id)
+        {
+            // We already know about this event.
+            return;
+        }
+
+        let action = fields.action();
+
+        if matches!(action, Action::Edit(_) | Action::React { .. })
+            && state.redacted.contains(&fields.event_id)
+        {
+            return;
+        }
+
+        match action {
+            Action::Add => {
+                state.event_types.insert(fields.event_id.clone(), fields.event_type.clone());
+
+                let mut item = TimelineItem {
+                    event_id: fields.event_id,
+                    sender: fields.sender,
+                    event,
+                    edit: None,
+                    reactions: BTreeMap::new(),
+                };
+                apply_aggregations(&state, &mut item);
+
+                let mut items = self.inner.items.lock_mut();
+
+                if backward {
+                    items.insert_cloned(0, item);
+                } else {
+                    items.push_cloned(item);
+                }
+            }
+            Action::Edit(target) => {
+                state.relations.insert(fields.event_id.clone(), target.clone());
+                state.aggregations.entry(target.clone()).or_default().edits.push(Edit {
+                    event_id: fields.event_id,
+                    sender: fields.sender,
+                    event_type: fields.event_type,
+                    origin_server_ts: fields.origin_server_ts,
+                    event,
+                });
+
+                self.update_item(&state, &target);
+            }
+            Action::React { target, key } => {
+                state.relations.insert(fields.event_id.clone(), target.clone());
+                state
+                    .aggregations
+                    .entry(target.clone())
+                    .or_default()
+                    .reactions
+                    .entry(key)
+                    .or_default()
+                    .reactions
+                    .insert(fields.event_id, fields.sender);
+
+                self.update_item(&state, &target);
+            }
+            Action::Redact(redacted) => {
+                state.redacted.insert(redacted.clone());
+
+                let target = match state.relations.remove(&redacted) {
+                    Some(t) => t,
+                    // Redactions of regular events are handled by the server,
+                    // which sends the redacted event the next time it is
+                    // fetched.
+                    None => return,
+                };
+
+                if let Some(aggregations) = state.aggregations.get_mut(&target) {
+                    aggregations.edits.retain(|e| e.event_id != redacted);
+
+                    for group in aggregations.reactions.values_mut() {
+                        group.reactions.remove(&redacted);
+                    }
+
+                    aggregations.reactions.retain(|_, group| !group.reactions.is_empty());
+                }
+
+                self.update_item(&state, &target);
+            }
+            Action::Ignore => {}
+        }
+    }
+
+    /// Re-apply the aggregations to the item of the given event, if it is in
+    /// the timeline.
+    fn update_item(&self, state: &AggregationState, event_id: &EventId) {
+        let mut items = self.inner.items.lock_mut();
+
+        if let Some(index) = items.iter().position(|i| i.event_id == event_id) {
+            let mut item = items[index].clone();
+            apply_aggregations(state, &mut item);
+            items.set_cloned(index, item);
+        }
+    }
+
+    /// Feed the timeline with the events of the given streams until the
+    /// forward stream ends.
+    ///
+    /// Events are only taken from the backward stream when they were requested
+    /// with [`AggregatedTimeline::paginate_backward()`].
+    #[cfg(feature = "experimental-timeline")]
+    pub(crate) fn run(
+        self,
+        forward: impl Stream<Item = SyncTimelineEvent>,
+        backward: impl Stream<Item = crate::Result<SyncTimelineEvent>>,
+    ) -> impl Future<Output = ()> {
+        self.inner.fetches_backward.store(true, Ordering::Release);
+
+        async move { self.run_inner(forward, backward).await }
+    }
+
+    #[cfg(feature = "experimental-timeline")]
+    async fn run_inner(
+        &self,
+        forward: impl Stream<Item = SyncTimelineEvent>,
+        backward: impl Stream<Item = crate::Result<SyncTimelineEvent>>,
+    ) {
+        let forward = async {
+            pin_mut!(forward);
+
+            while let Some(event) = forward.next().await {
+                self.handle_forward_event(event);
+            }
+        };
+
+        let backward = async {
+            pin_mut!(backward);
+            let requests = self.inner.backward_requests.signal().to_stream();
+            pin_mut!(requests);
+            let mut failed = false;
+            let mut ended = false;
+
+            while let Some(count) = requests.next().await {
+                if count == 0 {
+                    continue;
+                }
+
+                // There are no older events, but the pending pagination must
+                // still be completed.
+                if ended {
+                    self.inner.backward_requests.set(0);
+                    continue;
+                }
+
+                match backward.next().await {
+                    Some(Ok(event)) => {
+                        failed = false;
+                        self.handle_backward_event(event);
+                        self.inner.backward_requests.replace_with(|n| n.saturating_sub(1));
+                    }
+                    Some(Err(error)) => {
+                        warn!(?error, "Failed to fetch older events of the timeline");
+                        failed = true;
+                        *self.inner.backward_error.lock().unwrap() = Some(error);
+                        self.inner.backward_requests.set(0);
+                    }
+                    None => {
+                        ended = true;
+                        self.inner.backward_requests.set(0);
+
+                        // A stream that ends after an error stopped because of
+                        // the error, not because the start of the room was
+                        // reached.
+                        if failed {
+                            warn!("The backward stream of the timeline ended after an error");
+                        } else {
+                            self.inner.start_reached.set(true);
+                        }
+                    }
+                }
+            }
+
+            // The signal of the requests never ends, but the forward stream
+            // must still be handled if it does.
+            future::pending::<()>().await;
+        };
+
+        // The backward future never completes, the forward one is the one
+        // that decides when we're done.
+        future::select(Box::pin(forward), Box::pin(backward)).await;
+    }
+}
+
+/// Set the latest valid edit and the reactions of the given item.
+fn apply_aggregations(state: &AggregationState, item: &mut TimelineItem) {
+    let aggregations = match state.aggregations.get(&item.event_id) {
+        Some(a) => a,
+        None => {
+            item.edit = None;
+            item.reactions.clear();
+            return;
+        }
+    };
+
+    let event_type = state.event_types.get(&item.event_id);
+
+    item.edit = aggregations
+        .edits
+        .iter()
+        .filter(|e| e.sender == item.sender && Some(&e.event_type) == event_type)
+        .max_by(|a, b| {
+            a.origin_server_ts.cmp(&b.origin_server_ts).then_with(|| a.event_id.cmp(&b.event_id))
+        })
+        .map(|e| e.event.clone());
+    item.reactions = aggregations.reactions.clone();
+}
+
+#[cfg(test)]
+mod tests {
+    use matrix_sdk_base::deserialized_responses::SyncTimelineEvent;
+    use ruma::{event_id, serde::Raw, user_id, EventId};
+    use serde_json::{json, Value as JsonValue};
+
+    use super::AggregatedTimeline;
+
+    fn event(json: JsonValue) -> SyncTimelineEvent {
+        SyncTimelineEvent::from(Raw::new(&json).unwrap().cast())
+    }
+
+    fn message(event_id: &str, sender: &str, ts: u64, body: &str) -> SyncTimelineEvent {
+        event(json!({
+            "content": { "body": body, "msgtype": "m.text" },
+            "event_id": event_id,
+            "origin_server_ts": ts,
+            "sender": sender,
+            "type": "m.room.message",
+        }))
+    }
+
+    fn edit(event_id: &str, sender: &str, ts: u64, target: &str, body: &str) -> SyncTimelineEvent {
+        event(json!({
+            "content": {
+                "body": format!("* {}", body),
+                "msgtype": "m.text",
+                "m.new_content": { "body": body, "msgtype": "m.text" },
+                "m.relates_to": { "rel_type": "m.replace", "event_id": target },
+            },
+            "event_id": event_id,
+            "origin_server_ts": ts,
+            "sender": sender,
+            "type": "m.room.message",
+        }))
+    }
+
+    fn reaction(event_id: &str, sender: &str, target: &str, key: &str) -> SyncTimelineEvent {
+        event(json!({
+            "content": {
+                "m.relates_to": { "rel_type": "m.annotation", "event_id": target, "key": key },
+            },
+            "event_id": event_id,
+            "origin_server_ts": 0,
+            "sender": sender,
+            "type": "m.reaction",
+        }))
+    }
+
+    fn redaction(event_id: &str, redacts: &str) -> SyncTimelineEvent {
+        event(json!({
+            "content": {},
+            "event_id": event_id,
+            "origin_server_ts": 0,
+            "redacts": redacts,
+            "sender": "@alice:example.org",
+            "type": "m.room.redaction",
+        }))
+    }
+
+    #[matrix_sdk_test::async_test]
+    #[cfg(feature = "experimental-timeline")]
+    async fn backward_error_does_not_reach_start() {
+        use futures_util::{future::FutureExt, pin_mut, stream};
+
+        let timeline = AggregatedTimeline::new();
+        let backward = stream::iter(vec![
+            Ok(message("$first:example.org", "@alice:example.org", 1, "Hi")),
+            Err(crate::Error::AuthenticationRequired),
+        ]);
+        let run = timeline.clone().run(stream::pending(), backward);
+        pin_mut!(run);
+
+        let paginate = timeline.paginate_backward(5);
+        pin_mut!(paginate);
+        assert!(paginate.as_mut().now_or_never().is_none());
+        assert!(run.as_mut().now_or_never().is_none());
+        assert!(matches!(paginate.now_or_never(), Some(Err(crate::Error::AuthenticationRequired))));
+        assert_eq!(timeline.items().len(), 1);
+        assert_eq!(timeline.inner.backward_requests.get(), 0);
+
+        // The stream ended because of the error.
+        let paginate = timeline.paginate_backward(5);
+        pin_mut!(paginate);
+        assert!(paginate.as_mut().now_or_never().is_none());
+        assert!(run.as_mut().now_or_never().is_none());
+        assert!(matches!(paginate.now_or_never(), Some(Ok(()))));
+        assert!(!timeline.inner.start_reached.get());
+    }
+
+    fn body(timeline: &AggregatedTimeline, event_id: &EventId) -> String {
+        let content = timeline.item(event_id).unwrap().content().unwrap();
+        content.get_field::<String>("body").unwrap().unwrap()
+    }
+
+    #[test]
+    fn edits() {
+        let timeline = AggregatedTimeline::new();
+        let original = event_id!("$original:example.org");
+
+        timeline.handle_forward_event(message(
+            "$original:example.org",
+            "@alice:example.org",
+            1,
+            "Hi",
+        ));
+        timeline.handle_forward_event(edit(
+            "$edit1:example.org",
+            "@alice:example.org",
+            2,
+            "$original:example.org",
+            "Hello",
+        ));
+        // Edits from other users are ignored.
+        timeline.handle_forward_event(edit(
+            "$edit2:example.org",
+            "@mallory:example.org",
+            3,
+            "$original:example.org",
+            "Bye",
+        ));
+
+        assert_eq!(timeline.items().len(), 1);
+        assert!(timeline.item(original).unwrap().is_edited());
+        assert_eq!(body(&timeline, original), "Hello");
+
+        // An older edit doesn't replace a more recent one.
+        timeline.handle_backward_event(edit(
+            "$edit0:example.org",
+            "@alice:example.org",
+            0,
+            "$original:example.org",
+            "Hey",
+        ));
+        assert_eq!(body(&timeline, original), "Hello");
+
+        // Redacting the latest edit falls back to the previous one.
+        timeline.handle_forward_event(redaction("$redaction:example.org", "$edit1:example.org"));
+        assert_eq!(body(&timeline, original), "Hey");
+    }
+
+    #[test]
+    fn edit_before_original() {
+        let timeline = AggregatedTimeline::new();
+        let original = event_id!("$original:example.org");
+
+        timeline.handle_backward_event(edit(
+            "$edit:example.org",
+            "@alice:example.org",
+            2,
+            "$original:example.org",
+            "Hello",
+        ));
+        assert!(timeline.items().is_empty());
+
+        timeline.handle_backward_event(message(
+            "$original:example.org",
+            "@alice:example.org",
+            1,
+            "Hi",
+        ));
+        assert_eq!(body(&timeline, original), "Hello");
+    }
+
+    #[test]
+    fn reactions() {
+        let timeline = AggregatedTimeline::new();
+        let original = event_id!("$original:example.org");
+
+        timeline.handle_forward_event(message(
+            "$original:example.org",
+            "@alice:example.org",
+            1,
+            "Hi",
+        ));
+        timeline.handle_forward_event(reaction(
+            "$reaction1:example.org",
+            "@alice:example.org",
+            "$original:example.org",
+            "👍",
+        ));
+        timeline.handle_forward_event(reaction(
+            "$reaction2:example.org",
+            "@bob:example.org",
+            "$original:example.org",
+            "👍",
+        ));
+        timeline.handle_forward_event(reaction(
+            "$reaction3:example.org",
+            "@bob:example.org",
+            "$original:example.org",
+            "🎉",
+        ));
+        // Receiving the same reaction twice doesn't count it twice.
+        timeline.handle_forward_event(reaction(
+            "$reaction3:example.org",
+            "@bob:example.org",
+            "$original:example.org",
+            "🎉",
+        ));
+
+        let item = timeline.item(original).unwrap();
+        assert_eq!(timeline.items().len(), 1);
+        assert_eq!(item.reactions.len(), 2);
+        assert_eq!(item.reactions["👍"].count(), 2);
+        assert_eq!(
+            item.reactions["👍"].senders().collect::<Vec<_>>(),
+            [user_id!("@alice:example.org"), user_id!("@bob:example.org")]
+        );
+        assert_eq!(item.reactions["🎉"].count(), 1);
+
+        timeline
+            .handle_forward_event(redaction("$redaction:example.org", "$reaction3:example.org"));
+
+        let item = timeline.item(original).unwrap();
+        assert_eq!(item.reactions.len(), 1);
+        assert!(!item.reactions.contains_key("🎉"));
+    }
+
+    #[test]
+    fn redaction_before_reaction() {
+        let timeline = AggregatedTimeline::new();
+        let original = event_id!("$original:example.org");
+
+        // When paginating backward the redaction is received first.
+        timeline
+            .handle_backward_event(redaction("$redaction:example.org", "$reaction:example.org"));
+        timeline.handle_backward_event(reaction(
+            "$reaction:example.org",
+            "@bob:example.org",
+            "$original:example.org",
+            "👍",
+        ));
+        timeline.handle_backward_event(message(
+            "$original:example.org",
+            "@alice:example.org",
+            1,
+            "Hi",
+        ));
+
+        assert_eq!(timeline.items().len(), 1);
+        assert!(timeline.item(original).unwrap().reactions.is_empty());
+    }
+
+    #[test]
+    fn receipt_position() {
+        let timeline = AggregatedTimeline::new();
+
+        timeline.handle_forward_event(message("$first:example.org", "@alice:example.org", 1, "Hi"));
+        timeline.handle_forward_event(message("$second:example.org", "@bob:example.org", 2, "Hey"));
+        timeline.handle_forward_event(reaction(
+            "$reaction:example.org",
+            "@alice:example.org",
+            "$first:example.org",
+            "👍",
+        ));
+
+        assert_eq!(timeline.receipt_position(event_id!("$second:example.org")), Some(1));
+        // A receipt on a reaction points to the event it relates to.
+        assert_eq!(timeline.receipt_position(event_id!("$reaction:example.org")), Some(0));
+        assert_eq!(timeline.receipt_position(event_id!("$unknown:example.org")), None);
+    }
+}
@@ -1,7 +1,12 @@
-use std::{borrow::Borrow, collections::BTreeMap, future::Future, ops::Deref, sync::Arc};
+use std::{
+    borrow::Borrow, cmp::Ordering, collections::BTreeMap, future::Future, ops::Deref, sync::Arc,
+};
 
 #[cfg(feature = "experimental-timeline")]
 use futures_core::stream::Stream;
+use futures_signals::signal::Signal;
+#[cfg(feature = "experimental-timeline")]
+use futures_util::{future, StreamExt};
 use matrix_sdk_base::{
     deserialized_responses::{MembersResponse, TimelineEvent},
     store::StateStoreExt,
@@ -25,32 +30,45 @@ use ruma::{
         filter::RoomEventFilter,
         membership::{get_member_events, join_room_by_id, leave_room},
         message::get_message_events::{self, v3::Direction},
+        relations::get_relating_events_with_rel_type,
         room::get_room_event,
         tag::{create_tag, delete_tag},
     },
     assign,
     events::{
         direct::DirectEventContent,
+        receipt::ReceiptType,
+        relation::RelationType,
         room::{
-            history_visibility::HistoryVisibility, server_acl::RoomServerAclEventContent,
+            history_visibility::HistoryVisibility,
+            power_levels::{RoomPowerLevels, RoomPowerLevelsEventContent},
+            server_acl::RoomServerAclEventContent,
             MediaSource,
         },
+        space::{child::SpaceChildEventContent, parent::SpaceParentEventContent},
         tag::{TagInfo, TagName},
-        AnyRoomAccountDataEvent, AnyStateEvent, AnySyncStateEvent, EmptyStateKey, RedactContent,
-        RedactedStateEventContent, RoomAccountDataEvent, RoomAccountDataEventContent,
-        RoomAccountDataEventType, StateEventContent, StateEventType, StaticEventContent,
-        SyncStateEvent,
+        AnyRoomAccountDataEvent, AnyStateEvent, AnySyncStateEvent, AnyTimelineEvent, EmptyStateKey,
+        RedactContent, RedactedStateEventContent, RoomAccountDataEvent,
+        RoomAccountDataEventContent, RoomAccountDataEventType, StateEventContent, StateEventType,
+        StaticEventContent, SyncStateEvent,
     },
     serde::Raw,
-    uint, EventId, MatrixToUri, MatrixUri, OwnedEventId, OwnedServerName, RoomId, UInt, UserId,
+    uint, EventId, MatrixToUri, MatrixUri, OwnedEventId, OwnedRoomId, OwnedServerName, OwnedUserId,
+    RoomId, RoomOrAliasId, UInt, UserId,
 };
 use serde::de::DeserializeOwned;
 
+#[cfg(feature = "experimental-timeline")]
+use crate::room::AggregatedTimeline;
 use crate::{
     event_handler::{EventHandler, EventHandlerHandle, EventHandlerResult, SyncEvent},
     media::{MediaFormat, MediaRequest},
-    room::{RoomMember, RoomType},
-    BaseRoom, Client, Error, HttpError, HttpResult, Result,
+    room::{
+        get_threads::{self, IncludeThreads},
+        EphemeralState, PowerLevelAction, ReadReceipt, RoomMember, RoomType,
+    },
+    space::{SpaceChild, SpaceParent},
+    BaseRoom, Client, Error, Feature, HttpError, HttpResult, Result,
 };
 
 /// A struct containing methods that are common for Joined, Invited and Left
@@ -197,24 +215,111 @@ impl Common {
         let request = options.into_request(room_id);
         let http_response = self.client.send(request, None).await?;
 
-        #[allow(unused_mut)]
-        let mut response = Messages {
+        Ok(Messages {
             start: http_response.start,
             end: http_response.end,
-            #[cfg(not(feature = "e2e-encryption"))]
-            chunk: http_response
-                .chunk
-                .into_iter()
-                .map(|event| TimelineEvent { event, encryption_info: None })
-                .collect(),
-            #[cfg(feature = "e2e-encryption")]
-            chunk: Vec::with_capacity(http_response.chunk.len()),
+            chunk: self.decrypt_events(http_response.chunk).await,
             state: http_response.state,
-        };
+        })
+    }
+
+    /// List the threads of this room, the threads with the most recent
+    /// activity are returned first.
+    ///
+    /// The returned events are the thread roots. The homeserver bundles a
+    /// summary of the thread with them, containing the latest event of the
+    /// thread and the number of replies, which can be accessed with
+    /// [`SyncTimelineEvent::thread_summary()`].
+    ///
+    /// With the encryption feature, the thread roots are decrypted if possible.
+    ///
+    /// Returns an [`Error::UnsupportedFeature`] error if the homeserver doesn't
+    /// support [`Feature::Threads`].
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use matrix_sdk::{room::ThreadsOptions, Client};
+    /// # use matrix_sdk::ruma::room_id;
+    /// # use url::Url;
+    ///
+    /// # let homeserver = Url::parse("http://example.com").unwrap();
+    /// # use futures::executor::block_on;
+    /// # block_on(async {
+    /// let client = Client::new(homeserver).await?;
+    /// let room = client.get_joined_room(room_id!("!roomid:example.com")).unwrap();
+    ///
+    /// let mut from = None;
+    ///
+    /// loop {
+    ///     let threads = room.threads(ThreadsOptions::default().from(from.as_deref())).await?;
+    ///
+    ///     for root in threads.chunk {
+    ///         println!("{:?}", root.event);
+    ///     }
+    ///
+    ///     from = threads.next_batch;
+    ///
+    ///     if from.is_none() {
+    ///         break;
+    ///     }
+    /// }
+    /// # anyhow::Ok(())
+    /// # });
+    /// ```
+    ///
+    /// [`SyncTimelineEvent::thread_summary()`]: crate::deserialized_responses::SyncTimelineEvent::thread_summary
+    pub async fn threads(&self, options: ThreadsOptions<'_>) -> Result<Threads> {
+        self.client.ensure_feature(Feature::Threads).await?;
+
+        let request = options.into_request(self.inner.room_id());
+        let response = self.client.send(request, None).await?;
+
+        Ok(Threads {
+            chunk: self.decrypt_events(response.chunk).await,
+            next_batch: response.next_batch,
+        })
+    }
+
+    /// Fetch the events of the thread with the given root, the most recent
+    /// events are returned first.
+    ///
+    /// The thread root itself isn't part of the returned events, it can be
+    /// fetched with [`event()`](Self::event).
+    ///
+    /// With the encryption feature, the events are decrypted if possible.
+    ///
+    /// # Arguments
+    ///
+    /// * `thread_root` - The ID of the event that started the thread.
+    ///
+    /// * `options` - The pagination options of the request.
+    pub async fn thread_events(
+        &self,
+        thread_root: &EventId,
+        options: ThreadEventsOptions<'_>,
+    ) -> Result<ThreadEvents> {
+        let request = options.into_request(self.inner.room_id(), thread_root);
+        let response = self.client.send(request, None).await?;
+
+        Ok(ThreadEvents {
+            chunk: self.decrypt_events(response.chunk.into_iter().map(Raw::cast).collect()).await,
+            next_batch: response.next_batch,
+            prev_batch: response.prev_batch,
+        })
+    }
 
+    /// Try to decrypt the given events, events that aren't encrypted or that
+    /// can't be decrypted are returned as they are.
+    pub(crate) async fn decrypt_events(
+        &self,
+        events: Vec<Raw<AnyTimelineEvent>>,
+    ) -> Vec<TimelineEvent> {
         #[cfg(feature = "e2e-encryption")]
         if let Some(machine) = self.client.olm_machine() {
-            for event in http_response.chunk {
+            let room_id = self.inner.room_id();
+            let mut decrypted_events = Vec::with_capacity(events.len());
+
+            for event in events {
                 let decrypted_event = if let Ok(AnySyncTimelineEvent::MessageLike(
                     AnySyncMessageLikeEvent::RoomEncrypted(SyncMessageLikeEvent::Original(_)),
                 )) = event.deserialize_as::<AnySyncTimelineEvent>()
@@ -228,18 +333,13 @@ impl Common {
                     TimelineEvent { event, encryption_info: None }
                 };
 
-                response.chunk.push(decrypted_event);
+                decrypted_events.push(decrypted_event);
             }
-        } else {
-            response.chunk.extend(
-                http_response
-                    .chunk
-                    .into_iter()
-                    .map(|event| TimelineEvent { event, encryption_info: None }),
-            );
+
+            return decrypted_events;
         }
 
-        Ok(response)
+        events.into_iter().map(|event| TimelineEvent { event, encryption_info: None }).collect()
     }
 
     /// Register a handler for events of a specific type, within this room.
@@ -485,6 +585,102 @@ impl Common {
         Ok(backward)
     }
 
+    /// Create an [`AggregatedTimeline`] of this room, where edits and
+    /// reactions are applied to the events they relate to.
+    ///
+    /// Returns the timeline and a future that feeds it with the events of
+    /// [`timeline()`](Self::timeline). The future needs to be polled, e.g. by
+    /// spawning it, for the timeline to receive new events. It completes once
+    /// the forward stream ends, which only happens when a gapped sync was
+    /// performed.
+    ///
+    /// Older events are only fetched once they are requested with
+    /// [`AggregatedTimeline::paginate_backward()`].
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use futures_signals::signal_vec::SignalVecExt;
+    /// use matrix_sdk::Client;
+    /// # use matrix_sdk::ruma::room_id;
+    /// # use url::Url;
+    ///
+    /// # use futures::executor::block_on;
+    /// # block_on(async {
+    /// # let homeserver = Url::parse("http://example.com")?;
+    /// let client = Client::new(homeserver).await?;
+    ///
+    /// if let Some(room) = client.get_joined_room(room_id!("!roomid:example.com"))
+    /// {
+    ///     let (timeline, future) = room.aggregated_timeline().await?;
+    ///     tokio::spawn(future);
+    ///
+    ///     timeline.paginate_backward(20).await?;
+    ///
+    ///     let future = timeline.signal().for_each(|diff| {
+    ///         println!("{:?}", diff);
+    ///         async {}
+    ///     });
+    ///     tokio::spawn(future);
+    /// }
+    /// # anyhow::Ok(())
+    /// # });
+    /// ```
+    #[cfg(feature = "experimental-timeline")]
+    pub async fn aggregated_timeline(
+        &self,
+    ) -> Result<(AggregatedTimeline, impl Future<Output = ()>)> {
+        let (forward, backward) = self.timeline().await?;
+        let timeline = AggregatedTimeline::new();
+
+        Ok((timeline.clone(), timeline.run(forward, backward)))
+    }
+
+    /// Create a stream that returns all events of the room's timeline backward
+    /// in time, and continues with the timelines of the rooms it replaces once
+    /// the start of the room was reached.
+    ///
+    /// The timelines of the [`predecessors()`](Self::predecessors) are
+    /// linked until a room that isn't known locally is reached. The
+    /// `m.room.create` event of a room is followed by the `m.room.tombstone`
+    /// event of its predecessor, which allows to show where a room was
+    /// upgraded.
+    ///
+    /// See [`timeline_backward()`](Self::timeline_backward) for the details of
+    /// the stream.
+    #[cfg(feature = "experimental-timeline")]
+    pub async fn timeline_backward_with_predecessors(
+        &self,
+    ) -> Result<impl Stream<Item = Result<SyncTimelineEvent>>> {
+        let backward = self.timeline_backward().await?;
+        let predecessors = self.predecessors();
+        let client = self.client.clone();
+
+        Ok(async_stream::stream! {
+            for await item in backward {
+                yield item;
+            }
+
+            for room_id in predecessors {
+                let room = match client.get_room(&room_id) {
+                    Some(r) => r,
+                    None => break,
+                };
+
+                let backward = match room.timeline_backward().await {
+                    Ok(b) => b,
+                    Err(error) => {
+                        yield Err(error);
+                        break;
+                    }
+                };
+
+                for await item in backward {
+                    yield item;
+                }
+            }
+        })
+    }
+
     #[cfg(feature = "experimental-timeline")]
     async fn request_messages(&self, token: &str) -> Result<()> {
         let filter = assign!(RoomEventFilter::default(), {
@@ -510,6 +706,101 @@ impl Common {
         Ok(())
     }
 
+    /// Create a stream that returns the events of the room's main timeline
+    /// forward in time, i.e. all the events of the timeline that aren't part
+    /// of a thread.
+    ///
+    /// Thread roots are part of the main timeline, the summary of their thread
+    /// can be accessed with [`SyncTimelineEvent::thread_summary()`].
+    ///
+    /// See [`timeline_forward()`](Self::timeline_forward) for the details of
+    /// the stream.
+    #[cfg(feature = "experimental-timeline")]
+    pub async fn main_timeline_forward(&self) -> Result<impl Stream<Item = SyncTimelineEvent>> {
+        let forward = self.timeline_forward().await?;
+        Ok(forward.filter(|event| future::ready(event.thread_root().is_none())))
+    }
+
+    /// Create a stream that returns the events of the room's main timeline
+    /// backward in time, i.e. all the events of the timeline that aren't part
+    /// of a thread.
+    ///
+    /// See [`timeline_backward()`](Self::timeline_backward) for the details of
+    /// the stream.
+    #[cfg(feature = "experimental-timeline")]
+    pub async fn main_timeline_backward(
+        &self,
+    ) -> Result<impl Stream<Item = Result<SyncTimelineEvent>>> {
+        let backward = self.timeline_backward().await?;
+        Ok(backward.filter(|item| {
+            future::ready(item.as_ref().map_or(true, |event| event.thread_root().is_none()))
+        }))
+    }
+
+    /// Create a stream that returns the events of the thread with the given
+    /// root forward in time, as they are received from the sync.
+    ///
+    /// See [`timeline_forward()`](Self::timeline_forward) for the details of
+    /// the stream.
+    #[cfg(feature = "experimental-timeline")]
+    pub async fn thread_timeline_forward(
+        &self,
+        thread_root: &EventId,
+    ) -> Result<impl Stream<Item = SyncTimelineEvent>> {
+        let thread_root = thread_root.to_owned();
+        let forward = self.timeline_forward().await?;
+
+        Ok(forward
+            .filter(move |event| future::ready(event.thread_root().as_ref() == Some(&thread_root))))
+    }
+
+    /// Create a stream that returns the events of the thread with the given
+    /// root backward in time.
+    ///
+    /// The events are fetched from the homeserver with
+    /// [`thread_events()`](Self::thread_events), the stream returns `None`
+    /// once the oldest event of the thread was reached. The thread root itself
+    /// isn't returned.
+    ///
+    /// The stream may return an error when a request to the server failed, in
+    /// that case it stops and a new stream needs to be created.
+    #[cfg(feature = "experimental-timeline")]
+    pub fn thread_timeline_backward(
+        &self,
+        thread_root: &EventId,
+    ) -> impl Stream<Item = Result<SyncTimelineEvent>> {
+        let thread_root = thread_root.to_owned();
+        let room = self.to_owned();
+
+        async_stream::stream! {
+            let mut from = None;
+
+            loop {
+                let options = assign!(ThreadEventsOptions::default(), {
+                    from: from.as_deref(),
+                    limit: Some(uint!(10)),
+                });
+
+                let events = match room.thread_events(&thread_root, options).await {
+                    Ok(events) => events,
+                    Err(error) => {
+                        yield Err(error);
+                        break;
+                    }
+                };
+
+                for event in events.chunk {
+                    yield Ok(SyncTimelineEvent::from(event));
+                }
+
+                match events.next_batch {
+                    Some(token) => from = Some(token),
+                    None => break,
+                }
+            }
+        }
+    }
+
     /// Fetch the event with the given `EventId` in this room.
     pub async fn event(&self, event_id: &EventId) -> Result<TimelineEvent> {
         let request = get_room_event::v3::Request::new(self.room_id(), event_id);
@@ -585,6 +876,15 @@ impl Common {
         true
     }
 
+    /// Mark the timeline of this room as reset, after it was flagged by
+    /// [`is_timeline_reset_needed()`] because the list of ignored users
+    /// changed.
+    ///
+    /// [`is_timeline_reset_needed()`]: BaseRoom::is_timeline_reset_needed
+    pub async fn mark_timeline_reset(&self) -> Result<()> {
+        Ok(self.client.base_client().mark_timeline_reset(self.room_id()).await?)
+    }
+
     /// Sync the member list with the server.
     ///
     /// This method will de-duplicate requests if it is called multiple times in
@@ -727,6 +1027,87 @@ impl Common {
             .collect())
     }
 
+    /// Get the users that are currently typing in this room as a [`Signal`].
+    ///
+    /// The list is updated with the `m.typing` events received during sync and
+    /// doesn't contain the current user. Updates can be watched by calling
+    /// methods like `for_each()` or `to_stream()` on the signal.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use futures::executor::block_on;
+    /// # use matrix_sdk::{ruma::room_id, Client};
+    /// # use url::Url;
+    /// # block_on(async {
+    /// # let homeserver = Url::parse("http://example.com")?;
+    /// use futures::StreamExt;
+    /// use futures_signals::signal::SignalExt;
+    ///
+    /// let client = Client::new(homeserver).await?;
+    ///
+    /// if let Some(room) = client.get_joined_room(room_id!("!roomid:example.com")) {
+    ///     let mut typing = room.typing_users_signal().await?.to_stream();
+    ///
+    ///     while let Some(user_ids) = typing.next().await {
+    ///         println!("{} users are typing", user_ids.len());
+    ///     }
+    /// }
+    /// # anyhow::Ok(()) });
+    /// ```
+    pub async fn typing_users_signal(&self) -> Result<impl Signal<Item = Vec<OwnedUserId>>> {
+        Ok(self.ephemeral_state().await?.typing.signal_cloned())
+    }
+
+    /// Get the latest read receipt of each user of this room as a [`Signal`].
+    ///
+    /// The receipts are initially loaded from the store, and updated with the
+    /// `m.receipt` events received during sync. Use
+    /// [`AggregatedTimeline::receipt_position()`] to find the item of the
+    /// timeline a receipt points to.
+    ///
+    /// [`AggregatedTimeline::receipt_position()`]: crate::room::AggregatedTimeline::receipt_position
+    pub async fn read_receipts_signal(
+        &self,
+    ) -> Result<impl Signal<Item = BTreeMap<OwnedUserId, ReadReceipt>>> {
+        Ok(self.ephemeral_state().await?.read_receipts.signal_cloned())
+    }
+
+    /// Get the observed ephemeral state of this room, loading the read
+    /// receipts of the joined members from the store the first time.
+    async fn ephemeral_state(&self) -> Result<Arc<EphemeralState>> {
+        let room_id = self.room_id();
+
+        if let Some(state) = self.client.inner.ephemeral_states.get(room_id) {
+            return Ok(state.clone());
+        }
+
+        let mut read_receipts = BTreeMap::new();
+
+        for user_id in self.client.store().get_joined_user_ids(room_id).await? {
+            let receipt = self
+                .client
+                .store()
+                .get_user_room_receipt_event(room_id, ReceiptType::Read, &user_id)
+                .await?;
+
+            if let Some((event_id, receipt)) = receipt {
+                read_receipts.insert(user_id, ReadReceipt::new(event_id, receipt));
+            }
+        }
+
+        let state = EphemeralState::default();
+        state.read_receipts.set(read_receipts);
+
+        Ok(self
+            .client
+            .inner
+            .ephemeral_states
+            .entry(room_id.to_owned())
+            .or_insert_with(|| Arc::new(state))
+            .clone())
+    }
+
     /// Get all state events of a given type in this room.
     pub async fn get_state_events(
         &self,
@@ -1058,6 +1439,188 @@ impl Common {
             .collect())
     }
 
+    /// Get the IDs of the rooms that replace this room, following the
+    /// `m.room.tombstone` events of the rooms, from the direct successor to
+    /// the most recent one.
+    ///
+    /// The chain is resolved with the rooms that are known locally, the last
+    /// room of the list is thus either the most recent successor or the first
+    /// one that isn't known locally.
+    pub fn successors(&self) -> Vec<OwnedRoomId> {
+        self.room_chain(|room| room.successor())
+    }
+
+    /// Get the IDs of the rooms that this room replaces, following the
+    /// `predecessor` of the `m.room.create` events of the rooms, from the
+    /// direct predecessor to the oldest one.
+    ///
+    /// The chain is resolved with the rooms that are known locally, the last
+    /// room of the list is thus either the oldest predecessor or the first one
+    /// that isn't known locally.
+    pub fn predecessors(&self) -> Vec<OwnedRoomId> {
+        self.room_chain(|room| room.predecessor().map(|p| p.room_id))
+    }
+
+    fn room_chain(&self, next: impl Fn(&BaseRoom) -> Option<OwnedRoomId>) -> Vec<OwnedRoomId> {
+        let mut chain: Vec<OwnedRoomId> = Vec::new();
+        let mut current = next(&self.inner);
+
+        while let Some(room_id) = current {
+            // Don't loop forever if the rooms point at each other.
+            if *room_id == *self.room_id() || chain.contains(&room_id) {
+                break;
+            }
+
+            current = self.client.get_room(&room_id).and_then(|room| next(&room.inner));
+            chain.push(room_id);
+        }
+
+        chain
+    }
+
+    /// Join the most recent room that replaces this room, if this room was
+    /// upgraded.
+    ///
+    /// The servers of the members of this room are used to join the
+    /// successor, see [`route()`](Self::route).
+    ///
+    /// Returns the ID of the joined room, or `None` if this room wasn't
+    /// upgraded or if the most recent successor is already joined.
+    pub async fn join_successor(&self) -> Result<Option<OwnedRoomId>> {
+        let successor = match self.successors().pop() {
+            Some(s) => s,
+            None => return Ok(None),
+        };
+
+        if self.client.get_joined_room(&successor).is_some() {
+            return Ok(None);
+        }
+
+        let servers = self.route().await?;
+        self.client.join_room_by_id_or_alias(<&RoomOrAliasId>::from(&*successor), &servers).await?;
+
+        Ok(Some(successor))
+    }
+
+    /// Get the children of this space, read from its `m.space.child` state
+    /// events.
+    ///
+    /// The children are sorted as described in the spec: the ones with a
+    /// valid `order` first, then by the time they were added and by room ID.
+    pub async fn space_children(&self) -> Result<Vec<SpaceChild>> {
+        let mut children: Vec<_> = self
+            .get_state_events_static::<SpaceChildEventContent>()
+            .await?
+            .into_iter()
+            .filter_map(|event| event.deserialize().ok())
+            .filter_map(|event| {
+                let event = event.as_original()?;
+                // A child without `via` was removed from the space.
+                let via = event.content.via.clone().filter(|via| !via.is_empty())?;
+
+                Some(SpaceChild {
+                    room_id: event.state_key.clone(),
+                    via,
+                    order: event.content.order.clone(),
+                    suggested: event.content.suggested.unwrap_or(false),
+                    origin_server_ts: event.origin_server_ts,
+                })
+            })
+            .collect();
+
+        children.sort_by(|a, b| {
+            match (a.valid_order(), b.valid_order()) {
+                (Some(order_a), Some(order_b)) => order_a.cmp(order_b),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => Ordering::Equal,
+            }
+            .then(a.origin_server_ts.cmp(&b.origin_server_ts))
+            .then_with(|| a.room_id.cmp(&b.room_id))
+        });
+
+        Ok(children)
+    }
+
+    /// Get the power levels of this room, read from its `m.room.power_levels`
+    /// state event.
+    ///
+    /// If the room doesn't have this event, the default power levels of the
+    /// spec are returned.
+    pub async fn power_levels(&self) -> Result<RoomPowerLevels> {
+        Ok(self
+            .get_state_event_static::<RoomPowerLevelsEventContent>()
+            .await?
+            .and_then(|event| event.deserialize().ok())
+            .map(|event| event.power_levels())
+            .unwrap_or_else(|| RoomPowerLevelsEventContent::default().into()))
+    }
+
+    /// Get the power level of the given user in this room.
+    pub async fn user_power_level(&self, user_id: &UserId) -> Result<i64> {
+        Ok(self.power_levels().await?.for_user(user_id).into())
+    }
+
+    /// Whether the given user is allowed to perform the given action in this
+    /// room, according to the power levels in the store.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use futures::executor::block_on;
+    /// # block_on(async {
+    /// # let homeserver = url::Url::parse("http://localhost:8080")?;
+    /// # let client = matrix_sdk::Client::new(homeserver).await?;
+    /// # let room_id = matrix_sdk::ruma::room_id!("!test:localhost");
+    /// # let user_id = matrix_sdk::ruma::user_id!("@alice:localhost");
+    /// use matrix_sdk::{room::PowerLevelAction, ruma::events::StateEventType};
+    ///
+    /// let room = client.get_joined_room(&room_id).unwrap();
+    ///
+    /// if room.can_user(user_id, PowerLevelAction::Ban).await? {
+    ///     room.ban_user(user_id, Some("Spam")).await?;
+    /// }
+    ///
+    /// let action = PowerLevelAction::SendState(StateEventType::RoomTopic);
+    /// let can_change_topic = room.can_own_user(action).await?;
+    /// # anyhow::Ok(()) });
+    /// ```
+    pub async fn can_user(&self, user_id: &UserId, action: PowerLevelAction) -> Result<bool> {
+        Ok(action.is_allowed(&self.power_levels().await?, user_id))
+    }
+
+    /// Whether the own user is allowed to perform the given action in this
+    /// room, according to the power levels in the store.
+    pub async fn can_own_user(&self, action: PowerLevelAction) -> Result<bool> {
+        let user_id = self.client.user_id().ok_or(HttpError::AuthenticationRequired)?;
+        self.can_user(user_id, action).await
+    }
+
+    /// Get the spaces this room claims to be part of, read from its
+    /// `m.space.parent` state events.
+    ///
+    /// The claims are not validated, see [`Client::space_tree()`] for the
+    /// relations that are.
+    pub async fn space_parents(&self) -> Result<Vec<SpaceParent>> {
+        Ok(self
+            .get_state_events_static::<SpaceParentEventContent>()
+            .await?
+            .into_iter()
+            .filter_map(|event| event.deserialize().ok())
+            .filter_map(|event| {
+                let event = event.as_original()?;
+                let via = event.content.via.clone().filter(|via| !via.is_empty())?;
+
+                Some(SpaceParent {
+                    room_id: event.state_key.clone(),
+                    via,
+                    canonical: event.content.canonical,
+                    sender: event.sender.clone(),
+                })
+            })
+            .collect())
+    }
+
     /// Get a `matrix.to` permalink to this room.
     ///
     /// If this room has an alias, we use it. Otherwise, we try to use the
@@ -1220,3 +1783,125 @@ impl<'a> MessagesOptions<'a> {
         })
     }
 }
+
+/// The result of a [`threads`][Common::threads] call.
+#[derive(Debug)]
+pub struct Threads {
+    /// The thread roots, possibly decrypted.
+    pub chunk: Vec<TimelineEvent>,
+
+    /// The token to fetch the next batch of threads, `None` if there are no
+    /// more threads.
+    pub next_batch: Option<String>,
+}
+
+/// Options for [`threads`][Common::threads].
+///
+/// See that method and
+/// <https://spec.matrix.org/v1.4/client-server-api/#get_matrixclientv1roomsroomidthreads>
+/// for details.
+#[derive(Debug, Default)]
+#[non_exhaustive]
+pub struct ThreadsOptions<'a> {
+    /// The token to start returning threads from.
+    ///
+    /// This token can be obtained from the `next_batch` token returned by a
+    /// previous `threads` call.
+    pub from: Option<&'a str>,
+
+    /// Whether to return all the threads of the room, or only the ones the
+    /// user participated in.
+    ///
+    /// Default: all the threads.
+    pub include: IncludeThreads,
+
+    /// The maximum number of threads to return.
+    ///
+    /// If this isn't set the homeserver picks a default.
+    pub limit: Option<UInt>,
+}
+
+impl<'a> ThreadsOptions<'a> {
+    /// Creates a new `ThreadsOptions` from `self` with the `from` field set to
+    /// the given value.
+    ///
+    /// Since the field is public, you can also assign to it directly. This
+    /// method merely acts as a shorthand for that, because it is very
+    /// common to set this field.
+    pub fn from(self, from: impl Into<Option<&'a str>>) -> Self {
+        Self { from: from.into(), ..self }
+    }
+
+    fn into_request(self, room_id: &'a RoomId) -> get_threads::Request<'a> {
+        assign!(get_threads::Request::new(room_id), {
+            from: self.from,
+            include: self.include,
+            limit: self.limit,
+        })
+    }
+}
+
+/// The result of a [`thread_events`][Common::thread_events] call.
+#[derive(Debug)]
+pub struct ThreadEvents {
+    /// The events of the thread, possibly decrypted.
+    pub chunk: Vec<TimelineEvent>,
+
+    /// The token to fetch the next batch of older events, `None` if the
+    /// oldest event of the thread was reached.
+    pub next_batch: Option<String>,
+
+    /// The token to fetch the previous batch of newer events, `None` if this
+    /// batch contains the most recent event of the thread.
+    pub prev_batch: Option<String>,
+}
+
+/// Options for [`thread_events`][Common::thread_events].
+///
+/// See that method and
+/// <https://spec.matrix.org/v1.4/client-server-api/#get_matrixclientv1roomsroomidrelationseventidreltype>
+/// for details.
+#[derive(Debug, Default)]
+#[non_exhaustive]
+pub struct ThreadEventsOptions<'a> {
+    /// The token to start returning events from.
+    ///
+    /// This token can be obtained from the `next_batch` or `prev_batch` token
+    /// returned by a previous `thread_events` call.
+    pub from: Option<&'a str>,
+
+    /// The token to stop returning events at.
+    pub to: Option<&'a str>,
+
+    /// The maximum number of events to return.
+    ///
+    /// If this isn't set the homeserver picks a default.
+    pub limit: Option<UInt>,
+}
+
+impl<'a> ThreadEventsOptions<'a> {
+    /// Creates a new `ThreadEventsOptions` from `self` with the `from` field
+    /// set to the given value.
+    ///
+    /// Since the field is public, you can also assign to it directly. This
+    /// method merely acts as a shorthand for that, because it is very
+    /// common to set this field.
+    pub fn from(self, from: impl Into<Option<&'a str>>) -> Self {
+        Self { from: from.into(), ..self }
+    }
+
+    fn into_request(
+        self,
+        room_id: &'a RoomId,
+        thread_root: &'a EventId,
+    ) -> get_relating_events_with_rel_type::v1::Request<'a> {
+        assign!(
+            get_relating_events_with_rel_type::v1::Request::new(
+                room_id,
+                thread_root,
+                RelationType::Thread,
+            ),
+            { from: self.from, to: self.to, limit: self.limit }
+        )
+    }
+}
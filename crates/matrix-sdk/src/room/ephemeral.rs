@@ -0,0 +1,90 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Observable state of the ephemeral events of a room, i.e. typing
+//! notifications and read receipts.
+
+use std::collections::BTreeMap;
+
+use futures_signals::signal::Mutable;
+use ruma::{
+    events::{
+        receipt::{Receipt, ReceiptEventContent, ReceiptType},
+        typing::TypingEventContent,
+        AnySyncEphemeralRoomEvent,
+    },
+    MilliSecondsSinceUnixEpoch, OwnedEventId, OwnedUserId, UserId,
+};
+
+/// The latest read receipt of a user in a room.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReadReceipt {
+    /// The ID of the last event the user read.
+    pub event_id: OwnedEventId,
+
+    /// When the user read the event, if known.
+    pub ts: Option<MilliSecondsSinceUnixEpoch>,
+}
+
+impl ReadReceipt {
+    pub(crate) fn new(event_id: OwnedEventId, receipt: Receipt) -> Self {
+        Self { event_id, ts: receipt.ts }
+    }
+}
+
+/// The state of the ephemeral events of a room that is being observed.
+#[derive(Debug, Default)]
+pub(crate) struct EphemeralState {
+    /// The users that are currently typing, except the current user.
+    pub(crate) typing: Mutable<Vec<OwnedUserId>>,
+    /// The latest read receipt of each user.
+    pub(crate) read_receipts: Mutable<BTreeMap<OwnedUserId, ReadReceipt>>,
+}
+
+impl EphemeralState {
+    /// Update the state with an ephemeral event received during sync.
+    pub(crate) fn handle_event(&self, event: AnySyncEphemeralRoomEvent, own_user_id: &UserId) {
+        match event {
+            AnySyncEphemeralRoomEvent::Typing(event) => {
+                self.handle_typing(event.content, own_user_id)
+            }
+            AnySyncEphemeralRoomEvent::Receipt(event) => self.handle_receipts(event.content),
+            _ => {}
+        }
+    }
+
+    fn handle_typing(&self, content: TypingEventContent, own_user_id: &UserId) {
+        let typing: Vec<_> =
+            content.user_ids.into_iter().filter(|user_id| *user_id != *own_user_id).collect();
+
+        if *self.typing.lock_ref() != typing {
+            self.typing.set(typing);
+        }
+    }
+
+    fn handle_receipts(&self, content: ReceiptEventContent) {
+        let mut read_receipts = self.read_receipts.lock_mut();
+
+        for (event_id, mut receipts) in content.0 {
+            let users = match receipts.remove(&ReceiptType::Read) {
+                Some(users) => users,
+                None => continue,
+            };
+
+            for (user_id, receipt) in users {
+                read_receipts.insert(user_id, ReadReceipt::new(event_id.clone(), receipt));
+            }
+        }
+    }
+}
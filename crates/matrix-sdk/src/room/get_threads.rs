@@ -0,0 +1,181 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! `GET /_matrix/client/*/rooms/{roomId}/threads` ([MSC3856]).
+//!
+//! This endpoint is not available in the version of Ruma we depend on yet, so
+//! it is implemented here. Ruma doesn't know the Matrix version where it was
+//! stabilized either, so only the unstable path is used.
+//!
+//! [MSC3856]: https://github.com/matrix-org/matrix-spec-proposals/pull/3856
+
+use ruma::{
+    api::{
+        client::Error,
+        error::{FromHttpResponseError, IntoHttpError, ServerError},
+        AuthScheme, EndpointError, IncomingResponse, MatrixVersion, Metadata, OutgoingRequest,
+        SendAccessToken,
+    },
+    events::AnyTimelineEvent,
+    exports::{bytes::BufMut, http, percent_encoding},
+    serde::{urlencoded, Raw},
+    RoomId, UInt,
+};
+use serde::{Deserialize, Serialize};
+
+/// The unstable prefix of the endpoint.
+pub(crate) const UNSTABLE_PREFIX: &str = "org.matrix.msc3856";
+
+/// Which threads to include in the response.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IncludeThreads {
+    /// All the threads of the room.
+    All,
+
+    /// Only the threads the user participated in, i.e. the threads where the
+    /// user sent the root event or replied, or reacted to the root.
+    Participated,
+}
+
+impl Default for IncludeThreads {
+    fn default() -> Self {
+        Self::All
+    }
+}
+
+/// Request type for the `get_threads` endpoint.
+#[derive(Clone, Debug)]
+pub(crate) struct Request<'a> {
+    /// The ID of the room to list the threads of.
+    pub room_id: &'a RoomId,
+
+    /// The pagination token to start returning results from.
+    pub from: Option<&'a str>,
+
+    /// Which threads to include in the response.
+    pub include: IncludeThreads,
+
+    /// The maximum number of results to return in a single `chunk`.
+    pub limit: Option<UInt>,
+}
+
+impl<'a> Request<'a> {
+    /// Creates a new `Request` with the given room ID.
+    pub fn new(room_id: &'a RoomId) -> Self {
+        Self { room_id, from: None, include: IncludeThreads::default(), limit: None }
+    }
+}
+
+/// The query string of a [`Request`].
+#[derive(Serialize)]
+struct RequestQuery<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    from: Option<&'a str>,
+
+    #[serde(skip_serializing_if = "ruma::serde::is_default")]
+    include: IncludeThreads,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    limit: Option<UInt>,
+}
+
+impl OutgoingRequest for Request<'_> {
+    type EndpointError = Error;
+    type IncomingResponse = Response;
+
+    const METADATA: Metadata = Metadata {
+        description: "Retrieve a list of threads in a room, with optional filters.",
+        method: http::Method::GET,
+        name: "get_threads",
+        unstable_path: Some("/_matrix/client/unstable/org.matrix.msc3856/rooms/:room_id/threads"),
+        r0_path: None,
+        stable_path: None,
+        rate_limited: false,
+        authentication: AuthScheme::AccessToken,
+        added: None,
+        deprecated: None,
+        removed: None,
+    };
+
+    fn try_into_http_request<T: Default + BufMut>(
+        self,
+        base_url: &str,
+        access_token: SendAccessToken<'_>,
+        _considering_versions: &[MatrixVersion],
+    ) -> Result<http::Request<T>, IntoHttpError> {
+        let room_id = percent_encoding::utf8_percent_encode(
+            self.room_id.as_str(),
+            percent_encoding::NON_ALPHANUMERIC,
+        );
+        let query = urlencoded::to_string(RequestQuery {
+            from: self.from,
+            include: self.include,
+            limit: self.limit,
+        })?;
+        let access_token =
+            access_token.get_required_for_endpoint().ok_or(IntoHttpError::NeedsAuthentication)?;
+
+        let request = http::Request::builder()
+            .method(http::Method::GET)
+            .uri(format!(
+                "{}/_matrix/client/unstable/{UNSTABLE_PREFIX}/rooms/{room_id}/threads?{query}",
+                base_url.strip_suffix('/').unwrap_or(base_url),
+            ))
+            .header(http::header::AUTHORIZATION, format!("Bearer {access_token}"))
+            .body(T::default())?;
+
+        Ok(request)
+    }
+}
+
+/// Response type for the `get_threads` endpoint.
+#[derive(Debug)]
+pub(crate) struct Response {
+    /// The thread roots, ordered by the latest event in the thread, most
+    /// recent first.
+    pub chunk: Vec<Raw<AnyTimelineEvent>>,
+
+    /// The token to fetch the next batch of threads, `None` if there are no
+    /// more threads.
+    pub next_batch: Option<String>,
+}
+
+/// The body of a [`Response`].
+#[derive(Deserialize)]
+struct ResponseBody {
+    chunk: Vec<Raw<AnyTimelineEvent>>,
+
+    #[serde(default)]
+    next_batch: Option<String>,
+}
+
+impl IncomingResponse for Response {
+    type EndpointError = Error;
+
+    fn try_from_http_response<T: AsRef<[u8]>>(
+        response: http::Response<T>,
+    ) -> Result<Self, FromHttpResponseError<Error>> {
+        if response.status().as_u16() < 400 {
+            let body: ResponseBody = serde_json::from_slice(response.body().as_ref())?;
+
+            Ok(Self { chunk: body.chunk, next_batch: body.next_batch })
+        } else {
+            match Error::try_from_http_response(response) {
+                Ok(error) => Err(ServerError::Known(error).into()),
+                Err(error) => Err(ServerError::Unknown(error).into()),
+            }
+        }
+    }
+}
@@ -1,5 +1,3 @@
-#[cfg(feature = "image-proc")]
-use std::io::Cursor;
 #[cfg(feature = "e2e-encryption")]
 use std::sync::Arc;
 use std::{borrow::Borrow, ops::Deref};
@@ -10,6 +8,9 @@ use matrix_sdk_common::locks::Mutex;
 use mime::{self, Mime};
 use ruma::{
     api::client::{
+        alias::{create_alias, delete_alias},
+        directory::{get_room_visibility, set_room_visibility},
+        error::ErrorKind,
         membership::{
             ban_user,
             invite_user::{self, v3::InvitationRecipient},
@@ -19,30 +20,44 @@ use ruma::{
         read_marker::set_read_marker,
         receipt::create_receipt::{self, v3::ReceiptType},
         redact::redact_event,
+        relations::get_relating_events_with_rel_type,
+        room::{aliases, upgrade_room, Visibility},
         state::send_state_event,
         typing::create_typing_event::v3::{Request as TypingRequest, Typing},
     },
     assign,
     events::{
-        room::message::RoomMessageEventContent, EmptyStateKey, MessageLikeEventContent,
-        StateEventContent,
+        reaction::{self, ReactionEventContent},
+        relation::RelationType,
+        room::{
+            canonical_alias::RoomCanonicalAliasEventContent,
+            message::{
+                EmoteMessageEventContent, FormattedBody, MessageType, NoticeMessageEventContent,
+                OriginalRoomMessageEvent, Relation, Replacement, RoomMessageEvent,
+                RoomMessageEventContent, TextMessageEventContent, Thread,
+            },
+            power_levels::RoomPowerLevelsEventContent,
+        },
+        space::child::SpaceChildEventContent,
+        EmptyStateKey, MessageLikeEventContent, StateEventContent, StateEventType, SyncStateEvent,
     },
     serde::Raw,
-    EventId, OwnedTransactionId, TransactionId, UserId,
+    uint, EventId, Int, OwnedEventId, OwnedRoomAliasId, OwnedRoomId, OwnedServerName,
+    OwnedTransactionId, OwnedUserId, RoomAliasId, RoomId, RoomVersionId, TransactionId, UserId,
 };
+use serde::Deserialize;
 use serde_json::Value;
 use tracing::debug;
 #[cfg(feature = "e2e-encryption")]
 use tracing::instrument;
 
 use crate::{
-    attachment::AttachmentConfig, error::HttpResult, room::Common, BaseRoom, Client, Result,
-    RoomType,
-};
-#[cfg(feature = "image-proc")]
-use crate::{
-    attachment::{generate_image_thumbnail, Thumbnail},
-    error::ImageError,
+    attachment::{add_blurhash, generate_thumbnail, AttachmentConfig, Thumbnail},
+    config::RequestConfig,
+    deserialized_responses::SyncTimelineEvent,
+    error::{HttpResult, PowerLevelsError, RelationError, RoomAliasError},
+    room::{can_change_power_levels, Common, PowerLevelAction, RoomSendQueue, ThreadEventsOptions},
+    BaseRoom, Client, HttpError, Result, RoomType,
 };
 
 const TYPING_NOTICE_TIMEOUT: Duration = Duration::from_secs(4);
@@ -310,6 +325,303 @@ impl Joined {
         Ok(())
     }
 
+    /// Upgrade this room to the given room version.
+    ///
+    /// The homeserver creates a new room with the given version, copies the
+    /// important state events into it and sends an `m.room.tombstone` event to
+    /// this room pointing at the new room.
+    ///
+    /// Returns the ID of the new room. The new room and the tombstone of this
+    /// room will be received with the next sync.
+    ///
+    /// # Arguments
+    ///
+    /// * `new_version` - The version of the new room.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use matrix_sdk::Client;
+    /// # use url::Url;
+    /// # use futures::executor::block_on;
+    /// # use matrix_sdk::ruma::{room_id, RoomVersionId};
+    /// # block_on(async {
+    /// # let homeserver = Url::parse("http://localhost:8080")?;
+    /// # let client = Client::new(homeserver).await?;
+    /// # let room_id = room_id!("!test:localhost");
+    /// if let Some(room) = client.get_joined_room(&room_id) {
+    ///     let new_room_id = room.upgrade(RoomVersionId::V9).await?;
+    /// }
+    /// # anyhow::Ok(()) });
+    /// ```
+    pub async fn upgrade(&self, new_version: RoomVersionId) -> Result<OwnedRoomId> {
+        let request = upgrade_room::v3::Request::new(self.inner.room_id(), &new_version);
+        let response = self.client.send(request, None).await?;
+
+        Ok(response.replacement_room)
+    }
+
+    /// Add a room to this space, or update its `m.space.child` event if it is
+    /// already a child.
+    ///
+    /// # Arguments
+    ///
+    /// * `child_id` - The ID of the room to add.
+    ///
+    /// * `via` - The servers that can be used to join the room, must not be
+    ///   empty.
+    ///
+    /// * `order` - The string used to order the children of the space, it
+    ///   must consist of at most 50 characters in the `\x20` to `\x7E` range.
+    ///
+    /// * `suggested` - Whether the room should be suggested to the members of
+    ///   the space.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use matrix_sdk::Client;
+    /// # use url::Url;
+    /// # use futures::executor::block_on;
+    /// # use matrix_sdk::ruma::{room_id, server_name};
+    /// # block_on(async {
+    /// # let homeserver = Url::parse("http://localhost:8080")?;
+    /// # let client = Client::new(homeserver).await?;
+    /// let child_id = room_id!("!child:example.org");
+    /// let via = vec![server_name!("example.org").to_owned()];
+    ///
+    /// if let Some(space) = client.get_joined_room(room_id!("!space:example.org")) {
+    ///     space.add_space_child(child_id, via, Some("a".to_owned()), false).await?;
+    /// }
+    /// # anyhow::Ok(()) });
+    /// ```
+    pub async fn add_space_child(
+        &self,
+        child_id: &RoomId,
+        via: Vec<OwnedServerName>,
+        order: Option<String>,
+        suggested: bool,
+    ) -> Result<send_state_event::v3::Response> {
+        let content = assign!(SpaceChildEventContent::new(), {
+            via: Some(via),
+            order,
+            suggested: Some(suggested),
+        });
+
+        self.send_state_event_for_key(child_id, content).await
+    }
+
+    /// Remove a room from this space.
+    ///
+    /// This replaces the `m.space.child` event of the room with an empty one.
+    ///
+    /// # Arguments
+    ///
+    /// * `child_id` - The ID of the room to remove.
+    pub async fn remove_space_child(
+        &self,
+        child_id: &RoomId,
+    ) -> Result<send_state_event::v3::Response> {
+        self.send_state_event_for_key(child_id, SpaceChildEventContent::new()).await
+    }
+
+    /// Get the visibility of this room in the room directory of the
+    /// homeserver.
+    pub async fn directory_visibility(&self) -> HttpResult<Visibility> {
+        let request = get_room_visibility::v3::Request::new(self.inner.room_id());
+        let response = self.client.send(request, None).await?;
+
+        Ok(response.visibility)
+    }
+
+    /// Publish this room in, or remove it from, the room directory of the
+    /// homeserver.
+    ///
+    /// # Arguments
+    ///
+    /// * `visibility` - [`Visibility::Public`] to publish the room,
+    ///   [`Visibility::Private`] to remove it from the directory.
+    pub async fn set_directory_visibility(&self, visibility: Visibility) -> Result<()> {
+        let request = set_room_visibility::v3::Request::new(self.inner.room_id(), visibility);
+        self.client.send(request, None).await?;
+
+        Ok(())
+    }
+
+    /// Get the aliases of this room that were created on the homeserver of
+    /// the current user.
+    pub async fn local_aliases(&self) -> HttpResult<Vec<OwnedRoomAliasId>> {
+        let request = aliases::v3::Request::new(self.inner.room_id());
+        let response = self.client.send(request, None).await?;
+
+        Ok(response.aliases)
+    }
+
+    /// Create a new alias for this room on the homeserver of the current user.
+    ///
+    /// The alias isn't advertised in the room, use
+    /// [`set_canonical_alias()`](Self::set_canonical_alias) for that.
+    ///
+    /// # Arguments
+    ///
+    /// * `alias` - The alias to create, its server name must be the one of
+    ///   the homeserver.
+    pub async fn create_alias(&self, alias: &RoomAliasId) -> Result<()> {
+        let request = create_alias::v3::Request::new(alias, self.inner.room_id());
+        self.client.send(request, None).await?;
+
+        Ok(())
+    }
+
+    /// Delete an alias of this room from the homeserver of the current user.
+    ///
+    /// # Arguments
+    ///
+    /// * `alias` - The alias to delete.
+    pub async fn delete_alias(&self, alias: &RoomAliasId) -> Result<()> {
+        let request = delete_alias::v3::Request::new(alias);
+        self.client.send(request, None).await?;
+
+        Ok(())
+    }
+
+    /// Set the aliases advertised in the `m.room.canonical_alias` event of
+    /// this room.
+    ///
+    /// The aliases that aren't already advertised are checked to point to this
+    /// room before the event is sent.
+    ///
+    /// # Arguments
+    ///
+    /// * `alias` - The main alias of the room.
+    ///
+    /// * `alt_aliases` - The alternative aliases of the room.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`RoomAliasError`] if one of the new aliases doesn't exist or
+    /// points to another room.
+    pub async fn set_canonical_alias(
+        &self,
+        alias: Option<OwnedRoomAliasId>,
+        alt_aliases: Vec<OwnedRoomAliasId>,
+    ) -> Result<send_state_event::v3::Response> {
+        let current_alias = self.canonical_alias();
+        let current_alt_aliases = self.alt_aliases();
+
+        for new_alias in alias.iter().chain(&alt_aliases) {
+            if current_alias.as_ref() == Some(new_alias) || current_alt_aliases.contains(new_alias)
+            {
+                continue;
+            }
+
+            self.validate_alias(new_alias).await?;
+        }
+
+        let content = assign!(RoomCanonicalAliasEventContent::new(), { alias, alt_aliases });
+        self.send_state_event(content).await
+    }
+
+    /// Check that the given alias exists and points to this room.
+    async fn validate_alias(&self, alias: &RoomAliasId) -> Result<()> {
+        let room_id = match self.client.resolve_room_alias(alias).await {
+            Ok(response) => response.room_id,
+            Err(error) if matches!(error.client_api_error_kind(), Some(ErrorKind::NotFound)) => {
+                return Err(RoomAliasError::NotFound(alias.to_owned()).into());
+            }
+            Err(error) => return Err(error.into()),
+        };
+
+        if *room_id != *self.inner.room_id() {
+            return Err(RoomAliasError::WrongRoom { alias: alias.to_owned(), room_id }.into());
+        }
+
+        Ok(())
+    }
+
+    /// Update the `m.room.power_levels` event of this room.
+    ///
+    /// The current power levels are read from the store and passed to `f` to
+    /// be modified, then the result is sent to the homeserver.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`PowerLevelsError`] if the room doesn't have a power levels
+    /// event in the store, or if the own user isn't allowed to make the
+    /// change according to the authorization rules of the spec. In the latter
+    /// case, nothing is sent to the homeserver.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use futures::executor::block_on;
+    /// # block_on(async {
+    /// # let homeserver = url::Url::parse("http://localhost:8080")?;
+    /// # let client = matrix_sdk::Client::new(homeserver).await?;
+    /// # let room_id = matrix_sdk::ruma::room_id!("!test:localhost");
+    /// use matrix_sdk::ruma::{events::StateEventType, int};
+    ///
+    /// let room = client.get_joined_room(&room_id).unwrap();
+    ///
+    /// room.update_power_levels(|power_levels| {
+    ///     power_levels.events.insert(StateEventType::RoomTopic.into(), int!(0));
+    /// })
+    /// .await?;
+    /// # anyhow::Ok(()) });
+    /// ```
+    pub async fn update_power_levels(
+        &self,
+        f: impl FnOnce(&mut RoomPowerLevelsEventContent),
+    ) -> Result<send_state_event::v3::Response> {
+        let user_id = self.client.user_id().ok_or(HttpError::AuthenticationRequired)?;
+
+        let old = match self
+            .get_state_event_static::<RoomPowerLevelsEventContent>()
+            .await?
+            .and_then(|event| event.deserialize().ok())
+        {
+            Some(SyncStateEvent::Original(event)) => event.content,
+            _ => return Err(PowerLevelsError::Missing.into()),
+        };
+
+        let action = PowerLevelAction::SendState(StateEventType::RoomPowerLevels);
+        if !action.is_allowed(&old.clone().into(), user_id) {
+            return Err(PowerLevelsError::Forbidden.into());
+        }
+
+        let mut new = old.clone();
+        f(&mut new);
+
+        if !can_change_power_levels(user_id, &old, &new) {
+            return Err(PowerLevelsError::Forbidden.into());
+        }
+
+        self.send_state_event(new).await
+    }
+
+    /// Set the power level of the given user in this room.
+    ///
+    /// If the level is the default level of users in this room, the user is
+    /// removed from the `users` of the power levels.
+    ///
+    /// # Errors
+    ///
+    /// See [`Joined::update_power_levels()`].
+    pub async fn set_user_power_level(
+        &self,
+        user_id: &UserId,
+        level: Int,
+    ) -> Result<send_state_event::v3::Response> {
+        self.update_power_levels(|power_levels| {
+            if level == power_levels.users_default {
+                power_levels.users.remove(user_id);
+            } else {
+                power_levels.users.insert(user_id.to_owned(), level);
+            }
+        })
+        .await
+    }
+
     /// Share a room key with users in the given room.
     ///
     /// This will create Olm sessions with all the users/device pairs in the
@@ -382,6 +694,40 @@ impl Joined {
         Ok(())
     }
 
+    /// Get the send queue of this room.
+    ///
+    /// Unlike [`Joined::send()`], events pushed into the queue are persisted
+    /// until they are acknowledged by the homeserver, are sent in order and
+    /// are retried if the homeserver can't be reached. See [`RoomSendQueue`]
+    /// for more details.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use futures::executor::block_on;
+    /// # use matrix_sdk::{Client, ruma::room_id};
+    /// # use url::Url;
+    /// use matrix_sdk::ruma::events::room::message::RoomMessageEventContent;
+    /// # block_on(async {
+    /// # let homeserver = Url::parse("http://localhost:8080")?;
+    /// # let client = Client::new(homeserver).await?;
+    /// # let room_id = room_id!("!test:localhost");
+    ///
+    /// if let Some(room) = client.get_joined_room(&room_id) {
+    ///     let queue = room.send_queue();
+    ///     let content = RoomMessageEventContent::text_plain("Hello world");
+    ///     queue.push(content).await?;
+    ///
+    ///     for echo in queue.local_echoes() {
+    ///         println!("{}: {:?}", echo.txn_id, echo.state);
+    ///     }
+    /// }
+    /// # anyhow::Ok(()) });
+    /// ```
+    pub fn send_queue(&self) -> RoomSendQueue {
+        RoomSendQueue::new(self.clone())
+    }
+
     /// Send a room message to this room.
     ///
     /// Returns the parsed response from the server.
@@ -476,6 +822,278 @@ impl Joined {
         self.send_raw(content, &event_type, txn_id).await
     }
 
+    /// Send a message to the thread with the given root in this room.
+    ///
+    /// The message is sent with an `m.thread` relation to `thread_root`.
+    /// Clients that don't support threads display it as a reply, either to
+    /// `in_reply_to` if it is set, or to the latest event of the thread
+    /// otherwise. In the latter case the latest event is fetched from the
+    /// homeserver first.
+    ///
+    /// # Arguments
+    ///
+    /// * `content` - The content of the message.
+    ///
+    /// * `thread_root` - The ID of the event that started the thread.
+    ///
+    /// * `in_reply_to` - The ID of the event of the thread this message
+    ///   explicitly replies to, if any.
+    ///
+    /// * `txn_id` - A locally-unique ID describing a message transaction with
+    ///   the homeserver, see [`Joined::send()`].
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use matrix_sdk::Client;
+    /// # use url::Url;
+    /// # use futures::executor::block_on;
+    /// # use matrix_sdk::ruma::{event_id, room_id};
+    /// use matrix_sdk::ruma::events::room::message::RoomMessageEventContent;
+    /// # block_on(async {
+    /// # let homeserver = Url::parse("http://localhost:8080")?;
+    /// # let mut client = Client::new(homeserver).await?;
+    /// # let room_id = room_id!("!test:localhost");
+    /// let content = RoomMessageEventContent::text_plain("Hello thread");
+    /// let thread_root = event_id!("$root:localhost");
+    ///
+    /// if let Some(room) = client.get_joined_room(&room_id) {
+    ///     room.send_thread_reply(content, thread_root, None, None).await?;
+    /// }
+    /// # anyhow::Ok(()) });
+    /// ```
+    pub async fn send_thread_reply(
+        &self,
+        mut content: RoomMessageEventContent,
+        thread_root: &EventId,
+        in_reply_to: Option<&EventId>,
+        txn_id: Option<&TransactionId>,
+    ) -> Result<send_message_event::v3::Response> {
+        let thread = match in_reply_to {
+            Some(event_id) => Thread::reply(thread_root.to_owned(), event_id.to_owned()),
+            None => {
+                let options = assign!(ThreadEventsOptions::default(), { limit: Some(uint!(1)) });
+                let latest_event_id = self
+                    .thread_events(thread_root, options)
+                    .await?
+                    .chunk
+                    .into_iter()
+                    .next()
+                    .and_then(|e| e.event.get_field::<OwnedEventId>("event_id").ok().flatten())
+                    .unwrap_or_else(|| thread_root.to_owned());
+
+                Thread::plain(thread_root.to_owned(), latest_event_id)
+            }
+        };
+
+        content.relates_to = Some(Relation::Thread(thread));
+
+        self.send(content, txn_id).await
+    }
+
+    /// Send a reply to the room message with the given ID.
+    ///
+    /// The original message is fetched from the homeserver, and decrypted if
+    /// needed, to build the rich reply fallback of the reply. If the original
+    /// message is part of a thread, the reply is sent to the same thread.
+    ///
+    /// # Arguments
+    ///
+    /// * `content` - The content of the reply.
+    ///
+    /// * `in_reply_to` - The ID of the message to reply to.
+    ///
+    /// * `txn_id` - A locally-unique ID describing a message transaction with
+    ///   the homeserver, see [`Joined::send()`].
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use matrix_sdk::Client;
+    /// # use url::Url;
+    /// # use futures::executor::block_on;
+    /// # use matrix_sdk::ruma::{event_id, room_id};
+    /// use matrix_sdk::ruma::events::room::message::RoomMessageEventContent;
+    /// # block_on(async {
+    /// # let homeserver = Url::parse("http://localhost:8080")?;
+    /// # let mut client = Client::new(homeserver).await?;
+    /// # let room_id = room_id!("!test:localhost");
+    /// let content = RoomMessageEventContent::text_plain("I agree");
+    ///
+    /// if let Some(room) = client.get_joined_room(&room_id) {
+    ///     room.reply_to(content, event_id!("$original:localhost"), None).await?;
+    /// }
+    /// # anyhow::Ok(()) });
+    /// ```
+    pub async fn reply_to(
+        &self,
+        content: RoomMessageEventContent,
+        in_reply_to: &EventId,
+        txn_id: Option<&TransactionId>,
+    ) -> Result<send_message_event::v3::Response> {
+        let (original, thread_root) = self.room_message(in_reply_to).await?;
+        let mut content = content.make_reply_to(&original);
+
+        if let Some(thread_root) = thread_root {
+            content.relates_to =
+                Some(Relation::Thread(Thread::reply(thread_root, original.event_id)));
+        }
+
+        self.send(content, txn_id).await
+    }
+
+    /// Edit the room message with the given ID.
+    ///
+    /// The original message is fetched from the homeserver first, only
+    /// messages sent by the current user can be edited.
+    ///
+    /// The edit is sent with an `m.replace` relation and the new content in
+    /// `m.new_content`. Clients that don't support edits display the fallback
+    /// instead, whose body is the new body prefixed with `* ` for text,
+    /// notices and emotes.
+    ///
+    /// # Arguments
+    ///
+    /// * `event_id` - The ID of the message to edit.
+    ///
+    /// * `new_content` - The new content of the message, its relation is
+    ///   ignored.
+    ///
+    /// * `txn_id` - A locally-unique ID describing a message transaction with
+    ///   the homeserver, see [`Joined::send()`].
+    pub async fn edit(
+        &self,
+        event_id: &EventId,
+        mut new_content: RoomMessageEventContent,
+        txn_id: Option<&TransactionId>,
+    ) -> Result<send_message_event::v3::Response> {
+        let (original, _) = self.room_message(event_id).await?;
+        let own_user_id = self.own_user_id();
+
+        if *original.sender != *own_user_id {
+            return Err(RelationError::NotOwnEvent.into());
+        }
+
+        new_content.relates_to = None;
+
+        let mut content = RoomMessageEventContent::new(edit_fallback(new_content.msgtype.clone()));
+        content.relates_to = Some(Relation::Replacement(Replacement::new(
+            event_id.to_owned(),
+            Box::new(new_content),
+        )));
+
+        self.send(content, txn_id).await
+    }
+
+    /// React to the event with the given ID.
+    ///
+    /// # Arguments
+    ///
+    /// * `event_id` - The ID of the event to react to.
+    ///
+    /// * `key` - The key of the reaction, usually an emoji.
+    pub async fn react(
+        &self,
+        event_id: &EventId,
+        key: &str,
+    ) -> Result<send_message_event::v3::Response> {
+        let content =
+            ReactionEventContent::new(reaction::Relation::new(event_id.to_owned(), key.to_owned()));
+
+        self.send(content, None).await
+    }
+
+    /// Remove the reactions of the current user with the given key to the
+    /// event with the given ID, by redacting them.
+    ///
+    /// The reactions are fetched from the homeserver, and decrypted if needed.
+    ///
+    /// Returns `false` if the current user didn't react with the given key.
+    ///
+    /// # Arguments
+    ///
+    /// * `event_id` - The ID of the event that was reacted to.
+    ///
+    /// * `key` - The key of the reaction.
+    pub async fn unreact(&self, event_id: &EventId, key: &str) -> Result<bool> {
+        #[derive(Deserialize)]
+        struct RelatesTo {
+            key: String,
+        }
+
+        #[derive(Deserialize)]
+        struct ReactionContent {
+            #[serde(rename = "m.relates_to")]
+            relates_to: RelatesTo,
+        }
+
+        #[derive(Deserialize)]
+        struct Reaction {
+            event_id: OwnedEventId,
+            sender: OwnedUserId,
+            #[serde(rename = "type")]
+            event_type: String,
+            content: ReactionContent,
+        }
+
+        let own_user_id = self.own_user_id();
+        let mut reactions = Vec::new();
+        let mut from = None;
+
+        loop {
+            let request = assign!(
+                get_relating_events_with_rel_type::v1::Request::new(
+                    self.inner.room_id(),
+                    event_id,
+                    RelationType::Annotation,
+                ),
+                { from: from.as_deref() }
+            );
+            let response = self.client.send(request, None).await?;
+            let events =
+                self.decrypt_events(response.chunk.into_iter().map(Raw::cast).collect()).await;
+
+            reactions.extend(
+                events
+                    .iter()
+                    .filter_map(|e| e.event.deserialize_as::<Reaction>().ok())
+                    .filter(|r| {
+                        r.event_type == "m.reaction"
+                            && *r.sender == *own_user_id
+                            && r.content.relates_to.key == key
+                    })
+                    .map(|r| r.event_id),
+            );
+
+            from = response.next_batch;
+
+            if from.is_none() {
+                break;
+            }
+        }
+
+        for reaction in &reactions {
+            self.redact(reaction, None, None).await?;
+        }
+
+        Ok(!reactions.is_empty())
+    }
+
+    /// Fetch the room message with the given ID, and the root of the thread it
+    /// is part of, if any.
+    async fn room_message(
+        &self,
+        event_id: &EventId,
+    ) -> Result<(OriginalRoomMessageEvent, Option<OwnedEventId>)> {
+        let event = self.event(event_id).await?;
+        let thread_root = SyncTimelineEvent::from(event.clone()).thread_root();
+
+        match event.event.deserialize_as::<RoomMessageEvent>() {
+            Ok(RoomMessageEvent::Original(original)) => Ok((original, thread_root)),
+            _ => Err(RelationError::NotARoomMessage.into()),
+        }
+    }
+
     /// Send a room message to this room from a json `Value`.
     ///
     /// Returns the parsed response from the server.
@@ -537,6 +1155,18 @@ impl Joined {
         content: Value,
         event_type: &str,
         txn_id: Option<&TransactionId>,
+    ) -> Result<send_message_event::v3::Response> {
+        self.send_raw_with_config(content, event_type, txn_id, None).await
+    }
+
+    /// Same as [`Joined::send_raw()`], but allows overriding the
+    /// [`RequestConfig`] used to send the event.
+    pub(crate) async fn send_raw_with_config(
+        &self,
+        content: Value,
+        event_type: &str,
+        txn_id: Option<&TransactionId>,
+        config: Option<RequestConfig>,
     ) -> Result<send_message_event::v3::Response> {
         let txn_id: OwnedTransactionId = txn_id.map_or_else(TransactionId::new, ToOwned::to_owned);
 
@@ -595,7 +1225,7 @@ impl Joined {
             content,
         );
 
-        let response = self.client.send(request, None).await?;
+        let response = self.client.send(request, config).await?;
         Ok(response)
     }
 
@@ -656,43 +1286,34 @@ impl Joined {
         if config.thumbnail.is_some() {
             self.prepare_and_send_attachment(body, content_type, data, config).await
         } else {
-            #[cfg(not(feature = "image-proc"))]
-            let thumbnail = None;
-
-            #[cfg(feature = "image-proc")]
-            let data_slot;
-            #[cfg(feature = "image-proc")]
-            let thumbnail = if config.generate_thumbnail {
-                match generate_image_thumbnail(
+            let generated = if config.generate_thumbnail {
+                generate_thumbnail(
+                    config.thumbnailer.as_deref(),
                     content_type,
-                    Cursor::new(data),
+                    data,
                     config.thumbnail_size,
-                ) {
-                    Ok((thumbnail_data, thumbnail_info)) => {
-                        data_slot = thumbnail_data;
-                        Some(Thumbnail {
-                            data: &data_slot,
-                            content_type: &mime::IMAGE_JPEG,
-                            info: Some(thumbnail_info),
-                        })
-                    }
-                    Err(
-                        ImageError::ThumbnailBiggerThanOriginal | ImageError::FormatNotSupported,
-                    ) => None,
-                    Err(error) => return Err(error.into()),
-                }
+                )?
             } else {
                 None
             };
 
+            let info = match generated.as_ref().and_then(|thumbnail| thumbnail.blurhash.clone()) {
+                Some(blurhash) => add_blurhash(config.info, content_type, blurhash),
+                None => config.info,
+            };
+            let thumbnail = generated.as_ref().map(|thumbnail| Thumbnail {
+                data: &thumbnail.data,
+                content_type: &thumbnail.content_type,
+                info: Some(thumbnail.info.clone()),
+            });
+
             let config = AttachmentConfig {
                 txn_id: config.txn_id,
-                info: config.info,
+                info,
                 thumbnail,
-                #[cfg(feature = "image-proc")]
                 generate_thumbnail: false,
-                #[cfg(feature = "image-proc")]
                 thumbnail_size: None,
+                thumbnailer: None,
             };
 
             self.prepare_and_send_attachment(body, content_type, data, config).await
@@ -960,3 +1581,36 @@ impl Joined {
         self.client.send(request, None).await
     }
 }
+
+/// The fallback content of an edit with the given message type, for clients
+/// that don't support edits.
+fn edit_fallback(msgtype: MessageType) -> MessageType {
+    fn prefix(
+        mut body: String,
+        formatted: Option<FormattedBody>,
+    ) -> (String, Option<FormattedBody>) {
+        body.insert_str(0, "* ");
+        let formatted = formatted.map(|mut f| {
+            f.body.insert_str(0, "* ");
+            f
+        });
+
+        (body, formatted)
+    }
+
+    match msgtype {
+        MessageType::Text(c) => {
+            let (body, formatted) = prefix(c.body, c.formatted);
+            MessageType::Text(assign!(TextMessageEventContent::plain(body), { formatted }))
+        }
+        MessageType::Notice(c) => {
+            let (body, formatted) = prefix(c.body, c.formatted);
+            MessageType::Notice(assign!(NoticeMessageEventContent::plain(body), { formatted }))
+        }
+        MessageType::Emote(c) => {
+            let (body, formatted) = prefix(c.body, c.formatted);
+            MessageType::Emote(assign!(EmoteMessageEventContent::plain(body), { formatted }))
+        }
+        msgtype => msgtype,
+    }
+}
@@ -4,18 +4,36 @@ use std::ops::Deref;
 
 use crate::RoomType;
 
+mod aggregation;
 mod common;
+mod ephemeral;
+pub(crate) mod get_threads;
 mod invited;
 mod joined;
 mod left;
 mod member;
+mod power_levels;
+mod send_queue;
 
 pub use self::{
-    common::{Common, Messages, MessagesOptions},
+    aggregation::{AggregatedTimeline, ReactionGroup, TimelineItem},
+    common::{
+        Common, Messages, MessagesOptions, ThreadEvents, ThreadEventsOptions, Threads,
+        ThreadsOptions,
+    },
+    ephemeral::ReadReceipt,
+    get_threads::IncludeThreads,
     invited::Invited,
     joined::Joined,
     left::Left,
     member::RoomMember,
+    power_levels::PowerLevelAction,
+    send_queue::{LocalEcho, LocalEchoState, RoomSendQueue},
+};
+pub(crate) use self::{
+    ephemeral::EphemeralState,
+    power_levels::can_change_power_levels,
+    send_queue::SendQueueState,
 };
 
 /// An enum that abstracts over the different states a room can be in.
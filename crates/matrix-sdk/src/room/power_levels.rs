@@ -0,0 +1,208 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Permission checks based on the `m.room.power_levels` event of a room.
+
+use ruma::{
+    events::{
+        room::power_levels::{RoomPowerLevels, RoomPowerLevelsEventContent},
+        MessageLikeEventType, StateEventType,
+    },
+    Int, UserId,
+};
+
+/// An action in a room that requires a minimum power level.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum PowerLevelAction {
+    /// Ban a user from the room.
+    Ban,
+
+    /// Invite a user to the room.
+    Invite,
+
+    /// Kick a user from the room.
+    Kick,
+
+    /// Redact an event sent by another user.
+    ///
+    /// Redacting one's own events only requires to be able to send
+    /// `m.room.redaction` events.
+    RedactOther,
+
+    /// Send a message-like event of the given type.
+    SendMessage(MessageLikeEventType),
+
+    /// Send a state event of the given type.
+    SendState(StateEventType),
+}
+
+impl PowerLevelAction {
+    /// The power level a user needs to perform this action, according to the
+    /// given power levels.
+    pub fn required_power_level(&self, power_levels: &RoomPowerLevels) -> i64 {
+        match self {
+            Self::Ban => power_levels.ban,
+            Self::Invite => power_levels.invite,
+            Self::Kick => power_levels.kick,
+            Self::RedactOther => power_levels.redact,
+            Self::SendMessage(event_type) => power_levels
+                .events
+                .get(&event_type.clone().into())
+                .copied()
+                .unwrap_or(power_levels.events_default),
+            Self::SendState(event_type) => power_levels
+                .events
+                .get(&event_type.clone().into())
+                .copied()
+                .unwrap_or(power_levels.state_default),
+        }
+        .into()
+    }
+
+    /// Whether the given user is allowed to perform this action, according to
+    /// the given power levels.
+    pub fn is_allowed(&self, power_levels: &RoomPowerLevels, user_id: &UserId) -> bool {
+        i64::from(power_levels.for_user(user_id)) >= self.required_power_level(power_levels)
+    }
+}
+
+/// Whether the given user is allowed to replace the `old` power levels of a
+/// room with the `new` ones.
+///
+/// This follows the authorization rules of the spec: every level that changes
+/// must be lower or equal to the user's own level before and after the change,
+/// and the level of another user can't be changed if it is equal to the
+/// user's own level.
+pub(crate) fn can_change_power_levels(
+    user_id: &UserId,
+    old: &RoomPowerLevelsEventContent,
+    new: &RoomPowerLevelsEventContent,
+) -> bool {
+    let own_level = old.users.get(user_id).copied().unwrap_or(old.users_default);
+    let is_allowed = |old_level: Option<Int>, new_level: Option<Int>| {
+        old_level == new_level
+            || (old_level.map_or(true, |level| level <= own_level)
+                && new_level.map_or(true, |level| level <= own_level))
+    };
+
+    let defaults_allowed = [
+        (old.ban, new.ban),
+        (old.events_default, new.events_default),
+        (old.invite, new.invite),
+        (old.kick, new.kick),
+        (old.redact, new.redact),
+        (old.state_default, new.state_default),
+        (old.users_default, new.users_default),
+        (old.notifications.room, new.notifications.room),
+    ]
+    .into_iter()
+    .all(|(old_level, new_level)| is_allowed(Some(old_level), Some(new_level)));
+
+    let events_allowed = old.events.keys().chain(new.events.keys()).all(|event_type| {
+        is_allowed(old.events.get(event_type).copied(), new.events.get(event_type).copied())
+    });
+
+    let users_allowed = old.users.keys().chain(new.users.keys()).all(|other_user_id| {
+        let old_level = old.users.get(other_user_id).copied();
+        let new_level = new.users.get(other_user_id).copied();
+
+        old_level == new_level
+            || (is_allowed(old_level, new_level)
+                && (other_user_id == user_id || old_level != Some(own_level)))
+    });
+
+    defaults_allowed && events_allowed && users_allowed
+}
+
+#[cfg(test)]
+mod tests {
+    use ruma::{
+        events::{
+            room::power_levels::{RoomPowerLevels, RoomPowerLevelsEventContent},
+            MessageLikeEventType, StateEventType,
+        },
+        int, user_id,
+    };
+
+    use super::{can_change_power_levels, PowerLevelAction};
+
+    fn power_levels() -> RoomPowerLevelsEventContent {
+        let mut content = RoomPowerLevelsEventContent::default();
+        content.users.insert(user_id!("@admin:localhost").to_owned(), int!(100));
+        content.users.insert(user_id!("@mod:localhost").to_owned(), int!(50));
+        content.users.insert(user_id!("@other_mod:localhost").to_owned(), int!(50));
+        content.events.insert(MessageLikeEventType::Reaction.into(), int!(10));
+        content
+    }
+
+    #[test]
+    fn actions() {
+        let power_levels = RoomPowerLevels::from(power_levels());
+        let admin = user_id!("@admin:localhost");
+        let moderator = user_id!("@mod:localhost");
+        let user = user_id!("@user:localhost");
+
+        assert!(PowerLevelAction::Ban.is_allowed(&power_levels, moderator));
+        assert!(!PowerLevelAction::Ban.is_allowed(&power_levels, user));
+        assert!(PowerLevelAction::RedactOther.is_allowed(&power_levels, moderator));
+
+        let message = PowerLevelAction::SendMessage(MessageLikeEventType::RoomMessage);
+        assert!(message.is_allowed(&power_levels, user));
+        let reaction = PowerLevelAction::SendMessage(MessageLikeEventType::Reaction);
+        assert_eq!(reaction.required_power_level(&power_levels), 10);
+        assert!(!reaction.is_allowed(&power_levels, user));
+
+        let topic = PowerLevelAction::SendState(StateEventType::RoomTopic);
+        assert!(topic.is_allowed(&power_levels, moderator));
+        assert!(!topic.is_allowed(&power_levels, user));
+        let power_levels_change = PowerLevelAction::SendState(StateEventType::RoomPowerLevels);
+        assert!(power_levels_change.is_allowed(&power_levels, admin));
+    }
+
+    #[test]
+    fn power_levels_change() {
+        let old = power_levels();
+        let admin = user_id!("@admin:localhost");
+        let moderator = user_id!("@mod:localhost");
+
+        // Promote a user to the own level.
+        let mut new = old.clone();
+        new.users.insert(user_id!("@user:localhost").to_owned(), int!(50));
+        assert!(can_change_power_levels(moderator, &old, &new));
+
+        // Promote a user above the own level.
+        new.users.insert(user_id!("@user:localhost").to_owned(), int!(51));
+        assert!(!can_change_power_levels(moderator, &old, &new));
+        assert!(can_change_power_levels(admin, &old, &new));
+
+        // Demote a user with the same level.
+        let mut new = old.clone();
+        new.users.remove(user_id!("@other_mod:localhost"));
+        assert!(!can_change_power_levels(moderator, &old, &new));
+        assert!(can_change_power_levels(admin, &old, &new));
+
+        // Demote oneself.
+        let mut new = old.clone();
+        new.users.insert(moderator.to_owned(), int!(0));
+        assert!(can_change_power_levels(moderator, &old, &new));
+
+        // Change a level above the own level.
+        let mut new = old.clone();
+        new.ban = int!(40);
+        assert!(can_change_power_levels(moderator, &old, &new));
+        new.ban = int!(60);
+        assert!(!can_change_power_levels(moderator, &old, &new));
+    }
+}
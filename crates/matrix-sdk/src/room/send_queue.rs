@@ -0,0 +1,606 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Persistent, ordered queue of outgoing room events with local echoes.
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use futures_signals::{
+    signal::Mutable,
+    signal_vec::{MutableVec, SignalVec},
+};
+use matrix_sdk_common::locks::Mutex;
+use ruma::{
+    api::{
+        client::error::ErrorKind,
+        error::{FromHttpResponseError, ServerError},
+    },
+    events::MessageLikeEventContent,
+    OwnedEventId, OwnedTransactionId, RoomId, TransactionId,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::{debug, warn};
+
+use crate::{config::RequestConfig, room::Joined, Error, HttpError, Result, RumaApiError};
+
+/// The delay before the first retry of a failed send.
+const INITIAL_RETRY_DELAY: Duration = Duration::from_secs(1);
+/// The upper bound of the exponential backoff between two retries.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+/// How many times a transient failure is retried before the event is marked as
+/// failed.
+const MAX_ATTEMPTS: u32 = 5;
+
+/// An event that was put into the send queue and that is persisted in the
+/// state store until the homeserver acknowledges it.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct QueuedEvent {
+    txn_id: OwnedTransactionId,
+    event_type: String,
+    content: Value,
+}
+
+/// The state of a [`LocalEcho`].
+#[derive(Clone, Debug)]
+pub enum LocalEchoState {
+    /// The event is waiting in the queue or is currently being sent.
+    Sending,
+
+    /// The homeserver accepted the event. The local echo is removed once the
+    /// remote echo comes down the sync.
+    Sent {
+        /// The ID the homeserver assigned to the event.
+        event_id: OwnedEventId,
+    },
+
+    /// Sending the event failed.
+    ///
+    /// The event stays in the queue and blocks the events queued after it,
+    /// until it is either retried with [`RoomSendQueue::retry()`] or removed
+    /// with [`RoomSendQueue::cancel()`].
+    Failed {
+        /// The error of the last attempt to send the event.
+        error: Arc<Error>,
+    },
+}
+
+/// A local representation of an event that was queued with a
+/// [`RoomSendQueue`].
+#[derive(Clone, Debug)]
+pub struct LocalEcho {
+    /// The transaction ID the event is sent with.
+    pub txn_id: OwnedTransactionId,
+    /// The type of the event.
+    pub event_type: String,
+    /// The content of the event, as it will be sent if the room is not
+    /// encrypted.
+    pub content: Value,
+    /// The current state of the event.
+    pub state: LocalEchoState,
+}
+
+/// The state of the send queue of a single room, shared between all the
+/// [`RoomSendQueue`] handles of that room.
+#[derive(Debug, Default)]
+pub(crate) struct SendQueueState {
+    /// The local echoes of the queued events, in the order they will be sent.
+    echoes: MutableVec<LocalEcho>,
+    /// The transaction ID of the event that is currently being sent, if any.
+    in_flight: Mutable<Option<OwnedTransactionId>>,
+    /// Makes sure that only one task processes the queue at a time.
+    flush_lock: Mutex<()>,
+    /// Whether the queue should be sent again, because events were pushed
+    /// while another task was possibly done sending it.
+    flush_requested: AtomicBool,
+    /// Guards read-modify-write cycles of the persisted queue.
+    store_lock: Mutex<()>,
+    /// Whether the persisted queue was already loaded into `echoes`.
+    loaded: Mutable<bool>,
+}
+
+/// A handle to the send queue of a joined room.
+///
+/// Events pushed into the queue are persisted in the state store together with
+/// their transaction ID, and are sent in order. Sending is retried with an
+/// exponential backoff if the homeserver can't be reached, and the queue
+/// survives restarts of the client: the events that weren't sent are picked up
+/// again by [`RoomSendQueue::retry()`] or [`Client::resume_send_queues()`].
+///
+/// While an event isn't acknowledged by the homeserver it is represented by a
+/// [`LocalEcho`], which can be observed with
+/// [`RoomSendQueue::local_echoes_signal()`].
+///
+/// [`Client::resume_send_queues()`]: crate::Client::resume_send_queues
+#[derive(Debug, Clone)]
+pub struct RoomSendQueue {
+    room: Joined,
+    state: Arc<SendQueueState>,
+}
+
+impl RoomSendQueue {
+    pub(crate) fn new(room: Joined) -> Self {
+        let state =
+            room.client.inner.send_queues.entry(room.room_id().to_owned()).or_default().clone();
+
+        Self { room, state }
+    }
+
+    /// Put an event at the end of the queue and start sending the queue in the
+    /// background.
+    ///
+    /// Returns the transaction ID of the event, which identifies its
+    /// [`LocalEcho`].
+    ///
+    /// # Arguments
+    ///
+    /// * `content` - The content of the message event.
+    pub async fn push(&self, content: impl MessageLikeEventContent) -> Result<OwnedTransactionId> {
+        let event_type = content.event_type().to_string();
+        let content = serde_json::to_value(&content)?;
+
+        self.push_raw(content, &event_type).await
+    }
+
+    /// Put an event with a raw JSON content at the end of the queue and start
+    /// sending the queue in the background.
+    ///
+    /// Returns the transaction ID of the event, which identifies its
+    /// [`LocalEcho`].
+    ///
+    /// # Arguments
+    ///
+    /// * `content` - The content of the event as a json `Value`.
+    ///
+    /// * `event_type` - The type of the event.
+    pub async fn push_raw(&self, content: Value, event_type: &str) -> Result<OwnedTransactionId> {
+        self.load().await?;
+
+        let event = QueuedEvent {
+            txn_id: TransactionId::new(),
+            event_type: event_type.to_owned(),
+            content,
+        };
+
+        {
+            let _guard = self.state.store_lock.lock().await;
+            let mut queue = self.load_queue().await?;
+            queue.push(event.clone());
+            self.save_queue(&queue).await?;
+
+            self.state.echoes.lock_mut().push_cloned(LocalEcho {
+                txn_id: event.txn_id.clone(),
+                event_type: event.event_type,
+                content: event.content,
+                state: LocalEchoState::Sending,
+            });
+        }
+
+        self.spawn_flush();
+
+        Ok(event.txn_id)
+    }
+
+    /// Remove an event that wasn't sent yet from the queue.
+    ///
+    /// Returns `false` if the event isn't in the queue anymore or if it is
+    /// currently being sent, in which case it can't be cancelled.
+    ///
+    /// # Arguments
+    ///
+    /// * `txn_id` - The transaction ID of the event.
+    pub async fn cancel(&self, txn_id: &TransactionId) -> Result<bool> {
+        self.load().await?;
+
+        let guard = self.state.store_lock.lock().await;
+
+        if self.state.in_flight.lock_ref().as_deref() == Some(txn_id) {
+            return Ok(false);
+        }
+
+        let mut queue = self.load_queue().await?;
+        let len = queue.len();
+        queue.retain(|event| event.txn_id != txn_id);
+
+        if queue.len() == len {
+            return Ok(false);
+        }
+
+        self.save_queue(&queue).await?;
+        self.state.echoes.lock_mut().retain(|echo| echo.txn_id != txn_id);
+
+        // The cancelled event might have been a failed event that blocked the
+        // rest of the queue.
+        drop(guard);
+        self.spawn_flush();
+
+        Ok(true)
+    }
+
+    /// Replace the content of an event that wasn't sent yet.
+    ///
+    /// Returns `false` if the event isn't in the queue anymore or if it is
+    /// currently being sent, in which case it can't be edited anymore.
+    ///
+    /// # Arguments
+    ///
+    /// * `txn_id` - The transaction ID of the event.
+    ///
+    /// * `content` - The new content of the event. It must have the same event
+    ///   type as the original content.
+    pub async fn edit(
+        &self,
+        txn_id: &TransactionId,
+        content: impl MessageLikeEventContent,
+    ) -> Result<bool> {
+        let event_type = content.event_type().to_string();
+        let content = serde_json::to_value(&content)?;
+
+        self.load().await?;
+
+        let _guard = self.state.store_lock.lock().await;
+
+        if self.state.in_flight.lock_ref().as_deref() == Some(txn_id) {
+            return Ok(false);
+        }
+
+        let mut queue = self.load_queue().await?;
+        let event = match queue.iter_mut().find(|event| event.txn_id == txn_id) {
+            Some(event) => event,
+            None => return Ok(false),
+        };
+
+        event.event_type = event_type;
+        event.content = content;
+        let event = event.clone();
+
+        self.save_queue(&queue).await?;
+
+        let mut echoes = self.state.echoes.lock_mut();
+        if let Some(index) = echoes.iter().position(|echo| echo.txn_id == txn_id) {
+            let mut echo = echoes[index].clone();
+            echo.event_type = event.event_type;
+            echo.content = event.content;
+            echoes.set_cloned(index, echo);
+        }
+
+        Ok(true)
+    }
+
+    /// Send all the events that are still in the queue, including the ones
+    /// that previously failed.
+    ///
+    /// Resolves once the queue is empty or an event failed to be sent.
+    pub async fn retry(&self) -> Result<()> {
+        self.load().await?;
+        self.flush().await
+    }
+
+    /// Get the current local echoes of this room.
+    pub fn local_echoes(&self) -> Vec<LocalEcho> {
+        self.state.echoes.lock_ref().to_vec()
+    }
+
+    /// Get a [`SignalVec`] of the local echoes of this room.
+    ///
+    /// The signal is updated every time an event is queued, changes state,
+    /// gets edited or cancelled, and when the remote echo of a sent event is
+    /// received.
+    pub fn local_echoes_signal(&self) -> impl SignalVec<Item = LocalEcho> {
+        self.state.echoes.signal_vec_cloned()
+    }
+
+    /// Whether there are events left in the queue.
+    pub async fn is_empty(&self) -> Result<bool> {
+        Ok(self.load_queue().await?.is_empty())
+    }
+
+    /// Load the persisted queue into the local echoes, if it wasn't done
+    /// already.
+    async fn load(&self) -> Result<()> {
+        if self.state.loaded.get() {
+            return Ok(());
+        }
+
+        let _guard = self.state.store_lock.lock().await;
+
+        if !self.state.loaded.get() {
+            let queue = self.load_queue().await?;
+            let mut echoes = self.state.echoes.lock_mut();
+
+            for event in queue {
+                if !echoes.iter().any(|echo| echo.txn_id == event.txn_id) {
+                    echoes.push_cloned(LocalEcho {
+                        txn_id: event.txn_id,
+                        event_type: event.event_type,
+                        content: event.content,
+                        state: LocalEchoState::Sending,
+                    });
+                }
+            }
+
+            self.state.loaded.set(true);
+        }
+
+        Ok(())
+    }
+
+    /// Whether the next event to send previously failed, and is waiting for an
+    /// explicit retry.
+    fn is_blocked(&self) -> bool {
+        self.state
+            .echoes
+            .lock_ref()
+            .iter()
+            .find(|echo| !matches!(echo.state, LocalEchoState::Sent { .. }))
+            .map_or(false, |echo| matches!(echo.state, LocalEchoState::Failed { .. }))
+    }
+
+    /// Send the queue in the background, unless another task is already
+    /// sending it, in which case that task will pick up the new events.
+    fn spawn_flush(&self) {
+        self.state.flush_requested.store(true, Ordering::SeqCst);
+        let queue = self.clone();
+
+        matrix_sdk_common::executor::spawn(async move {
+            // The task holding the lock checks for new requests after releasing
+            // it, so the events pushed while it was finishing are not missed.
+            while queue.state.flush_requested.load(Ordering::SeqCst) {
+                #[cfg(not(target_arch = "wasm32"))]
+                let guard = queue.state.flush_lock.try_lock().ok();
+                #[cfg(target_arch = "wasm32")]
+                let guard = queue.state.flush_lock.try_lock();
+
+                let _guard = match guard {
+                    Some(guard) => guard,
+                    None => return,
+                };
+                queue.state.flush_requested.store(false, Ordering::SeqCst);
+
+                if queue.is_blocked() {
+                    return;
+                }
+
+                if let Err(error) = queue.flush_locked().await {
+                    warn!(room_id = %queue.room.room_id(), "Error while sending the queue: {error}");
+                }
+            }
+        });
+    }
+
+    /// Send the queue, waiting for any other task that is currently sending it
+    /// to finish first.
+    async fn flush(&self) -> Result<()> {
+        let result = {
+            let _guard = self.state.flush_lock.lock().await;
+            self.state.flush_requested.store(false, Ordering::SeqCst);
+            self.flush_locked().await
+        };
+
+        if self.state.flush_requested.load(Ordering::SeqCst) {
+            self.spawn_flush();
+        }
+
+        result
+    }
+
+    /// Send the events of the queue in order, until it is empty or an event
+    /// fails to be sent.
+    ///
+    /// The `flush_lock` must be held while calling this.
+    async fn flush_locked(&self) -> Result<()> {
+        loop {
+            let event = {
+                let _guard = self.state.store_lock.lock().await;
+                let event = match self.load_queue().await?.into_iter().next() {
+                    Some(event) => event,
+                    None => break,
+                };
+
+                self.state.in_flight.set(Some(event.txn_id.clone()));
+                event
+            };
+
+            self.set_echo_state(&event.txn_id, LocalEchoState::Sending);
+
+            let result = self.send_with_backoff(&event).await;
+
+            let _guard = self.state.store_lock.lock().await;
+            self.state.in_flight.set(None);
+
+            match result {
+                Ok(event_id) => {
+                    let mut queue = self.load_queue().await?;
+                    queue.retain(|e| e.txn_id != event.txn_id);
+                    self.save_queue(&queue).await?;
+
+                    self.set_echo_state(&event.txn_id, LocalEchoState::Sent { event_id });
+                }
+                Err(_) if !self.has_echo(event.txn_id.as_str()) => {
+                    // The remote echo was received, so the homeserver has the
+                    // event even though the response was lost.
+                    let mut queue = self.load_queue().await?;
+                    queue.retain(|e| e.txn_id != event.txn_id);
+                    self.save_queue(&queue).await?;
+                }
+                Err(error) => {
+                    warn!(
+                        room_id = %self.room.room_id(),
+                        txn_id = %event.txn_id,
+                        "Failed to send a queued event: {error}"
+                    );
+
+                    self.set_echo_state(
+                        &event.txn_id,
+                        LocalEchoState::Failed { error: Arc::new(error) },
+                    );
+
+                    // Don't send the rest of the queue, it would break the
+                    // order of the events.
+                    break;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn send_with_backoff(&self, event: &QueuedEvent) -> Result<OwnedEventId> {
+        let mut delay = INITIAL_RETRY_DELAY;
+        let mut attempt = 1;
+
+        loop {
+            let result = self
+                .room
+                .send_raw_with_config(
+                    event.content.clone(),
+                    &event.event_type,
+                    Some(&event.txn_id),
+                    Some(RequestConfig::new().disable_retry()),
+                )
+                .await;
+
+            match result {
+                Ok(response) => return Ok(response.event_id),
+                Err(error) if attempt < MAX_ATTEMPTS && is_transient(&error) => {
+                    debug!(
+                        txn_id = %event.txn_id,
+                        attempt,
+                        "Transient error while sending a queued event, retrying in {delay:?}"
+                    );
+
+                    sleep(retry_after(&error).unwrap_or(delay)).await;
+
+                    delay = (delay * 2).min(MAX_RETRY_DELAY);
+                    attempt += 1;
+                }
+                Err(error) => return Err(error),
+            }
+        }
+    }
+
+    /// Whether there is a local echo with the given transaction ID.
+    fn has_echo(&self, txn_id: &str) -> bool {
+        self.state.echoes.lock_ref().iter().any(|echo| echo.txn_id.as_str() == txn_id)
+    }
+
+    /// Remove the event with the given transaction ID, once its remote echo
+    /// was received.
+    ///
+    /// The remote echo can be received in a sync response before the response
+    /// to the send request, so the local echo is removed whatever its state.
+    /// An event that isn't being sent is also removed from the persisted
+    /// queue, e.g. when it failed because the response was lost, so it isn't
+    /// sent again.
+    pub(crate) async fn remove_local_echo(&self, txn_id: &str) -> Result<()> {
+        if !self.has_echo(txn_id) {
+            return Ok(());
+        }
+
+        let _guard = self.state.store_lock.lock().await;
+
+        let in_flight =
+            self.state.in_flight.lock_ref().as_deref().map(TransactionId::as_str) == Some(txn_id);
+        if !in_flight {
+            let mut queue = self.load_queue().await?;
+            let len = queue.len();
+            queue.retain(|event| event.txn_id.as_str() != txn_id);
+
+            if queue.len() != len {
+                self.save_queue(&queue).await?;
+            }
+        }
+
+        self.state.echoes.lock_mut().retain(|echo| echo.txn_id.as_str() != txn_id);
+
+        Ok(())
+    }
+
+    /// Update the state of the local echo with the given transaction ID.
+    ///
+    /// Does nothing if the local echo was already removed because the remote
+    /// echo of the event was received.
+    fn set_echo_state(&self, txn_id: &TransactionId, state: LocalEchoState) {
+        let mut echoes = self.state.echoes.lock_mut();
+
+        if let Some(index) = echoes.iter().position(|echo| echo.txn_id == txn_id) {
+            let mut echo = echoes[index].clone();
+            echo.state = state;
+            echoes.set_cloned(index, echo);
+        }
+    }
+
+    fn store_key(room_id: &RoomId) -> Vec<u8> {
+        format!("send_queue.{room_id}").into_bytes()
+    }
+
+    async fn load_queue(&self) -> Result<Vec<QueuedEvent>> {
+        let key = Self::store_key(self.room.room_id());
+
+        Ok(match self.room.client.store().get_custom_value(&key).await? {
+            Some(value) => serde_json::from_slice(&value)?,
+            None => Vec::new(),
+        })
+    }
+
+    async fn save_queue(&self, queue: &[QueuedEvent]) -> Result<()> {
+        let key = Self::store_key(self.room.room_id());
+        let value = serde_json::to_vec(queue)?;
+        self.room.client.store().set_custom_value(&key, value).await?;
+
+        Ok(())
+    }
+}
+
+/// Whether sending a request that failed with the given error should be tried
+/// again later.
+fn is_transient(error: &Error) -> bool {
+    match error {
+        Error::Http(HttpError::Reqwest(_) | HttpError::Server(_)) => true,
+        Error::Http(HttpError::Api(FromHttpResponseError::Server(ServerError::Known(
+            RumaApiError::ClientApi(error),
+        )))) => {
+            error.status_code.is_server_error()
+                || matches!(error.kind, ErrorKind::LimitExceeded { .. })
+        }
+        _ => false,
+    }
+}
+
+/// The delay the homeserver asked us to wait before retrying, if any.
+fn retry_after(error: &Error) -> Option<Duration> {
+    match error {
+        Error::Http(HttpError::Api(FromHttpResponseError::Server(ServerError::Known(
+            RumaApiError::ClientApi(error),
+        )))) => match error.kind {
+            ErrorKind::LimitExceeded { retry_after_ms } => retry_after_ms,
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+async fn sleep(duration: Duration) {
+    #[cfg(target_arch = "wasm32")]
+    let _ = wasm_timer::Delay::new(duration).await;
+
+    #[cfg(not(target_arch = "wasm32"))]
+    tokio::time::sleep(duration).await;
+}
@@ -0,0 +1,158 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Automatic persistence of the session of a [`Client`].
+//!
+//! Instead of serializing the [`Session`] after login and every time the
+//! access token is refreshed, a [`SessionPersister`] can be set with
+//! [`ClientBuilder::session_persister()`]. The `Client` then:
+//!
+//! * restores the persisted session, if any, when it is built,
+//! * persists the session when it logs in and every time the tokens change,
+//! * removes the persisted session when it is logged out.
+//!
+//! [`FileSessionPersister`] is a ready-to-use implementation that stores the
+//! session in a JSON file.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # use futures::executor::block_on;
+//! use matrix_sdk::{session_persistence::FileSessionPersister, Client};
+//!
+//! # block_on(async {
+//! let client = Client::builder()
+//!     .homeserver_url("http://example.com")
+//!     .handle_refresh_tokens()
+//!     .session_persister(FileSessionPersister::new("/home/example/session.json"))
+//!     .build()
+//!     .await?;
+//!
+//! if !client.logged_in() {
+//!     client.login_username("user", "wordpass").request_refresh_token().send().await?;
+//! }
+//! # anyhow::Ok(()) });
+//! ```
+//!
+//! [`Client`]: crate::Client
+//! [`ClientBuilder::session_persister()`]: crate::ClientBuilder::session_persister
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::{Path, PathBuf};
+
+use async_trait::async_trait;
+use matrix_sdk_base::{Session, StoreError};
+use matrix_sdk_common::AsyncTraitDeps;
+use serde::{Deserialize, Serialize};
+#[cfg(not(target_arch = "wasm32"))]
+use tokio::io::AsyncWriteExt;
+use url::Url;
+
+/// A [`Session`] with the homeserver it is valid on, as it is persisted by a
+/// [`SessionPersister`].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PersistedSession {
+    /// The URL of the homeserver of the session.
+    pub homeserver_url: Url,
+
+    /// The session.
+    #[serde(flatten)]
+    pub session: Session,
+}
+
+/// Storage for the session of a [`Client`](crate::Client).
+///
+/// See the [module-level documentation](self) for more details.
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+pub trait SessionPersister: AsyncTraitDeps {
+    /// Load the persisted session.
+    ///
+    /// Returns `None` if no session was persisted.
+    async fn load(&self) -> Result<Option<PersistedSession>, StoreError>;
+
+    /// Persist the given session, replacing the previous one.
+    async fn save(&self, session: &PersistedSession) -> Result<(), StoreError>;
+
+    /// Remove the persisted session.
+    ///
+    /// This must succeed if no session was persisted.
+    async fn remove(&self) -> Result<(), StoreError>;
+}
+
+/// A [`SessionPersister`] storing the session as JSON in a file.
+///
+/// The parent directories of the file are created when the session is first
+/// saved. On Unix, the file is only readable by its owner, since it contains
+/// the access token.
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Clone, Debug)]
+pub struct FileSessionPersister {
+    path: PathBuf,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl FileSessionPersister {
+    /// Create a `FileSessionPersister` using the file at the given path.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// The path of the file where the session is stored.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[async_trait]
+impl SessionPersister for FileSessionPersister {
+    async fn load(&self) -> Result<Option<PersistedSession>, StoreError> {
+        match tokio::fs::read(&self.path).await {
+            Ok(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(StoreError::backend(error)),
+        }
+    }
+
+    async fn save(&self, session: &PersistedSession) -> Result<(), StoreError> {
+        let bytes = serde_json::to_vec(session)?;
+
+        if let Some(parent) = self.path.parent() {
+            tokio::fs::create_dir_all(parent).await.map_err(StoreError::backend)?;
+        }
+
+        // Write to a temporary file first so the persisted session is never
+        // left half-written.
+        let tmp_path = self.path.with_extension("tmp");
+        let mut options = tokio::fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+
+        let mut file = options.open(&tmp_path).await.map_err(StoreError::backend)?;
+        file.write_all(&bytes).await.map_err(StoreError::backend)?;
+        file.sync_all().await.map_err(StoreError::backend)?;
+
+        tokio::fs::rename(&tmp_path, &self.path).await.map_err(StoreError::backend)
+    }
+
+    async fn remove(&self) -> Result<(), StoreError> {
+        match tokio::fs::remove_file(&self.path).await {
+            Ok(()) => Ok(()),
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(error) => Err(StoreError::backend(error)),
+        }
+    }
+}
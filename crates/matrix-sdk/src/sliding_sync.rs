@@ -27,7 +27,7 @@ use ruma::{
 };
 use url::Url;
 
-use crate::{Client, Result};
+use crate::{Client, Feature, Result};
 
 /// The state the [`SlidingSyncView`] is in.
 ///
@@ -397,9 +397,19 @@ impl SlidingSync {
     /// Create the inner stream for the view.
     ///
     /// Run this stream to receive new updates from the server.
+    ///
+    /// Returns an [`Error::UnsupportedFeature`] error if no sliding sync proxy
+    /// is set with [`SlidingSyncBuilder::homeserver()`] and the homeserver
+    /// doesn't support [`Feature::SlidingSync`].
+    ///
+    /// [`Error::UnsupportedFeature`]: crate::Error::UnsupportedFeature
     pub async fn stream<'a>(
         &self,
     ) -> anyhow::Result<impl Stream<Item = anyhow::Result<UpdateSummary>> + '_> {
+        if self.homeserver.is_none() {
+            self.client.ensure_feature(Feature::SlidingSync).await?;
+        }
+
         let views = self.views.lock_ref().to_vec();
         let _pos = self.pos.clone();
 
@@ -0,0 +1,335 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Types to work with [spaces].
+//!
+//! The relations between spaces and their rooms are defined by `m.space.child`
+//! state events in the space and `m.space.parent` state events in the rooms.
+//! The ones of the joined rooms can be read from the store with
+//! [`Common::space_children()`] and [`Common::space_parents()`], and are
+//! combined into a [`SpaceTree`] by [`Client::space_tree()`].
+//!
+//! The rooms of a space that the user hasn't joined can be discovered with
+//! [`Client::space_hierarchy()`].
+//!
+//! [spaces]: https://spec.matrix.org/v1.4/client-server-api/#spaces
+//! [`Common::space_children()`]: crate::room::Common::space_children
+//! [`Common::space_parents()`]: crate::room::Common::space_parents
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use futures_core::stream::Stream;
+use ruma::{
+    api::client::space::{get_hierarchy, SpaceHierarchyRoomsChunk},
+    assign,
+    events::{room::power_levels::RoomPowerLevelsEventContent, RoomEventType},
+    MilliSecondsSinceUnixEpoch, OwnedRoomId, OwnedServerName, OwnedUserId, RoomId, UInt, UserId,
+};
+
+use crate::{room, Client, Error, Result};
+
+/// A child of a space, as defined by an `m.space.child` state event in the
+/// space.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct SpaceChild {
+    /// The ID of the child room.
+    pub room_id: OwnedRoomId,
+
+    /// The servers that can be used to join the child room.
+    pub via: Vec<OwnedServerName>,
+
+    /// The string used to order the children of the space, if any.
+    pub order: Option<String>,
+
+    /// Whether the space admins suggest the child room to the members of the
+    /// space.
+    pub suggested: bool,
+
+    /// The time at which the child was added to the space.
+    pub origin_server_ts: MilliSecondsSinceUnixEpoch,
+}
+
+impl SpaceChild {
+    /// The `order` of the child, if it is valid according to the spec.
+    ///
+    /// It must consist of at most 50 characters in the `\x20` (space) to
+    /// `\x7E` (`~`) range, invalid orders must be ignored.
+    pub fn valid_order(&self) -> Option<&str> {
+        self.order
+            .as_deref()
+            .filter(|order| order.len() <= 50 && order.bytes().all(|b| (0x20..=0x7E).contains(&b)))
+    }
+}
+
+/// A parent of a room, as defined by an `m.space.parent` state event in the
+/// room.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct SpaceParent {
+    /// The ID of the parent space.
+    pub room_id: OwnedRoomId,
+
+    /// The servers that can be used to join the parent space.
+    pub via: Vec<OwnedServerName>,
+
+    /// Whether the parent is the main parent of the room.
+    pub canonical: bool,
+
+    /// The user that declared the parent.
+    pub sender: OwnedUserId,
+}
+
+/// Options for [`space_hierarchy`][Client::space_hierarchy].
+///
+/// See that method and
+/// <https://spec.matrix.org/v1.4/client-server-api/#get_matrixclientv1roomsroomidhierarchy>
+/// for details.
+#[derive(Debug, Default)]
+#[non_exhaustive]
+pub struct SpaceHierarchyOptions {
+    /// The maximum number of rooms to request per page.
+    ///
+    /// If this isn't set the homeserver picks a default.
+    pub limit: Option<UInt>,
+
+    /// How deep in the space tree the homeserver should go.
+    ///
+    /// If this isn't set the homeserver picks a default.
+    pub max_depth: Option<UInt>,
+
+    /// Whether to only return the rooms that are suggested by the space
+    /// admins.
+    pub suggested_only: bool,
+}
+
+impl SpaceHierarchyOptions {
+    fn to_request<'a>(
+        &self,
+        room_id: &'a RoomId,
+        from: Option<&'a str>,
+    ) -> get_hierarchy::v1::Request<'a> {
+        assign!(get_hierarchy::v1::Request::new(room_id), {
+            from,
+            limit: self.limit,
+            max_depth: self.max_depth,
+            suggested_only: self.suggested_only,
+        })
+    }
+}
+
+/// The relations between the spaces and rooms that the user joined.
+///
+/// This is built from the local store, so it doesn't contain the rooms of a
+/// space that the user hasn't joined. A room can have several parent spaces.
+#[derive(Clone, Debug, Default)]
+pub struct SpaceTree {
+    children: BTreeMap<OwnedRoomId, BTreeSet<OwnedRoomId>>,
+    parents: BTreeMap<OwnedRoomId, BTreeSet<OwnedRoomId>>,
+}
+
+impl SpaceTree {
+    fn insert(&mut self, space_id: OwnedRoomId, room_id: OwnedRoomId) {
+        self.parents.entry(room_id.clone()).or_default().insert(space_id.clone());
+        self.children.entry(space_id).or_default().insert(room_id);
+    }
+
+    /// The direct children of the given space.
+    pub fn children(&self, space_id: &RoomId) -> impl Iterator<Item = &RoomId> {
+        self.children.get(space_id).into_iter().flatten().map(|room_id| &**room_id)
+    }
+
+    /// The direct parents of the given room.
+    pub fn parents(&self, room_id: &RoomId) -> impl Iterator<Item = &RoomId> {
+        self.parents.get(room_id).into_iter().flatten().map(|room_id| &**room_id)
+    }
+
+    /// The spaces that don't have a parent.
+    pub fn root_spaces(&self) -> impl Iterator<Item = &RoomId> {
+        self.children
+            .keys()
+            .filter(|space_id| !self.parents.contains_key(*space_id))
+            .map(|space_id| &**space_id)
+    }
+
+    /// All the rooms and subspaces in the given space, recursively.
+    pub fn descendants(&self, space_id: &RoomId) -> BTreeSet<OwnedRoomId> {
+        let mut descendants = BTreeSet::new();
+        let mut queue: Vec<&RoomId> = self.children(space_id).collect();
+
+        while let Some(room_id) = queue.pop() {
+            // Spaces can contain each other, so make sure to visit each room
+            // only once.
+            if descendants.insert(room_id.to_owned()) {
+                queue.extend(self.children(room_id));
+            }
+        }
+
+        descendants
+    }
+
+    /// Whether the given room is in the given space, directly or through a
+    /// subspace.
+    pub fn contains(&self, space_id: &RoomId, room_id: &RoomId) -> bool {
+        self.descendants(space_id).contains(room_id)
+    }
+}
+
+impl Client {
+    /// Get the rooms of the given space, recursively.
+    ///
+    /// The rooms are fetched page by page from the homeserver, which also
+    /// returns the rooms that the user hasn't joined if they are
+    /// accessible. The space itself is the first item of the stream.
+    ///
+    /// # Arguments
+    ///
+    /// * `space_id` - The ID of the space.
+    ///
+    /// * `options` - Options to limit the returned rooms.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use futures::executor::block_on;
+    /// # use matrix_sdk::ruma::room_id;
+    /// # use url::Url;
+    /// # block_on(async {
+    /// # let homeserver = Url::parse("http://example.com")?;
+    /// use futures::StreamExt;
+    /// use matrix_sdk::{space::SpaceHierarchyOptions, Client};
+    ///
+    /// let client = Client::new(homeserver).await?;
+    /// let space_id = room_id!("!space:example.com");
+    ///
+    /// let hierarchy = client.space_hierarchy(space_id, SpaceHierarchyOptions::default());
+    /// futures::pin_mut!(hierarchy);
+    ///
+    /// while let Some(room) = hierarchy.next().await {
+    ///     let room = room?;
+    ///     println!("{} has {} members", room.room_id, room.num_joined_members);
+    /// }
+    /// # anyhow::Ok(()) });
+    /// ```
+    pub fn space_hierarchy<'a>(
+        &'a self,
+        space_id: &'a RoomId,
+        options: SpaceHierarchyOptions,
+    ) -> impl Stream<Item = Result<SpaceHierarchyRoomsChunk>> + 'a {
+        async_stream::stream! {
+            let mut from: Option<String> = None;
+
+            loop {
+                let request = options.to_request(space_id, from.as_deref());
+                let response = match self.send(request, None).await {
+                    Ok(r) => r,
+                    Err(e) => {
+                        yield Err(Error::from(e));
+                        break;
+                    }
+                };
+
+                for room in response.rooms {
+                    yield Ok(room);
+                }
+
+                from = response.next_batch;
+                if from.is_none() {
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Get the relations between the spaces and rooms that the user joined.
+    ///
+    /// The tree is built from the local store and cached until a sync
+    /// changes it, so this doesn't make any request to the homeserver.
+    ///
+    /// A room is considered part of a space if the space has an
+    /// `m.space.child` event for it, or if the room has an `m.space.parent`
+    /// event for the space sent by a user that is allowed to add children to
+    /// the space.
+    pub async fn space_tree(&self) -> Result<SpaceTree> {
+        let mut cache = self.inner.space_tree.lock().await;
+
+        if let Some(tree) = &*cache {
+            return Ok(tree.clone());
+        }
+
+        let tree = self.build_space_tree().await?;
+        *cache = Some(tree.clone());
+
+        Ok(tree)
+    }
+
+    /// Forget the cached [`SpaceTree`], it will be rebuilt the next time it
+    /// is requested.
+    pub(crate) async fn invalidate_space_tree(&self) {
+        self.inner.space_tree.lock().await.take();
+    }
+
+    async fn build_space_tree(&self) -> Result<SpaceTree> {
+        let mut tree = SpaceTree::default();
+
+        for room in self.joined_rooms() {
+            if room.is_space() {
+                for child in room.space_children().await? {
+                    tree.insert(room.room_id().to_owned(), child.room_id);
+                }
+            }
+
+            for parent in room.space_parents().await? {
+                let space = match self.get_joined_room(&parent.room_id) {
+                    Some(space) if space.is_space() => space,
+                    _ => continue,
+                };
+
+                if can_add_space_child(&space, &parent.sender).await? {
+                    tree.insert(parent.room_id, room.room_id().to_owned());
+                }
+            }
+        }
+
+        Ok(tree)
+    }
+}
+
+/// Whether the given user is allowed to send `m.space.child` events in the
+/// given space.
+async fn can_add_space_child(space: &room::Joined, user_id: &UserId) -> Result<bool> {
+    let member = match space.get_member_no_sync(user_id).await? {
+        Some(member) => member,
+        None => return Ok(false),
+    };
+
+    let power_levels = space
+        .get_state_event_static::<RoomPowerLevelsEventContent>()
+        .await?
+        .and_then(|event| event.deserialize().ok());
+    let required = match &power_levels {
+        Some(event) => {
+            let power_levels = event.power_levels();
+            power_levels
+                .events
+                .get(&RoomEventType::SpaceChild)
+                .copied()
+                .unwrap_or(power_levels.state_default)
+                .into()
+        }
+        None => 0,
+    };
+
+    Ok(member.power_level() >= required)
+}
@@ -22,8 +22,11 @@
 //! 2. The `indexeddb` feature also provides a `StateStore` for storing state
 //!    and a `CryptoStore` (if `e2e-encryption` is enabled). This is the default
 //!    persistent store implementation for WebAssembly targets.
+//! 3. The `sqlite` feature provides the same stores backed by SQLite, as an
+//!    alternative to `sled` for non-WebAssembly targets. Its types are only
+//!    re-exported here if `sled` isn't enabled too.
 //!
-//! Both options provide a `make_store_config` convenience method to create a
+//! All options provide a `make_store_config` convenience method to create a
 //! [`StoreConfig`] for [`ClientBuilder::store_config()`].
 //!
 //! [`StoreConfig`]: crate::config::StoreConfig
@@ -36,3 +39,5 @@
 pub use matrix_sdk_indexeddb::*;
 #[cfg(feature = "sled")]
 pub use matrix_sdk_sled::*;
+#[cfg(all(feature = "sqlite", not(any(feature = "sled", feature = "indexeddb"))))]
+pub use matrix_sdk_sqlite::*;
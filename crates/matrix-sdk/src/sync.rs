@@ -4,11 +4,37 @@ use matrix_sdk_base::{
     deserialized_responses::{JoinedRoom, LeftRoom, SyncResponse},
     instant::Instant,
 };
-use ruma::api::client::sync::sync_events;
+use ruma::{api::client::sync::sync_events, serde::Raw};
+use serde::Deserialize;
 use tracing::{error, warn};
 
 use crate::{event_handler::HandlerKind, Client, Result};
 
+/// The part of the `unsigned` field of a timeline event needed to match it
+/// with a local echo.
+#[derive(Deserialize)]
+struct Unsigned {
+    transaction_id: Option<String>,
+}
+
+/// Whether the given event is an `m.room.tombstone` event.
+fn is_tombstone<T>(event: &Raw<T>) -> bool {
+    event.get_field::<String>("type").ok().flatten().as_deref() == Some("m.room.tombstone")
+}
+
+/// Whether the given event can change the relations between spaces and rooms.
+fn affects_space_tree<T>(event: &Raw<T>) -> bool {
+    matches!(
+        event.get_field::<String>("type").ok().flatten().as_deref(),
+        Some("m.space.child" | "m.space.parent" | "m.room.create" | "m.room.power_levels")
+    )
+}
+
+/// Whether the given event is an `m.room.member` event.
+fn is_member_event<T>(event: &Raw<T>) -> bool {
+    event.get_field::<String>("type").ok().flatten().as_deref() == Some("m.room.member")
+}
+
 /// Internal functionality related to getting events from the server
 /// (`sync_events` endpoint)
 impl Client {
@@ -40,8 +66,39 @@ impl Client {
         self.handle_sync_events(HandlerKind::GlobalAccountData, &None, &account_data.events)
             .await?;
         self.handle_sync_events(HandlerKind::Presence, &None, &presence.events).await?;
+
+        for event in presence.events.iter().filter_map(|e| e.deserialize().ok()) {
+            if let Some(observed) = self.inner.presences.get(&event.sender) {
+                observed.set(Some(event));
+            }
+        }
+
+        // Users can only stop sharing a room with us if we left a room or if
+        // the members of a room changed.
+        if !self.inner.presences.is_empty()
+            && (!rooms.leave.is_empty()
+                || rooms.join.values().any(|room| {
+                    room.state.events.iter().any(is_member_event)
+                        || room.timeline.events.iter().any(|e| is_member_event(&e.event))
+                }))
+        {
+            self.prune_presences().await?;
+        }
         self.handle_sync_events(HandlerKind::ToDevice, &None, &to_device.events).await?;
 
+        // New rooms come with their `m.room.create` event, and left rooms are
+        // not part of the tree anymore.
+        if !rooms.leave.is_empty()
+            || rooms.join.values().any(|room| {
+                room.state.events.iter().any(affects_space_tree)
+                    || room.timeline.events.iter().any(|e| affects_space_tree(&e.event))
+            })
+        {
+            self.invalidate_space_tree().await;
+        }
+
+        let mut upgraded_rooms = Vec::new();
+
         for (room_id, room_info) in &rooms.join {
             let room = self.get_room(room_id);
             if room.is_none() {
@@ -54,10 +111,63 @@ impl Client {
 
             self.handle_sync_events(HandlerKind::EphemeralRoomData, &room, &ephemeral.events)
                 .await?;
+
+            if let Some(ephemeral_state) = self.inner.ephemeral_states.get(room_id) {
+                if let Some(own_user_id) = self.user_id() {
+                    for event in ephemeral.events.iter().filter_map(|e| e.deserialize().ok()) {
+                        ephemeral_state.handle_event(event, own_user_id);
+                    }
+                }
+            }
+
             self.handle_sync_events(HandlerKind::RoomAccountData, &room, &account_data.events)
                 .await?;
             self.handle_sync_state_events(&room, &state.events).await?;
             self.handle_sync_timeline_events(&room, &timeline.events).await?;
+
+            if self.inner.auto_join_room_successors {
+                if let Some(room) = &room {
+                    // A tombstone in the state can come from an initial sync, in which
+                    // case the user might already have left the successor on purpose.
+                    let upgraded = timeline.events.iter().any(|e| is_tombstone(&e.event))
+                        || (state.events.iter().any(is_tombstone)
+                            && room.successors().first().map_or(false, |successor| {
+                                self.get_joined_room(successor).is_none()
+                                    && self.get_left_room(successor).is_none()
+                            }));
+
+                    if upgraded {
+                        upgraded_rooms.push(room.clone());
+                    }
+                }
+            }
+
+            // Don't create a send queue for rooms that never used it.
+            let send_queue = if self.inner.send_queues.contains_key(room_id) {
+                self.get_joined_room(room_id).map(|room| room.send_queue())
+            } else {
+                None
+            };
+            if let Some(send_queue) = send_queue {
+                for event in &timeline.events {
+                    let txn_id = event
+                        .event
+                        .get_field::<Unsigned>("unsigned")
+                        .ok()
+                        .flatten()
+                        .and_then(|unsigned| unsigned.transaction_id);
+
+                    if let Some(txn_id) = txn_id {
+                        if let Err(error) = send_queue.remove_local_echo(&txn_id).await {
+                            warn!(
+                                %room_id,
+                                %txn_id,
+                                "Failed to remove a sent event from the send queue: {error}"
+                            );
+                        }
+                    }
+                }
+            }
         }
 
         for (room_id, room_info) in &rooms.leave {
@@ -115,6 +225,19 @@ impl Client {
             fut.await;
         }
 
+        // Joining a room sends requests, don't hold up the sync for it.
+        for room in upgraded_rooms {
+            matrix_sdk_common::executor::spawn(async move {
+                if let Err(error) = room.join_successor().await {
+                    warn!(
+                        room_id = %room.room_id(),
+                        ?error,
+                        "Failed to join the successor of a tombstoned room"
+                    );
+                }
+            });
+        }
+
         Ok(response)
     }
 
@@ -0,0 +1,474 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Driver for the [User-Interactive Authentication API].
+//!
+//! Some endpoints, like deleting devices or changing the password of the
+//! account, require the user to authenticate again, possibly in several
+//! stages. The first request is rejected by the homeserver with the list of
+//! flows it supports, and the same request must be retried with some
+//! authentication data until all the stages of a flow are completed.
+//!
+//! [`Uiaa`] keeps track of such an authentication session: it selects a flow,
+//! asks a [`UiaaHandler`] for the credentials of each stage and sends the
+//! request again with the [`AuthData`] of the next stage until it succeeds.
+//! The `m.login.dummy` stage is completed automatically, and stages without
+//! dedicated support are completed with the [fallback web page].
+//!
+//! # Example
+//!
+//! ```no_run
+//! # use futures::executor::block_on;
+//! # use matrix_sdk::{
+//! #     async_trait,
+//! #     ruma::{api::client::uiaa::UiaaInfo, device_id},
+//! #     uiaa::UiaaHandler,
+//! #     Client,
+//! # };
+//! # use url::Url;
+//! #[derive(Debug)]
+//! struct PasswordHandler;
+//!
+//! #[async_trait]
+//! impl UiaaHandler for PasswordHandler {
+//!     async fn password(&self, info: &UiaaInfo) -> Option<String> {
+//!         // Don't send the same password again if it was rejected.
+//!         info.auth_error.is_none().then(|| "wordpass".to_owned())
+//!     }
+//! }
+//!
+//! # block_on(async {
+//! # let homeserver = Url::parse("http://localhost:8080")?;
+//! # let client = Client::new(homeserver).await?;
+//! let devices = &[device_id!("DEVICEID").to_owned()];
+//!
+//! let response = client
+//!     .uiaa(PasswordHandler)
+//!     .send(|client, auth| async move {
+//!         client.delete_devices(devices, auth.auth_data()).await
+//!     })
+//!     .await?;
+//! # anyhow::Ok(()) });
+//! ```
+//!
+//! [User-Interactive Authentication API]: https://spec.matrix.org/v1.3/client-server-api/#user-interactive-authentication-api
+//! [fallback web page]: https://spec.matrix.org/v1.3/client-server-api/#fallback
+
+use std::{future::Future, sync::Arc};
+
+use async_trait::async_trait;
+use matrix_sdk_common::AsyncTraitDeps;
+use ruma::{
+    api::client::uiaa::{
+        AuthData, AuthFlow, AuthType, Dummy, FallbackAcknowledgement, IncomingAuthData, Password,
+        RegistrationToken, ThirdpartyIdCredentials, UiaaInfo, UserIdentifier,
+    },
+    assign,
+    serde::JsonObject,
+    OwnedUserId,
+};
+use url::Url;
+
+use crate::{error::InteractiveAuthError, Client, Error, Result};
+
+/// Provider of the credentials required by the stages of a User-Interactive
+/// Authentication session.
+///
+/// Every method receives the latest [`UiaaInfo`] returned by the homeserver,
+/// which contains the parameters of the stages and the error of the previous
+/// attempt, if any. Returning `None` cancels the authentication.
+///
+/// The default implementations of the methods don't provide any credentials.
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+pub trait UiaaHandler: AsyncTraitDeps {
+    /// Select the flow to follow among the ones supported by the homeserver.
+    ///
+    /// Returns the index of the flow in the list, or `None` if none of them
+    /// can be completed.
+    ///
+    /// The default implementation selects the shortest flow, preferring the
+    /// ones that don't require the fallback.
+    fn select_flow(&self, flows: &[AuthFlow]) -> Option<usize> {
+        default_flow(flows)
+    }
+
+    /// Get the password of the current user, for the `m.login.password`
+    /// stage.
+    async fn password(&self, _info: &UiaaInfo) -> Option<String> {
+        None
+    }
+
+    /// Get the credentials of a validated email address, for the
+    /// `m.login.email.identity` stage.
+    ///
+    /// The validation token is requested with
+    /// [`Client::request_registration_email_token()`] during registration, and
+    /// with [`Account::request_3pid_email_token()`] otherwise.
+    ///
+    /// [`Account::request_3pid_email_token()`]: crate::Account::request_3pid_email_token
+    async fn email_identity(&self, _info: &UiaaInfo) -> Option<ThirdpartyIdCredentials> {
+        None
+    }
+
+    /// Get a registration token, for the `m.login.registration_token` stage.
+    async fn registration_token(&self, _info: &UiaaInfo) -> Option<String> {
+        None
+    }
+
+    /// Let the user accept the terms of service listed in the `params` of the
+    /// given info, for the `m.login.terms` stage.
+    ///
+    /// Returns `true` if the user accepted the terms.
+    async fn terms(&self, _info: &UiaaInfo) -> bool {
+        false
+    }
+
+    /// Let the user complete the given stage with the fallback web page at
+    /// the given URL.
+    ///
+    /// Returns `true` once the user has completed the stage, or `false` to
+    /// cancel the authentication.
+    async fn fallback(&self, _url: Url, _stage: &AuthType, _info: &UiaaInfo) -> bool {
+        false
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl<T: UiaaHandler + ?Sized> UiaaHandler for Box<T> {
+    fn select_flow(&self, flows: &[AuthFlow]) -> Option<usize> {
+        (**self).select_flow(flows)
+    }
+
+    async fn password(&self, info: &UiaaInfo) -> Option<String> {
+        (**self).password(info).await
+    }
+
+    async fn email_identity(&self, info: &UiaaInfo) -> Option<ThirdpartyIdCredentials> {
+        (**self).email_identity(info).await
+    }
+
+    async fn registration_token(&self, info: &UiaaInfo) -> Option<String> {
+        (**self).registration_token(info).await
+    }
+
+    async fn terms(&self, info: &UiaaInfo) -> bool {
+        (**self).terms(info).await
+    }
+
+    async fn fallback(&self, url: Url, stage: &AuthType, info: &UiaaInfo) -> bool {
+        (**self).fallback(url, stage, info).await
+    }
+}
+
+/// The type of the terms of service stage, which doesn't have a dedicated
+/// variant in [`AuthType`].
+const TERMS_STAGE: &str = "m.login.terms";
+
+/// The maximum number of attempts of a stage whose credentials are rejected
+/// by the homeserver.
+const MAX_STAGE_ATTEMPTS: u8 = 3;
+
+/// The credentials for a stage, kept between attempts of the request.
+#[derive(Clone, Debug)]
+enum StageAuth {
+    Password { user_id: OwnedUserId, password: String },
+    Dummy,
+    EmailIdentity(Arc<IncomingAuthData>),
+    RegistrationToken(String),
+    Terms(Arc<IncomingAuthData>),
+    FallbackAcknowledgement,
+}
+
+/// The authentication data of an attempt of a request sent with
+/// [`Uiaa::send()`].
+#[derive(Clone, Debug)]
+pub struct UiaaAuth {
+    stage: Option<StageAuth>,
+    session: Option<String>,
+}
+
+impl UiaaAuth {
+    /// The authentication data to send with this attempt of the request.
+    ///
+    /// This is `None` for the first attempt.
+    pub fn auth_data(&self) -> Option<AuthData<'_>> {
+        auth_data(self.stage.as_ref()?, self.session.as_deref())
+    }
+}
+
+/// A User-Interactive Authentication session.
+///
+/// Created with [`Client::uiaa()`]. See the [module documentation] for an
+/// example.
+///
+/// [module documentation]: self
+#[derive(Debug)]
+pub struct Uiaa<H> {
+    client: Client,
+    handler: H,
+    flow: Option<Vec<AuthType>>,
+    session: Option<String>,
+    stage: Option<(AuthType, StageAuth)>,
+    attempts: u8,
+}
+
+impl<H: UiaaHandler> Uiaa<H> {
+    pub(crate) fn new(client: Client, handler: H) -> Self {
+        Self { client, handler, flow: None, session: None, stage: None, attempts: 0 }
+    }
+
+    /// Send a request until the authentication succeeds.
+    ///
+    /// The `request` closure is called for every attempt with the
+    /// authentication data to send, and the errors of the attempts are
+    /// handled with [`handle_error()`](Self::handle_error). Returns the
+    /// response of the first successful attempt, or the first error that is
+    /// not a User-Interactive Authentication response.
+    ///
+    /// A stage whose credentials are rejected by the homeserver is retried a
+    /// few times, with the [`UiaaHandler`] being able to check the error of the
+    /// previous attempt in the [`UiaaInfo`], before giving up with an
+    /// [`InteractiveAuthError::TooManyAttempts`] error.
+    ///
+    /// # Arguments
+    ///
+    /// * `request` - The closure sending an attempt of the request with the
+    /// given client and [`UiaaAuth`].
+    pub async fn send<F, Fut, T, E>(&mut self, mut request: F) -> Result<T>
+    where
+        F: FnMut(Client, UiaaAuth) -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+        E: Into<Error>,
+    {
+        loop {
+            let auth = UiaaAuth {
+                stage: self.stage.as_ref().map(|(_, auth)| auth.clone()),
+                session: self.session.clone(),
+            };
+
+            match request(self.client.clone(), auth).await {
+                Ok(response) => return Ok(response),
+                Err(error) => self.handle_error(error).await?,
+            }
+        }
+    }
+
+    /// The authentication data to send with the next attempt of the request.
+    ///
+    /// This is `None` until the homeserver rejected a first attempt.
+    pub fn auth_data(&self) -> Option<AuthData<'_>> {
+        let (_, auth) = self.stage.as_ref()?;
+        auth_data(auth, self.session.as_deref())
+    }
+
+    /// Handle the error returned by an attempt of the request.
+    ///
+    /// If the error is a User-Interactive Authentication response, the next
+    /// stage is prepared and the request should be sent again with the
+    /// updated [`auth_data()`](Self::auth_data). Otherwise the error is
+    /// returned as is.
+    pub async fn handle_error(&mut self, error: impl Into<Error>) -> Result<()> {
+        let error = error.into();
+
+        match error.uiaa_response() {
+            Some(info) => self.handle_info(info).await,
+            None => Err(error),
+        }
+    }
+
+    async fn handle_info(&mut self, info: &UiaaInfo) -> Result<()> {
+        if info.session.is_some() {
+            self.session = info.session.clone();
+        }
+
+        if let Some((stage, auth)) = &self.stage {
+            if info.auth_error.is_some() || !info.completed.contains(stage) {
+                // Retrying a stage that doesn't require any input would fail again.
+                let needs_input =
+                    !matches!(auth, StageAuth::Dummy | StageAuth::FallbackAcknowledgement);
+                if !needs_input {
+                    return Err(InteractiveAuthError::StageFailed(stage.clone()).into());
+                }
+
+                self.attempts += 1;
+                if self.attempts >= MAX_STAGE_ATTEMPTS {
+                    return Err(InteractiveAuthError::TooManyAttempts(stage.clone()).into());
+                }
+            }
+        }
+
+        let is_flow_supported = self
+            .flow
+            .as_ref()
+            .map_or(false, |stages| info.flows.iter().any(|flow| flow.stages == *stages));
+        if !is_flow_supported {
+            let flow = self
+                .handler
+                .select_flow(&info.flows)
+                .and_then(|index| info.flows.get(index))
+                .ok_or(InteractiveAuthError::NoSupportedFlow)?;
+            self.flow = Some(flow.stages.clone());
+        }
+
+        let stage = self
+            .flow
+            .iter()
+            .flatten()
+            .find(|stage| !info.completed.contains(*stage))
+            .cloned()
+            .ok_or(InteractiveAuthError::NoSupportedFlow)?;
+
+        let auth = match &stage {
+            AuthType::Password => {
+                let user_id = self.client.user_id().ok_or(Error::AuthenticationRequired)?;
+                let password =
+                    self.handler.password(info).await.ok_or(InteractiveAuthError::Cancelled)?;
+                StageAuth::Password { user_id: user_id.to_owned(), password }
+            }
+            AuthType::Dummy => StageAuth::Dummy,
+            AuthType::EmailIdentity => {
+                let credentials = self
+                    .handler
+                    .email_identity(info)
+                    .await
+                    .ok_or(InteractiveAuthError::Cancelled)?;
+                // Ruma doesn't allow to construct the outgoing data of this stage
+                // directly, so it is built from JSON with the session.
+                let mut data = JsonObject::new();
+                data.insert("threepid_creds".to_owned(), serde_json::to_value(credentials)?);
+
+                StageAuth::EmailIdentity(Arc::new(IncomingAuthData::new(
+                    AuthType::EmailIdentity.as_str(),
+                    self.session.clone(),
+                    data,
+                )?))
+            }
+            AuthType::RegistrationToken => {
+                let token = self
+                    .handler
+                    .registration_token(info)
+                    .await
+                    .ok_or(InteractiveAuthError::Cancelled)?;
+                StageAuth::RegistrationToken(token)
+            }
+            _ if stage.as_str() == TERMS_STAGE => {
+                if !self.handler.terms(info).await {
+                    return Err(InteractiveAuthError::Cancelled.into());
+                }
+
+                // Ruma doesn't have a type for this stage, it is built like a
+                // custom one.
+                StageAuth::Terms(Arc::new(IncomingAuthData::new(
+                    TERMS_STAGE,
+                    self.session.clone(),
+                    JsonObject::new(),
+                )?))
+            }
+            _ => {
+                let session =
+                    self.session.as_deref().ok_or(InteractiveAuthError::MissingSession)?;
+                let url = self.fallback_url(&stage, session).await;
+
+                if !self.handler.fallback(url, &stage, info).await {
+                    return Err(InteractiveAuthError::Cancelled.into());
+                }
+
+                StageAuth::FallbackAcknowledgement
+            }
+        };
+
+        if self.stage.as_ref().map_or(true, |(previous, _)| *previous != stage) {
+            self.attempts = 0;
+        }
+        self.stage = Some((stage, auth));
+
+        Ok(())
+    }
+
+    /// The URL of the fallback web page for the given stage.
+    async fn fallback_url(&self, stage: &AuthType, session: &str) -> Url {
+        let mut url = self.client.homeserver().await;
+        url.path_segments_mut()
+            .expect("the homeserver URL should be a base URL")
+            .pop_if_empty()
+            .extend(["_matrix", "client", "v3", "auth", stage.as_str(), "fallback", "web"]);
+        url.query_pairs_mut().append_pair("session", session);
+
+        url
+    }
+}
+
+/// Build the authentication data for the given stage credentials.
+fn auth_data<'a>(auth: &'a StageAuth, session: Option<&'a str>) -> Option<AuthData<'a>> {
+    let auth_data = match auth {
+        StageAuth::Password { user_id, password } => AuthData::Password(assign!(
+            Password::new(UserIdentifier::UserIdOrLocalpart(user_id.as_str()), password),
+            { session }
+        )),
+        StageAuth::Dummy => AuthData::Dummy(assign!(Dummy::new(), { session })),
+        StageAuth::EmailIdentity(auth_data) | StageAuth::Terms(auth_data) => {
+            auth_data.to_outgoing()
+        }
+        StageAuth::RegistrationToken(token) => {
+            AuthData::RegistrationToken(assign!(RegistrationToken::new(token), { session }))
+        }
+        StageAuth::FallbackAcknowledgement => {
+            AuthData::FallbackAcknowledgement(FallbackAcknowledgement::new(session?))
+        }
+    };
+
+    Some(auth_data)
+}
+
+/// Select the shortest flow, preferring the ones whose stages all have
+/// dedicated support.
+fn default_flow(flows: &[AuthFlow]) -> Option<usize> {
+    let has_dedicated_support = |stage: &AuthType| {
+        matches!(
+            stage,
+            AuthType::Password
+                | AuthType::Dummy
+                | AuthType::EmailIdentity
+                | AuthType::RegistrationToken
+        ) || stage.as_str() == TERMS_STAGE
+    };
+
+    flows
+        .iter()
+        .enumerate()
+        .min_by_key(|(_, flow)| (!flow.stages.iter().all(has_dedicated_support), flow.stages.len()))
+        .map(|(index, _)| index)
+}
+
+#[cfg(test)]
+mod tests {
+    use ruma::api::client::uiaa::{AuthFlow, AuthType};
+
+    use super::default_flow;
+
+    #[test]
+    fn flow_selection() {
+        let flows = vec![
+            AuthFlow::new(vec![AuthType::Sso]),
+            AuthFlow::new(vec![AuthType::Password, AuthType::Dummy]),
+            AuthFlow::new(vec![AuthType::EmailIdentity]),
+        ];
+        assert_eq!(default_flow(&flows), Some(2));
+        assert_eq!(default_flow(&flows[..2]), Some(1));
+        assert_eq!(default_flow(&flows[..1]), Some(0));
+        assert_eq!(default_flow(&[]), None);
+    }
+}
@@ -1,11 +1,19 @@
+#[cfg(feature = "e2e-encryption")]
+use std::sync::{Arc, Mutex};
 use std::{collections::BTreeMap, str::FromStr, time::Duration};
 
+use futures::{FutureExt, StreamExt};
+use futures_signals::signal::Mutable;
+use futures_util::io::Cursor;
+use matches::assert_matches;
 use matrix_sdk::{
-    config::SyncSettings,
-    media::{MediaFormat, MediaRequest, MediaThumbnailSize},
-    Error, HttpError, RumaApiError, Session,
+    config::{RequestConfig, SyncSettings},
+    media::{MediaFormat, MediaRequest, MediaThumbnailSize, TransmissionProgress},
+    uiaa::UiaaHandler,
+    BackoffState, Client, Error, Feature, HttpError, InteractiveAuthError, LoopCtrl, RumaApiError,
+    Session, SessionState,
 };
-use matrix_sdk_test::{async_test, test_json};
+use matrix_sdk_test::{async_test, test_json, EventBuilder, JoinedRoomBuilder, StateTestEvent};
 use ruma::{
     api::{
         client::{
@@ -17,23 +25,29 @@ use ruma::{
             },
             media::get_content_thumbnail::v3::Method,
             session::get_login_types::v3::LoginType,
-            uiaa::{self, UiaaResponse},
+            space::get_hierarchy,
+            uiaa::{self, AuthType, UiaaInfo, UiaaResponse},
         },
         error::{FromHttpResponseError, ServerError},
+        MatrixVersion,
     },
     assign, device_id,
     directory::Filter,
     events::room::{message::ImageMessageEventContent, ImageInfo, MediaSource},
-    mxc_uri, room_id, uint, user_id,
+    mxc_uri,
+    presence::PresenceState,
+    room_id, uint, user_id,
 };
 use serde_json::{from_value as from_json_value, json, to_value as to_json_value};
 use url::Url;
 use wiremock::{
-    matchers::{header, method, path, path_regex},
-    Mock, ResponseTemplate,
+    matchers::{
+        body_json, body_partial_json, body_string, header, method, path, path_regex, query_param,
+    },
+    Mock, MockServer, ResponseTemplate,
 };
 
-use crate::{logged_in_client, mock_sync, no_retry_test_client};
+use crate::{logged_in_client, mock_sync, no_retry_test_client, test_client_builder};
 
 #[async_test]
 async fn login() {
@@ -105,6 +119,121 @@ async fn login_no_discovery() {
     assert_eq!(client.homeserver().await, Url::parse(&server.uri()).unwrap());
 }
 
+#[async_test]
+async fn login_retries_after_rate_limit() {
+    let (builder, server) = test_client_builder().await;
+    let client = builder.build().await.unwrap();
+
+    Mock::given(method("POST"))
+        .and(path("/_matrix/client/r0/login"))
+        .respond_with(ResponseTemplate::new(429).set_body_json(json!({
+            "errcode": "M_LIMIT_EXCEEDED",
+            "error": "Too many requests",
+            "retry_after_ms": 100,
+        })))
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("POST"))
+        .and(path("/_matrix/client/r0/login"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::LOGIN))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    client.login_username("example", "wordpass").send().await.unwrap();
+
+    assert!(client.logged_in(), "Client should be logged in");
+    assert_eq!(client.request_backoff().get(), BackoffState::Idle);
+}
+
+#[async_test]
+async fn rate_limit_without_retry() {
+    let (client, server) = logged_in_client().await;
+
+    Mock::given(method("GET"))
+        .and(path("/_matrix/client/r0/account/whoami"))
+        .respond_with(ResponseTemplate::new(429).set_body_json(json!({
+            "errcode": "M_LIMIT_EXCEEDED",
+            "error": "Too many requests",
+            "retry_after_ms": 60000,
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let error = client.whoami().await.unwrap_err();
+
+    assert!(matches!(
+        error,
+        HttpError::Api(FromHttpResponseError::Server(ServerError::Known(RumaApiError::ClientApi(
+            client_api::Error { kind: client_api::error::ErrorKind::LimitExceeded { .. }, .. }
+        ))))
+    ));
+    assert!(matches!(client.request_backoff().get(), BackoffState::RateLimited { .. }));
+}
+
+#[async_test]
+async fn rate_limit_retry_limit() {
+    let (builder, server) = test_client_builder().await;
+    let client = builder.request_config(RequestConfig::new().retry_limit(2)).build().await.unwrap();
+    client
+        .restore_login(Session {
+            access_token: "1234".to_owned(),
+            refresh_token: None,
+            user_id: user_id!("@example:localhost").to_owned(),
+            device_id: device_id!("DEVICEID").to_owned(),
+        })
+        .await
+        .unwrap();
+
+    Mock::given(method("GET"))
+        .and(path("/_matrix/client/r0/account/whoami"))
+        .respond_with(ResponseTemplate::new(429).set_body_json(json!({
+            "errcode": "M_LIMIT_EXCEEDED",
+            "error": "Too many requests",
+            "retry_after_ms": 10,
+        })))
+        .expect(2)
+        .mount(&server)
+        .await;
+
+    // The retry limit is the total number of attempts.
+    client.whoami().await.unwrap_err();
+}
+
+#[async_test]
+async fn rate_limit_default_config() {
+    let (builder, server) = test_client_builder().await;
+    let client = builder.build().await.unwrap();
+    client
+        .restore_login(Session {
+            access_token: "1234".to_owned(),
+            refresh_token: None,
+            user_id: user_id!("@example:localhost").to_owned(),
+            device_id: device_id!("DEVICEID").to_owned(),
+        })
+        .await
+        .unwrap();
+
+    Mock::given(method("GET"))
+        .and(path("/_matrix/client/r0/account/whoami"))
+        .respond_with(ResponseTemplate::new(429).set_body_json(json!({
+            "errcode": "M_LIMIT_EXCEEDED",
+            "error": "Too many requests",
+            "retry_after_ms": 10,
+        })))
+        .expect(3)
+        .mount(&server)
+        .await;
+
+    // Without a retry limit or timeout, rate limited requests are not retried
+    // forever.
+    client.whoami().await.unwrap_err();
+}
+
 #[async_test]
 #[cfg(feature = "sso-login")]
 async fn login_with_sso() {
@@ -206,6 +335,107 @@ async fn login_error() {
     }
 }
 
+#[async_test]
+async fn soft_logout() {
+    let (client, server) = logged_in_client().await;
+    assert_eq!(client.session_state(), SessionState::LoggedIn);
+
+    Mock::given(method("GET"))
+        .and(path("/_matrix/client/r0/sync"))
+        .respond_with(
+            ResponseTemplate::new(401).set_body_json(&*test_json::UNKNOWN_TOKEN_SOFT_LOGOUT),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    // The sync loop stops even if the callback asks to continue.
+    client
+        .sync_with_result_callback(SyncSettings::default(), |result| async move {
+            assert!(result.is_err());
+            Ok(LoopCtrl::Continue)
+        })
+        .await
+        .unwrap();
+    assert_eq!(client.session_state(), SessionState::SoftLoggedOut);
+
+    Mock::given(method("POST"))
+        .and(path("/_matrix/client/r0/login"))
+        .and(body_partial_json(json!({ "device_id": "DEVICEID" })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "user_id": "@example:localhost",
+            "access_token": "5678",
+            "device_id": "DEVICEID",
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    // Logging in with another user or device is refused before sending the
+    // request.
+    assert_matches!(
+        client.login_username("other", "wordpass").send().await,
+        Err(Error::SessionMismatch)
+    );
+    assert_matches!(
+        client.login_username("example", "wordpass").device_id("OTHER").send().await,
+        Err(Error::SessionMismatch)
+    );
+
+    client.login_username("example", "wordpass").send().await.unwrap();
+
+    assert_eq!(client.session_state(), SessionState::LoggedIn);
+    assert_eq!(client.access_token().as_deref(), Some("5678"));
+    assert_eq!(client.device_id(), Some(device_id!("DEVICEID")));
+}
+
+#[async_test]
+async fn soft_logout_other_device() {
+    let (client, server) = logged_in_client().await;
+
+    Mock::given(method("GET"))
+        .and(path("/_matrix/client/r0/sync"))
+        .respond_with(
+            ResponseTemplate::new(401).set_body_json(&*test_json::UNKNOWN_TOKEN_SOFT_LOGOUT),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    client.sync_once(SyncSettings::default()).await.unwrap_err();
+    assert_eq!(client.session_state(), SessionState::SoftLoggedOut);
+
+    // The homeserver ignores the device ID of the request.
+    Mock::given(method("POST"))
+        .and(path("/_matrix/client/r0/login"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "user_id": "@example:localhost",
+            "access_token": "5678",
+            "device_id": "OTHER",
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    // The new session is logged out.
+    Mock::given(method("POST"))
+        .and(path("/_matrix/client/r0/logout"))
+        .and(header("authorization", "Bearer 5678"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    assert_matches!(
+        client.login_username("example", "wordpass").send().await,
+        Err(Error::SessionMismatch)
+    );
+
+    assert_eq!(client.session_state(), SessionState::SoftLoggedOut);
+    assert_eq!(client.access_token().as_deref(), Some("1234"));
+    assert_eq!(client.device_id(), Some(device_id!("DEVICEID")));
+}
+
 #[async_test]
 async fn register_error() {
     let (client, server) = no_retry_test_client().await;
@@ -246,6 +476,133 @@ async fn register_error() {
     }
 }
 
+#[derive(Debug)]
+struct RegistrationTokenHandler;
+
+#[matrix_sdk::async_trait]
+impl UiaaHandler for RegistrationTokenHandler {
+    async fn registration_token(&self, _info: &UiaaInfo) -> Option<String> {
+        Some("TOKEN".to_owned())
+    }
+}
+
+#[async_test]
+async fn register_username() {
+    let (client, server) = no_retry_test_client().await;
+
+    Mock::given(method("POST"))
+        .and(path("/_matrix/client/r0/register"))
+        .and(body_partial_json(json!({
+            "auth": {
+                "type": "m.login.registration_token",
+                "token": "TOKEN",
+                "session": "SESSION",
+            }
+        })))
+        .respond_with(ResponseTemplate::new(401).set_body_json(json!({
+            "flows": [{ "stages": ["m.login.registration_token", "m.login.dummy"] }],
+            "completed": ["m.login.registration_token"],
+            "params": {},
+            "session": "SESSION",
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("POST"))
+        .and(path("/_matrix/client/r0/register"))
+        .and(body_partial_json(json!({
+            "username": "user",
+            "password": "password",
+            "auth": {
+                "type": "m.login.dummy",
+                "session": "SESSION",
+            }
+        })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "user_id": "@user:localhost",
+            "access_token": "abcd",
+            "device_id": "NEWDEVICE",
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("POST"))
+        .and(path("/_matrix/client/r0/register"))
+        .respond_with(ResponseTemplate::new(401).set_body_json(json!({
+            "flows": [{ "stages": ["m.login.registration_token", "m.login.dummy"] }],
+            "params": {},
+            "session": "SESSION",
+        })))
+        .up_to_n_times(1)
+        .mount(&server)
+        .await;
+
+    let session = client
+        .register_username("user", "password")
+        .uiaa_handler(RegistrationTokenHandler)
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(session.user_id, user_id!("@user:localhost"));
+    assert_eq!(session.device_id, device_id!("NEWDEVICE"));
+    assert!(client.logged_in());
+    assert_eq!(client.user_id(), Some(user_id!("@user:localhost")));
+}
+
+#[async_test]
+async fn upgrade_guest() {
+    let (client, server) = logged_in_client().await;
+
+    Mock::given(method("POST"))
+        .and(path("/_matrix/client/r0/register"))
+        .and(header("authorization", "Bearer 1234"))
+        .and(body_partial_json(json!({
+            "username": "user",
+            "device_id": "DEVICEID",
+        })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "user_id": "@example:localhost",
+            "access_token": "abcd",
+            "device_id": "DEVICEID",
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    client.upgrade_guest("user", "password").send().await.unwrap();
+
+    assert_eq!(client.access_token().as_deref(), Some("abcd"));
+    assert_eq!(client.device_id(), Some(device_id!("DEVICEID")));
+}
+
+#[async_test]
+async fn username_availability() {
+    let (client, server) = no_retry_test_client().await;
+
+    Mock::given(method("GET"))
+        .and(path("/_matrix/client/r0/register/available"))
+        .and(query_param("username", "free"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "available": true })))
+        .mount(&server)
+        .await;
+
+    Mock::given(method("GET"))
+        .and(path("/_matrix/client/r0/register/available"))
+        .and(query_param("username", "taken"))
+        .respond_with(ResponseTemplate::new(400).set_body_json(json!({
+            "errcode": "M_USER_IN_USE",
+            "error": "Desired user ID is already taken.",
+        })))
+        .mount(&server)
+        .await;
+
+    assert!(client.is_username_available("free").await.unwrap());
+    assert!(!client.is_username_available("taken").await.unwrap());
+}
+
 #[async_test]
 async fn sync() {
     let (client, server) = logged_in_client().await;
@@ -340,6 +697,149 @@ async fn delete_devices() {
     }
 }
 
+#[derive(Debug)]
+struct PasswordHandler(Option<&'static str>);
+
+#[matrix_sdk::async_trait]
+impl UiaaHandler for PasswordHandler {
+    async fn password(&self, _info: &UiaaInfo) -> Option<String> {
+        self.0.map(ToOwned::to_owned)
+    }
+}
+
+/// Mount the mocks for a `POST /delete_devices` request that requires the
+/// `m.login.password` and `m.login.dummy` stages.
+async fn mock_delete_devices_uiaa(server: &MockServer) {
+    Mock::given(method("POST"))
+        .and(path("/_matrix/client/r0/delete_devices"))
+        .and(body_partial_json(json!({
+            "auth": {
+                "type": "m.login.password",
+                "identifier": {
+                    "type": "m.id.user",
+                    "user": "@example:localhost",
+                },
+                "password": "wordpass",
+                "session": "SESSION",
+            }
+        })))
+        .respond_with(ResponseTemplate::new(401).set_body_json(json!({
+            "flows": [{ "stages": ["m.login.password", "m.login.dummy"] }],
+            "completed": ["m.login.password"],
+            "params": {},
+            "session": "SESSION",
+        })))
+        .expect(1)
+        .mount(server)
+        .await;
+
+    Mock::given(method("POST"))
+        .and(path("/_matrix/client/r0/delete_devices"))
+        .and(body_partial_json(json!({
+            "auth": {
+                "type": "m.login.dummy",
+                "session": "SESSION",
+            }
+        })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+        .expect(1)
+        .mount(server)
+        .await;
+
+    Mock::given(method("POST"))
+        .and(path("/_matrix/client/r0/delete_devices"))
+        .respond_with(ResponseTemplate::new(401).set_body_json(json!({
+            "flows": [
+                { "stages": ["m.login.sso"] },
+                { "stages": ["m.login.password", "m.login.dummy"] },
+            ],
+            "params": {},
+            "session": "SESSION",
+        })))
+        .up_to_n_times(1)
+        .mount(server)
+        .await;
+}
+
+#[async_test]
+async fn delete_devices_with_uiaa() {
+    let (client, server) = logged_in_client().await;
+    mock_delete_devices_uiaa(&server).await;
+
+    let devices = &[device_id!("DEVICEID").to_owned()];
+
+    client
+        .uiaa(PasswordHandler(Some("wordpass")))
+        .send(|client, auth| async move { client.delete_devices(devices, auth.auth_data()).await })
+        .await
+        .unwrap();
+}
+
+#[async_test]
+async fn delete_devices_with_cancelled_uiaa() {
+    let (client, server) = logged_in_client().await;
+
+    Mock::given(method("POST"))
+        .and(path("/_matrix/client/r0/delete_devices"))
+        .respond_with(ResponseTemplate::new(401).set_body_json(json!({
+            "flows": [{ "stages": ["m.login.password"] }],
+            "params": {},
+            "session": "SESSION",
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let devices = &[device_id!("DEVICEID").to_owned()];
+
+    let result = client
+        .uiaa(PasswordHandler(None))
+        .send(|client, auth| async move { client.delete_devices(devices, auth.auth_data()).await })
+        .await;
+    assert_matches!(result, Err(Error::InteractiveAuth(InteractiveAuthError::Cancelled)));
+}
+
+#[async_test]
+async fn delete_devices_with_wrong_password() {
+    let (client, server) = logged_in_client().await;
+
+    Mock::given(method("POST"))
+        .and(path("/_matrix/client/r0/delete_devices"))
+        .and(body_partial_json(json!({ "auth": { "type": "m.login.password" } })))
+        .respond_with(ResponseTemplate::new(401).set_body_json(json!({
+            "errcode": "M_FORBIDDEN",
+            "error": "Invalid password",
+            "flows": [{ "stages": ["m.login.password"] }],
+            "params": {},
+            "session": "SESSION",
+        })))
+        .expect(3)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("POST"))
+        .and(path("/_matrix/client/r0/delete_devices"))
+        .respond_with(ResponseTemplate::new(401).set_body_json(json!({
+            "flows": [{ "stages": ["m.login.password"] }],
+            "params": {},
+            "session": "SESSION",
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let devices = &[device_id!("DEVICEID").to_owned()];
+
+    let result = client
+        .uiaa(PasswordHandler(Some("wrongpass")))
+        .send(|client, auth| async move { client.delete_devices(devices, auth.auth_data()).await })
+        .await;
+    assert_matches!(
+        result,
+        Err(Error::InteractiveAuth(InteractiveAuthError::TooManyAttempts(AuthType::Password)))
+    );
+}
+
 #[async_test]
 async fn resolve_room_alias() {
     let (client, server) = no_retry_test_client().await;
@@ -465,6 +965,50 @@ async fn room_search_filtered() {
     assert_eq!(chunk.len(), 1);
 }
 
+#[async_test]
+async fn public_rooms_stream() {
+    let (client, server) = logged_in_client().await;
+
+    let public_room = |room_id: &str| {
+        json!({
+            "room_id": room_id,
+            "num_joined_members": 3,
+            "world_readable": true,
+            "guest_can_join": false,
+        })
+    };
+
+    Mock::given(method("POST"))
+        .and(path("/_matrix/client/r0/publicRooms"))
+        .and(body_partial_json(json!({ "since": "page2" })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "chunk": [public_room("!b:localhost")],
+            "prev_batch": "page1",
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("POST"))
+        .and(path("/_matrix/client/r0/publicRooms"))
+        .and(body_partial_json(json!({ "filter": { "generic_search_term": "cheese" } })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "chunk": [public_room("!a:localhost")],
+            "next_batch": "page2",
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let rooms: Vec<_> = client
+        .public_rooms_stream(None, Some("cheese".to_owned()), Some(1))
+        .map(|room| room.unwrap().room_id)
+        .collect()
+        .await;
+
+    assert_eq!(rooms, [room_id!("!a:localhost").to_owned(), room_id!("!b:localhost").to_owned()]);
+}
+
 #[async_test]
 async fn invited_rooms() {
     let (client, server) = logged_in_client().await;
@@ -563,6 +1107,113 @@ async fn get_media_file() {
         .unwrap();
 }
 
+#[async_test]
+async fn stream_media() {
+    let (client, server) = logged_in_client().await;
+    let data = "Some very interesting text.";
+
+    Mock::given(method("POST"))
+        .and(path("/_matrix/media/r0/upload"))
+        .and(header("content-type", "text/plain"))
+        .and(header("content-length", "27"))
+        .and(body_string(data))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(json!({ "content_uri": "mxc://localhost/textfile" })),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let progress = Mutable::new(TransmissionProgress::default());
+    let response = client
+        .media()
+        .upload_stream(
+            &mime::TEXT_PLAIN,
+            Cursor::new(data.as_bytes()),
+            data.len() as u64,
+            Some(progress.clone()),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.content_uri, mxc_uri!("mxc://localhost/textfile"));
+    assert_eq!(progress.get(), TransmissionProgress { current: 27, total: Some(27) });
+
+    Mock::given(method("GET"))
+        .and(path("/_matrix/media/r0/download/localhost/textfile"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(data))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let request = MediaRequest {
+        source: MediaSource::Plain(response.content_uri),
+        format: MediaFormat::File,
+    };
+    let progress = Mutable::new(TransmissionProgress::default());
+    let mut content = Vec::new();
+
+    client.media().download_to(&request, &mut content, Some(progress.clone())).await.unwrap();
+
+    assert_eq!(content, data.as_bytes());
+    assert_eq!(progress.get(), TransmissionProgress { current: 27, total: Some(27) });
+}
+
+#[cfg(feature = "e2e-encryption")]
+#[async_test]
+async fn stream_encrypted_media() {
+    let (client, server) = logged_in_client().await;
+    let data = "Some very secret text.";
+    let uploaded = Arc::new(Mutex::new(Vec::new()));
+
+    Mock::given(method("POST"))
+        .and(path("/_matrix/media/r0/upload"))
+        .respond_with({
+            let uploaded = uploaded.clone();
+            move |request: &wiremock::Request| {
+                *uploaded.lock().unwrap() = request.body.clone();
+                ResponseTemplate::new(200)
+                    .set_body_json(json!({ "content_uri": "mxc://localhost/encrypted" }))
+            }
+        })
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let file = client
+        .media()
+        .upload_encrypted_stream(
+            &mime::TEXT_PLAIN,
+            Cursor::new(data.as_bytes()),
+            data.len() as u64,
+            None,
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(file.url, mxc_uri!("mxc://localhost/encrypted"));
+    assert_eq!(uploaded.lock().unwrap().len(), data.len());
+    assert_ne!(*uploaded.lock().unwrap(), data.as_bytes());
+
+    Mock::given(method("GET"))
+        .and(path("/_matrix/media/r0/download/localhost/encrypted"))
+        .respond_with(move |_: &wiremock::Request| {
+            ResponseTemplate::new(200).set_body_bytes(uploaded.lock().unwrap().clone())
+        })
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let request =
+        MediaRequest { source: MediaSource::Encrypted(Box::new(file)), format: MediaFormat::File };
+    let mut content = Vec::new();
+
+    client.media().download_to(&request, &mut content, None).await.unwrap();
+
+    assert_eq!(content, data.as_bytes());
+}
+
 #[async_test]
 async fn whoami() {
     let (client, server) = logged_in_client().await;
@@ -637,3 +1288,140 @@ fn serialize_session() {
         })
     );
 }
+
+#[async_test]
+async fn presence() {
+    let (client, server) = logged_in_client().await;
+
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/presence/.*/status$"))
+        .and(body_json(json!({ "presence": "unavailable", "status_msg": "Out for lunch" })))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    client.account().set_presence(PresenceState::Unavailable, Some("Out for lunch")).await.unwrap();
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/r0/presence/.*/status$"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(json!({ "presence": "online", "last_active_ago": 420845 })),
+        )
+        .mount(&server)
+        .await;
+
+    let response = client.get_presence(user_id!("@alice:localhost")).await.unwrap();
+    assert_eq!(response.presence, PresenceState::Online);
+
+    let mut presence_stream =
+        Box::pin(client.presence_stream(user_id!("@example:localhost")).await.unwrap());
+
+    Mock::given(method("GET"))
+        .and(path("/_matrix/client/r0/sync"))
+        .and(query_param("set_presence", "offline"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::SYNC))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    client.sync_once(SyncSettings::new().set_presence(PresenceState::Offline)).await.unwrap();
+
+    let event = presence_stream.next().await.unwrap();
+    assert_eq!(event.content.presence, PresenceState::Online);
+    assert_eq!(event.content.status_msg.as_deref(), Some("Making cupcakes"));
+}
+
+#[async_test]
+async fn presence_stream_ends_without_shared_room() {
+    let (client, server) = logged_in_client().await;
+
+    let mut member_presence =
+        Box::pin(client.presence_stream(user_id!("@example:localhost")).await.unwrap());
+    let mut stranger_presence =
+        Box::pin(client.presence_stream(user_id!("@stranger:localhost")).await.unwrap());
+
+    let mut ev_builder = EventBuilder::new();
+    ev_builder.add_joined_room(
+        JoinedRoomBuilder::new(room_id!("!room:localhost")).add_state_event(StateTestEvent::Member),
+    );
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    client.sync_once(SyncSettings::new()).await.unwrap();
+
+    assert!(member_presence.next().now_or_never().is_none());
+    assert!(stranger_presence.next().await.is_none());
+}
+
+#[async_test]
+async fn server_features() {
+    let server = MockServer::start().await;
+    let client = Client::builder()
+        .homeserver_url(server.uri())
+        .request_config(RequestConfig::new().disable_retry())
+        .build()
+        .await
+        .unwrap();
+    client
+        .restore_login(Session {
+            access_token: "1234".to_owned(),
+            refresh_token: None,
+            user_id: user_id!("@example:localhost").to_owned(),
+            device_id: device_id!("DEVICEID").to_owned(),
+        })
+        .await
+        .unwrap();
+
+    Mock::given(method("GET"))
+        .and(path("/_matrix/client/versions"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "versions": ["r0.6.1", "v1.1"],
+            "unstable_features": {
+                "org.matrix.msc2946.stable": true,
+                "org.matrix.msc3856": true,
+                "org.matrix.e2e_cross_signing": false,
+            },
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("GET"))
+        .and(path("/_matrix/client/v3/capabilities"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "capabilities": {
+                "m.change_password": { "enabled": false },
+                "m.room_versions": { "default": "9", "available": { "9": "stable" } },
+            },
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let features = client.server_features().await.unwrap();
+    assert!(features.supports(Feature::Version(MatrixVersion::V1_1)));
+    assert!(!features.supports(Feature::RefreshTokens));
+    assert!(features.supports(Feature::Threads));
+    assert!(!features.supports(Feature::ChangePassword));
+    assert!(!features.supports(Feature::Unstable("org.matrix.e2e_cross_signing".to_owned())));
+
+    // The features are cached.
+    client.server_features().await.unwrap();
+
+    // The stable endpoint is used, even if the version where it was stabilized
+    // is not advertised.
+    let room_id = room_id!("!test:localhost");
+    Mock::given(method("GET"))
+        .and(path("/_matrix/client/v1/rooms/%21test%3Alocalhost/hierarchy"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "rooms": [] })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    client.send(get_hierarchy::v1::Request::new(room_id), None).await.unwrap();
+}
@@ -10,8 +10,13 @@ use wiremock::{
 };
 
 mod client;
+mod notification_settings;
+#[cfg(feature = "experimental-oidc")]
+mod oidc;
 mod refresh_token;
 mod room;
+mod session_persistence;
+mod space;
 
 async fn test_client_builder() -> (ClientBuilder, MockServer) {
     let server = MockServer::start().await;
@@ -0,0 +1,140 @@
+use matrix_sdk::notification_settings::RoomNotificationMode;
+use matrix_sdk_test::async_test;
+use ruma::{api::client::push::RuleKind, room_id};
+use serde_json::json;
+use wiremock::{
+    matchers::{body_json, body_partial_json, header, method, path, path_regex},
+    Mock, ResponseTemplate,
+};
+
+use crate::logged_in_client;
+
+#[async_test]
+async fn room_notification_mode() {
+    let (client, server) = logged_in_client().await;
+    let settings = client.notification_settings();
+    let room_id = room_id!("!test:localhost");
+
+    assert_eq!(settings.room_notification_mode(room_id).await.unwrap(), None);
+
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/pushrules/global/override/.*"))
+        .and(body_partial_json(json!({
+            "actions": ["dont_notify"],
+            "conditions": [{
+                "kind": "event_match",
+                "key": "room_id",
+                "pattern": "!test:localhost",
+            }],
+        })))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    settings.set_room_notification_mode(room_id, Some(RoomNotificationMode::Mute)).await.unwrap();
+    assert_eq!(
+        settings.room_notification_mode(room_id).await.unwrap(),
+        Some(RoomNotificationMode::Mute)
+    );
+
+    Mock::given(method("DELETE"))
+        .and(path_regex(r"^/_matrix/client/r0/pushrules/global/override/.*"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/pushrules/global/room/.*"))
+        .and(body_partial_json(json!({ "actions": ["notify"] })))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    settings
+        .set_room_notification_mode(room_id, Some(RoomNotificationMode::AllMessages))
+        .await
+        .unwrap();
+    assert_eq!(
+        settings.room_notification_mode(room_id).await.unwrap(),
+        Some(RoomNotificationMode::AllMessages)
+    );
+}
+
+#[async_test]
+async fn keywords() {
+    let (client, server) = logged_in_client().await;
+    let settings = client.notification_settings();
+
+    Mock::given(method("PUT"))
+        .and(path("/_matrix/client/r0/pushrules/global/content/rust"))
+        .and(body_partial_json(json!({ "pattern": "rust" })))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("PUT"))
+        .and(path("/_matrix/client/r0/pushrules/global/content/matrix"))
+        .respond_with(ResponseTemplate::new(500).set_body_json(json!({
+            "errcode": "M_UNKNOWN",
+            "error": "Internal server error",
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    settings.add_keyword("rust").await.unwrap();
+    assert_eq!(settings.keywords().await.unwrap(), ["rust"]);
+
+    // The local push rules are restored when the request fails.
+    settings.add_keyword("matrix").await.unwrap_err();
+    assert_eq!(settings.keywords().await.unwrap(), ["rust"]);
+
+    Mock::given(method("DELETE"))
+        .and(path("/_matrix/client/r0/pushrules/global/content/rust"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    settings.remove_keyword("rust").await.unwrap();
+    assert!(settings.keywords().await.unwrap().is_empty());
+}
+
+#[async_test]
+async fn disable_default_rule() {
+    let (client, server) = logged_in_client().await;
+    let settings = client.notification_settings();
+
+    Mock::given(method("PUT"))
+        .and(path(
+            "/_matrix/client/r0/pushrules/global/override/%2Em%2Erule%2Econtains%5Fdisplay%5Fname/enabled",
+        ))
+        .and(body_json(json!({ "enabled": false })))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    settings
+        .set_rule_enabled(RuleKind::Override, ".m.rule.contains_display_name", false)
+        .await
+        .unwrap();
+
+    let ruleset = settings.ruleset().await.unwrap();
+    let rule = ruleset
+        .override_
+        .iter()
+        .find(|rule| rule.rule_id == ".m.rule.contains_display_name")
+        .unwrap();
+    assert!(!rule.enabled);
+}
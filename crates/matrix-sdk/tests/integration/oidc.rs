@@ -0,0 +1,204 @@
+use matches::assert_matches;
+use matrix_sdk::{
+    config::RequestConfig,
+    oidc::{ApplicationType, ClientMetadata},
+    Client, HttpError, OidcError, RefreshTokenError, Session,
+};
+use matrix_sdk_test::async_test;
+use ruma::{api::MatrixVersion, device_id, user_id};
+use serde_json::json;
+use url::Url;
+use wiremock::{
+    matchers::{body_partial_json, body_string_contains, header, method, path},
+    Mock, MockServer, ResponseTemplate,
+};
+
+/// Create a client whose issuer is stubbed by the returned mock server, and
+/// register it with the issuer.
+async fn registered_client() -> (Client, MockServer, Url) {
+    let server = MockServer::start().await;
+    let issuer = Url::parse(&format!("{}/issuer/", server.uri())).unwrap();
+    let client = Client::builder()
+        .homeserver_url(server.uri())
+        .server_versions([MatrixVersion::V1_0])
+        .request_config(RequestConfig::new().disable_retry())
+        .authentication_issuer(issuer.clone())
+        .build()
+        .await
+        .unwrap();
+
+    Mock::given(method("GET"))
+        .and(path("/issuer/.well-known/openid-configuration"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "issuer": issuer,
+            "authorization_endpoint": issuer.join("authorize").unwrap(),
+            "token_endpoint": issuer.join("token").unwrap(),
+            "registration_endpoint": issuer.join("register").unwrap(),
+            "revocation_endpoint": issuer.join("revoke").unwrap(),
+            "account_management_uri": issuer.join("account").unwrap(),
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("POST"))
+        .and(path("/issuer/register"))
+        .and(body_partial_json(json!({
+            "application_type": "native",
+            "redirect_uris": ["com.example.app:/callback"],
+            "token_endpoint_auth_method": "none",
+        })))
+        .respond_with(ResponseTemplate::new(201).set_body_json(json!({
+            "client_id": "01CLIENT",
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let redirect_uri = Url::parse("com.example.app:/callback").unwrap();
+    let metadata = ClientMetadata::new(ApplicationType::Native, vec![redirect_uri.clone()]);
+    let client_id = client.oidc().register_client(&metadata).await.unwrap();
+    assert_eq!(client_id, "01CLIENT");
+
+    (client, server, redirect_uri)
+}
+
+#[async_test]
+async fn login_refresh_logout() {
+    let (client, server, redirect_uri) = registered_client().await;
+    let oidc = client.oidc();
+
+    assert_eq!(
+        oidc.account_management_url().await.unwrap().unwrap().as_str(),
+        format!("{}/issuer/account", server.uri())
+    );
+
+    let authorization_data = oidc.url_for_login(&redirect_uri).await.unwrap();
+    let params: Vec<_> = authorization_data.url.query_pairs().into_owned().collect();
+    let param = |name: &str| {
+        params.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str()).unwrap()
+    };
+    assert_eq!(authorization_data.url.path(), "/issuer/authorize");
+    assert_eq!(param("client_id"), "01CLIENT");
+    assert_eq!(param("redirect_uri"), "com.example.app:/callback");
+    assert_eq!(param("state"), authorization_data.state);
+    assert_eq!(param("code_challenge_method"), "S256");
+    assert!(param("scope").contains("urn:matrix:org.matrix.msc2967.client:api:*"));
+
+    Mock::given(method("POST"))
+        .and(path("/issuer/token"))
+        .and(body_string_contains("grant_type=authorization_code"))
+        .and(body_string_contains("code=AUTHCODE"))
+        .and(body_string_contains("code_verifier="))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "access_token": "1234",
+            "refresh_token": "REFRESH1",
+            "token_type": "Bearer",
+            "expires_in": 300,
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("GET"))
+        .and(path("/_matrix/client/r0/account/whoami"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "user_id": "@example:localhost",
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let mut callback_url = redirect_uri.clone();
+    callback_url
+        .query_pairs_mut()
+        .append_pair("state", &authorization_data.state)
+        .append_pair("code", "AUTHCODE");
+    oidc.finish_login(&callback_url).await.unwrap();
+
+    assert!(client.logged_in());
+    assert!(oidc.logged_in());
+    assert_eq!(client.user_id(), Some(user_id!("@example:localhost")));
+    assert_eq!(client.refresh_token().as_deref(), Some("REFRESH1"));
+
+    // The same authorization can't be used twice.
+    assert_matches!(oidc.finish_login(&callback_url).await, Err(OidcError::InvalidState));
+
+    Mock::given(method("POST"))
+        .and(path("/issuer/token"))
+        .and(body_string_contains("grant_type=refresh_token"))
+        .and(body_string_contains("refresh_token=REFRESH1"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "access_token": "5678",
+            "refresh_token": "REFRESH2",
+            "token_type": "Bearer",
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    client.refresh_access_token().await.unwrap().unwrap();
+    assert_eq!(client.access_token().as_deref(), Some("5678"));
+    assert_eq!(client.refresh_token().as_deref(), Some("REFRESH2"));
+
+    Mock::given(method("POST"))
+        .and(path("/issuer/revoke"))
+        .and(body_string_contains("client_id=01CLIENT"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&server)
+        .await;
+
+    oidc.logout().await.unwrap();
+}
+
+#[async_test]
+async fn password_session_refresh() {
+    let (client, server, _redirect_uri) = registered_client().await;
+
+    client
+        .restore_login(Session {
+            access_token: "1234".to_owned(),
+            refresh_token: Some("REFRESH1".to_owned()),
+            user_id: user_id!("@example:localhost").to_owned(),
+            device_id: device_id!("DEVICEID").to_owned(),
+        })
+        .await
+        .unwrap();
+    assert!(!client.oidc().logged_in());
+
+    Mock::given(method("POST"))
+        .and(path("/issuer/token"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&server)
+        .await;
+
+    // The session wasn't created with OpenID Connect, so the token is
+    // refreshed with the homeserver, which doesn't support it.
+    assert_matches!(
+        client.refresh_access_token().await,
+        Err(HttpError::RefreshToken(RefreshTokenError::NotSupported))
+    );
+}
+
+#[async_test]
+async fn login_callback_error() {
+    let (client, _server, redirect_uri) = registered_client().await;
+    let oidc = client.oidc();
+
+    let authorization_data = oidc.url_for_login(&redirect_uri).await.unwrap();
+
+    let mut callback_url = redirect_uri.clone();
+    callback_url
+        .query_pairs_mut()
+        .append_pair("state", &authorization_data.state)
+        .append_pair("error", "access_denied");
+
+    assert_matches!(
+        oidc.finish_login(&callback_url).await,
+        Err(OidcError::Issuer { error, .. }) if error == "access_denied"
+    );
+    assert!(!client.logged_in());
+}
@@ -7,7 +7,8 @@ use futures::{
 use futures_signals::signal::SignalExt;
 use matches::assert_matches;
 use matrix_sdk::{
-    config::RequestConfig, executor::spawn, HttpError, RefreshTokenError, RumaApiError, Session,
+    config::RequestConfig, executor::spawn, Client, HttpError, RefreshTokenError, RumaApiError,
+    Session,
 };
 use matrix_sdk_test::{async_test, test_json};
 use ruma::{
@@ -20,8 +21,8 @@ use ruma::{
 };
 use serde_json::json;
 use wiremock::{
-    matchers::{body_partial_json, header, method, path},
-    Mock, ResponseTemplate,
+    matchers::{body_partial_json, header, method, path, path_regex},
+    Mock, MockServer, ResponseTemplate,
 };
 
 use crate::{logged_in_client, no_retry_test_client, test_client_builder};
@@ -195,6 +196,71 @@ async fn refresh_token() {
     assert_eq!(tokens.refresh_token.as_deref(), Some("wxyz"));
 }
 
+#[async_test]
+async fn refresh_token_unsupported() {
+    let (client, server) = no_retry_test_client().await;
+
+    let session = Session {
+        access_token: "1234".to_owned(),
+        refresh_token: Some("abcd".to_owned()),
+        user_id: user_id!("@example:localhost").to_owned(),
+        device_id: device_id!("DEVICEID").to_owned(),
+    };
+    client.restore_login(session).await.unwrap();
+
+    Mock::given(method("POST"))
+        .and(path_regex(r"^/_matrix/client/.*/refresh$"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::REFRESH_TOKEN))
+        .expect(0)
+        .mount(&server)
+        .await;
+
+    let res = client.refresh_access_token().await;
+    assert_matches!(res, Err(HttpError::RefreshToken(RefreshTokenError::NotSupported)));
+    assert_eq!(client.access_token().as_deref(), Some("1234"));
+}
+
+#[async_test]
+async fn refresh_token_unstable() {
+    let server = MockServer::start().await;
+    let client = Client::builder()
+        .homeserver_url(server.uri())
+        .request_config(RequestConfig::new().disable_retry())
+        .build()
+        .await
+        .unwrap();
+
+    let session = Session {
+        access_token: "1234".to_owned(),
+        refresh_token: Some("abcd".to_owned()),
+        user_id: user_id!("@example:localhost").to_owned(),
+        device_id: device_id!("DEVICEID").to_owned(),
+    };
+    client.restore_login(session).await.unwrap();
+
+    Mock::given(method("GET"))
+        .and(path("/_matrix/client/versions"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "versions": ["r0.6.1"],
+            "unstable_features": { "org.matrix.msc2918": true },
+        })))
+        .mount(&server)
+        .await;
+
+    Mock::given(method("POST"))
+        .and(path("/_matrix/client/unstable/org.matrix.msc2918/refresh"))
+        .and(body_partial_json(json!({
+            "refresh_token": "abcd",
+        })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::REFRESH_TOKEN))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    client.refresh_access_token().await.unwrap().unwrap();
+    assert_eq!(client.access_token().as_deref(), Some("5678"));
+}
+
 #[async_test]
 async fn refresh_token_not_handled() {
     let (builder, server) = test_client_builder().await;
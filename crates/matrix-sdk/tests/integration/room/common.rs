@@ -1,21 +1,27 @@
 use std::time::Duration;
 
-use matrix_sdk::{config::SyncSettings, room::RoomMember, DisplayName};
+use matrix_sdk::{
+    config::{RequestConfig, SyncSettings},
+    deserialized_responses::SyncTimelineEvent,
+    room::{RoomMember, ThreadsOptions},
+    Client, DisplayName, Error, Feature, Session,
+};
 use matrix_sdk_test::{
-    async_test, bulk_room_members, test_json, EventBuilder, JoinedRoomBuilder, TimelineTestEvent,
+    async_test, bulk_room_members, test_json, EventBuilder, JoinedRoomBuilder, LeftRoomBuilder,
+    StateTestEvent, TimelineTestEvent,
 };
 use ruma::{
-    event_id,
+    device_id, event_id,
     events::{room::member::MembershipState, AnySyncStateEvent, StateEventType},
-    room_id,
+    room_id, uint, user_id,
 };
 use serde_json::json;
 use wiremock::{
-    matchers::{header, method, path_regex},
-    Mock, ResponseTemplate,
+    matchers::{header, method, path, path_regex, query_param},
+    Mock, MockServer, ResponseTemplate,
 };
 
-use crate::{logged_in_client, mock_sync};
+use crate::{logged_in_client, mock_sync, test_client_builder};
 
 #[async_test]
 async fn user_presence() {
@@ -692,3 +698,273 @@ async fn room_event_permalink() {
         "matrix:roomid/test_room:127.0.0.1/e/15139375512JaHAW?via=notarealhs&via=localhost"
     );
 }
+
+#[async_test]
+async fn room_threads() {
+    let server = MockServer::start().await;
+    let client = Client::builder()
+        .homeserver_url(server.uri())
+        .request_config(RequestConfig::new().disable_retry())
+        .build()
+        .await
+        .unwrap();
+    client
+        .restore_login(Session {
+            access_token: "1234".to_owned(),
+            refresh_token: None,
+            user_id: user_id!("@example:localhost").to_owned(),
+            device_id: device_id!("DEVICEID").to_owned(),
+        })
+        .await
+        .unwrap();
+
+    Mock::given(method("GET"))
+        .and(path("/_matrix/client/versions"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "versions": ["r0.6.1"],
+            "unstable_features": { "org.matrix.msc3856": true },
+        })))
+        .mount(&server)
+        .await;
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/.*/rooms/.*/threads$"))
+        .and(query_param("from", "page1"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "chunk": [{
+                "content": { "body": "Thread root", "msgtype": "m.text" },
+                "event_id": "$root:localhost",
+                "origin_server_ts": 152037280,
+                "room_id": "!SVkFJHzfwvuaIEawgC:localhost",
+                "sender": "@example:localhost",
+                "type": "m.room.message",
+                "unsigned": {
+                    "m.relations": {
+                        "m.thread": {
+                            "latest_event": {
+                                "content": {
+                                    "body": "Thread reply",
+                                    "msgtype": "m.text",
+                                    "m.relates_to": {
+                                        "rel_type": "m.thread",
+                                        "event_id": "$root:localhost",
+                                    },
+                                },
+                                "event_id": "$reply:localhost",
+                                "origin_server_ts": 152037290,
+                                "room_id": "!SVkFJHzfwvuaIEawgC:localhost",
+                                "sender": "@example:localhost",
+                                "type": "m.room.message",
+                            },
+                            "count": 2,
+                            "current_user_participated": true,
+                        },
+                    },
+                },
+            }],
+            "next_batch": "page2",
+        })))
+        .mount(&server)
+        .await;
+
+    mock_sync(&server, &*test_json::SYNC, None).await;
+    client.sync_once(SyncSettings::new()).await.unwrap();
+
+    let room = client.get_joined_room(&test_json::DEFAULT_SYNC_ROOM_ID).unwrap();
+    let threads = room.threads(ThreadsOptions::default().from("page1")).await.unwrap();
+
+    assert_eq!(threads.chunk.len(), 1);
+    assert_eq!(threads.next_batch.as_deref(), Some("page2"));
+
+    let root = SyncTimelineEvent::from(threads.chunk.into_iter().next().unwrap());
+    assert_eq!(root.event_id().as_deref(), Some(event_id!("$root:localhost")));
+    assert!(root.thread_root().is_none());
+
+    let summary = root.thread_summary().unwrap();
+    assert_eq!(summary.count, uint!(2));
+    assert!(summary.current_user_participated);
+}
+
+#[async_test]
+async fn room_threads_unsupported() {
+    let (client, server) = logged_in_client().await;
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/.*/rooms/.*/threads$"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "chunk": [] })))
+        .expect(0)
+        .mount(&server)
+        .await;
+
+    mock_sync(&server, &*test_json::SYNC, None).await;
+    client.sync_once(SyncSettings::new()).await.unwrap();
+
+    let room = client.get_joined_room(&test_json::DEFAULT_SYNC_ROOM_ID).unwrap();
+    let res = room.threads(ThreadsOptions::default()).await;
+    matches::assert_matches!(res, Err(Error::UnsupportedFeature(Feature::Threads)));
+}
+
+fn upgraded_rooms_sync(ev_builder: &mut EventBuilder) {
+    ev_builder.add_joined_room(
+        JoinedRoomBuilder::new(room_id!("!old:localhost")).add_timeline_event(
+            TimelineTestEvent::Custom(json!({
+                "content": {
+                    "body": "This room has been replaced",
+                    "replacement_room": "!new:localhost",
+                },
+                "event_id": "$tombstone:localhost",
+                "origin_server_ts": 152039280,
+                "sender": "@example:localhost",
+                "state_key": "",
+                "type": "m.room.tombstone",
+            })),
+        ),
+    );
+}
+
+#[async_test]
+async fn room_successors_and_predecessors() {
+    let (client, server) = logged_in_client().await;
+    let mut ev_builder = EventBuilder::new();
+
+    upgraded_rooms_sync(&mut ev_builder);
+    ev_builder.add_joined_room(
+        JoinedRoomBuilder::new(room_id!("!new:localhost")).add_timeline_event(
+            TimelineTestEvent::Custom(json!({
+                "content": {
+                    "creator": "@example:localhost",
+                    "predecessor": {
+                        "event_id": "$tombstone:localhost",
+                        "room_id": "!old:localhost",
+                    },
+                    "room_version": "9",
+                },
+                "event_id": "$create:localhost",
+                "origin_server_ts": 152039290,
+                "sender": "@example:localhost",
+                "state_key": "",
+                "type": "m.room.create",
+            })),
+        ),
+    );
+
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    client.sync_once(SyncSettings::new()).await.unwrap();
+
+    let old_room = client.get_room(room_id!("!old:localhost")).unwrap();
+    let new_room = client.get_room(room_id!("!new:localhost")).unwrap();
+
+    assert_eq!(old_room.successor().as_deref(), Some(room_id!("!new:localhost")));
+    assert_eq!(old_room.successors(), [room_id!("!new:localhost").to_owned()]);
+    assert!(old_room.predecessors().is_empty());
+
+    assert_eq!(
+        new_room.predecessor().map(|p| p.room_id).as_deref(),
+        Some(room_id!("!old:localhost"))
+    );
+    assert_eq!(new_room.predecessors(), [room_id!("!old:localhost").to_owned()]);
+    assert!(new_room.successors().is_empty());
+
+    // The successor is already joined.
+    assert_eq!(old_room.join_successor().await.unwrap(), None);
+}
+
+#[async_test]
+async fn auto_join_room_successor() {
+    let (builder, server) = test_client_builder().await;
+    let client = builder
+        .request_config(RequestConfig::new().disable_retry())
+        .auto_join_room_successors()
+        .build()
+        .await
+        .unwrap();
+    client
+        .restore_login(Session {
+            access_token: "1234".to_owned(),
+            refresh_token: None,
+            user_id: user_id!("@example:localhost").to_owned(),
+            device_id: device_id!("DEVICEID").to_owned(),
+        })
+        .await
+        .unwrap();
+
+    Mock::given(method("POST"))
+        .and(path_regex(r"^/_matrix/client/r0/join/"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(json!({ "room_id": "!new:localhost" })),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let mut ev_builder = EventBuilder::new();
+    upgraded_rooms_sync(&mut ev_builder);
+
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    client.sync_once(SyncSettings::new()).await.unwrap();
+
+    // The successor is joined in the background.
+    for _ in 0..100 {
+        let requests = server.received_requests().await.unwrap();
+        if requests.iter().any(|request| request.url.path().contains("/join/")) {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+}
+
+#[async_test]
+async fn auto_join_room_successor_left_in_initial_sync() {
+    let (builder, server) = test_client_builder().await;
+    let client = builder
+        .request_config(RequestConfig::new().disable_retry())
+        .auto_join_room_successors()
+        .build()
+        .await
+        .unwrap();
+    client
+        .restore_login(Session {
+            access_token: "1234".to_owned(),
+            refresh_token: None,
+            user_id: user_id!("@example:localhost").to_owned(),
+            device_id: device_id!("DEVICEID").to_owned(),
+        })
+        .await
+        .unwrap();
+
+    Mock::given(method("POST"))
+        .and(path_regex(r"^/_matrix/client/r0/join/"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(json!({ "room_id": "!new:localhost" })),
+        )
+        .expect(0)
+        .mount(&server)
+        .await;
+
+    // The tombstone is part of the state of the old room and the user already
+    // left the new room.
+    let mut ev_builder = EventBuilder::new();
+    ev_builder
+        .add_joined_room(JoinedRoomBuilder::new(room_id!("!old:localhost")).add_state_event(
+            StateTestEvent::Custom(json!({
+                "content": {
+                    "body": "This room has been replaced",
+                    "replacement_room": "!new:localhost",
+                },
+                "event_id": "$tombstone:localhost",
+                "origin_server_ts": 152039280,
+                "sender": "@example:localhost",
+                "state_key": "",
+                "type": "m.room.tombstone",
+            })),
+        ))
+        .add_left_room(LeftRoomBuilder::new(room_id!("!new:localhost")));
+
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    client.sync_once(SyncSettings::new()).await.unwrap();
+
+    assert!(client.get_left_room(room_id!("!new:localhost")).is_some());
+    tokio::time::sleep(Duration::from_millis(100)).await;
+}
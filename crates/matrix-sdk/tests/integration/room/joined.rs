@@ -1,17 +1,26 @@
 use std::time::Duration;
 
+use futures::StreamExt;
+use futures_signals::signal::SignalExt;
+use matches::assert_matches;
 use matrix_sdk::{
     attachment::{
         AttachmentConfig, AttachmentInfo, BaseImageInfo, BaseThumbnailInfo, BaseVideoInfo,
-        Thumbnail,
+        GeneratedThumbnail, Thumbnail, Thumbnailer,
     },
     config::SyncSettings,
+    room::{LocalEchoState, PowerLevelAction},
+    Error, PowerLevelsError, RelationError, RoomAliasError, ThumbnailError,
+};
+use matrix_sdk_test::{
+    async_test, test_json, EphemeralTestEvent, EventBuilder, JoinedRoomBuilder, StateTestEvent,
+    TimelineTestEvent,
 };
-use matrix_sdk_test::{async_test, test_json};
 use ruma::{
-    api::client::membership::Invite3pidInit, assign, event_id,
-    events::room::message::RoomMessageEventContent, mxc_uri, thirdparty, uint, user_id,
-    TransactionId,
+    api::client::{membership::Invite3pidInit, room::Visibility},
+    assign, event_id,
+    events::room::message::RoomMessageEventContent,
+    int, mxc_uri, room_alias_id, room_id, thirdparty, uint, user_id, RoomVersionId, TransactionId,
 };
 use serde_json::json;
 use wiremock::{
@@ -212,6 +221,60 @@ async fn typing_notice() {
     room.typing_notice(true).await.unwrap();
 }
 
+#[async_test]
+async fn typing_users_and_read_receipts() {
+    let (client, server) = logged_in_client().await;
+    let room_id = room_id!("!test:localhost");
+    let mut ev_builder = EventBuilder::new();
+
+    ev_builder.add_joined_room(
+        JoinedRoomBuilder::new(room_id)
+            .add_state_event(StateTestEvent::Custom(json!({
+                "content": { "membership": "join" },
+                "event_id": "$member:localhost",
+                "origin_server_ts": 152037280,
+                "sender": "@bob:localhost",
+                "state_key": "@bob:localhost",
+                "type": "m.room.member",
+            })))
+            .add_ephemeral_event(EphemeralTestEvent::Custom(json!({
+                "content": { "$first:localhost": { "m.read": { "@bob:localhost": { "ts": 1 } } } },
+                "type": "m.receipt",
+            }))),
+    );
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    client.sync_once(SyncSettings::new()).await.unwrap();
+
+    let room = client.get_joined_room(room_id).unwrap();
+    let mut typing = room.typing_users_signal().await.unwrap().to_stream();
+    let mut receipts = room.read_receipts_signal().await.unwrap().to_stream();
+
+    assert!(typing.next().await.unwrap().is_empty());
+    // Receipts received before the room was observed are loaded from the store.
+    let initial = receipts.next().await.unwrap();
+    assert_eq!(initial[user_id!("@bob:localhost")].event_id, event_id!("$first:localhost"));
+
+    ev_builder.add_joined_room(
+        JoinedRoomBuilder::new(room_id)
+            .add_ephemeral_event(EphemeralTestEvent::Custom(json!({
+                "content": { "user_ids": ["@bob:localhost", "@example:localhost"] },
+                "type": "m.typing",
+            })))
+            .add_ephemeral_event(EphemeralTestEvent::Custom(json!({
+                "content": { "$second:localhost": { "m.read": { "@bob:localhost": { "ts": 2 } } } },
+                "type": "m.receipt",
+            }))),
+    );
+    let sync_token = client.sync_token().await.unwrap();
+    mock_sync(&server, ev_builder.build_json_sync_response(), Some(sync_token.clone())).await;
+    client.sync_once(SyncSettings::new().token(sync_token)).await.unwrap();
+
+    // The current user is not part of the typing users.
+    assert_eq!(typing.next().await.unwrap(), [user_id!("@bob:localhost").to_owned()]);
+    let updated = receipts.next().await.unwrap();
+    assert_eq!(updated[user_id!("@bob:localhost")].event_id, event_id!("$second:localhost"));
+}
+
 #[async_test]
 async fn room_state_event_send() {
     use ruma::events::room::member::{MembershipState, RoomMemberEventContent};
@@ -270,6 +333,377 @@ async fn room_message_send() {
     assert_eq!(event_id!("$h29iv0s8:example.com"), response.event_id)
 }
 
+#[async_test]
+async fn room_thread_reply_send() {
+    let (client, server) = logged_in_client().await;
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/.*/rooms/.*/relations/.*%2Ethread$"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "chunk": [{
+                "content": {
+                    "body": "Thread reply",
+                    "msgtype": "m.text",
+                    "m.relates_to": {
+                        "rel_type": "m.thread",
+                        "event_id": "$root:localhost",
+                    },
+                },
+                "event_id": "$latest:localhost",
+                "origin_server_ts": 152037290,
+                "room_id": "!SVkFJHzfwvuaIEawgC:localhost",
+                "sender": "@example:localhost",
+                "type": "m.room.message",
+            }],
+        })))
+        .mount(&server)
+        .await;
+
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/send/m%2Eroom%2Emessage/.*"))
+        .and(body_partial_json(json!({
+            "m.relates_to": {
+                "rel_type": "io.element.thread",
+                "event_id": "$root:localhost",
+                "io.element.show_reply": true,
+                "m.in_reply_to": { "event_id": "$latest:localhost" },
+            },
+        })))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EVENT_ID))
+        .mount(&server)
+        .await;
+
+    mock_sync(&server, &*test_json::SYNC, None).await;
+    client.sync_once(SyncSettings::new()).await.unwrap();
+
+    let room = client.get_joined_room(&test_json::DEFAULT_SYNC_ROOM_ID).unwrap();
+
+    let content = RoomMessageEventContent::text_plain("Hello thread");
+    let response =
+        room.send_thread_reply(content, event_id!("$root:localhost"), None, None).await.unwrap();
+
+    assert_eq!(event_id!("$h29iv0s8:example.com"), response.event_id)
+}
+
+fn mock_original_message(sender: &str) -> Mock {
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/event/"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "content": { "body": "Original message", "msgtype": "m.text" },
+            "event_id": "$original:localhost",
+            "origin_server_ts": 152037280,
+            "room_id": "!SVkFJHzfwvuaIEawgC:localhost",
+            "sender": sender,
+            "type": "m.room.message",
+        })))
+}
+
+#[async_test]
+async fn room_reply_send() {
+    let (client, server) = logged_in_client().await;
+
+    mock_original_message("@alice:localhost").mount(&server).await;
+
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/send/m%2Eroom%2Emessage/.*"))
+        .and(body_partial_json(json!({
+            "m.relates_to": { "m.in_reply_to": { "event_id": "$original:localhost" } },
+        })))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EVENT_ID))
+        .mount(&server)
+        .await;
+
+    mock_sync(&server, &*test_json::SYNC, None).await;
+    client.sync_once(SyncSettings::new()).await.unwrap();
+
+    let room = client.get_joined_room(&test_json::DEFAULT_SYNC_ROOM_ID).unwrap();
+
+    let content = RoomMessageEventContent::text_plain("Reply");
+    let response = room.reply_to(content, event_id!("$original:localhost"), None).await.unwrap();
+
+    assert_eq!(event_id!("$h29iv0s8:example.com"), response.event_id)
+}
+
+#[async_test]
+async fn room_edit_send() {
+    let (client, server) = logged_in_client().await;
+
+    mock_original_message("@example:localhost").mount(&server).await;
+
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/send/m%2Eroom%2Emessage/.*"))
+        .and(body_partial_json(json!({
+            "body": "* Edited message",
+            "m.new_content": { "body": "Edited message", "msgtype": "m.text" },
+            "m.relates_to": { "rel_type": "m.replace", "event_id": "$original:localhost" },
+        })))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EVENT_ID))
+        .mount(&server)
+        .await;
+
+    mock_sync(&server, &*test_json::SYNC, None).await;
+    client.sync_once(SyncSettings::new()).await.unwrap();
+
+    let room = client.get_joined_room(&test_json::DEFAULT_SYNC_ROOM_ID).unwrap();
+
+    let content = RoomMessageEventContent::text_plain("Edited message");
+    let response = room.edit(event_id!("$original:localhost"), content, None).await.unwrap();
+
+    assert_eq!(event_id!("$h29iv0s8:example.com"), response.event_id)
+}
+
+#[async_test]
+async fn room_edit_other_user() {
+    let (client, server) = logged_in_client().await;
+
+    mock_original_message("@alice:localhost").mount(&server).await;
+    mock_sync(&server, &*test_json::SYNC, None).await;
+    client.sync_once(SyncSettings::new()).await.unwrap();
+
+    let room = client.get_joined_room(&test_json::DEFAULT_SYNC_ROOM_ID).unwrap();
+
+    let content = RoomMessageEventContent::text_plain("Edited message");
+    let error = room.edit(event_id!("$original:localhost"), content, None).await.unwrap_err();
+
+    assert_matches!(error, Error::Relation(RelationError::NotOwnEvent));
+}
+
+#[async_test]
+async fn room_react_and_unreact() {
+    let (client, server) = logged_in_client().await;
+
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/send/m%2Ereaction/.*"))
+        .and(body_partial_json(json!({
+            "m.relates_to": {
+                "rel_type": "m.annotation",
+                "event_id": "$original:localhost",
+                "key": "👍",
+            },
+        })))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EVENT_ID))
+        .mount(&server)
+        .await;
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/.*/rooms/.*/relations/.*/m%2Eannotation$"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "chunk": [
+                {
+                    "content": {
+                        "m.relates_to": {
+                            "rel_type": "m.annotation",
+                            "event_id": "$original:localhost",
+                            "key": "👍",
+                        },
+                    },
+                    "event_id": "$own_reaction:localhost",
+                    "origin_server_ts": 152037290,
+                    "room_id": "!SVkFJHzfwvuaIEawgC:localhost",
+                    "sender": "@example:localhost",
+                    "type": "m.reaction",
+                },
+                {
+                    "content": {
+                        "m.relates_to": {
+                            "rel_type": "m.annotation",
+                            "event_id": "$original:localhost",
+                            "key": "👍",
+                        },
+                    },
+                    "event_id": "$other_reaction:localhost",
+                    "origin_server_ts": 152037291,
+                    "room_id": "!SVkFJHzfwvuaIEawgC:localhost",
+                    "sender": "@alice:localhost",
+                    "type": "m.reaction",
+                },
+            ],
+        })))
+        .mount(&server)
+        .await;
+
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/redact/%24own%5Freaction.*"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EVENT_ID))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    mock_sync(&server, &*test_json::SYNC, None).await;
+    client.sync_once(SyncSettings::new()).await.unwrap();
+
+    let room = client.get_joined_room(&test_json::DEFAULT_SYNC_ROOM_ID).unwrap();
+    let original = event_id!("$original:localhost");
+
+    room.react(original, "👍").await.unwrap();
+
+    assert!(room.unreact(original, "👍").await.unwrap());
+    assert!(!room.unreact(original, "🎉").await.unwrap());
+}
+
+#[async_test]
+async fn room_upgrade() {
+    let (client, server) = logged_in_client().await;
+
+    Mock::given(method("POST"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/upgrade$"))
+        .and(body_partial_json(json!({ "new_version": "9" })))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(json!({ "replacement_room": "!new:localhost" })),
+        )
+        .mount(&server)
+        .await;
+
+    mock_sync(&server, &*test_json::SYNC, None).await;
+    client.sync_once(SyncSettings::new()).await.unwrap();
+
+    let room = client.get_joined_room(&test_json::DEFAULT_SYNC_ROOM_ID).unwrap();
+    let new_room_id = room.upgrade(RoomVersionId::V9).await.unwrap();
+
+    assert_eq!(new_room_id, room_id!("!new:localhost"));
+}
+
+#[async_test]
+async fn room_send_queue() {
+    let (client, server) = logged_in_client().await;
+
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/send/.*"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EVENT_ID))
+        .expect(2)
+        .mount(&server)
+        .await;
+
+    mock_sync(&server, &*test_json::SYNC, None).await;
+
+    let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+
+    let _response = client.sync_once(sync_settings).await.unwrap();
+
+    let room = client.get_joined_room(&test_json::DEFAULT_SYNC_ROOM_ID).unwrap();
+    let queue = room.send_queue();
+
+    let first = queue.push(RoomMessageEventContent::text_plain("Hello")).await.unwrap();
+    let second = queue.push(RoomMessageEventContent::text_plain("world")).await.unwrap();
+    queue.retry().await.unwrap();
+
+    assert!(queue.is_empty().await.unwrap());
+
+    let echoes = queue.local_echoes();
+    assert_eq!(echoes.len(), 2);
+    assert_eq!(echoes[0].txn_id, first);
+    assert_eq!(echoes[1].txn_id, second);
+
+    for echo in echoes {
+        assert_matches!(
+            echo.state,
+            LocalEchoState::Sent { event_id } if event_id == event_id!("$h29iv0s8:example.com")
+        );
+    }
+}
+
+#[async_test]
+async fn room_send_queue_failed() {
+    let (client, server) = logged_in_client().await;
+
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/send/.*"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(403).set_body_json(json!({
+            "errcode": "M_FORBIDDEN",
+            "error": "You are not allowed to send messages in this room",
+        })))
+        .mount(&server)
+        .await;
+
+    mock_sync(&server, &*test_json::SYNC, None).await;
+
+    let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+
+    let _response = client.sync_once(sync_settings).await.unwrap();
+
+    let room = client.get_joined_room(&test_json::DEFAULT_SYNC_ROOM_ID).unwrap();
+    let queue = room.send_queue();
+
+    let txn_id = queue.push(RoomMessageEventContent::text_plain("Hello")).await.unwrap();
+    queue.retry().await.unwrap();
+
+    let echoes = queue.local_echoes();
+    assert_eq!(echoes.len(), 1);
+    assert_matches!(echoes[0].state, LocalEchoState::Failed { .. });
+    assert!(!queue.is_empty().await.unwrap());
+
+    let edited = queue.edit(&txn_id, RoomMessageEventContent::text_plain("Hi")).await.unwrap();
+    assert!(edited);
+    assert_eq!(queue.local_echoes()[0].content["body"], "Hi");
+
+    assert!(queue.cancel(&txn_id).await.unwrap());
+    assert!(queue.local_echoes().is_empty());
+    assert!(queue.is_empty().await.unwrap());
+}
+
+#[async_test]
+async fn room_send_queue_remote_echo() {
+    let (client, server) = logged_in_client().await;
+
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/send/.*"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(403).set_body_json(json!({
+            "errcode": "M_FORBIDDEN",
+            "error": "You are not allowed to send messages in this room",
+        })))
+        .mount(&server)
+        .await;
+
+    mock_sync(&server, &*test_json::SYNC, None).await;
+
+    let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+
+    let _response = client.sync_once(sync_settings).await.unwrap();
+
+    let room = client.get_joined_room(&test_json::DEFAULT_SYNC_ROOM_ID).unwrap();
+    let queue = room.send_queue();
+
+    let txn_id = queue.push(RoomMessageEventContent::text_plain("Hello")).await.unwrap();
+    queue.retry().await.unwrap();
+    assert_matches!(queue.local_echoes()[0].state, LocalEchoState::Failed { .. });
+
+    // The remote echo removes the local echo, even if it isn't marked as sent,
+    // and the event from the queue so it isn't sent again.
+    let mut ev_builder = EventBuilder::new();
+    ev_builder.add_joined_room(
+        JoinedRoomBuilder::new(*test_json::DEFAULT_SYNC_ROOM_ID).add_timeline_event(
+            TimelineTestEvent::Custom(json!({
+                "content": { "body": "Hello", "msgtype": "m.text" },
+                "event_id": "$remote_echo:localhost",
+                "origin_server_ts": 152039280,
+                "sender": "@example:localhost",
+                "type": "m.room.message",
+                "unsigned": { "transaction_id": txn_id },
+            })),
+        ),
+    );
+
+    let sync_token = client.sync_token().await.unwrap();
+    mock_sync(&server, ev_builder.build_json_sync_response(), Some(sync_token.clone())).await;
+    let _response = client.sync_once(SyncSettings::new().token(sync_token)).await.unwrap();
+
+    assert!(queue.local_echoes().is_empty());
+    assert!(queue.is_empty().await.unwrap());
+}
+
 #[async_test]
 async fn room_attachment_send() {
     let (client, server) = logged_in_client().await;
@@ -486,6 +920,198 @@ async fn room_attachment_send_info_thumbnail() {
     assert_eq!(event_id!("$h29iv0s8:example.com"), response.event_id)
 }
 
+#[derive(Debug)]
+struct VideoThumbnailer;
+
+impl Thumbnailer for VideoThumbnailer {
+    fn generate_thumbnail(
+        &self,
+        content_type: &mime::Mime,
+        _data: &[u8],
+        size: (u32, u32),
+    ) -> Result<Option<GeneratedThumbnail>, ThumbnailError> {
+        if content_type.type_() != mime::VIDEO {
+            return Ok(None);
+        }
+
+        Ok(Some(GeneratedThumbnail {
+            data: b"Thumbnail".to_vec(),
+            content_type: mime::IMAGE_PNG,
+            info: BaseThumbnailInfo {
+                width: Some(size.0.into()),
+                height: Some(size.1.into()),
+                size: Some(uint!(9)),
+            },
+            blurhash: Some("LEHV6nWB2yk8pyo0adR*.7kCMdnj".to_owned()),
+        }))
+    }
+}
+
+#[async_test]
+async fn room_attachment_send_generated_thumbnail() {
+    let (client, server) = logged_in_client().await;
+
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/send/.*"))
+        .and(header("authorization", "Bearer 1234"))
+        .and(body_partial_json(json!({
+            "msgtype": "m.video",
+            "info": {
+                "mimetype": "video/mp4",
+                "xyz.amorgan.blurhash": "LEHV6nWB2yk8pyo0adR*.7kCMdnj",
+                "thumbnail_info": {
+                    "h": 240,
+                    "w": 320,
+                    "mimetype": "image/png",
+                    "size": 9,
+                },
+                "thumbnail_url": "mxc://example.com/AQwafuaFswefuhsfAFAgsw",
+            }
+        })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EVENT_ID))
+        .mount(&server)
+        .await;
+
+    Mock::given(method("POST"))
+        .and(path("/_matrix/media/r0/upload"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+          "content_uri": "mxc://example.com/AQwafuaFswefuhsfAFAgsw"
+        })))
+        .expect(2)
+        .mount(&server)
+        .await;
+
+    mock_sync(&server, &*test_json::SYNC, None).await;
+
+    let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+
+    let _response = client.sync_once(sync_settings).await.unwrap();
+
+    let room = client.get_joined_room(&test_json::DEFAULT_SYNC_ROOM_ID).unwrap();
+
+    let config = AttachmentConfig::new().thumbnailer(VideoThumbnailer, Some((320, 240)));
+    let content_type = "video/mp4".parse().unwrap();
+
+    let response = room
+        .send_attachment("video", &content_type, "Hello world".as_bytes(), config)
+        .await
+        .unwrap();
+
+    assert_eq!(event_id!("$h29iv0s8:example.com"), response.event_id)
+}
+
+#[async_test]
+async fn set_directory_visibility() {
+    let (client, server) = logged_in_client().await;
+
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/directory/list/room/"))
+        .and(header("authorization", "Bearer 1234"))
+        .and(body_partial_json(json!({ "visibility": "public" })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EMPTY))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    mock_sync(&server, &*test_json::SYNC, None).await;
+    client.sync_once(SyncSettings::default()).await.unwrap();
+    let room = client.get_joined_room(&test_json::DEFAULT_SYNC_ROOM_ID).unwrap();
+
+    room.set_directory_visibility(Visibility::Public).await.unwrap();
+}
+
+#[async_test]
+async fn set_canonical_alias() {
+    let (client, server) = logged_in_client().await;
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/r0/directory/room/%23main"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "room_id": *test_json::DEFAULT_SYNC_ROOM_ID,
+            "servers": ["localhost"],
+        })))
+        .mount(&server)
+        .await;
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/r0/directory/room/%23other"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "room_id": "!other:localhost",
+            "servers": ["localhost"],
+        })))
+        .mount(&server)
+        .await;
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/r0/directory/room/%23unknown"))
+        .respond_with(ResponseTemplate::new(404).set_body_json(json!({
+            "errcode": "M_NOT_FOUND",
+            "error": "Room alias not found",
+        })))
+        .mount(&server)
+        .await;
+
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/state/m%2Eroom%2Ecanonical%5Falias$"))
+        .and(body_partial_json(json!({ "alias": "#main:localhost" })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EVENT_ID))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    mock_sync(&server, &*test_json::SYNC, None).await;
+    client.sync_once(SyncSettings::default()).await.unwrap();
+    let room = client.get_joined_room(&test_json::DEFAULT_SYNC_ROOM_ID).unwrap();
+
+    let main = room_alias_id!("#main:localhost").to_owned();
+    let other = room_alias_id!("#other:localhost").to_owned();
+    let unknown = room_alias_id!("#unknown:localhost").to_owned();
+
+    assert_matches!(
+        room.set_canonical_alias(Some(main.clone()), vec![other]).await,
+        Err(Error::RoomAlias(RoomAliasError::WrongRoom { .. }))
+    );
+    assert_matches!(
+        room.set_canonical_alias(Some(main.clone()), vec![unknown]).await,
+        Err(Error::RoomAlias(RoomAliasError::NotFound(_)))
+    );
+    room.set_canonical_alias(Some(main), Vec::new()).await.unwrap();
+}
+
+#[async_test]
+async fn set_user_power_level() {
+    let (client, server) = logged_in_client().await;
+
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/state/m%2Eroom%2Epower%5Flevels$"))
+        .and(body_partial_json(json!({
+            "users": {
+                "@example:localhost": 100,
+                "@example2:localhost": 50,
+            },
+        })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EVENT_ID))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    mock_sync(&server, &*test_json::SYNC, None).await;
+    client.sync_once(SyncSettings::default()).await.unwrap();
+    let room = client.get_joined_room(&test_json::DEFAULT_SYNC_ROOM_ID).unwrap();
+
+    let user_id = user_id!("@example2:localhost");
+    assert_eq!(room.user_power_level(user_id).await.unwrap(), 0);
+    assert!(room.can_own_user(PowerLevelAction::Ban).await.unwrap());
+    assert!(!room.can_user(user_id, PowerLevelAction::Kick).await.unwrap());
+
+    room.set_user_power_level(user_id, int!(50)).await.unwrap();
+    assert_matches!(
+        room.set_user_power_level(user_id, int!(101)).await,
+        Err(Error::PowerLevels(PowerLevelsError::Forbidden))
+    );
+}
+
 #[async_test]
 async fn room_redact() {
     let (client, server) = logged_in_client().await;
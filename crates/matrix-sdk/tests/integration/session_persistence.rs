@@ -0,0 +1,188 @@
+use futures::StreamExt;
+use futures_signals::signal::{Mutable, SignalExt};
+use futures_util::future;
+use matrix_sdk::{
+    async_trait,
+    config::RequestConfig,
+    session_persistence::{PersistedSession, SessionPersister},
+    Client, Session, StoreError,
+};
+use matrix_sdk_test::{async_test, test_json};
+use ruma::{api::MatrixVersion, device_id, user_id};
+use serde_json::json;
+use url::Url;
+use wiremock::{
+    matchers::{header, method, path},
+    Mock, MockServer, ResponseTemplate,
+};
+
+use crate::test_client_builder;
+
+#[derive(Clone, Debug, Default)]
+struct MemoryPersister(Mutable<Option<PersistedSession>>);
+
+impl MemoryPersister {
+    async fn wait_for(&self, persisted: bool) -> Option<PersistedSession> {
+        self.0
+            .signal_cloned()
+            .to_stream()
+            .filter(|session| future::ready(session.is_some() == persisted))
+            .next()
+            .await
+            .unwrap()
+    }
+}
+
+#[async_trait]
+impl SessionPersister for MemoryPersister {
+    async fn load(&self) -> Result<Option<PersistedSession>, StoreError> {
+        Ok(self.0.get_cloned())
+    }
+
+    async fn save(&self, session: &PersistedSession) -> Result<(), StoreError> {
+        self.0.set(Some(session.clone()));
+        Ok(())
+    }
+
+    async fn remove(&self) -> Result<(), StoreError> {
+        self.0.set(None);
+        Ok(())
+    }
+}
+
+async fn mock_logout(server: &MockServer, access_token: &str) {
+    Mock::given(method("POST"))
+        .and(path("/_matrix/client/r0/logout"))
+        .and(header("authorization", format!("Bearer {access_token}").as_str()))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+        .expect(1)
+        .mount(server)
+        .await;
+}
+
+#[async_test]
+async fn persist_session_on_login_and_logout() {
+    let (builder, server) = test_client_builder().await;
+    let persister = MemoryPersister::default();
+    let client = builder
+        .request_config(RequestConfig::new().disable_retry())
+        .session_persister(persister.clone())
+        .build()
+        .await
+        .unwrap();
+    assert!(!client.logged_in());
+
+    Mock::given(method("POST"))
+        .and(path("/_matrix/client/r0/login"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::LOGIN))
+        .mount(&server)
+        .await;
+
+    client.login_username("example", "wordpass").send().await.unwrap();
+
+    let persisted = persister.wait_for(true).await.unwrap();
+    assert_eq!(persisted.homeserver_url, Url::parse(&server.uri()).unwrap());
+    assert_eq!(persisted.session, client.session().unwrap());
+
+    mock_logout(&server, "abc123").await;
+    client.logout().await.unwrap();
+
+    assert_eq!(persister.wait_for(false).await, None);
+}
+
+#[async_test]
+async fn restore_persisted_session() {
+    let server = MockServer::start().await;
+    let session = Session {
+        access_token: "1234".to_owned(),
+        refresh_token: None,
+        user_id: user_id!("@example:localhost").to_owned(),
+        device_id: device_id!("DEVICEID").to_owned(),
+    };
+    let homeserver_url = Url::parse(&server.uri()).unwrap();
+    let persister = MemoryPersister(Mutable::new(Some(PersistedSession {
+        homeserver_url: homeserver_url.clone(),
+        session: session.clone(),
+    })));
+
+    // No homeserver is configured, the one of the persisted session is used.
+    let client = Client::builder()
+        .server_versions([MatrixVersion::V1_0])
+        .session_persister(persister)
+        .build()
+        .await
+        .unwrap();
+
+    assert!(client.logged_in());
+    assert_eq!(client.homeserver().await, homeserver_url);
+    assert_eq!(client.session(), Some(session));
+}
+
+#[cfg(any(feature = "sled", feature = "sqlite"))]
+#[async_test]
+async fn account_manager() {
+    use std::time::Duration;
+
+    use matches::assert_matches;
+    use matrix_sdk::{AccountManager, AccountManagerError};
+
+    let server = MockServer::start().await;
+    let dir = tempfile::tempdir().unwrap();
+    let manager = AccountManager::new(
+        dir.path(),
+        Client::builder()
+            .server_versions([MatrixVersion::V1_0])
+            .request_config(RequestConfig::new().disable_retry()),
+    );
+    let user_id = user_id!("@cheeky_monkey:matrix.org");
+
+    assert!(manager.accounts().await.unwrap().is_empty());
+    assert!(manager.active_account().await.unwrap().is_none());
+    assert_matches!(
+        manager.open_account(user_id).await,
+        Err(AccountManagerError::UnknownAccount(_))
+    );
+
+    // The data of an account that was never logged in is removed once its
+    // client is dropped.
+    let accounts_dir = dir.path().join("accounts");
+    let client = manager.add_account(server.uri()).await.unwrap();
+    assert!(manager.accounts().await.unwrap().is_empty());
+    assert_eq!(std::fs::read_dir(&accounts_dir).unwrap().count(), 1);
+    drop(client);
+    assert!(manager.accounts().await.unwrap().is_empty());
+    assert_eq!(std::fs::read_dir(&accounts_dir).unwrap().count(), 0);
+
+    Mock::given(method("POST"))
+        .and(path("/_matrix/client/r0/login"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::LOGIN))
+        .mount(&server)
+        .await;
+
+    let client = manager.add_account(server.uri()).await.unwrap();
+    client.login_username("example", "wordpass").send().await.unwrap();
+
+    // The session is persisted in the background.
+    let mut accounts = manager.accounts().await.unwrap();
+    for _ in 0..100 {
+        if !accounts.is_empty() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        accounts = manager.accounts().await.unwrap();
+    }
+    assert_eq!(accounts, [user_id.to_owned()]);
+
+    // The same client is used for the account.
+    let switched = manager.switch_account(user_id).await.unwrap();
+    assert_eq!(switched.session(), client.session());
+    let active = manager.active_account().await.unwrap().unwrap();
+    assert_eq!(active.user_id(), Some(user_id));
+
+    mock_logout(&server, "abc123").await;
+    manager.remove_account(user_id).await.unwrap();
+
+    assert!(manager.accounts().await.unwrap().is_empty());
+    assert!(manager.active_account().await.unwrap().is_none());
+    assert_eq!(std::fs::read_dir(&accounts_dir).unwrap().count(), 0);
+}
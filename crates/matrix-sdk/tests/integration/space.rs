@@ -0,0 +1,212 @@
+use futures::{pin_mut, StreamExt};
+use matrix_sdk::{config::SyncSettings, space::SpaceHierarchyOptions};
+use matrix_sdk_test::{async_test, EventBuilder, JoinedRoomBuilder, TimelineTestEvent};
+use ruma::{event_id, room_id, server_name, RoomId};
+use serde_json::{json, Value as JsonValue};
+use wiremock::{
+    matchers::{body_partial_json, header, method, path_regex, query_param},
+    Mock, ResponseTemplate,
+};
+
+use crate::{logged_in_client, mock_sync};
+
+fn hierarchy_room(room_id: &str) -> JsonValue {
+    json!({
+        "room_id": room_id,
+        "num_joined_members": 1,
+        "world_readable": false,
+        "guest_can_join": false,
+        "children_state": [],
+    })
+}
+
+#[async_test]
+async fn space_hierarchy() {
+    let (client, server) = logged_in_client().await;
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/.*/rooms/.*/hierarchy$"))
+        .and(query_param("from", "next"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "rooms": [hierarchy_room("!b:localhost")],
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/.*/rooms/.*/hierarchy$"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "rooms": [hierarchy_room("!space:localhost"), hierarchy_room("!a:localhost")],
+            "next_batch": "next",
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let hierarchy =
+        client.space_hierarchy(room_id!("!space:localhost"), SpaceHierarchyOptions::default());
+    pin_mut!(hierarchy);
+
+    let mut room_ids = Vec::new();
+    while let Some(room) = hierarchy.next().await {
+        room_ids.push(room.unwrap().room_id);
+    }
+
+    assert_eq!(
+        room_ids,
+        [
+            room_id!("!space:localhost").to_owned(),
+            room_id!("!a:localhost").to_owned(),
+            room_id!("!b:localhost").to_owned()
+        ]
+    );
+}
+
+fn state_event(event_type: &str, state_key: &str, content: JsonValue) -> TimelineTestEvent {
+    TimelineTestEvent::Custom(json!({
+        "content": content,
+        "event_id": format!(
+            "${event_type}-{}:localhost",
+            state_key.replace(|c: char| !c.is_ascii_alphanumeric(), "")
+        ),
+        "origin_server_ts": 152039280,
+        "sender": "@example:localhost",
+        "state_key": state_key,
+        "type": event_type,
+    }))
+}
+
+fn space(room_id: &RoomId, children: &[&str]) -> JoinedRoomBuilder {
+    let mut room = JoinedRoomBuilder::new(room_id)
+        .add_timeline_event(state_event(
+            "m.room.create",
+            "",
+            json!({ "creator": "@example:localhost", "type": "m.space" }),
+        ))
+        .add_timeline_event(state_event(
+            "m.room.member",
+            "@example:localhost",
+            json!({ "membership": "join" }),
+        ));
+
+    for child in children {
+        room = room.add_timeline_event(state_event(
+            "m.space.child",
+            child,
+            json!({ "via": ["localhost"] }),
+        ));
+    }
+
+    room
+}
+
+#[async_test]
+async fn space_tree() {
+    let (client, server) = logged_in_client().await;
+    let mut ev_builder = EventBuilder::new();
+
+    ev_builder
+        .add_joined_room(space(room_id!("!space:localhost"), &["!a:localhost", "!sub:localhost"]))
+        .add_joined_room(space(room_id!("!sub:localhost"), &["!c:localhost"]))
+        .add_joined_room(JoinedRoomBuilder::new(room_id!("!b:localhost")).add_timeline_event(
+            state_event(
+                "m.space.parent",
+                "!space:localhost",
+                json!({ "via": ["localhost"], "canonical": true }),
+            ),
+        ));
+
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    client.sync_once(SyncSettings::new()).await.unwrap();
+
+    let space = client.get_joined_room(room_id!("!space:localhost")).unwrap();
+    let children = space.space_children().await.unwrap();
+    assert_eq!(children.len(), 2);
+    assert_eq!(children[0].via, [server_name!("localhost").to_owned()]);
+
+    let b = client.get_joined_room(room_id!("!b:localhost")).unwrap();
+    let parents = b.space_parents().await.unwrap();
+    assert_eq!(parents.len(), 1);
+    assert_eq!(parents[0].room_id, room_id!("!space:localhost").to_owned());
+
+    let tree = client.space_tree().await.unwrap();
+    assert_eq!(tree.root_spaces().collect::<Vec<_>>(), [room_id!("!space:localhost")]);
+    assert_eq!(
+        tree.descendants(room_id!("!space:localhost")).into_iter().collect::<Vec<_>>(),
+        [
+            room_id!("!a:localhost").to_owned(),
+            room_id!("!b:localhost").to_owned(),
+            room_id!("!c:localhost").to_owned(),
+            room_id!("!sub:localhost").to_owned()
+        ]
+    );
+    assert!(tree.contains(room_id!("!space:localhost"), room_id!("!c:localhost")));
+    assert!(!tree.contains(room_id!("!sub:localhost"), room_id!("!a:localhost")));
+
+    // Removing a child updates the tree.
+    ev_builder.add_joined_room(
+        JoinedRoomBuilder::new(room_id!("!sub:localhost")).add_timeline_event(state_event(
+            "m.space.child",
+            "!c:localhost",
+            json!({}),
+        )),
+    );
+    let sync_token = client.sync_token().await.unwrap();
+    mock_sync(&server, ev_builder.build_json_sync_response(), Some(sync_token.clone())).await;
+    client.sync_once(SyncSettings::new().token(sync_token)).await.unwrap();
+
+    let tree = client.space_tree().await.unwrap();
+    assert!(!tree.contains(room_id!("!space:localhost"), room_id!("!c:localhost")));
+    assert_eq!(tree.children(room_id!("!sub:localhost")).count(), 0);
+}
+
+#[async_test]
+async fn add_and_remove_space_child() {
+    let (client, server) = logged_in_client().await;
+
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/state/m%2Espace%2Echild/.*"))
+        .and(body_partial_json(json!({ "via": ["localhost"], "order": "a" })))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(json!({ "event_id": "$add:localhost" })),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/state/m%2Espace%2Echild/.*"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(json!({ "event_id": "$remove:localhost" })),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let mut ev_builder = EventBuilder::new();
+    ev_builder.add_joined_room(space(room_id!("!space:localhost"), &[]));
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    client.sync_once(SyncSettings::new()).await.unwrap();
+
+    let space = client.get_joined_room(room_id!("!space:localhost")).unwrap();
+    let child_id = room_id!("!a:localhost");
+
+    let response = space
+        .add_space_child(
+            child_id,
+            vec![server_name!("localhost").to_owned()],
+            Some("a".to_owned()),
+            false,
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.event_id, event_id!("$add:localhost"));
+
+    let response = space.remove_space_child(child_id).await.unwrap();
+    assert_eq!(response.event_id, event_id!("$remove:localhost"));
+}
@@ -59,6 +59,7 @@ enum FeatureSet {
     Markdown,
     Socks,
     SsoLogin,
+    ExperimentalOidc,
     ExperimentalTimeline,
 }
 
@@ -163,6 +164,7 @@ fn run_feature_tests(cmd: Option<FeatureSet>) -> Result<()> {
         (FeatureSet::Markdown, "--features markdown"),
         (FeatureSet::Socks, "--features socks"),
         (FeatureSet::SsoLogin, "--features sso-login"),
+        (FeatureSet::ExperimentalOidc, "--features experimental-oidc"),
         (FeatureSet::ExperimentalTimeline, "--features experimental-timeline"),
     ]);
 